@@ -0,0 +1,185 @@
+//! Feeds random instruction data and synthetic accounts into `Processor::process`,
+//! run it from the crate root with `cargo +nightly fuzz run process`
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use solana_escrow::collateral::find_authority_address;
+use solana_escrow::params::{STAKE_POOL_PROGRAM_ADDRESS, SYSTEM_ACCOUNT_ADDRESS};
+use solana_escrow::processor::Processor;
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+    sysvar::{self, Sysvar},
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+/// Addresses the processor checks accounts against, and a few others
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum Key {
+    Other(u8),
+    Program,
+    Authority,
+    SystemAccount,
+    SystemProgram,
+    TokenProgram,
+    StakePoolProgram,
+    Rent,
+    Clock,
+}
+
+impl Key {
+    fn pubkey(self, program_id: &Pubkey) -> Pubkey {
+        match self {
+            Key::Other(seed) => Pubkey::new_from_array([seed; 32]),
+            Key::Program => *program_id,
+            Key::Authority => find_authority_address(program_id).0,
+            Key::SystemAccount => SYSTEM_ACCOUNT_ADDRESS,
+            Key::SystemProgram => system_program::id(),
+            Key::TokenProgram => spl_token::id(),
+            Key::StakePoolProgram => STAKE_POOL_PROGRAM_ADDRESS,
+            Key::Rent => sysvar::rent::id(),
+            Key::Clock => sysvar::clock::id(),
+        }
+    }
+}
+
+/// Account data, raw bytes reach the program states through their headers
+#[derive(Arbitrary, Debug)]
+enum Data {
+    Raw(Vec<u8>),
+    Rent,
+    Clock { unix_timestamp: i64 },
+    Mint { authority: Option<Key>, supply: u64, decimals: u8 },
+    Token { mint: Key, owner: Key, amount: u64 },
+}
+
+impl Data {
+    fn bytes(self, program_id: &Pubkey) -> Vec<u8> {
+        match self {
+            Data::Raw(bytes) => bytes,
+            Data::Rent => sysvar_bytes(&Rent::default()),
+            Data::Clock { unix_timestamp } => sysvar_bytes(&Clock { unix_timestamp, ..Clock::default() }),
+            Data::Mint { authority, supply, decimals } => {
+                let mint = Mint {
+                    mint_authority: authority.map(|key| key.pubkey(program_id)).into(),
+                    supply,
+                    decimals,
+                    is_initialized: true,
+                    freeze_authority: COption::None,
+                };
+                pack_bytes(mint)
+            }
+            Data::Token { mint, owner, amount } => {
+                let account = TokenAccount {
+                    mint: mint.pubkey(program_id),
+                    owner: owner.pubkey(program_id),
+                    amount,
+                    state: AccountState::Initialized,
+                    ..TokenAccount::default()
+                };
+                pack_bytes(account)
+            }
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzAccount {
+    key: Key,
+    owner: Key,
+    is_signer: bool,
+    is_writable: bool,
+    lamports: u64,
+    data: Data,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    accounts: Vec<FuzzAccount>,
+    /// Indices into `accounts` of the instruction accounts, repeated ones alias
+    /// the same account as they do in the runtime
+    indices: Vec<u8>,
+    instruction_data: Vec<u8>,
+}
+
+/// Storage behind an `AccountInfo`
+struct Storage {
+    key: Pubkey,
+    owner: Pubkey,
+    is_signer: bool,
+    is_writable: bool,
+    lamports: u64,
+    data: Vec<u8>,
+}
+
+fn sysvar_bytes<S: Sysvar>(sysvar: &S) -> Vec<u8> {
+    let mut data = vec![0; S::size_of()];
+    let (key, mut lamports) = (Pubkey::default(), 0);
+    sysvar
+        .to_account_info(&mut AccountInfo::new(&key, false, true, &mut lamports, &mut data, &key, false, 0))
+        .unwrap();
+    data
+}
+
+fn pack_bytes<T: Pack>(state: T) -> Vec<u8> {
+    let mut data = vec![0; T::LEN];
+    state.pack_into_slice(&mut data);
+    data
+}
+
+fuzz_target!(|input: Input| {
+    let program_id = Pubkey::new_from_array([255; 32]);
+
+    // The runtime passes each address once, duplicates of an address are the same account
+    let mut storages: Vec<Storage> = Vec::new();
+    for account in input.accounts {
+        let key = account.key.pubkey(&program_id);
+        if storages.iter().any(|storage| storage.key == key) {
+            continue;
+        }
+        storages.push(Storage {
+            key,
+            owner: account.owner.pubkey(&program_id),
+            is_signer: account.is_signer,
+            is_writable: account.is_writable,
+            lamports: account.lamports,
+            data: account.data.bytes(&program_id),
+        });
+    }
+    if storages.is_empty() {
+        return;
+    }
+
+    let infos: Vec<AccountInfo> = storages
+        .iter_mut()
+        .map(|storage| {
+            AccountInfo::new(
+                &storage.key,
+                storage.is_signer,
+                storage.is_writable,
+                &mut storage.lamports,
+                &mut storage.data,
+                &storage.owner,
+                false,
+                0,
+            )
+        })
+        .collect();
+    let accounts: Vec<AccountInfo> = input
+        .indices
+        .iter()
+        .map(|index| infos[*index as usize % infos.len()].clone())
+        .collect();
+
+    let lamports: u128 = infos.iter().map(|info| info.lamports() as u128).sum();
+    if Processor::process(&program_id, &accounts, &input.instruction_data).is_ok() {
+        // Lamports only move between the accounts of the instruction
+        assert_eq!(infos.iter().map(|info| info.lamports() as u128).sum::<u128>(), lamports);
+    }
+});
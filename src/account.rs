@@ -0,0 +1,142 @@
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::rent::Rent,
+};
+use std::ops::{Deref, DerefMut};
+use crate::error::LiquityError;
+use crate::math;
+use crate::state::{AccountType, ProgramAccount};
+
+/// Rejects the same account passed for several roles that must be distinct
+pub fn check_distinct(accounts: &[&AccountInfo]) -> ProgramResult {
+    for (index, account) in accounts.iter().enumerate() {
+        if accounts[..index].iter().any(|other| other.key == account.key) {
+            return Err(LiquityError::DuplicateAccount.into());
+        }
+    }
+    Ok(())
+}
+
+/// Sweeps the lamports of `info` to `recipient`, zeroes its data and marks it closed,
+/// so that neither a later instruction nor a revival in the same transaction sees the old state
+pub fn close_account<'a>(info: &AccountInfo<'a>, recipient: &AccountInfo<'a>) -> ProgramResult {
+    check_distinct(&[info, recipient])?;
+
+    **recipient.lamports.borrow_mut() = math::add(recipient.lamports(), info.lamports())?;
+    **info.lamports.borrow_mut() = 0;
+
+    let mut data = info.data.borrow_mut();
+    data.fill(0);
+    if let Some(account_type) = data.first_mut() {
+        *account_type = AccountType::Closed as u8;
+    }
+
+    Ok(())
+}
+
+/// Moves `amount` lamports out of `info`, an account of the program, to `recipient`
+pub fn transfer_lamports<'a>(info: &AccountInfo<'a>, recipient: &AccountInfo<'a>, amount: u64) -> ProgramResult {
+    check_distinct(&[info, recipient])?;
+
+    **info.lamports.borrow_mut() = math::sub(info.lamports(), amount)?;
+    **recipient.lamports.borrow_mut() = math::add(recipient.lamports(), amount)?;
+
+    Ok(())
+}
+
+/// Program account whose owner, account type and layout have been checked,
+/// together with its unpacked state
+pub struct Account<'a, 'b, T: ProgramAccount> {
+    pub info: &'a AccountInfo<'b>,
+    pub state: T,
+}
+
+impl<'a, 'b, T: ProgramAccount> Account<'a, 'b, T> {
+    /// Loads an initialized account the instruction only reads
+    pub fn load(info: &'a AccountInfo<'b>, program_id: &Pubkey) -> Result<Self, ProgramError> {
+        let account = Self::unpack(info, program_id)?;
+        if !account.state.is_initialized() {
+            return Err(T::uninitialized_error());
+        }
+        Ok(account)
+    }
+
+    /// Loads an initialized account the instruction writes to
+    pub fn load_mut(info: &'a AccountInfo<'b>, program_id: &Pubkey) -> Result<Self, ProgramError> {
+        Self::check_writable(info)?;
+        Self::load(info, program_id)
+    }
+
+    /// Loads a rent exempt account which is initialized by the instruction
+    pub fn load_uninitialized(info: &'a AccountInfo<'b>, program_id: &Pubkey, rent: &Rent) -> Result<Self, ProgramError> {
+        let account = Self::load_or_uninitialized(info, program_id, rent)?;
+        if account.state.is_initialized() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        Ok(account)
+    }
+
+    /// Loads a rent exempt account which is initialized on first use
+    pub fn load_or_uninitialized(info: &'a AccountInfo<'b>, program_id: &Pubkey, rent: &Rent) -> Result<Self, ProgramError> {
+        Self::check_writable(info)?;
+        if !rent.is_exempt(info.lamports(), info.data_len()) {
+            return Err(LiquityError::NotRentExempt.into());
+        }
+        Self::unpack(info, program_id)
+    }
+
+    /// Packs the state back into the account
+    pub fn save(&self) -> ProgramResult {
+        T::pack_into_slice(&self.state, &mut self.info.data.borrow_mut());
+        Ok(())
+    }
+
+    /// Closes the account, sending its lamports to `recipient`
+    pub fn close(self, recipient: &AccountInfo<'b>) -> ProgramResult {
+        close_account(self.info, recipient)
+    }
+
+    fn check_writable(info: &AccountInfo) -> ProgramResult {
+        if !info.is_writable {
+            return Err(LiquityError::AccountNotWritable.into());
+        }
+        Ok(())
+    }
+
+    fn unpack(info: &'a AccountInfo<'b>, program_id: &Pubkey) -> Result<Self, ProgramError> {
+        if info.owner != program_id {
+            return Err(LiquityError::InvalidAccountOwner.into());
+        }
+
+        let data = info.data.borrow();
+        match AccountType::of(&data) {
+            Some(AccountType::Closed) => return Err(LiquityError::AccountClosed.into()),
+            Some(account_type) if account_type == T::ACCOUNT_TYPE || account_type == AccountType::Uninitialized => {}
+            _ => return Err(LiquityError::InvalidAccountType.into()),
+        }
+        // Legacy layouts are only read by the migration, saving them back would not fit
+        if data.len() != T::LEN {
+            return Err(LiquityError::AccountNotMigrated.into());
+        }
+
+        let state = T::unpack_unchecked(&data)?;
+        Ok(Account { info, state })
+    }
+}
+
+impl<'a, 'b, T: ProgramAccount> Deref for Account<'a, 'b, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.state
+    }
+}
+
+impl<'a, 'b, T: ProgramAccount> DerefMut for Account<'a, 'b, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.state
+    }
+}
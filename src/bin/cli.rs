@@ -0,0 +1,560 @@
+//! Builds, signs and inspects transactions of the program offline, decodes its
+//! accounts and sends transactions to an RPC node, `cargo run --features cli --bin liquity-cli -- --help`
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::Value;
+use solana_escrow::client;
+use solana_escrow::collateral::find_collateral_type_address;
+use solana_escrow::instruction::{self, CoinSource, LiquityInstruction, StakePoolAccounts};
+use solana_escrow::rpc::{account_data, Result, Rpc};
+use solana_escrow::state::ProtocolConfig;
+use solana_escrow::units::{CollateralAmount, GovAmount, Lamports, Ratio, StableAmount};
+use solana_program::{
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    pubkey::Pubkey,
+};
+use solana_sdk::{
+    signature::{read_keypair_file, Keypair, Signer},
+    transaction::Transaction,
+};
+use std::{fs, io::Read, str::FromStr};
+
+#[derive(Parser)]
+#[clap(name = "liquity-cli", about = "Offline transactions and account inspection for the protocol")]
+struct Cli {
+    /// JSON RPC URL of the cluster, a local `solana-test-validator` by default
+    #[clap(long, global = true, env = "LIQUITY_RPC_URL", default_value = "http://127.0.0.1:8899")]
+    url: String,
+    /// Address of the deployed program
+    #[clap(long, global = true, env = "LIQUITY_PROGRAM_ID")]
+    program_id: Option<Pubkey>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+// Parsed once, the size of the variants does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum Command {
+    /// Build a transaction of one instruction, sign it and print or send it
+    Tx(TxArgs),
+    /// Decode a transaction printed by `tx`
+    Inspect {
+        /// Encoded transaction, `-` to read it from stdin
+        transaction: String,
+        #[clap(long, value_enum, default_value = "base64")]
+        encoding: Encoding,
+    },
+    /// Send a transaction signed offline
+    Send {
+        /// Encoded transaction, `-` to read it from stdin
+        transaction: String,
+        #[clap(long, value_enum, default_value = "base64")]
+        encoding: Encoding,
+    },
+    /// Decode an account of the program from a base64 dump or a JSON RPC file
+    Decode {
+        /// Base64 data, `getAccountInfo` response or `solana account --output json` file, `-` for stdin
+        file: String,
+    },
+    /// Fetch an account of the program from the RPC node and decode it
+    Fetch {
+        address: Pubkey,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Encoding {
+    Base58,
+    Base64,
+}
+
+#[derive(Args)]
+struct TxArgs {
+    /// Keypair file signing the transaction, repeated for every signer
+    #[clap(long = "keypair", required = true)]
+    keypairs: Vec<String>,
+    /// Fee payer, the first keypair by default
+    #[clap(long)]
+    fee_payer: Option<Pubkey>,
+    /// Recent blockhash, fetched from the RPC node when omitted
+    #[clap(long)]
+    blockhash: Option<Hash>,
+    #[clap(long, value_enum, default_value = "base64")]
+    encoding: Encoding,
+    /// Send the transaction instead of printing it
+    #[clap(long)]
+    send: bool,
+    #[clap(subcommand)]
+    instruction: InstructionCommand,
+}
+
+// Stake pool accounts of the instructions taking stake pool collateral
+#[derive(Args)]
+struct StakePoolArgs {
+    /// Stake pool of the collateral, for stake pool troves
+    #[clap(long, requires_all = &["vault", "pool-token-account"])]
+    stake_pool: Option<Pubkey>,
+    /// Collateral vault of the stake pool
+    #[clap(long)]
+    vault: Option<Pubkey>,
+    /// Pool token account of the user
+    #[clap(long)]
+    pool_token_account: Option<Pubkey>,
+}
+
+impl StakePoolArgs {
+    fn accounts(&self) -> Option<StakePoolAccounts> {
+        Some(StakePoolAccounts {
+            stake_pool: self.stake_pool?,
+            vault: self.vault?,
+            user_token: self.pool_token_account?,
+        })
+    }
+}
+
+#[derive(Subcommand)]
+enum InstructionCommand {
+    /// Borrow stablecoin against native SOL
+    Borrow {
+        #[clap(long)] borrower: Pubkey,
+        #[clap(long)] trove: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] collateral_type: Pubkey,
+        #[clap(long)] stablecoin_mint: Pubkey,
+        #[clap(long)] user_token: Pubkey,
+        #[clap(long)] borrow_amount: u64,
+        #[clap(long)] lamports: u64,
+    },
+    /// Repay and close a trove
+    CloseTrove {
+        #[clap(long)] borrower: Pubkey,
+        #[clap(long)] trove: Pubkey,
+        #[clap(long)] user_token: Pubkey,
+        #[clap(long)] stablecoin_mint: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] collateral_type: Pubkey,
+        #[clap(flatten)] stake_pool: StakePoolArgs,
+    },
+    /// Liquidate an undercollateralized trove
+    LiquidateTrove {
+        #[clap(long)] liquidator: Pubkey,
+        #[clap(long)] trove: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] collateral_type: Pubkey,
+        #[clap(flatten)] stake_pool: StakePoolArgs,
+    },
+    /// Withdraw collateral from a trove
+    WithdrawCoin {
+        #[clap(long)] borrower: Pubkey,
+        #[clap(long)] trove: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] collateral_type: Pubkey,
+        #[clap(flatten)] stake_pool: StakePoolArgs,
+        #[clap(long)] amount: u64,
+    },
+    /// Redeem collateral of a trove, burning its value in stablecoin
+    RedeemCoin {
+        #[clap(long)] borrower: Pubkey,
+        #[clap(long)] trove: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] collateral_type: Pubkey,
+        #[clap(long)] user_token: Pubkey,
+        #[clap(long)] stablecoin_mint: Pubkey,
+        #[clap(flatten)] stake_pool: StakePoolArgs,
+        #[clap(long)] amount: u64,
+    },
+    /// Add collateral to a trove
+    AddCoin {
+        #[clap(long)] borrower: Pubkey,
+        #[clap(long)] trove: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        /// Empty account of the program holding exactly the lamports, for native SOL troves
+        #[clap(long, required_unless_present = "stake-pool")]
+        temp_account: Option<Pubkey>,
+        #[clap(flatten)] stake_pool: StakePoolArgs,
+        #[clap(long)] amount: u64,
+    },
+    /// Deposit stablecoin
+    AddDeposit {
+        #[clap(long)] depositor: Pubkey,
+        #[clap(long)] deposit: Pubkey,
+        #[clap(long)] user_token: Pubkey,
+        #[clap(long)] user_governance_token: Pubkey,
+        #[clap(long)] stablecoin_mint: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] amount: u64,
+    },
+    /// Withdraw deposited stablecoin
+    WithdrawDeposit {
+        #[clap(long)] depositor: Pubkey,
+        #[clap(long)] deposit: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] stablecoin_mint: Pubkey,
+        #[clap(long)] user_token: Pubkey,
+        #[clap(long)] amount: u64,
+    },
+    /// Claim the rewards of a deposit
+    ClaimDepositReward {
+        #[clap(long)] deposit: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+    },
+    /// Mark a trove as received
+    ReceiveTrove {
+        #[clap(long)] trove: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+    },
+    /// Set the rewards of a deposit
+    AddDepositReward {
+        #[clap(long)] deposit: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] coin: u64,
+        #[clap(long)] governance: u64,
+        #[clap(long)] token: u64,
+    },
+    /// Borrow stablecoin against stake pool tokens
+    BorrowWithStakePoolToken {
+        #[clap(long)] borrower: Pubkey,
+        #[clap(long)] trove: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] collateral_type: Pubkey,
+        #[clap(long)] stake_pool: Pubkey,
+        #[clap(long)] vault: Pubkey,
+        #[clap(long)] pool_token_account: Pubkey,
+        #[clap(long)] stablecoin_mint: Pubkey,
+        #[clap(long)] user_token: Pubkey,
+        #[clap(long)] borrow_amount: u64,
+        #[clap(long)] pool_tokens: u64,
+    },
+    /// Initialize the protocol account
+    InitProtocol {
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] stablecoin_mint: Pubkey,
+        /// JSON file of the protocol config, with the field names of `ProtocolConfig`
+        #[clap(long)] config: String,
+    },
+    /// Queue a protocol config change
+    QueueConfigChange {
+        #[clap(long)] admin: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        /// JSON file of the protocol config, with the field names of `ProtocolConfig`
+        #[clap(long)] config: String,
+    },
+    /// Flash mint stablecoin to a receiver program
+    FlashMint {
+        #[clap(long)] stablecoin_mint: Pubkey,
+        #[clap(long)] receiver_token: Pubkey,
+        #[clap(long)] fee_receiver_token: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] receiver_program: Pubkey,
+        /// Writable account passed through to the receiver program, repeated in order
+        #[clap(long = "remaining")]
+        remaining: Vec<Pubkey>,
+        #[clap(long)] amount: u64,
+    },
+    /// Set the bitmask of paused operations
+    SetPause {
+        #[clap(long)] pauser: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        /// Bitmask of the paused operations
+        #[clap(long)] paused: u64,
+    },
+    /// Initialize the debt ceiling of a collateral type
+    InitCollateralType {
+        #[clap(long)] admin: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        /// Stake pool of the collateral, the default pubkey for native SOL
+        #[clap(long)] collateral: Pubkey,
+        #[clap(long)] debt_ceiling: u64,
+    },
+    /// Update the debt ceiling of a collateral type
+    UpdateCollateralType {
+        #[clap(long)] admin: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        #[clap(long)] collateral_type: Pubkey,
+        #[clap(long)] debt_ceiling: u64,
+    },
+    /// Execute the queued config change
+    ExecuteConfigChange {
+        #[clap(long)] protocol: Pubkey,
+    },
+    /// Cancel the queued config change
+    CancelConfigChange {
+        #[clap(long)] guardian: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+    },
+    /// Upgrade a legacy account to the current layout in place
+    MigrateAccount {
+        #[clap(long)] payer: Pubkey,
+        #[clap(long)] legacy_account: Pubkey,
+        #[clap(long)] protocol: Pubkey,
+        /// Collateral type of native SOL, for legacy troves
+        #[clap(long)] collateral_type: Option<Pubkey>,
+    },
+}
+
+impl InstructionCommand {
+    fn instruction(&self, program_id: &Pubkey) -> Result<Instruction> {
+        use InstructionCommand::*;
+
+        let ix = match self {
+            Borrow { borrower, trove, protocol, collateral_type, stablecoin_mint, user_token, borrow_amount, lamports } => {
+                instruction::borrow(
+                    program_id, borrower, trove, protocol, collateral_type, stablecoin_mint, user_token,
+                    StableAmount(*borrow_amount), Lamports(*lamports),
+                )
+            }
+            CloseTrove { borrower, trove, user_token, stablecoin_mint, protocol, collateral_type, stake_pool } => {
+                instruction::close_trove(
+                    program_id, borrower, trove, user_token, stablecoin_mint, protocol, collateral_type,
+                    stake_pool.accounts().as_ref(),
+                )
+            }
+            LiquidateTrove { liquidator, trove, protocol, collateral_type, stake_pool } => instruction::liquidate_trove(
+                program_id, liquidator, trove, protocol, collateral_type, stake_pool.accounts().as_ref(),
+            ),
+            WithdrawCoin { borrower, trove, protocol, collateral_type, stake_pool, amount } => instruction::withdraw_coin(
+                program_id, borrower, trove, protocol, collateral_type, stake_pool.accounts().as_ref(), CollateralAmount(*amount),
+            ),
+            RedeemCoin { borrower, trove, protocol, collateral_type, user_token, stablecoin_mint, stake_pool, amount } => {
+                instruction::redeem_coin(
+                    program_id, borrower, trove, protocol, collateral_type, user_token, stablecoin_mint,
+                    stake_pool.accounts().as_ref(), CollateralAmount(*amount),
+                )
+            }
+            AddCoin { borrower, trove, protocol, temp_account, stake_pool, amount } => {
+                let source = match (stake_pool.accounts(), temp_account) {
+                    (Some(stake_pool), _) => CoinSource::StakePool(stake_pool),
+                    (None, Some(temp_account)) => CoinSource::Lamports(*temp_account),
+                    (None, None) => return Err("add-coin needs --temp-account or the stake pool accounts".into()),
+                };
+                instruction::add_coin(program_id, borrower, trove, protocol, &source, CollateralAmount(*amount))
+            }
+            AddDeposit { depositor, deposit, user_token, user_governance_token, stablecoin_mint, protocol, amount } => {
+                instruction::add_deposit(
+                    program_id, depositor, deposit, user_token, user_governance_token, stablecoin_mint, protocol,
+                    StableAmount(*amount),
+                )
+            }
+            WithdrawDeposit { depositor, deposit, protocol, stablecoin_mint, user_token, amount } => {
+                instruction::withdraw_deposit(
+                    program_id, depositor, deposit, protocol, stablecoin_mint, user_token, StableAmount(*amount),
+                )
+            }
+            ClaimDepositReward { deposit, protocol } => instruction::claim_deposit_reward(program_id, deposit, protocol),
+            ReceiveTrove { trove, protocol } => instruction::receive_trove(program_id, trove, protocol),
+            AddDepositReward { deposit, protocol, coin, governance, token } => instruction::add_deposit_reward(
+                program_id, deposit, protocol, Lamports(*coin), GovAmount(*governance), StableAmount(*token),
+            ),
+            BorrowWithStakePoolToken {
+                borrower, trove, protocol, collateral_type, stake_pool, vault, pool_token_account, stablecoin_mint, user_token,
+                borrow_amount, pool_tokens,
+            } => {
+                let stake_pool = StakePoolAccounts { stake_pool: *stake_pool, vault: *vault, user_token: *pool_token_account };
+                instruction::borrow_with_stake_pool_token(
+                    program_id, borrower, trove, protocol, collateral_type, &stake_pool, stablecoin_mint, user_token,
+                    StableAmount(*borrow_amount),
+                    CollateralAmount(*pool_tokens),
+                )
+            }
+            InitProtocol { protocol, stablecoin_mint, config } => {
+                instruction::init_protocol(program_id, protocol, stablecoin_mint, read_config(config)?)
+            }
+            QueueConfigChange { admin, protocol, config } => {
+                instruction::queue_config_change(program_id, admin, protocol, read_config(config)?)
+            }
+            FlashMint { stablecoin_mint, receiver_token, fee_receiver_token, protocol, receiver_program, remaining, amount } => {
+                let remaining: Vec<AccountMeta> = remaining.iter().map(|key| AccountMeta::new(*key, false)).collect();
+                instruction::flash_mint(
+                    program_id, stablecoin_mint, receiver_token, fee_receiver_token, protocol, receiver_program, &remaining,
+                    StableAmount(*amount),
+                )
+            }
+            SetPause { pauser, protocol, paused } => instruction::set_pause(program_id, pauser, protocol, *paused),
+            InitCollateralType { admin, protocol, collateral, debt_ceiling } => {
+                let (collateral_type, _) = find_collateral_type_address(program_id, protocol, collateral);
+                instruction::init_collateral_type(
+                    program_id, admin, protocol, &collateral_type, *collateral, StableAmount(*debt_ceiling),
+                )
+            }
+            UpdateCollateralType { admin, protocol, collateral_type, debt_ceiling } => {
+                instruction::update_collateral_type(program_id, admin, protocol, collateral_type, StableAmount(*debt_ceiling))
+            }
+            ExecuteConfigChange { protocol } => instruction::execute_config_change(program_id, protocol),
+            CancelConfigChange { guardian, protocol } => instruction::cancel_config_change(program_id, guardian, protocol),
+            MigrateAccount { payer, legacy_account, protocol, collateral_type } => {
+                instruction::migrate_account(program_id, payer, legacy_account, protocol, collateral_type.as_ref())
+            }
+        };
+        Ok(ix)
+    }
+}
+
+/// Reads a protocol config from a JSON object with the field names of `ProtocolConfig`
+fn read_config(path: &str) -> Result<ProtocolConfig> {
+    let config: Value = serde_json::from_str(&read_input(path)?)?;
+    let u64_field = |name: &str| -> Result<u64> {
+        config[name].as_u64().ok_or_else(|| format!("config field {} is not an unsigned integer", name).into())
+    };
+    let pubkey_field = |name: &str| -> Result<Pubkey> {
+        let key = config[name].as_str().ok_or_else(|| format!("config field {} is not a pubkey", name))?;
+        Ok(Pubkey::from_str(key)?)
+    };
+
+    Ok(ProtocolConfig {
+        interest_rate: Ratio(u64_field("interest_rate")?),
+        flash_mint_fee: Ratio(u64_field("flash_mint_fee")?),
+        pauser: pubkey_field("pauser")?,
+        debt_ceiling: StableAmount(u64_field("debt_ceiling")?),
+        admin: pubkey_field("admin")?,
+        guardian: pubkey_field("guardian")?,
+        min_collateral_ratio: Ratio(u64_field("min_collateral_ratio")?),
+        deposit_fee: Ratio(u64_field("deposit_fee")?),
+        team_fee: Ratio(u64_field("team_fee")?),
+        oracle: pubkey_field("oracle")?,
+        timelock_delay: config["timelock_delay"].as_i64().ok_or("config field timelock_delay is not an integer")?,
+    })
+}
+
+fn read_stdin() -> Result<String> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    Ok(input)
+}
+
+/// Contents of the file at `path`, or of stdin for `-`
+fn read_input(path: &str) -> Result<String> {
+    match path {
+        "-" => read_stdin(),
+        path => Ok(fs::read_to_string(path)?),
+    }
+}
+
+/// `arg` itself, or stdin for `-`
+fn read_arg(arg: &str) -> Result<String> {
+    match arg {
+        "-" => read_stdin(),
+        arg => Ok(arg.to_string()),
+    }
+}
+
+/// Reads a keypair file as written by `solana-keygen`, a JSON array of 64 bytes
+fn read_keypair(path: &str) -> Result<Keypair> {
+    read_keypair_file(path).map_err(|err| format!("invalid keypair {}: {}", path, err).into())
+}
+
+fn encode(bytes: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Base58 => bs58::encode(bytes).into_string(),
+        Encoding::Base64 => base64::encode(bytes),
+    }
+}
+
+fn decode(input: &str, encoding: Encoding) -> Result<Vec<u8>> {
+    let input = input.trim();
+    Ok(match encoding {
+        Encoding::Base58 => bs58::decode(input).into_vec()?,
+        Encoding::Base64 => base64::decode(input)?,
+    })
+}
+
+fn program_id(cli: &Cli) -> Result<Pubkey> {
+    cli.program_id.ok_or_else(|| "--program-id or LIQUITY_PROGRAM_ID is required".into())
+}
+
+fn tx(cli: &Cli, rpc: &Rpc, args: &TxArgs) -> Result<()> {
+    let program_id = program_id(cli)?;
+    let keypairs = args.keypairs.iter().map(|path| read_keypair(path)).collect::<Result<Vec<_>>>()?;
+    let fee_payer = args.fee_payer.unwrap_or_else(|| keypairs[0].pubkey());
+
+    let instruction = args.instruction.instruction(&program_id)?;
+    let blockhash = match args.blockhash {
+        Some(blockhash) => blockhash,
+        None => rpc.latest_blockhash()?,
+    };
+    let message = Message::new_with_blockhash(&[instruction], Some(&fee_payer), &blockhash);
+
+    // Only the keypairs of the signers the message requires sign it
+    let signers = message
+        .signer_keys()
+        .into_iter()
+        .map(|signer| {
+            keypairs
+                .iter()
+                .find(|keypair| keypair.pubkey() == *signer)
+                .ok_or_else(|| format!("missing --keypair of signer {}", signer).into())
+        })
+        .collect::<Result<Vec<&Keypair>>>()?;
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction.try_sign(&signers, blockhash)?;
+
+    let transaction = bincode::serialize(&transaction)?;
+    if args.send {
+        println!("{}", rpc.send_transaction(&transaction)?);
+    } else {
+        println!("{}", encode(&transaction, args.encoding));
+    }
+    Ok(())
+}
+
+fn inspect(cli: &Cli, transaction: &str, encoding: Encoding) -> Result<()> {
+    let Transaction { signatures, message } = bincode::deserialize(&decode(&read_arg(transaction)?, encoding)?)?;
+
+    println!("Recent blockhash: {}", message.recent_blockhash);
+    for (signer, signature) in message.account_keys.iter().zip(&signatures) {
+        println!("Signature of {}: {}", signer, signature);
+    }
+    for (index, compiled) in message.instructions.iter().enumerate() {
+        let program_id = message.account_keys[compiled.program_id_index as usize];
+        println!("Instruction {} of program {}", index, program_id);
+        for &account in &compiled.accounts {
+            let account = account as usize;
+            let signer = if message.is_signer(account) { " signer" } else { "" };
+            let writable = if message.is_writable(account) { " writable" } else { "" };
+            println!("  {}{}{}", message.account_keys[account], signer, writable);
+        }
+        if Some(program_id) == cli.program_id {
+            println!("{:#?}", LiquityInstruction::unpack(&compiled.data)?);
+        } else {
+            println!("  data: {}", bs58::encode(&compiled.data).into_string());
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(&cli) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    let rpc = Rpc { url: cli.url.clone() };
+
+    match &cli.command {
+        Command::Tx(args) => tx(cli, &rpc, args),
+        Command::Inspect { transaction, encoding } => inspect(cli, transaction, *encoding),
+        Command::Send { transaction, encoding } => {
+            let transaction = decode(&read_arg(transaction)?, *encoding)?;
+            println!("{}", rpc.send_transaction(&transaction)?);
+            Ok(())
+        }
+        Command::Decode { file } => {
+            let input = read_input(file)?;
+            let data = match serde_json::from_str::<Value>(&input) {
+                Ok(value) => account_data(&value).ok_or("no base64 account data in the JSON file")?,
+                Err(_) => base64::decode(input.trim())?,
+            };
+            println!("{:#?}", client::decode_account(&data)?);
+            Ok(())
+        }
+        Command::Fetch { address } => {
+            println!("{:#?}", client::decode_account(&rpc.account_data(address)?)?);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,6 @@
+//! Prints the JSON IDL of the program, `cargo run --features idl --bin liquity-idl > idl/solana_escrow.json`
+
+fn main() {
+    let idl = solana_escrow::idl::generate();
+    println!("{}", serde_json::to_string_pretty(&idl).unwrap());
+}
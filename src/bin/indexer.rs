@@ -0,0 +1,65 @@
+//! Indexes the history of the program into a SQLite database, from a file of
+//! JSON RPC responses or from a node, `cargo run --features indexer --bin liquity-indexer -- --help`
+
+use clap::{Parser, Subcommand};
+use solana_escrow::indexer::{Indexer, Result};
+use solana_escrow::rpc::Rpc;
+use solana_program::pubkey::Pubkey;
+use std::{thread, time::Duration};
+
+#[derive(Parser)]
+#[clap(name = "liquity-indexer", about = "SQLite index of troves, deposits, liquidations, redemptions and fees")]
+struct Cli {
+    /// SQLite database, created if missing
+    #[clap(long, env = "LIQUITY_INDEXER_DB", default_value = "liquity.sqlite")]
+    db: String,
+    /// Address of the deployed program
+    #[clap(long, env = "LIQUITY_PROGRAM_ID")]
+    program_id: Pubkey,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Index `getTransaction`, `getProgramAccounts` and `programNotification`
+    /// responses, as a JSON array or one response per line
+    File {
+        file: String,
+    },
+    /// Index the transactions and accounts of the program from an RPC node
+    Sync {
+        /// JSON RPC URL of the cluster, a local `solana-test-validator` by default
+        #[clap(long, env = "LIQUITY_RPC_URL", default_value = "http://127.0.0.1:8899")]
+        url: String,
+        /// Keep syncing every this many seconds
+        #[clap(long)]
+        poll: Option<u64>,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(&cli) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    let mut indexer = Indexer::open(cli.program_id, &cli.db)?;
+
+    match &cli.command {
+        Command::File { file } => indexer.index_file(file),
+        Command::Sync { url, poll } => {
+            let rpc = Rpc { url: url.clone() };
+            loop {
+                indexer.sync(&rpc)?;
+                match poll {
+                    Some(seconds) => thread::sleep(Duration::from_secs(*seconds)),
+                    None => return Ok(()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Off-chain decoding of the program accounts and views of trove health,
+//! computed with the same helpers the program checks against
+
+use solana_program::{native_token::LAMPORTS_PER_SOL, program_error::ProgramError};
+use crate::collateral::Collateral;
+use crate::error::LiquityError;
+use crate::helpers::{get_accrued_borrow_index, get_collateral_value, get_trove_debt_with_interest};
+use crate::math;
+use crate::params::BPS_DENOMINATOR;
+use crate::state::{AccountType, CollateralType, Deposit, Escrow, Protocol, ProgramAccount, ProtocolConfig, Trove};
+use crate::units::{CollateralAmount, Lamports, Price, Ratio, StableAmount};
+
+/// Decoded state of an account owned by the program
+#[derive(Debug)]
+pub enum ProgramAccountState {
+    Uninitialized,
+    Trove(Trove),
+    Deposit(Deposit),
+    Escrow(Escrow),
+    Protocol(Box<Protocol>),
+    CollateralType(CollateralType),
+}
+
+/// Decodes the data of an account owned by the program, legacy layouts included
+pub fn decode_account(data: &[u8]) -> Result<ProgramAccountState, ProgramError> {
+    match AccountType::of(data) {
+        Some(AccountType::Uninitialized) => Ok(ProgramAccountState::Uninitialized),
+        Some(AccountType::Trove) => decode(data).map(ProgramAccountState::Trove),
+        Some(AccountType::Deposit) => decode(data).map(ProgramAccountState::Deposit),
+        Some(AccountType::Escrow) => decode(data).map(ProgramAccountState::Escrow),
+        Some(AccountType::Protocol) => decode(data).map(|protocol| ProgramAccountState::Protocol(Box::new(protocol))),
+        Some(AccountType::CollateralType) => decode(data).map(ProgramAccountState::CollateralType),
+        Some(AccountType::Closed) => Err(LiquityError::AccountClosed.into()),
+        None => Err(LiquityError::InvalidAccountType.into()),
+    }
+}
+
+/// Decodes the data of an account expected to hold `T`, legacy layouts included
+pub fn decode<T: ProgramAccount>(data: &[u8]) -> Result<T, ProgramError> {
+    match AccountType::of(data) {
+        Some(AccountType::Closed) => return Err(LiquityError::AccountClosed.into()),
+        Some(account_type) if account_type == T::ACCOUNT_TYPE => {}
+        _ => return Err(LiquityError::InvalidAccountType.into()),
+    }
+    T::unpack_unchecked(data)
+}
+
+pub fn decode_trove(data: &[u8]) -> Result<Trove, ProgramError> {
+    decode(data)
+}
+
+pub fn decode_deposit(data: &[u8]) -> Result<Deposit, ProgramError> {
+    decode(data)
+}
+
+pub fn decode_protocol(data: &[u8]) -> Result<Protocol, ProgramError> {
+    decode(data)
+}
+
+pub fn decode_collateral_type(data: &[u8]) -> Result<CollateralType, ProgramError> {
+    decode(data)
+}
+
+/// Borrow index the program accrues `protocol` to at `unix_timestamp`
+pub fn borrow_index_at(protocol: &Protocol, unix_timestamp: i64) -> Result<u128, ProgramError> {
+    get_accrued_borrow_index(
+        protocol.borrow_index,
+        protocol.config.interest_rate,
+        math::elapsed(protocol.last_update_timestamp, unix_timestamp)?,
+    )
+}
+
+/// Largest `borrow_amount`, gas fee included, which `lamports` of native SOL
+/// collateral covers at `price`
+pub fn max_borrow(lamports: Lamports, price: Price, config: &ProtocolConfig) -> Result<StableAmount, ProgramError> {
+    let collateral_value = price.value(lamports)?;
+    math::mul_div(collateral_value.0, BPS_DENOMINATOR, config.min_collateral_ratio.0).map(StableAmount)
+}
+
+/// Collateralization of a trove at a given SOL price
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TroveHealth {
+    /// Debt the collateral is checked against, the borrowed amount with the
+    /// gas fee and accrued interest
+    pub debt: StableAmount,
+    pub collateral_value: StableAmount,
+    /// Collateral value to debt ratio, rounded down, `None` without debt
+    pub collateral_ratio: Option<Ratio>,
+    /// Whether the collateral covers the minimum collateral ratio
+    pub is_collateralized: bool,
+    /// Lowest SOL price at which the collateral covers the minimum ratio,
+    /// `None` without debt or if no price does
+    pub liquidation_price: Option<Price>,
+    /// Collateral which can be withdrawn keeping the minimum ratio covered
+    pub max_withdrawable: CollateralAmount,
+}
+
+impl Trove {
+    /// Debt the program checks the collateral against once it accrued to `borrow_index`
+    pub fn debt(&self, borrow_index: u128) -> Result<StableAmount, ProgramError> {
+        let debt = get_trove_debt_with_interest(self.amount_to_close, self.borrow_index, borrow_index)?;
+        let interest = debt.checked_sub(self.amount_to_close)?;
+        self.borrow_amount.checked_add(interest)
+    }
+
+    /// Health of the trove holding `collateral` at the SOL `price`, with the
+    /// interest accrued up to `unix_timestamp`
+    pub fn health(
+        &self,
+        collateral: &Collateral,
+        price: Price,
+        protocol: &Protocol,
+        unix_timestamp: i64,
+    ) -> Result<TroveHealth, ProgramError> {
+        let debt = self.debt(borrow_index_at(protocol, unix_timestamp)?)?;
+        let min_ratio = protocol.config.min_collateral_ratio;
+        let collateral_value = get_collateral_value(collateral, self.collateral_amount, price)?;
+        let is_covered = |amount: CollateralAmount, price: Price| -> Result<bool, ProgramError> {
+            Ok(min_ratio.is_covered(get_collateral_value(collateral, amount, price)?, debt))
+        };
+
+        let collateral_ratio = if debt == StableAmount::ZERO {
+            None
+        } else {
+            Some(Ratio(math::mul_div(collateral_value.0, BPS_DENOMINATOR, debt.0)?))
+        };
+
+        let lamports = collateral.to_lamports(self.collateral_amount)?;
+        let liquidation_price = if debt == StableAmount::ZERO || lamports == Lamports::ZERO {
+            None
+        } else {
+            // Highest price the collateral can be valued at without overflowing
+            let max_price = (u64::MAX as u128 * LAMPORTS_PER_SOL as u128 / lamports.0 as u128).min(u64::MAX as u128) as u64;
+            lowest(0, max_price, |price| is_covered(self.collateral_amount, Price(StableAmount(price))))?
+                .map(|price| Price(StableAmount(price)))
+        };
+
+        let max_withdrawable = lowest(0, self.collateral_amount.0, |amount| is_covered(CollateralAmount(amount), price))?
+            .map_or(CollateralAmount::ZERO, |amount| self.collateral_amount.saturating_sub(CollateralAmount(amount)));
+
+        Ok(TroveHealth {
+            debt,
+            collateral_value,
+            collateral_ratio,
+            is_collateralized: min_ratio.is_covered(collateral_value, debt),
+            liquidation_price,
+            max_withdrawable,
+        })
+    }
+}
+
+/// Lowest value in `low..=high` satisfying the monotonic `predicate`
+fn lowest(
+    mut low: u64,
+    mut high: u64,
+    predicate: impl Fn(u64) -> Result<bool, ProgramError>,
+) -> Result<Option<u64>, ProgramError> {
+    if !predicate(high)? {
+        return Ok(None);
+    }
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if predicate(mid)? {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Ok(Some(low))
+}
@@ -0,0 +1,229 @@
+use solana_program::{
+    account_info::{AccountInfo, next_account_info},
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::clock::Clock,
+};
+use arrayref::{array_ref, array_refs};
+use crate::account::check_distinct;
+use crate::error::LiquityError;
+use crate::{math, token};
+use crate::params::{AUTHORITY_SEED, COLLATERAL_TYPE_SEED, STAKE_POOL_PROGRAM_ADDRESS};
+use crate::state::Trove;
+use crate::units::{CollateralAmount, Lamports};
+
+/// Collateral posted into a trove, able to value itself in lamports
+pub enum Collateral {
+    /// Native SOL held by the trove account
+    Sol,
+    /// SPL stake pool tokens held by the collateral vault
+    StakePool {
+        total_lamports: u64,
+        pool_token_supply: u64,
+    },
+}
+
+impl Collateral {
+    /// Value of `amount` units of this collateral in lamports
+    pub fn to_lamports(&self, amount: CollateralAmount) -> Result<Lamports, ProgramError> {
+        match self {
+            Collateral::Sol => Ok(Lamports(amount.0)),
+            Collateral::StakePool { total_lamports, pool_token_supply } => {
+                // An empty pool mints tokens one to one, as the stake pool program does
+                if *pool_token_supply == 0 {
+                    return Ok(Lamports(amount.0));
+                }
+                math::mul_div(amount.0, *total_lamports, *pool_token_supply).map(Lamports)
+            }
+        }
+    }
+}
+
+/// Fields of an SPL stake pool account needed to value its pool tokens
+pub struct StakePool {
+    pub pool_mint: Pubkey,
+    pub total_lamports: u64,
+    pub pool_token_supply: u64,
+    pub last_update_epoch: u64,
+}
+
+impl StakePool {
+    /// Length of the stake pool account prefix read by `unpack`
+    const PREFIX_LEN: usize = 282;
+    /// `AccountType::StakePool` in the stake pool program
+    const ACCOUNT_TYPE: u8 = 1;
+
+    pub fn unpack(stake_pool_account: &AccountInfo) -> Result<Self, ProgramError> {
+        if *stake_pool_account.owner != STAKE_POOL_PROGRAM_ADDRESS {
+            return Err(LiquityError::InvalidStakePool.into());
+        }
+
+        let data = stake_pool_account.data.borrow();
+        if data.len() < Self::PREFIX_LEN {
+            return Err(LiquityError::InvalidStakePool.into());
+        }
+
+        let src = array_ref![data, 0, StakePool::PREFIX_LEN];
+        let (
+            account_type,
+            _authorities,
+            _bump_seed,
+            _accounts,
+            pool_mint,
+            _fee_and_token_program,
+            total_lamports,
+            pool_token_supply,
+            last_update_epoch,
+        ) = array_refs![src, 1, 96, 1, 64, 32, 64, 8, 8, 8];
+
+        if account_type[0] != Self::ACCOUNT_TYPE {
+            return Err(LiquityError::InvalidStakePool.into());
+        }
+
+        Ok(StakePool {
+            pool_mint: Pubkey::new_from_array(*pool_mint),
+            total_lamports: u64::from_le_bytes(*total_lamports),
+            pool_token_supply: u64::from_le_bytes(*pool_token_supply),
+            last_update_epoch: u64::from_le_bytes(*last_update_epoch),
+        })
+    }
+
+    /// Exchange rate of the pool, refusing rates not updated for the current epoch
+    pub fn collateral(&self, clock: &Clock) -> Result<Collateral, ProgramError> {
+        if self.last_update_epoch != clock.epoch {
+            return Err(LiquityError::StakePoolStale.into());
+        }
+
+        Ok(Collateral::StakePool {
+            total_lamports: self.total_lamports,
+            pool_token_supply: self.pool_token_supply,
+        })
+    }
+}
+
+/// Program authority owning every collateral vault
+pub fn find_authority_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[AUTHORITY_SEED], program_id)
+}
+
+/// Collateral type account of a collateral in a protocol, default collateral for native SOL
+pub fn find_collateral_type_address(program_id: &Pubkey, protocol: &Pubkey, collateral: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[COLLATERAL_TYPE_SEED, protocol.as_ref(), collateral.as_ref()], program_id)
+}
+
+/// Accounts moving stake pool tokens between a user and the collateral vault
+///
+/// 0. `[]` The stake pool the trove collateral belongs to
+/// 1. `[writable]` The collateral vault token account of the pool mint
+/// 2. `[writable]` The user pool token account
+/// 3. `[]` The program authority
+/// 4. `[]` Token program
+pub struct CollateralAccounts<'a, 'b> {
+    pub stake_pool: &'a AccountInfo<'b>,
+    pub vault: &'a AccountInfo<'b>,
+    pub user_token: &'a AccountInfo<'b>,
+    pub authority: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    pub pool: StakePool,
+    authority_bump_seed: u8,
+}
+
+impl<'a, 'b> CollateralAccounts<'a, 'b> {
+    /// Reads and validates the collateral accounts of an existing stake pool trove
+    pub fn next_for_trove<I: Iterator<Item = &'a AccountInfo<'b>>>(
+        iter: &mut I,
+        program_id: &Pubkey,
+        trove: &Trove,
+    ) -> Result<Self, ProgramError> {
+        let collateral_accounts = Self::next(iter, program_id)?;
+        if *collateral_accounts.stake_pool.key != trove.collateral {
+            return Err(LiquityError::InvalidStakePool.into());
+        }
+        Ok(collateral_accounts)
+    }
+
+    /// Reads and validates the collateral accounts of any stake pool
+    pub fn next<I: Iterator<Item = &'a AccountInfo<'b>>>(
+        iter: &mut I,
+        program_id: &Pubkey,
+    ) -> Result<Self, ProgramError> {
+        let stake_pool = next_account_info(iter)?;
+        let vault = next_account_info(iter)?;
+        let user_token = next_account_info(iter)?;
+        let authority = next_account_info(iter)?;
+        let token_program = next_account_info(iter)?;
+
+        check_distinct(&[stake_pool, vault, user_token, authority, token_program])?;
+
+        let pool = StakePool::unpack(stake_pool)?;
+
+        token::check_token_program(token_program)?;
+
+        let (authority_id, authority_bump_seed) = find_authority_address(program_id);
+        if *authority.key != authority_id {
+            return Err(LiquityError::InvalidMarketAuthority.into());
+        }
+
+        token::check_token_account(vault, &pool.pool_mint, &authority_id)?;
+
+        Ok(CollateralAccounts {
+            stake_pool,
+            vault,
+            user_token,
+            authority,
+            token_program,
+            pool,
+            authority_bump_seed,
+        })
+    }
+
+    /// Moves `amount` pool tokens from the user token account into the vault
+    pub fn deposit(&self, user: &AccountInfo<'b>, amount: CollateralAmount) -> ProgramResult {
+        let transfer_ix = spl_token::instruction::transfer(
+            self.token_program.key,
+            self.user_token.key,
+            self.vault.key,
+            user.key,
+            &[user.key],
+            amount.0,
+        )?;
+
+        msg!("Calling the token program to move the collateral into the vault...");
+        invoke(
+            &transfer_ix,
+            &[
+                self.user_token.clone(),
+                self.vault.clone(),
+                user.clone(),
+                self.token_program.clone(),
+            ],
+        )
+    }
+
+    /// Moves `amount` pool tokens from the vault into the user token account
+    pub fn withdraw(&self, amount: CollateralAmount) -> ProgramResult {
+        let transfer_ix = spl_token::instruction::transfer(
+            self.token_program.key,
+            self.vault.key,
+            self.user_token.key,
+            self.authority.key,
+            &[self.authority.key],
+            amount.0,
+        )?;
+
+        msg!("Calling the token program to move the collateral out of the vault...");
+        invoke_signed(
+            &transfer_ix,
+            &[
+                self.vault.clone(),
+                self.user_token.clone(),
+                self.authority.clone(),
+                self.token_program.clone(),
+            ],
+            &[&[AUTHORITY_SEED, &[self.authority_bump_seed]]],
+        )
+    }
+}
@@ -1,7 +1,8 @@
+use num_derive::FromPrimitive;
 use thiserror::Error;
-use solana_program::program_error::ProgramError;
+use solana_program::{decode_error::DecodeError, program_error::ProgramError};
 
-#[derive(Error, Debug, Copy, Clone)]
+#[derive(Error, Debug, Copy, Clone, FromPrimitive)]
 pub enum LiquityError {
     /// Invalid instruction
     #[error("Invalid Instruction")]
@@ -196,10 +197,63 @@ pub enum LiquityError {
     /// Not enough liquidity after flash loan
     #[error("Not enough liquidity after flash loan")]
     NotEnoughLiquidityAfterFlashLoan,
+
+    // 45
+    /// Stake pool account is not valid collateral
+    #[error("Input stake pool account is not valid")]
+    InvalidStakePool,
+    /// Stake pool was not updated for the current epoch
+    #[error("Stake pool needs to be updated for the current epoch")]
+    StakePoolStale,
+    /// Operation is paused
+    #[error("Operation is paused")]
+    OperationPaused,
+    /// Only for the pauser or the admin
+    #[error("Only For Pauser")]
+    OnlyForPauser,
+    /// Debt ceiling exceeded
+    #[error("Debt ceiling exceeded")]
+    DebtCeilingExceeded,
+    /// Only for the guardian or the admin
+    #[error("Only For Guardian")]
+    OnlyForGuardian,
+    /// A config change is already queued
+    #[error("Config change already queued")]
+    ConfigChangeAlreadyQueued,
+    /// No config change is queued
+    #[error("No config change queued")]
+    NoConfigChangeQueued,
+    /// The timelock of the queued config change has not expired
+    #[error("Config change timelock has not expired")]
+    TimelockNotExpired,
+    /// The account already uses the current layout
+    #[error("Account already migrated")]
+    AccountAlreadyMigrated,
+    /// The account holds a different kind of state
+    #[error("Invalid account type")]
+    InvalidAccountType,
+    /// The account needs to be migrated to the current layout
+    #[error("Account needs to be migrated")]
+    AccountNotMigrated,
+    /// The account is not writable
+    #[error("Account is not writable")]
+    AccountNotWritable,
+    /// The account was closed
+    #[error("Account is closed")]
+    AccountClosed,
+    /// The same account was passed for several roles
+    #[error("Duplicate account")]
+    DuplicateAccount,
 }
 
 impl From<LiquityError> for ProgramError {
     fn from(e: LiquityError) -> Self {
         ProgramError::Custom(e as u32)
     }
+}
+
+impl<T> DecodeError<T> for LiquityError {
+    fn type_of() -> &'static str {
+        "LiquityError"
+    }
 }
\ No newline at end of file
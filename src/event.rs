@@ -0,0 +1,305 @@
+//! Typed events logged by the processor for indexers. Each event is logged as
+//! `EVENT_LOG_PREFIX` followed by the base64 of its packed encoding: a tag byte
+//! then the fields in declaration order, little endian. Tags and field orders
+//! are never changed, new events take new tags. With the `borsh` feature, the
+//! packed encoding is the Borsh encoding of `LiquityEvent`
+
+use solana_program::{msg, pubkey::Pubkey};
+use std::convert::TryInto;
+use crate::units::{CollateralAmount, GovAmount, Lamports, StableAmount};
+
+/// Prefix of the program log lines holding an event
+pub const EVENT_LOG_PREFIX: &str = "Event: ";
+
+/// Amounts are in base units of their mint, collateral amounts in lamports for
+/// native SOL troves and in pool tokens for stake pool troves
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
+pub enum LiquityEvent {
+    /// A trove was opened by `Borrow` or `BorrowWithStakePoolToken`
+    TroveOpened {
+        trove: Pubkey,
+        owner: Pubkey,
+        /// Stake pool of the collateral, default for native SOL
+        collateral: Pubkey,
+        collateral_amount: CollateralAmount,
+        borrow_amount: StableAmount,
+        /// Stablecoin to repay to close the trove
+        debt: StableAmount,
+        /// Fee of the borrow withheld for depositors, part of the debt but never minted
+        depositor_fee: StableAmount,
+        /// Fee of the borrow withheld for the team, part of the debt but never minted
+        team_fee: StableAmount,
+    },
+    /// The collateral of a trove changed by `AddCoin` or `WithdrawCoin`, with
+    /// the totals of the trove afterwards
+    TroveAdjusted {
+        trove: Pubkey,
+        collateral_amount: CollateralAmount,
+        debt: StableAmount,
+    },
+    /// A trove was repaid and closed by its owner
+    TroveClosed {
+        trove: Pubkey,
+        owner: Pubkey,
+        repaid: StableAmount,
+        collateral_amount: CollateralAmount,
+    },
+    /// A trove was liquidated, its collateral going to the system account
+    TroveLiquidated {
+        trove: Pubkey,
+        liquidator: Pubkey,
+        debt: StableAmount,
+        collateral_amount: CollateralAmount,
+    },
+    /// Collateral was redeemed from a trove
+    Redemption {
+        trove: Pubkey,
+        amount: CollateralAmount,
+        /// Collateral left in the trove
+        collateral_amount: CollateralAmount,
+        /// Stablecoin burned against the debt of the trove
+        repaid: StableAmount,
+    },
+    /// Stablecoin was deposited or withdrawn, with the total of the deposit afterwards
+    DepositChanged {
+        deposit: Pubkey,
+        owner: Pubkey,
+        token_amount: StableAmount,
+    },
+    /// The rewards of a deposit were claimed
+    RewardClaimed {
+        deposit: Pubkey,
+        owner: Pubkey,
+        coin: Lamports,
+        governance: GovAmount,
+        token: StableAmount,
+    },
+    /// Rewards were credited to a deposit by `AddDepositReward`
+    RewardAdded {
+        deposit: Pubkey,
+        coin: Lamports,
+        governance: GovAmount,
+        token: StableAmount,
+    },
+    /// A legacy trove still open was migrated by `MigrateAccount`, its debt
+    /// joining the total debts
+    TroveMigrated {
+        trove: Pubkey,
+        owner: Pubkey,
+        /// Stake pool of the collateral, default for native SOL
+        collateral: Pubkey,
+        collateral_amount: CollateralAmount,
+        debt: StableAmount,
+    },
+}
+
+impl LiquityEvent {
+    /// Logs the event for indexers
+    pub fn emit(&self) {
+        msg!("{}{}", EVENT_LOG_PREFIX, base64::encode(self.pack()));
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Self::TroveOpened {
+                trove, owner, collateral, collateral_amount, borrow_amount, debt, depositor_fee, team_fee,
+            } => {
+                buf.push(0);
+                buf.extend_from_slice(trove.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(collateral.as_ref());
+                buf.extend_from_slice(&collateral_amount.to_le_bytes());
+                buf.extend_from_slice(&borrow_amount.to_le_bytes());
+                buf.extend_from_slice(&debt.to_le_bytes());
+                buf.extend_from_slice(&depositor_fee.to_le_bytes());
+                buf.extend_from_slice(&team_fee.to_le_bytes());
+            }
+            Self::TroveAdjusted { trove, collateral_amount, debt } => {
+                buf.push(1);
+                buf.extend_from_slice(trove.as_ref());
+                buf.extend_from_slice(&collateral_amount.to_le_bytes());
+                buf.extend_from_slice(&debt.to_le_bytes());
+            }
+            Self::TroveClosed { trove, owner, repaid, collateral_amount } => {
+                buf.push(2);
+                buf.extend_from_slice(trove.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&repaid.to_le_bytes());
+                buf.extend_from_slice(&collateral_amount.to_le_bytes());
+            }
+            Self::TroveLiquidated { trove, liquidator, debt, collateral_amount } => {
+                buf.push(3);
+                buf.extend_from_slice(trove.as_ref());
+                buf.extend_from_slice(liquidator.as_ref());
+                buf.extend_from_slice(&debt.to_le_bytes());
+                buf.extend_from_slice(&collateral_amount.to_le_bytes());
+            }
+            Self::Redemption { trove, amount, collateral_amount, repaid } => {
+                buf.push(4);
+                buf.extend_from_slice(trove.as_ref());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&collateral_amount.to_le_bytes());
+                buf.extend_from_slice(&repaid.to_le_bytes());
+            }
+            Self::DepositChanged { deposit, owner, token_amount } => {
+                buf.push(5);
+                buf.extend_from_slice(deposit.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&token_amount.to_le_bytes());
+            }
+            Self::RewardClaimed { deposit, owner, coin, governance, token } => {
+                buf.push(6);
+                buf.extend_from_slice(deposit.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(&coin.to_le_bytes());
+                buf.extend_from_slice(&governance.to_le_bytes());
+                buf.extend_from_slice(&token.to_le_bytes());
+            }
+            Self::RewardAdded { deposit, coin, governance, token } => {
+                buf.push(7);
+                buf.extend_from_slice(deposit.as_ref());
+                buf.extend_from_slice(&coin.to_le_bytes());
+                buf.extend_from_slice(&governance.to_le_bytes());
+                buf.extend_from_slice(&token.to_le_bytes());
+            }
+            Self::TroveMigrated { trove, owner, collateral, collateral_amount, debt } => {
+                buf.push(8);
+                buf.extend_from_slice(trove.as_ref());
+                buf.extend_from_slice(owner.as_ref());
+                buf.extend_from_slice(collateral.as_ref());
+                buf.extend_from_slice(&collateral_amount.to_le_bytes());
+                buf.extend_from_slice(&debt.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    /// Decodes a packed event, `None` for an unknown tag or a truncated event.
+    /// Trailing bytes are ignored, so that fields can be appended to an event
+    pub fn unpack(input: &[u8]) -> Option<Self> {
+        let (tag, rest) = input.split_first()?;
+        let mut reader = Reader(rest);
+
+        Some(match tag {
+            0 => Self::TroveOpened {
+                trove: reader.pubkey()?,
+                owner: reader.pubkey()?,
+                collateral: reader.pubkey()?,
+                collateral_amount: CollateralAmount(reader.u64()?),
+                borrow_amount: StableAmount(reader.u64()?),
+                debt: StableAmount(reader.u64()?),
+                depositor_fee: StableAmount(reader.u64()?),
+                team_fee: StableAmount(reader.u64()?),
+            },
+            1 => Self::TroveAdjusted {
+                trove: reader.pubkey()?,
+                collateral_amount: CollateralAmount(reader.u64()?),
+                debt: StableAmount(reader.u64()?),
+            },
+            2 => Self::TroveClosed {
+                trove: reader.pubkey()?,
+                owner: reader.pubkey()?,
+                repaid: StableAmount(reader.u64()?),
+                collateral_amount: CollateralAmount(reader.u64()?),
+            },
+            3 => Self::TroveLiquidated {
+                trove: reader.pubkey()?,
+                liquidator: reader.pubkey()?,
+                debt: StableAmount(reader.u64()?),
+                collateral_amount: CollateralAmount(reader.u64()?),
+            },
+            4 => Self::Redemption {
+                trove: reader.pubkey()?,
+                amount: CollateralAmount(reader.u64()?),
+                collateral_amount: CollateralAmount(reader.u64()?),
+                repaid: StableAmount(reader.u64()?),
+            },
+            5 => Self::DepositChanged {
+                deposit: reader.pubkey()?,
+                owner: reader.pubkey()?,
+                token_amount: StableAmount(reader.u64()?),
+            },
+            6 => Self::RewardClaimed {
+                deposit: reader.pubkey()?,
+                owner: reader.pubkey()?,
+                coin: Lamports(reader.u64()?),
+                governance: GovAmount(reader.u64()?),
+                token: StableAmount(reader.u64()?),
+            },
+            7 => Self::RewardAdded {
+                deposit: reader.pubkey()?,
+                coin: Lamports(reader.u64()?),
+                governance: GovAmount(reader.u64()?),
+                token: StableAmount(reader.u64()?),
+            },
+            8 => Self::TroveMigrated {
+                trove: reader.pubkey()?,
+                owner: reader.pubkey()?,
+                collateral: reader.pubkey()?,
+                collateral_amount: CollateralAmount(reader.u64()?),
+                debt: StableAmount(reader.u64()?),
+            },
+            _ => return None,
+        })
+    }
+
+    /// Decodes the event of a single log line, with or without the `Program log: ` prefix of the runtime
+    pub fn from_log(line: &str) -> Option<Self> {
+        let line = line.strip_prefix(PROGRAM_LOG_PREFIX).unwrap_or(line);
+        let data = base64::decode(line.strip_prefix(EVENT_LOG_PREFIX)?).ok()?;
+        Self::unpack(&data)
+    }
+}
+
+const PROGRAM_LOG_PREFIX: &str = "Program log: ";
+
+/// Decodes the events logged by `program_id` in the log messages of a
+/// transaction. Lines are attributed to the program running when they were
+/// logged, so that other programs cannot forge events
+pub fn decode_logs<S: AsRef<str>>(program_id: &Pubkey, logs: &[S]) -> Vec<LiquityEvent> {
+    let program_id = program_id.to_string();
+    let mut invoked: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+
+    for line in logs {
+        let line = line.as_ref();
+        let mut words = line.split(' ');
+        match (words.next(), words.next(), words.next()) {
+            (Some("Program"), Some(program), Some("invoke")) => invoked.push(program),
+            (Some("Program"), Some(program), Some("success")) | (Some("Program"), Some(program), Some("failed:"))
+                if invoked.last() == Some(&program) =>
+            {
+                invoked.pop();
+            }
+            _ if line.starts_with(PROGRAM_LOG_PREFIX) && invoked.last() == Some(&program_id.as_str()) => {
+                events.extend(LiquityEvent::from_log(line));
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
+/// Reads the fields of a packed event
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn pubkey(&mut self) -> Option<Pubkey> {
+        self.take(32).and_then(|bytes| bytes.try_into().ok()).map(Pubkey::new_from_array)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).and_then(|bytes| bytes.try_into().ok()).map(u64::from_le_bytes)
+    }
+}
@@ -1,40 +1,122 @@
-use crate::{params::MIN_COLLATERAL, params::GAS_FEE};
-use solana_program::native_token::lamports_to_sol;
-use std::ops::Mul;
-use crate::params::{DEPOSIT_FEE, TEAM_FEE};
+use crate::params::GAS_FEE;
+use crate::collateral::Collateral;
+use solana_program::program_error::ProgramError;
+use crate::state::ProtocolConfig;
+use crate::params::{BPS_DENOMINATOR, SECONDS_PER_YEAR};
+use crate::units::{CollateralAmount, Price, Ratio, StableAmount};
+use crate::error::LiquityError;
+use crate::math;
 
-pub fn check_min_collateral_include_gas_fee(
-    amount: u64,
-    lamports: u64
-) -> bool {
-    get_lamport_price(lamports - GAS_FEE) / amount as f64 >= MIN_COLLATERAL
+/// Whether the collateral covers the minimum collateral ratio of `amount`
+pub fn check_min_collateral_ratio(
+    amount: StableAmount,
+    collateral: &Collateral,
+    collateral_amount: CollateralAmount,
+    config: &ProtocolConfig,
+    decimals: u8,
+) -> Result<bool, ProgramError> {
+    let collateral_value = get_collateral_price(collateral, collateral_amount, decimals)?;
+    Ok(config.min_collateral_ratio.is_covered(collateral_value, amount))
 }
 
 pub fn get_trove_sent_amount(
-    amount: u64
-) -> u64 {
-    get_trove_debt_amount(amount) - get_depositors_fee(amount) - get_team_fee(amount)
+    amount: StableAmount,
+    config: &ProtocolConfig,
+    decimals: u8,
+) -> Result<StableAmount, ProgramError> {
+    get_trove_debt_amount(amount, decimals)?
+        .checked_sub(get_depositors_fee(amount, config, decimals)?)?
+        .checked_sub(get_team_fee(amount, config, decimals)?)
 }
 
 pub fn get_trove_debt_amount(
-    amount: u64
-) -> u64 {
-    amount - GAS_FEE
+    amount: StableAmount,
+    decimals: u8,
+) -> Result<StableAmount, ProgramError> {
+    amount.checked_sub(StableAmount::from_whole(GAS_FEE, decimals)?)
 }
 
 pub fn get_depositors_fee(
-    amount: u64
-) -> u64 {
-    get_trove_debt_amount(amount) * (DEPOSIT_FEE) / 100
+    amount: StableAmount,
+    config: &ProtocolConfig,
+    decimals: u8,
+) -> Result<StableAmount, ProgramError> {
+    config.deposit_fee.apply(get_trove_debt_amount(amount, decimals)?)
 }
 
 pub fn get_team_fee(
-    amount: u64
-) -> u64 {
-    get_trove_debt_amount(amount) * (TEAM_FEE) / 100
+    amount: StableAmount,
+    config: &ProtocolConfig,
+    decimals: u8,
+) -> Result<StableAmount, ProgramError> {
+    config.team_fee.apply(get_trove_debt_amount(amount, decimals)?)
 }
 
-fn get_lamport_price(lamports: u64) -> f64 {
-    // TODO get price for lamports from oracle
-    lamports_to_sol(lamports).mul(70.0 as f64)
+/// Fee owed on a flash mint of `amount`, rounded up
+pub fn get_flash_mint_fee(
+    amount: StableAmount,
+    flash_mint_fee: Ratio,
+) -> Result<StableAmount, ProgramError> {
+    flash_mint_fee.apply_ceil(amount)
+}
+
+/// Borrow index after `elapsed` seconds of simple interest at the annual `interest_rate`
+pub fn get_accrued_borrow_index(
+    borrow_index: u128,
+    interest_rate: Ratio,
+    elapsed: i64,
+) -> Result<u128, ProgramError> {
+    if elapsed <= 0 || interest_rate.0 == 0 {
+        return Ok(borrow_index);
+    }
+
+    let interest = borrow_index
+        .checked_mul(interest_rate.0 as u128)
+        .and_then(|v| v.checked_mul(elapsed as u128))
+        .ok_or(LiquityError::MathOverflow)?
+        / (BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR);
+
+    let borrow_index = borrow_index.checked_add(interest).ok_or(LiquityError::MathOverflow)?;
+    Ok(borrow_index)
+}
+
+/// Debt of a trove which snapshotted `trove_borrow_index`, at the current `borrow_index`
+pub fn get_trove_debt_with_interest(
+    amount_to_close: StableAmount,
+    trove_borrow_index: u128,
+    borrow_index: u128,
+) -> Result<StableAmount, ProgramError> {
+    if trove_borrow_index == 0 || trove_borrow_index == borrow_index {
+        return Ok(amount_to_close);
+    }
+
+    let debt = (amount_to_close.0 as u128)
+        .checked_mul(borrow_index)
+        .ok_or(LiquityError::MathOverflow)?
+        / trove_borrow_index;
+
+    math::to_u64(debt).map(StableAmount)
+}
+
+/// Stablecoin value of the collateral, valued through its lamports
+pub fn get_collateral_price(collateral: &Collateral, amount: CollateralAmount, decimals: u8) -> Result<StableAmount, ProgramError> {
+    get_collateral_value(collateral, amount, get_sol_price(decimals)?)
+}
+
+/// Stablecoin value of the collateral at the SOL `price`
+pub fn get_collateral_value(collateral: &Collateral, amount: CollateralAmount, price: Price) -> Result<StableAmount, ProgramError> {
+    price.value(collateral.to_lamports(amount)?)
+}
+
+/// Stablecoin burned to redeem `amount` of the collateral, its value rounded up
+pub fn get_redemption_amount(collateral: &Collateral, amount: CollateralAmount, decimals: u8) -> Result<StableAmount, ProgramError> {
+    get_sol_price(decimals)?.value_ceil(collateral.to_lamports(amount)?)
+}
+
+/// Price of SOL the collateral checks use, fixed at 70
+///
+/// The `oracle` of the protocol config is not read here, it only reserves
+/// the feed behind the config timelock until instructions pass the feed account.
+pub fn get_sol_price(decimals: u8) -> Result<Price, ProgramError> {
+    StableAmount::from_whole(70, decimals).map(Price)
 }
@@ -0,0 +1,331 @@
+//! JSON IDL of the program, generated from the instruction builders, the Borsh
+//! schemas of the state and the error enum so that it cannot drift from the code
+
+use borsh::schema::{BorshSchema, BorshSchemaContainer, Declaration, Definition, Fields};
+use num_traits::FromPrimitive;
+use serde_json::{json, Value};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program,
+    sysvar,
+};
+use std::collections::{BTreeMap, HashMap};
+use crate::collateral::find_authority_address;
+use crate::error::LiquityError;
+use crate::event::{LiquityEvent, EVENT_LOG_PREFIX};
+use crate::instruction::{self, CoinSource, LiquityInstruction, StakePoolAccounts};
+use crate::params::SYSTEM_ACCOUNT_ADDRESS;
+use crate::state::{AccountType, CollateralType, Deposit, Escrow, Protocol, ProtocolConfig, Trove, HEADER_LEN};
+use crate::units::{CollateralAmount, GovAmount, Lamports, StableAmount};
+
+/// Names the placeholder keys passed to the instruction builders
+struct Keys {
+    program_id: Pubkey,
+    names: HashMap<Pubkey, &'static str>,
+}
+
+impl Keys {
+    fn new() -> Self {
+        let program_id = Pubkey::new_unique();
+        let mut names = HashMap::new();
+        names.insert(sysvar::rent::id(), "rent_sysvar");
+        names.insert(sysvar::clock::id(), "clock_sysvar");
+        names.insert(spl_token::id(), "token_program");
+        names.insert(system_program::id(), "system_program");
+        names.insert(SYSTEM_ACCOUNT_ADDRESS, "sys_acc");
+        names.insert(find_authority_address(&program_id).0, "program_authority");
+        Keys { program_id, names }
+    }
+
+    fn key(&mut self, name: &'static str) -> Pubkey {
+        let key = Pubkey::new_unique();
+        self.names.insert(key, name);
+        key
+    }
+
+    fn stake_pool(&mut self, user_token: &'static str) -> StakePoolAccounts {
+        StakePoolAccounts {
+            stake_pool: self.key("stake_pool"),
+            vault: self.key("collateral_vault"),
+            user_token: self.key(user_token),
+        }
+    }
+
+    fn account(&self, meta: &AccountMeta, only_for: Option<&str>) -> Value {
+        let mut account = json!({
+            "name": self.names[&meta.pubkey],
+            "writable": meta.is_writable,
+            "signer": meta.is_signer,
+        });
+        if let Some(only_for) = only_for {
+            account["only_for"] = json!(only_for);
+        }
+        account
+    }
+
+    /// Accounts shared by every layout of an instruction, followed by the accounts
+    /// only some troves expect
+    fn accounts(&self, layouts: &[(Option<&str>, Instruction)]) -> Vec<Value> {
+        let first = &layouts[0].1.accounts;
+        let shared = (0..first.len())
+            .take_while(|&index| layouts.iter().all(|(_, ix)| ix.accounts.get(index) == first.get(index)))
+            .count();
+
+        let mut accounts: Vec<Value> = first[..shared].iter().map(|meta| self.account(meta, None)).collect();
+        for (only_for, ix) in layouts {
+            accounts.extend(ix.accounts[shared..].iter().map(|meta| self.account(meta, *only_for)));
+        }
+        accounts
+    }
+}
+
+/// Every instruction laid out by its builder, with the troves each layout is for
+fn instruction_layouts(keys: &mut Keys) -> Vec<Vec<(Option<&'static str>, Instruction)>> {
+    let id = keys.program_id;
+    let native = Some("native SOL troves");
+    let pool = Some("stake pool troves");
+    let user_pool = keys.stake_pool("user_pool_token");
+    let sys_pool = keys.stake_pool("sys_pool_token");
+    let config = || ProtocolConfig::unpack_from_slice(&[0; ProtocolConfig::LEN]).unwrap();
+
+    let borrower = keys.key("borrower");
+    let trove = keys.key("trove");
+    let protocol = keys.key("protocol");
+    let collateral_type = keys.key("collateral_type");
+    let user_token = keys.key("user_token");
+    let stablecoin_mint = keys.key("stablecoin_mint");
+    let depositor = keys.key("depositor");
+    let deposit = keys.key("deposit");
+    let admin = keys.key("admin");
+    let liquidator = keys.key("liquidator");
+    let owner = keys.key("owner");
+    let legacy_account = keys.key("legacy_account");
+
+    vec![
+        vec![(None, instruction::borrow(
+            &id, &borrower, &trove, &protocol, &collateral_type, &stablecoin_mint, &user_token, StableAmount::ZERO, Lamports::ZERO,
+        ))],
+        vec![
+            (native, instruction::close_trove(&id, &borrower, &trove, &user_token, &stablecoin_mint, &protocol, &collateral_type, None)),
+            (pool, instruction::close_trove(&id, &borrower, &trove, &user_token, &stablecoin_mint, &protocol, &collateral_type, Some(&user_pool))),
+        ],
+        vec![
+            (native, instruction::liquidate_trove(&id, &liquidator, &trove, &protocol, &collateral_type, None)),
+            (pool, instruction::liquidate_trove(&id, &liquidator, &trove, &protocol, &collateral_type, Some(&sys_pool))),
+        ],
+        vec![
+            (native, instruction::withdraw_coin(&id, &borrower, &trove, &protocol, &collateral_type, None, CollateralAmount::ZERO)),
+            (pool, instruction::withdraw_coin(&id, &borrower, &trove, &protocol, &collateral_type, Some(&user_pool), CollateralAmount::ZERO)),
+        ],
+        vec![
+            (native, instruction::add_coin(&id, &borrower, &trove, &protocol, &CoinSource::Lamports(keys.key("temp_lamport_account")), CollateralAmount::ZERO)),
+            (pool, instruction::add_coin(&id, &borrower, &trove, &protocol, &CoinSource::StakePool(keys.stake_pool("user_pool_token")), CollateralAmount::ZERO)),
+        ],
+        vec![
+            (native, instruction::redeem_coin(&id, &borrower, &trove, &protocol, &collateral_type, &user_token, &stablecoin_mint, None, CollateralAmount::ZERO)),
+            (pool, instruction::redeem_coin(
+                &id, &borrower, &trove, &protocol, &collateral_type, &user_token, &stablecoin_mint, Some(&user_pool), CollateralAmount::ZERO,
+            )),
+        ],
+        vec![(None, instruction::add_deposit(
+            &id, &depositor, &deposit, &user_token, &keys.key("user_governance_token"), &stablecoin_mint, &protocol, StableAmount::ZERO,
+        ))],
+        vec![(None, instruction::withdraw_deposit(
+            &id, &depositor, &deposit, &protocol, &stablecoin_mint, &user_token, StableAmount::ZERO,
+        ))],
+        vec![(None, instruction::claim_deposit_reward(&id, &deposit, &protocol))],
+        vec![(None, instruction::receive_trove(&id, &trove, &protocol))],
+        vec![(None, instruction::add_deposit_reward(&id, &deposit, &protocol, Lamports::ZERO, GovAmount::ZERO, StableAmount::ZERO))],
+        vec![(None, instruction::borrow_with_stake_pool_token(
+            &id, &borrower, &trove, &protocol, &collateral_type, &user_pool, &stablecoin_mint, &user_token, StableAmount::ZERO, CollateralAmount::ZERO,
+        ))],
+        vec![(None, instruction::init_protocol(&id, &protocol, &stablecoin_mint, config()))],
+        vec![(None, instruction::queue_config_change(&id, &admin, &protocol, config()))],
+        vec![(None, instruction::flash_mint(
+            &id,
+            &stablecoin_mint,
+            &keys.key("receiver_token"),
+            &keys.key("fee_receiver_token"),
+            &protocol,
+            &keys.key("receiver_program"),
+            &[],
+            StableAmount::ZERO,
+        ))],
+        vec![(None, instruction::set_pause(&id, &keys.key("pauser"), &protocol, 0))],
+        vec![(None, instruction::init_collateral_type(&id, &admin, &protocol, &collateral_type, Pubkey::default(), StableAmount::ZERO))],
+        vec![(None, instruction::update_collateral_type(&id, &admin, &protocol, &collateral_type, StableAmount::ZERO))],
+        vec![(None, instruction::execute_config_change(&id, &protocol))],
+        vec![(None, instruction::cancel_config_change(&id, &keys.key("guardian"), &protocol))],
+        vec![
+            (Some("legacy troves"), instruction::migrate_account(&id, &owner, &legacy_account, &protocol, Some(&collateral_type))),
+            (None, instruction::migrate_account(&id, &owner, &legacy_account, &protocol, None)),
+        ],
+    ]
+}
+
+type Definitions = BTreeMap<Declaration, Definition>;
+
+fn definitions(schema: &BorshSchemaContainer) -> Definitions {
+    schema.definitions().map(|(declaration, definition)| (declaration.clone(), definition.clone())).collect()
+}
+
+/// Size of a fixed size Borsh type
+fn size(declaration: &str, definitions: &Definitions) -> usize {
+    match &definitions[declaration] {
+        Definition::Primitive(len) => *len as usize,
+        Definition::Sequence { length_width: 0, length_range, elements } if length_range.start() == length_range.end() => {
+            *length_range.start() as usize * size(elements, definitions)
+        }
+        Definition::Struct { fields: Fields::NamedFields(fields) } => {
+            fields.iter().map(|(_, field)| size(field, definitions)).sum()
+        }
+        Definition::Struct { fields: Fields::UnnamedFields(fields) } => {
+            fields.iter().map(|field| size(field, definitions)).sum()
+        }
+        Definition::Struct { fields: Fields::Empty } => 0,
+        definition => panic!("{} has no fixed size: {:?}", declaration, definition),
+    }
+}
+
+fn named_fields<'a>(declaration: &str, definitions: &'a Definitions) -> &'a [(String, String)] {
+    match &definitions[declaration] {
+        Definition::Struct { fields: Fields::NamedFields(fields) } => fields,
+        Definition::Struct { fields: Fields::Empty } => &[],
+        definition => panic!("{} is not a struct with named fields: {:?}", declaration, definition),
+    }
+}
+
+/// Fields of a struct with their offsets, starting at `offset`
+fn layout(declaration: &str, definitions: &Definitions, mut offset: usize) -> (Vec<Value>, usize) {
+    let fields = named_fields(declaration, definitions)
+        .iter()
+        .map(|(name, field)| {
+            let value = json!({ "name": name, "type": field, "offset": offset });
+            offset += size(field, definitions);
+            value
+        })
+        .collect();
+    (fields, offset)
+}
+
+fn account<T: Pack + BorshSchema>(account_type: AccountType, version: u8, legacy_len: Option<usize>) -> Value {
+    let schema = BorshSchemaContainer::for_type::<T>();
+    let (fields, len) = layout(schema.declaration(), &definitions(&schema), HEADER_LEN);
+    assert_eq!(len, T::LEN, "{} layout does not match its Borsh schema", schema.declaration());
+
+    json!({
+        "name": schema.declaration(),
+        "discriminator": [account_type as u8, version],
+        "size": T::LEN,
+        "legacy_size": legacy_len,
+        "fields": fields,
+    })
+}
+
+/// Named types used by instruction args and account fields, other than `Pubkey`
+fn types(definitions: &Definitions) -> Vec<Value> {
+    let mut types = BTreeMap::new();
+    for (declaration, definition) in definitions {
+        let value = match definition {
+            Definition::Struct { fields: Fields::UnnamedFields(fields) } if declaration != "Pubkey" => {
+                json!({ "name": declaration, "type": fields[0] })
+            }
+            Definition::Struct { fields: Fields::NamedFields(_) } if declaration == "ProtocolConfig" => {
+                let (fields, len) = layout(declaration, definitions, 0);
+                assert_eq!(len, ProtocolConfig::LEN, "ProtocolConfig layout does not match its Borsh schema");
+                json!({ "name": declaration, "size": len, "fields": fields })
+            }
+            _ => continue,
+        };
+        types.insert(declaration.clone(), value);
+    }
+    types.into_values().collect()
+}
+
+/// Events with their tags, logged as `EVENT_LOG_PREFIX` and the base64 of their Borsh encoding
+fn events() -> Vec<Value> {
+    let definitions = definitions(&BorshSchemaContainer::for_type::<LiquityEvent>());
+    let variants = match &definitions["LiquityEvent"] {
+        Definition::Enum { variants, .. } => variants,
+        definition => panic!("LiquityEvent is not an enum: {:?}", definition),
+    };
+
+    variants
+        .iter()
+        .map(|(tag, name, declaration)| {
+            let (fields, _) = layout(declaration, &definitions, 1);
+            json!({ "name": name, "tag": tag, "fields": fields })
+        })
+        .collect()
+}
+
+fn errors() -> Vec<Value> {
+    (0..)
+        .map_while(LiquityError::from_u32)
+        .map(|error| json!({ "code": error as u32, "name": format!("{:?}", error), "msg": error.to_string() }))
+        .collect()
+}
+
+/// Generates the IDL of the program
+pub fn generate() -> Value {
+    let mut keys = Keys::new();
+    let mut definitions = definitions(&BorshSchemaContainer::for_type::<LiquityInstruction>());
+    let variants = match &definitions["LiquityInstruction"] {
+        Definition::Enum { variants, .. } => variants,
+        definition => panic!("LiquityInstruction is not an enum: {:?}", definition),
+    };
+
+    let instructions: Vec<Value> = instruction_layouts(&mut keys)
+        .iter()
+        .map(|layouts| {
+            let data = &layouts[0].1.data;
+            let tag = data[0] as usize;
+            let (_, name, declaration) = &variants[tag];
+            let args: Vec<Value> = named_fields(declaration, &definitions)
+                .iter()
+                .map(|(name, field)| json!({ "name": name, "type": field }))
+                .collect();
+            let authorization = LiquityInstruction::unpack(data).unwrap().authorization();
+
+            let mut instruction = json!({
+                "name": name,
+                "tag": tag,
+                "authorization": format!("{:?}", authorization),
+                "args": args,
+                "accounts": keys.accounts(layouts),
+            });
+            if matches!(LiquityInstruction::unpack(data), Ok(LiquityInstruction::FlashMint { .. })) {
+                instruction["remaining_accounts"] = json!("passed through to the receiver program");
+            }
+            instruction
+        })
+        .collect();
+    assert_eq!(instructions.len(), variants.len(), "every instruction needs a layout");
+
+    let accounts = vec![
+        account::<Trove>(AccountType::Trove, Trove::VERSION, Some(Trove::LEGACY_LEN)),
+        account::<Deposit>(AccountType::Deposit, Deposit::VERSION, Some(Deposit::LEGACY_LEN)),
+        account::<Escrow>(AccountType::Escrow, Escrow::VERSION, Some(Escrow::LEGACY_LEN)),
+        account::<Protocol>(AccountType::Protocol, Protocol::VERSION, None),
+        account::<CollateralType>(AccountType::CollateralType, CollateralType::VERSION, None),
+    ];
+    definitions.extend(self::definitions(&BorshSchemaContainer::for_type::<Trove>()));
+    definitions.extend(self::definitions(&BorshSchemaContainer::for_type::<Deposit>()));
+    definitions.extend(self::definitions(&BorshSchemaContainer::for_type::<Protocol>()));
+    definitions.extend(self::definitions(&BorshSchemaContainer::for_type::<LiquityEvent>()));
+
+    json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "instructions": instructions,
+        "accounts": accounts,
+        "events": {
+            "log_prefix": EVENT_LOG_PREFIX,
+            "events": events(),
+        },
+        "types": types(&definitions),
+        "errors": errors(),
+    })
+}
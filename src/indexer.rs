@@ -0,0 +1,378 @@
+//! Indexer of the program history into SQLite. Transactions are indexed from
+//! the events in their logs, account updates from the decoded account state,
+//! both read from JSON RPC responses of a file or of a node
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+use solana_program::pubkey::Pubkey;
+use crate::client::{self, ProgramAccountState};
+use crate::event::{decode_logs, LiquityEvent};
+use crate::rpc::Rpc;
+
+pub use crate::rpc::Result;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS transactions (
+    signature TEXT PRIMARY KEY,
+    slot INTEGER NOT NULL,
+    block_time INTEGER
+);
+-- Latest state of every trove, from its events and account updates
+CREATE TABLE IF NOT EXISTS troves (
+    address TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    collateral TEXT,
+    collateral_amount INTEGER NOT NULL,
+    debt INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    opened_slot INTEGER,
+    updated_slot INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS trove_history (
+    trove TEXT NOT NULL,
+    event TEXT NOT NULL,
+    collateral_amount INTEGER NOT NULL,
+    debt INTEGER NOT NULL,
+    signature TEXT,
+    slot INTEGER NOT NULL,
+    block_time INTEGER
+);
+CREATE TABLE IF NOT EXISTS deposits (
+    address TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    token_amount INTEGER NOT NULL,
+    updated_slot INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS deposit_history (
+    deposit TEXT NOT NULL,
+    token_amount INTEGER NOT NULL,
+    signature TEXT,
+    slot INTEGER NOT NULL,
+    block_time INTEGER
+);
+CREATE TABLE IF NOT EXISTS liquidations (
+    trove TEXT NOT NULL,
+    liquidator TEXT NOT NULL,
+    debt INTEGER NOT NULL,
+    collateral_amount INTEGER NOT NULL,
+    signature TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER
+);
+CREATE TABLE IF NOT EXISTS redemptions (
+    trove TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    collateral_amount INTEGER NOT NULL,
+    repaid INTEGER NOT NULL,
+    signature TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER
+);
+-- Fees of borrows and deposit rewards, `unit` is stablecoin, governance or lamports
+CREATE TABLE IF NOT EXISTS fee_flows (
+    kind TEXT NOT NULL,
+    account TEXT NOT NULL,
+    unit TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    signature TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER
+);
+";
+
+/// Where and when an event was logged
+struct Context<'a> {
+    signature: &'a str,
+    slot: u64,
+    block_time: Option<i64>,
+}
+
+pub struct Indexer {
+    program_id: Pubkey,
+    conn: Connection,
+}
+
+impl Indexer {
+    /// Opens the database at `path`, creating the tables it lacks
+    pub fn open(program_id: Pubkey, path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Indexer { program_id, conn })
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Indexes every JSON RPC response of the file: a JSON array or one value per line
+    pub fn index_file(&mut self, path: &str) -> Result<()> {
+        let contents = std::fs::read_to_string(path)?;
+        let values: Vec<Value> = match serde_json::from_str(&contents) {
+            Ok(Value::Array(values)) => values,
+            Ok(value) => vec![value],
+            Err(_) => contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<std::result::Result<_, _>>()?,
+        };
+        for value in &values {
+            self.index_response(value)?;
+        }
+        Ok(())
+    }
+
+    /// Indexes a `getTransaction`, `getProgramAccounts` or `programNotification` response
+    pub fn index_response(&mut self, value: &Value) -> Result<()> {
+        let result = value.get("result").unwrap_or(value);
+        if result.get("transaction").is_some() {
+            return self.index_transaction(result);
+        }
+
+        // `getProgramAccounts` with or without context, or a program notification
+        let params_result = &value["params"]["result"];
+        let (slot, accounts) = if let Some(accounts) = result.as_array() {
+            (None, accounts.clone())
+        } else if let Some(accounts) = result["value"].as_array() {
+            (result["context"]["slot"].as_u64(), accounts.clone())
+        } else if params_result["value"].get("pubkey").is_some() {
+            (params_result["context"]["slot"].as_u64(), vec![params_result["value"].clone()])
+        } else {
+            return Err("unrecognized JSON RPC response".into());
+        };
+        let slot = slot.ok_or("account updates need the slot of their context")?;
+        for account in &accounts {
+            self.index_account(account, slot)?;
+        }
+        Ok(())
+    }
+
+    /// Indexes the events of a `getTransaction` result, once per signature.
+    /// Failed transactions are skipped, their logged events were rolled back
+    pub fn index_transaction(&mut self, result: &Value) -> Result<()> {
+        let signature = result["transaction"]["signatures"][0].as_str().ok_or("transaction without signature")?;
+        let slot = result["slot"].as_u64().ok_or("transaction without slot")?;
+        let block_time = result["blockTime"].as_i64();
+        if !result["meta"]["err"].is_null() {
+            return Ok(());
+        }
+        let logs: Vec<&str> = result["meta"]["logMessages"]
+            .as_array()
+            .ok_or("transaction without log messages")?
+            .iter()
+            .filter_map(Value::as_str)
+            .collect();
+
+        let tx = self.conn.transaction()?;
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO transactions (signature, slot, block_time) VALUES (?1, ?2, ?3)",
+            params![signature, slot, block_time],
+        )?;
+        if inserted == 1 {
+            let context = Context { signature, slot, block_time };
+            for event in decode_logs(&self.program_id, &logs) {
+                index_event(&tx, &context, &event)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Indexes a `{ pubkey, account }` entry of the program accounts, updated at `slot`
+    pub fn index_account(&mut self, entry: &Value, slot: u64) -> Result<()> {
+        let address = entry["pubkey"].as_str().ok_or("account without pubkey")?;
+        let data = match entry["account"]["data"].as_array().map(Vec::as_slice) {
+            Some([Value::String(data), Value::String(encoding)]) if encoding == "base64" => base64::decode(data)?,
+            _ => return Err(format!("account {} has no base64 data", address).into()),
+        };
+
+        // Accounts of the program other than troves and deposits are not indexed
+        let state = match client::decode_account(&data) {
+            Ok(state) => state,
+            Err(_) => return Ok(()),
+        };
+        match state {
+            ProgramAccountState::Trove(trove) => {
+                let status = if trove.is_liquidated { "liquidated" } else { "open" };
+                self.conn.execute(
+                    "INSERT INTO troves (address, owner, collateral, collateral_amount, debt, status, updated_slot)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                     ON CONFLICT (address) DO UPDATE SET
+                         owner = ?2, collateral = ?3, collateral_amount = ?4, debt = ?5, status = ?6, updated_slot = ?7
+                     WHERE updated_slot <= ?7",
+                    params![
+                        address,
+                        trove.owner.to_string(),
+                        trove.collateral.to_string(),
+                        trove.collateral_amount.0,
+                        trove.amount_to_close.0,
+                        status,
+                        slot,
+                    ],
+                )?;
+            }
+            ProgramAccountState::Deposit(deposit) => {
+                self.conn.execute(
+                    "INSERT INTO deposits (address, owner, token_amount, updated_slot) VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT (address) DO UPDATE SET owner = ?2, token_amount = ?3, updated_slot = ?4
+                     WHERE updated_slot <= ?4",
+                    params![address, deposit.owner.to_string(), deposit.token_amount.0, slot],
+                )?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Indexes the transactions of the program newer than the last indexed one,
+    /// then the current state of its accounts
+    pub fn sync(&mut self, rpc: &Rpc) -> Result<()> {
+        let until: Option<String> = self
+            .conn
+            .query_row("SELECT signature FROM transactions ORDER BY slot DESC LIMIT 1", [], |row| row.get(0))
+            .optional()?;
+
+        // Signatures come newest first, a page at a time
+        let mut signatures = Vec::new();
+        let mut before: Option<String> = None;
+        loop {
+            let page = rpc.call(
+                "getSignaturesForAddress",
+                json!([self.program_id.to_string(), { "limit": 1000, "before": before, "until": until }]),
+            )?;
+            let page = page.as_array().ok_or("getSignaturesForAddress returned no signatures")?;
+            for entry in page {
+                signatures.push(entry["signature"].as_str().ok_or("signature without signature")?.to_string());
+            }
+            match page.last() {
+                Some(last) if page.len() == 1000 => before = last["signature"].as_str().map(String::from),
+                _ => break,
+            }
+        }
+
+        for signature in signatures.iter().rev() {
+            let result = rpc.call(
+                "getTransaction",
+                json!([signature, { "encoding": "json", "commitment": "confirmed", "maxSupportedTransactionVersion": 0 }]),
+            )?;
+            if !result.is_null() {
+                self.index_transaction(&result)?;
+            }
+        }
+
+        let accounts = rpc.call(
+            "getProgramAccounts",
+            json!([self.program_id.to_string(), { "encoding": "base64", "withContext": true }]),
+        )?;
+        self.index_response(&accounts)
+    }
+}
+
+fn index_event(tx: &rusqlite::Transaction, context: &Context, event: &LiquityEvent) -> Result<()> {
+    let Context { signature, slot, block_time } = *context;
+    let trove_history = |trove: &Pubkey, event: &str, collateral_amount: u64, debt: u64| {
+        tx.execute(
+            "INSERT INTO trove_history (trove, event, collateral_amount, debt, signature, slot, block_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![trove.to_string(), event, collateral_amount, debt, signature, slot, block_time],
+        )
+    };
+    let fee_flow = |kind: &str, account: &Pubkey, unit: &str, amount: u64| {
+        if amount == 0 {
+            return Ok(0);
+        }
+        tx.execute(
+            "INSERT INTO fee_flows (kind, account, unit, amount, signature, slot, block_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![kind, account.to_string(), unit, amount, signature, slot, block_time],
+        )
+    };
+    let update_trove = |trove: &Pubkey, status: &str, collateral_amount: u64, debt: u64| {
+        tx.execute(
+            "UPDATE troves SET status = ?2, collateral_amount = ?3, debt = ?4, updated_slot = ?5
+             WHERE address = ?1 AND updated_slot <= ?5",
+            params![trove.to_string(), status, collateral_amount, debt, slot],
+        )
+    };
+
+    match event {
+        LiquityEvent::TroveOpened {
+            trove, owner, collateral, collateral_amount, borrow_amount: _, debt, depositor_fee, team_fee,
+        } => {
+            tx.execute(
+                "INSERT INTO troves (address, owner, collateral, collateral_amount, debt, status, opened_slot, updated_slot)
+                 VALUES (?1, ?2, ?3, ?4, ?5, 'open', ?6, ?6)
+                 ON CONFLICT (address) DO UPDATE SET
+                     owner = ?2, collateral = ?3, collateral_amount = ?4, debt = ?5, status = 'open', opened_slot = ?6,
+                     updated_slot = ?6
+                 WHERE updated_slot <= ?6",
+                params![trove.to_string(), owner.to_string(), collateral.to_string(), collateral_amount.0, debt.0, slot],
+            )?;
+            trove_history(trove, "TroveOpened", collateral_amount.0, debt.0)?;
+            fee_flow("depositor_fee", trove, "stablecoin", depositor_fee.0)?;
+            fee_flow("team_fee", trove, "stablecoin", team_fee.0)?;
+        }
+        LiquityEvent::TroveAdjusted { trove, collateral_amount, debt } => {
+            update_trove(trove, "open", collateral_amount.0, debt.0)?;
+            trove_history(trove, "TroveAdjusted", collateral_amount.0, debt.0)?;
+        }
+        LiquityEvent::TroveClosed { trove, owner: _, repaid, collateral_amount } => {
+            update_trove(trove, "closed", 0, 0)?;
+            trove_history(trove, "TroveClosed", collateral_amount.0, repaid.0)?;
+        }
+        LiquityEvent::TroveLiquidated { trove, liquidator, debt, collateral_amount } => {
+            update_trove(trove, "liquidated", 0, 0)?;
+            trove_history(trove, "TroveLiquidated", collateral_amount.0, debt.0)?;
+            tx.execute(
+                "INSERT INTO liquidations (trove, liquidator, debt, collateral_amount, signature, slot, block_time)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![trove.to_string(), liquidator.to_string(), debt.0, collateral_amount.0, signature, slot, block_time],
+            )?;
+        }
+        LiquityEvent::Redemption { trove, amount, collateral_amount, repaid } => {
+            tx.execute(
+                "UPDATE troves SET collateral_amount = ?2, updated_slot = ?3 WHERE address = ?1 AND updated_slot <= ?3",
+                params![trove.to_string(), collateral_amount.0, slot],
+            )?;
+            tx.execute(
+                "INSERT INTO redemptions (trove, amount, collateral_amount, repaid, signature, slot, block_time)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![trove.to_string(), amount.0, collateral_amount.0, repaid.0, signature, slot, block_time],
+            )?;
+        }
+        LiquityEvent::DepositChanged { deposit, owner, token_amount } => {
+            tx.execute(
+                "INSERT INTO deposits (address, owner, token_amount, updated_slot) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (address) DO UPDATE SET owner = ?2, token_amount = ?3, updated_slot = ?4
+                 WHERE updated_slot <= ?4",
+                params![deposit.to_string(), owner.to_string(), token_amount.0, slot],
+            )?;
+            tx.execute(
+                "INSERT INTO deposit_history (deposit, token_amount, signature, slot, block_time) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![deposit.to_string(), token_amount.0, signature, slot, block_time],
+            )?;
+        }
+        LiquityEvent::RewardAdded { deposit, coin, governance, token } => {
+            fee_flow("reward_added", deposit, "lamports", coin.0)?;
+            fee_flow("reward_added", deposit, "governance", governance.0)?;
+            fee_flow("reward_added", deposit, "stablecoin", token.0)?;
+        }
+        LiquityEvent::RewardClaimed { deposit, owner: _, coin, governance, token } => {
+            fee_flow("reward_claimed", deposit, "lamports", coin.0)?;
+            fee_flow("reward_claimed", deposit, "governance", governance.0)?;
+            fee_flow("reward_claimed", deposit, "stablecoin", token.0)?;
+        }
+        LiquityEvent::TroveMigrated { trove, owner, collateral, collateral_amount, debt } => {
+            tx.execute(
+                "INSERT INTO troves (address, owner, collateral, collateral_amount, debt, status, updated_slot)
+                 VALUES (?1, ?2, ?3, ?4, ?5, 'open', ?6)
+                 ON CONFLICT (address) DO UPDATE SET
+                     owner = ?2, collateral = ?3, collateral_amount = ?4, debt = ?5, status = 'open', updated_slot = ?6
+                 WHERE updated_slot <= ?6",
+                params![trove.to_string(), owner.to_string(), collateral.to_string(), collateral_amount.0, debt.0, slot],
+            )?;
+            trove_history(trove, "TroveMigrated", collateral_amount.0, debt.0)?;
+        }
+    }
+    Ok(())
+}
@@ -1,26 +1,66 @@
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::mem::size_of;
 
 use crate::error::LiquityError::InvalidInstruction;
 use solana_program::{
     msg,
 };
 use crate::error::LiquityError;
+use crate::state::ProtocolConfig;
+use crate::units::{CollateralAmount, GovAmount, Lamports, StableAmount};
+use crate::collateral::find_authority_address;
+use crate::params::SYSTEM_ACCOUNT_ADDRESS;
+use solana_program::instruction::{AccountMeta, Instruction};
 use solana_program::program_error::ProgramError;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_program::system_program;
+use solana_program::sysvar;
 
+/// Who may send an instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Authorization {
+    /// The owner of the trove or deposit, who becomes its owner when it is opened
+    Owner,
+    /// A role the admin delegates in the protocol config, or the admin itself
+    Delegate,
+    /// The protocol admin or the system account
+    Authority,
+    /// Any signer, recorded as acting on the instruction
+    AnySigner,
+    /// No signer at all
+    Permissionless,
+}
+
+/// Token amounts are in base units of their mint. Variants are declared in tag
+/// order, so that the Borsh encoding matches `pack`
+#[derive(Debug)]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
 pub enum LiquityInstruction {
 
     /// Borrow money
     ///
+    /// The trove owes the borrow amount less the gas fee. The depositor and team
+    /// fees are part of that debt but are never minted: the borrower receives
+    /// the debt less the fees and burns the whole debt to close the trove, the
+    /// system account paying the fees out of band once it receives the trove
     ///
     /// Accounts expected:
     ///
     /// 0. `[signer]` The account of the person taking the trade
-    /// 1. `[writable]` The account to store trove
+    /// 1. `[writable]` The account to store trove, holding its rent and `lamports` of collateral
     /// 2. `[]` The rent sysvar
+    /// 3. `[writable]` The protocol account
+    /// 4. `[]` The clock sysvar
+    /// 5. `[writable]` The native SOL collateral type account
+    /// 6. `[writable]` The stablecoin mint
+    /// 7. `[writable]` User stablecoin token acc, minted the borrow amount less the gas fee and the fees
+    /// 8. `[]` The program authority
+    /// 9. `[]` Token program
     Borrow {
         /// the amount the taker expects to be paid in the other token, as a u64 because that's the max possible supply of a token
-        borrow_amount: u64,
-        lamports: u64
+        borrow_amount: StableAmount,
+        lamports: Lamports
     },
 
     /// Close Trove
@@ -28,94 +68,170 @@ pub enum LiquityInstruction {
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer, writable]` The trove owner
     /// 1. `[writable]` The Trove account
     /// 2. `[]` Token program
-    /// 3. `[]` User token acc
-    /// 4. `[]` Mint Token key
+    /// 3. `[writable]` User token acc
+    /// 4. `[writable]` Mint Token key
+    /// 5. `[writable]` The protocol account
+    /// 6. `[]` The clock sysvar
+    /// 7. `[writable]` The collateral type account of the trove
+    ///
+    /// Stake pool troves also expect:
+    ///
+    /// 8. `[]` The stake pool
+    /// 9. `[writable]` The collateral vault
+    /// 10. `[writable]` User pool token acc
+    /// 11. `[]` The program authority
+    /// 12. `[]` Token program
     CloseTrove {},
 
     /// Liquidate Trove
     ///
+    /// Only a trove below the minimum collateral ratio can be liquidated, its debt
+    /// with the interest accrued up to now
+    ///
+    ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer]` The liquidator, any signer, which may be the system account
     /// 1. `[writable]` The Trove account
-    /// 2. `[writable]` The Trove owner
+    /// 2. `[writable]` Sys acc
+    /// 3. `[writable]` The protocol account
+    /// 4. `[]` The clock sysvar
+    /// 5. `[writable]` The collateral type account of the trove
+    ///
+    /// Stake pool troves also expect:
+    ///
+    /// 6. `[]` The stake pool
+    /// 7. `[writable]` The collateral vault
+    /// 8. `[writable]` Sys acc pool token acc
+    /// 9. `[]` The program authority
+    /// 10. `[]` Token program
     LiquidateTrove {},
 
     /// Withdraw Coin
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer, writable]` The trove owner, paid the lamports of native SOL troves
     /// 1. `[writable]` The Trove account
+    /// 2. `[writable]` The protocol account
+    /// 3. `[]` The clock sysvar
+    /// 4. `[writable]` The collateral type account of the trove
+    ///
+    /// Stake pool troves also expect:
+    ///
+    /// 5. `[]` The stake pool
+    /// 6. `[writable]` The collateral vault
+    /// 7. `[writable]` User pool token acc
+    /// 8. `[]` The program authority
+    /// 9. `[]` Token program
     WithdrawCoin {
-        amount: u64,
+        amount: CollateralAmount,
     },
 
-    /// Redeem Coin
+    /// Add Coin
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer]` The trove owner
     /// 1. `[writable]` The Trove account
-    RedeemCoin {
-        amount: u64,
+    /// 2. `[]` The protocol account
+    /// 3. `[writable]` The Temp Account to get lamports, an empty account of the program
+    ///    holding exactly `amount`, swept into the trove
+    ///
+    /// Stake pool troves expect instead:
+    ///
+    /// 3. `[]` The stake pool
+    /// 4. `[writable]` The collateral vault
+    /// 5. `[writable]` User pool token acc
+    /// 6. `[]` The program authority
+    /// 7. `[]` Token program
+    AddCoin {
+        amount: CollateralAmount,
     },
 
-    /// Add Coin
+    /// Redeem Coin, paying for `amount` of collateral with stablecoin burned
+    /// against the debt of the trove, which must still cover the minimum
+    /// collateral ratio afterwards
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer, writable]` The trove owner, paid the lamports of native SOL troves
     /// 1. `[writable]` The Trove account
-    /// 2. `[writable]` The Temp Account to get lamports
-    AddCoin {
-        amount: u64,
+    /// 2. `[writable]` The protocol account
+    /// 3. `[]` The clock sysvar
+    /// 4. `[writable]` The collateral type account of the trove
+    /// 5. `[]` Token program
+    /// 6. `[writable]` User token acc, burned the value of the redeemed collateral
+    /// 7. `[writable]` Mint Token key
+    ///
+    /// Stake pool troves also expect:
+    ///
+    /// 8. `[]` The stake pool
+    /// 9. `[writable]` The collateral vault
+    /// 10. `[writable]` User pool token acc
+    /// 11. `[]` The program authority
+    /// 12. `[]` Token program
+    RedeemCoin {
+        amount: CollateralAmount,
     },
 
     /// Add deposit
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer]` The deposit owner
     /// 1. `[writable]` The Deposit account
     /// 2. `[]` The rent sysvar
     /// 3. `[]` Token program
-    /// 4. `[]` User token acc
-    /// 4. `[]` User governance token acc
-    /// 5. `[]` Mint Token key
+    /// 4. `[writable]` User token acc
+    /// 5. `[]` User governance token acc
+    /// 6. `[writable]` Mint Token key
+    /// 7. `[]` The protocol account
     AddDeposit {
-        amount: u64,
+        amount: StableAmount,
     },
 
     ///  Withdraw deposit
     ///
+    /// The stablecoin burned when it was deposited is minted back to the owner
+    ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer]` The deposit owner
     /// 1. `[writable]` The Deposit account
+    /// 2. `[]` The protocol account
+    /// 3. `[writable]` The stablecoin mint
+    /// 4. `[writable]` User stablecoin token acc, minted the withdrawn amount
+    /// 5. `[]` The program authority
+    /// 6. `[]` Token program
     WithdrawDeposit {
-        amount: u64
+        amount: StableAmount
     },
 
     ///  Claim deposit reward
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer]` Sys acc
     /// 1. `[writable]` The Deposit account
+    /// 2. `[]` The protocol account
     ClaimDepositReward {},
 
 
-    /// Trove received
-    ///
+    /// Trove received, the system account acknowledging that it has booked the
+    /// depositor and team fees of the trove, crediting the depositor fee to the
+    /// deposits with `AddDepositReward`. Only received troves can be liquidated,
+    /// so that the fees of a trove are booked before its collateral goes to the
+    /// system account
     ///
     /// Accounts expected:
     ///
     /// 0. `[signer]` Sys acc
     /// 1. `[writable]` The Trove account
+    /// 2. `[]` The protocol account
     ReceiveTrove {},
 
 
@@ -126,84 +242,429 @@ pub enum LiquityInstruction {
     ///
     /// 0. `[signer]` Sys acc
     /// 1. `[writable]` The Deposit account
+    /// 2. `[]` The protocol account
     AddDepositReward {
-        coin: u64,
-        governance: u64,
-        token: u64
+        coin: Lamports,
+        governance: GovAmount,
+        token: StableAmount
+    },
+
+    /// Borrow money against stake pool tokens, with the debt and fees of `Borrow`
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person taking the trade
+    /// 1. `[writable]` The account to store trove
+    /// 2. `[]` The rent sysvar
+    /// 3. `[writable]` The protocol account
+    /// 4. `[]` The clock sysvar
+    /// 5. `[writable]` The collateral type account of the stake pool
+    /// 6. `[]` The stake pool
+    /// 7. `[writable]` The collateral vault
+    /// 8. `[writable]` User pool token acc
+    /// 9. `[]` The program authority
+    /// 10. `[]` Token program
+    /// 11. `[writable]` The stablecoin mint
+    /// 12. `[writable]` User stablecoin token acc, minted the borrow amount less the gas fee and the fees
+    /// 13. `[]` The program authority
+    /// 14. `[]` Token program
+    BorrowWithStakePoolToken {
+        borrow_amount: StableAmount,
+        pool_tokens: CollateralAmount
+    },
+
+    /// Initialize the protocol account
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` Sys acc
+    /// 1. `[writable]` The protocol account
+    /// 2. `[]` The rent sysvar
+    /// 3. `[]` The clock sysvar
+    /// 4. `[]` The stablecoin mint, its mint authority the program authority
+    InitProtocol {
+        config: ProtocolConfig
+    },
+
+    /// Queue a protocol config change, executable once the timelock delay has passed
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The protocol admin
+    /// 1. `[writable]` The protocol account
+    /// 2. `[]` The clock sysvar
+    QueueConfigChange {
+        config: ProtocolConfig
+    },
+
+    /// Flash mint stablecoin to a receiver program, which must burn the amount
+    /// plus the fee, or return it to the fee receiver, before the call ends
+    ///
+    /// The receiver program is called with `[0, amount: u64, fee: u64]` and the
+    /// receiver token acc, the stablecoin mint, the token program and every
+    /// remaining account
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The stablecoin mint
+    /// 1. `[writable]` The receiver token acc
+    /// 2. `[writable]` Sys acc stablecoin token acc receiving returned fees
+    /// 3. `[]` The program authority
+    /// 4. `[]` Token program
+    /// 5. `[]` The protocol account
+    /// 6. `[]` The receiver program
+    /// 7. `[]` Any remaining accounts are passed through to the receiver program
+    FlashMint {
+        amount: StableAmount,
+    },
+
+    /// Set the bitmask of paused operations
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The pauser or the protocol admin
+    /// 1. `[writable]` The protocol account
+    SetPause {
+        paused: u64,
+    },
+
+    /// Initialize the debt ceiling of a collateral type
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The protocol admin, paying the rent of the collateral type
+    /// 1. `[]` The protocol account
+    /// 2. `[writable]` The collateral type account, derived from the protocol and the collateral
+    /// 3. `[]` The rent sysvar
+    /// 4. `[]` The system program
+    InitCollateralType {
+        /// Stake pool of the collateral, default for native SOL
+        collateral: Pubkey,
+        debt_ceiling: StableAmount,
+    },
+
+    /// Update the debt ceiling of a collateral type
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The protocol admin
+    /// 1. `[]` The protocol account
+    /// 2. `[writable]` The collateral type account, derived from the protocol and the collateral
+    UpdateCollateralType {
+        debt_ceiling: StableAmount,
     },
+
+    /// Execute the queued config change, accruing interest at the previous rate first
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The protocol account
+    /// 1. `[]` The clock sysvar
+    ExecuteConfigChange {},
+
+    /// Cancel the queued config change
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The guardian or the protocol admin
+    /// 1. `[writable]` The protocol account
+    CancelConfigChange {},
+
+    /// Upgrade a legacy Trove, Deposit or Escrow account to the current layout
+    ///
+    /// The account is reallocated in place, so its address is unchanged, and the
+    /// signer tops up the rent of the larger layout, never the collateral. A legacy trove
+    /// declaring more collateral than the lamports it holds above its rent is capped to
+    /// them. Any signer may migrate a trove, as legacy troves cannot be liquidated, while
+    /// deposits and escrows are migrated by their owner. Legacy troves and deposits
+    /// recorded whole stablecoins, their amounts are converted to base units of the
+    /// mint. The debt of a migrated trove is added to the total debts of the protocol
+    /// and of its collateral type, and accrues interest from the migration on
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The payer, the deposit owner or escrow initializer for those accounts
+    /// 1. `[writable]` The legacy account
+    /// 2. `[writable]` The protocol account
+    /// 3. `[]` The rent sysvar
+    /// 4. `[]` The system program
+    ///
+    /// Legacy troves also expect:
+    ///
+    /// 5. `[writable]` The collateral type of native SOL
+    /// 6. `[]` The clock sysvar
+    MigrateAccount {},
 }
 
 
 
 impl LiquityInstruction {
+    /// Who may send the instruction, each processor enforces it against the accounts
+    pub fn authorization(&self) -> Authorization {
+        match self {
+            Self::Borrow { .. }
+            | Self::CloseTrove {}
+            | Self::WithdrawCoin { .. }
+            | Self::RedeemCoin { .. }
+            | Self::AddCoin { .. }
+            | Self::AddDeposit { .. }
+            | Self::WithdrawDeposit { .. }
+            | Self::BorrowWithStakePoolToken { .. } => Authorization::Owner,
+            Self::SetPause { .. }
+            | Self::CancelConfigChange {} => Authorization::Delegate,
+            Self::ClaimDepositReward {}
+            | Self::ReceiveTrove {}
+            | Self::AddDepositReward { .. }
+            | Self::InitProtocol { .. }
+            | Self::QueueConfigChange { .. }
+            | Self::InitCollateralType { .. }
+            | Self::UpdateCollateralType { .. } => Authorization::Authority,
+            Self::LiquidateTrove {}
+            | Self::MigrateAccount {} => Authorization::AnySigner,
+            Self::FlashMint { .. }
+            | Self::ExecuteConfigChange {} => Authorization::Permissionless,
+        }
+    }
+
     /// Unpacks a byte buffer into a [EscrowInstruction](enum.EscrowInstruction.html).
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
         let (tag, rest) = input.split_first().ok_or(InvalidInstruction)?;
 
-        Ok(match tag {
+        let (instruction, rest) = match tag {
             0 => {
                 let (borrow_amount, rest) = Self::unpack_u64(rest)?;
-                let (lamports, _rest) = Self::unpack_u64(rest)?;
-                Self::Borrow {
-                    borrow_amount,
-                    lamports
-                }
+                let (lamports, rest) = Self::unpack_u64(rest)?;
+                (Self::Borrow {
+                    borrow_amount: StableAmount(borrow_amount),
+                    lamports: Lamports(lamports)
+                }, rest)
             },
             1 => {
-                Self::CloseTrove {}
+                (Self::CloseTrove {}, rest)
             },
             2 => {
-                Self::LiquidateTrove {}
+                (Self::LiquidateTrove {}, rest)
             },
             3 => {
-                let (amount, _rest) = Self::unpack_u64(rest)?;
-                Self::WithdrawCoin {
-                    amount
-                }
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                (Self::WithdrawCoin {
+                    amount: CollateralAmount(amount)
+                }, rest)
             },
             4 => {
-                let (amount, _rest) = Self::unpack_u64(rest)?;
-                Self::AddCoin {
-                    amount
-                }
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                (Self::AddCoin {
+                    amount: CollateralAmount(amount)
+                }, rest)
             },
             5 => {
-                let (amount, _rest) = Self::unpack_u64(rest)?;
-                Self::RedeemCoin {
-                    amount
-                }
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                (Self::RedeemCoin {
+                    amount: CollateralAmount(amount)
+                }, rest)
             },
             6 => {
-                let (amount, _rest) = Self::unpack_u64(rest)?;
-                Self::AddDeposit {
-                    amount
-                }
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                (Self::AddDeposit {
+                    amount: StableAmount(amount)
+                }, rest)
             },
             7 => {
-                let (amount, _rest) = Self::unpack_u64(rest)?;
-                Self::WithdrawDeposit {
-                    amount
-                }
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                (Self::WithdrawDeposit {
+                    amount: StableAmount(amount)
+                }, rest)
             },
             8 => {
-                Self::ClaimDepositReward {}
+                (Self::ClaimDepositReward {}, rest)
             },
             9 => {
-                Self::ReceiveTrove {}
+                (Self::ReceiveTrove {}, rest)
             },
             10 => {
                 let (coin, rest) = Self::unpack_u64(rest)?;
                 let (governance, rest) = Self::unpack_u64(rest)?;
-                let (token, _rest) = Self::unpack_u64(rest)?;
+                let (token, rest) = Self::unpack_u64(rest)?;
 
-                Self::AddDepositReward {
-                    coin,
-                    governance,
-                    token
-                }
+                (Self::AddDepositReward {
+                    coin: Lamports(coin),
+                    governance: GovAmount(governance),
+                    token: StableAmount(token)
+                }, rest)
+            }
+            11 => {
+                let (borrow_amount, rest) = Self::unpack_u64(rest)?;
+                let (pool_tokens, rest) = Self::unpack_u64(rest)?;
+                (Self::BorrowWithStakePoolToken {
+                    borrow_amount: StableAmount(borrow_amount),
+                    pool_tokens: CollateralAmount(pool_tokens)
+                }, rest)
+            }
+            12 => {
+                let (config, rest) = Self::unpack_config(rest)?;
+                (Self::InitProtocol {
+                    config
+                }, rest)
+            }
+            13 => {
+                let (config, rest) = Self::unpack_config(rest)?;
+                (Self::QueueConfigChange {
+                    config
+                }, rest)
+            }
+            14 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                (Self::FlashMint {
+                    amount: StableAmount(amount)
+                }, rest)
+            }
+            15 => {
+                let (paused, rest) = Self::unpack_u64(rest)?;
+                (Self::SetPause {
+                    paused
+                }, rest)
+            }
+            16 => {
+                let (collateral, rest) = Self::unpack_pubkey(rest)?;
+                let (debt_ceiling, rest) = Self::unpack_u64(rest)?;
+                (Self::InitCollateralType {
+                    collateral,
+                    debt_ceiling: StableAmount(debt_ceiling)
+                }, rest)
+            }
+            17 => {
+                let (debt_ceiling, rest) = Self::unpack_u64(rest)?;
+                (Self::UpdateCollateralType {
+                    debt_ceiling: StableAmount(debt_ceiling)
+                }, rest)
+            }
+            18 => {
+                (Self::ExecuteConfigChange {}, rest)
+            }
+            19 => {
+                (Self::CancelConfigChange {}, rest)
+            }
+            20 => {
+                (Self::MigrateAccount {}, rest)
             }
             _ => return Err(InvalidInstruction.into()),
-        })
+        };
+        if !rest.is_empty() {
+            msg!("Instruction has trailing bytes");
+            return Err(LiquityError::InstructionUnpackError.into());
+        }
+        Ok(instruction)
+    }
+
+    /// Packs the instruction into the byte buffer `unpack` reads
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(size_of::<Self>());
+        match self {
+            Self::Borrow { borrow_amount, lamports } => {
+                buf.push(0);
+                buf.extend_from_slice(&borrow_amount.to_le_bytes());
+                buf.extend_from_slice(&lamports.to_le_bytes());
+            }
+            Self::CloseTrove {} => buf.push(1),
+            Self::LiquidateTrove {} => buf.push(2),
+            Self::WithdrawCoin { amount } => {
+                buf.push(3);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::AddCoin { amount } => {
+                buf.push(4);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::RedeemCoin { amount } => {
+                buf.push(5);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::AddDeposit { amount } => {
+                buf.push(6);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::WithdrawDeposit { amount } => {
+                buf.push(7);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::ClaimDepositReward {} => buf.push(8),
+            Self::ReceiveTrove {} => buf.push(9),
+            Self::AddDepositReward { coin, governance, token } => {
+                buf.push(10);
+                buf.extend_from_slice(&coin.to_le_bytes());
+                buf.extend_from_slice(&governance.to_le_bytes());
+                buf.extend_from_slice(&token.to_le_bytes());
+            }
+            Self::BorrowWithStakePoolToken { borrow_amount, pool_tokens } => {
+                buf.push(11);
+                buf.extend_from_slice(&borrow_amount.to_le_bytes());
+                buf.extend_from_slice(&pool_tokens.to_le_bytes());
+            }
+            Self::InitProtocol { config } => {
+                buf.push(12);
+                Self::pack_config(config, &mut buf);
+            }
+            Self::QueueConfigChange { config } => {
+                buf.push(13);
+                Self::pack_config(config, &mut buf);
+            }
+            Self::FlashMint { amount } => {
+                buf.push(14);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::SetPause { paused } => {
+                buf.push(15);
+                buf.extend_from_slice(&paused.to_le_bytes());
+            }
+            Self::InitCollateralType { collateral, debt_ceiling } => {
+                buf.push(16);
+                buf.extend_from_slice(collateral.as_ref());
+                buf.extend_from_slice(&debt_ceiling.to_le_bytes());
+            }
+            Self::UpdateCollateralType { debt_ceiling } => {
+                buf.push(17);
+                buf.extend_from_slice(&debt_ceiling.to_le_bytes());
+            }
+            Self::ExecuteConfigChange {} => buf.push(18),
+            Self::CancelConfigChange {} => buf.push(19),
+            Self::MigrateAccount {} => buf.push(20),
+        }
+        buf
+    }
+
+    fn pack_config(config: &ProtocolConfig, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.resize(start + ProtocolConfig::LEN, 0);
+        config.pack_into_slice(&mut buf[start..]);
+    }
+
+    fn unpack_config(input: &[u8]) -> Result<(ProtocolConfig, &[u8]), ProgramError> {
+        if input.len() < ProtocolConfig::LEN {
+            msg!("Config cannot be unpacked");
+            return Err(LiquityError::InstructionUnpackError.into());
+        }
+        let (bytes, rest) = input.split_at(ProtocolConfig::LEN);
+        let config = ProtocolConfig::unpack_from_slice(bytes)?;
+        Ok((config, rest))
+    }
+
+    fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+        if input.len() < 32 {
+            msg!("Pubkey cannot be unpacked");
+            return Err(LiquityError::InstructionUnpackError.into());
+        }
+        let (key, rest) = input.split_at(32);
+        let pk = Pubkey::try_from(key).map_err(|_| LiquityError::InstructionUnpackError)?;
+        Ok((pk, rest))
     }
 
     fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
@@ -219,4 +680,505 @@ impl LiquityInstruction {
             .ok_or(LiquityError::InstructionUnpackError)?;
         Ok((value, rest))
     }
-}
\ No newline at end of file
+}
+
+/// Stake pool accounts following the trove accounts of a stake pool trove
+pub struct StakePoolAccounts {
+    pub stake_pool: Pubkey,
+    /// The collateral vault token account of the pool mint
+    pub vault: Pubkey,
+    /// The user, or sys acc, pool token account
+    pub user_token: Pubkey,
+}
+
+impl StakePoolAccounts {
+    fn append_metas(&self, program_id: &Pubkey, accounts: &mut Vec<AccountMeta>) {
+        accounts.extend_from_slice(&[
+            AccountMeta::new_readonly(self.stake_pool, false),
+            AccountMeta::new(self.vault, false),
+            AccountMeta::new(self.user_token, false),
+            AccountMeta::new_readonly(find_authority_address(program_id).0, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ]);
+    }
+}
+
+/// Where the collateral added to a trove comes from
+pub enum CoinSource {
+    /// Temp account already holding the lamports, for native SOL troves
+    Lamports(Pubkey),
+    /// Pool tokens moved from the user pool token account, for stake pool troves
+    StakePool(StakePoolAccounts),
+}
+
+/// Appends the accounts minting the borrowed stablecoin to `user_token`
+fn append_stablecoin_metas(program_id: &Pubkey, stablecoin_mint: &Pubkey, user_token: &Pubkey, accounts: &mut Vec<AccountMeta>) {
+    accounts.extend_from_slice(&[
+        AccountMeta::new(*stablecoin_mint, false),
+        AccountMeta::new(*user_token, false),
+        AccountMeta::new_readonly(find_authority_address(program_id).0, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+}
+
+/// Creates a `Borrow` instruction, minting the borrowed stablecoin to `user_token`
+#[allow(clippy::too_many_arguments)]
+pub fn borrow(
+    program_id: &Pubkey,
+    borrower: &Pubkey,
+    trove: &Pubkey,
+    protocol: &Pubkey,
+    collateral_type: &Pubkey,
+    stablecoin_mint: &Pubkey,
+    user_token: &Pubkey,
+    borrow_amount: StableAmount,
+    lamports: Lamports,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*borrower, true),
+        AccountMeta::new(*trove, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(*collateral_type, false),
+    ];
+    append_stablecoin_metas(program_id, stablecoin_mint, user_token, &mut accounts);
+    let data = LiquityInstruction::Borrow { borrow_amount, lamports }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `CloseTrove` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn close_trove(
+    program_id: &Pubkey,
+    borrower: &Pubkey,
+    trove: &Pubkey,
+    user_token: &Pubkey,
+    stablecoin_mint: &Pubkey,
+    protocol: &Pubkey,
+    collateral_type: &Pubkey,
+    stake_pool: Option<&StakePoolAccounts>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*borrower, true),
+        AccountMeta::new(*trove, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(*user_token, false),
+        AccountMeta::new(*stablecoin_mint, false),
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(*collateral_type, false),
+    ];
+    if let Some(stake_pool) = stake_pool {
+        stake_pool.append_metas(program_id, &mut accounts);
+    }
+    let data = LiquityInstruction::CloseTrove {}.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `LiquidateTrove` instruction, the stake pool user token being the sys acc pool token acc
+pub fn liquidate_trove(
+    program_id: &Pubkey,
+    liquidator: &Pubkey,
+    trove: &Pubkey,
+    protocol: &Pubkey,
+    collateral_type: &Pubkey,
+    stake_pool: Option<&StakePoolAccounts>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*liquidator, true),
+        AccountMeta::new(*trove, false),
+        AccountMeta::new(SYSTEM_ACCOUNT_ADDRESS, false),
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(*collateral_type, false),
+    ];
+    if let Some(stake_pool) = stake_pool {
+        stake_pool.append_metas(program_id, &mut accounts);
+    }
+    let data = LiquityInstruction::LiquidateTrove {}.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `WithdrawCoin` instruction
+pub fn withdraw_coin(
+    program_id: &Pubkey,
+    borrower: &Pubkey,
+    trove: &Pubkey,
+    protocol: &Pubkey,
+    collateral_type: &Pubkey,
+    stake_pool: Option<&StakePoolAccounts>,
+    amount: CollateralAmount,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*borrower, true),
+        AccountMeta::new(*trove, false),
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(*collateral_type, false),
+    ];
+    if let Some(stake_pool) = stake_pool {
+        stake_pool.append_metas(program_id, &mut accounts);
+    }
+    let data = LiquityInstruction::WithdrawCoin { amount }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `RedeemCoin` instruction, burning the stablecoin of `user_token`
+#[allow(clippy::too_many_arguments)]
+pub fn redeem_coin(
+    program_id: &Pubkey,
+    borrower: &Pubkey,
+    trove: &Pubkey,
+    protocol: &Pubkey,
+    collateral_type: &Pubkey,
+    user_token: &Pubkey,
+    stablecoin_mint: &Pubkey,
+    stake_pool: Option<&StakePoolAccounts>,
+    amount: CollateralAmount,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*borrower, true),
+        AccountMeta::new(*trove, false),
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(*collateral_type, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(*user_token, false),
+        AccountMeta::new(*stablecoin_mint, false),
+    ];
+    if let Some(stake_pool) = stake_pool {
+        stake_pool.append_metas(program_id, &mut accounts);
+    }
+    let data = LiquityInstruction::RedeemCoin { amount }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates an `AddCoin` instruction
+pub fn add_coin(
+    program_id: &Pubkey,
+    borrower: &Pubkey,
+    trove: &Pubkey,
+    protocol: &Pubkey,
+    source: &CoinSource,
+    amount: CollateralAmount,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*borrower, true),
+        AccountMeta::new(*trove, false),
+        AccountMeta::new_readonly(*protocol, false),
+    ];
+    match source {
+        CoinSource::Lamports(temp_lamport_account) => {
+            accounts.push(AccountMeta::new(*temp_lamport_account, false));
+        }
+        CoinSource::StakePool(stake_pool) => stake_pool.append_metas(program_id, &mut accounts),
+    }
+    let data = LiquityInstruction::AddCoin { amount }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates an `AddDeposit` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn add_deposit(
+    program_id: &Pubkey,
+    depositor: &Pubkey,
+    deposit: &Pubkey,
+    user_token: &Pubkey,
+    user_governance_token: &Pubkey,
+    stablecoin_mint: &Pubkey,
+    protocol: &Pubkey,
+    amount: StableAmount,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*depositor, true),
+        AccountMeta::new(*deposit, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(*user_token, false),
+        AccountMeta::new_readonly(*user_governance_token, false),
+        AccountMeta::new(*stablecoin_mint, false),
+        AccountMeta::new_readonly(*protocol, false),
+    ];
+    let data = LiquityInstruction::AddDeposit { amount }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `WithdrawDeposit` instruction
+pub fn withdraw_deposit(
+    program_id: &Pubkey,
+    depositor: &Pubkey,
+    deposit: &Pubkey,
+    protocol: &Pubkey,
+    stablecoin_mint: &Pubkey,
+    user_token: &Pubkey,
+    amount: StableAmount,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*depositor, true),
+        AccountMeta::new(*deposit, false),
+        AccountMeta::new_readonly(*protocol, false),
+    ];
+    append_stablecoin_metas(program_id, stablecoin_mint, user_token, &mut accounts);
+    let data = LiquityInstruction::WithdrawDeposit { amount }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `ClaimDepositReward` instruction, signed by the sys acc
+pub fn claim_deposit_reward(
+    program_id: &Pubkey,
+    deposit: &Pubkey,
+    protocol: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(SYSTEM_ACCOUNT_ADDRESS, true),
+        AccountMeta::new(*deposit, false),
+        AccountMeta::new_readonly(*protocol, false),
+    ];
+    let data = LiquityInstruction::ClaimDepositReward {}.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `ReceiveTrove` instruction, signed by the sys acc
+pub fn receive_trove(
+    program_id: &Pubkey,
+    trove: &Pubkey,
+    protocol: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(SYSTEM_ACCOUNT_ADDRESS, true),
+        AccountMeta::new(*trove, false),
+        AccountMeta::new_readonly(*protocol, false),
+    ];
+    let data = LiquityInstruction::ReceiveTrove {}.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates an `AddDepositReward` instruction, signed by the sys acc
+pub fn add_deposit_reward(
+    program_id: &Pubkey,
+    deposit: &Pubkey,
+    protocol: &Pubkey,
+    coin: Lamports,
+    governance: GovAmount,
+    token: StableAmount,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(SYSTEM_ACCOUNT_ADDRESS, true),
+        AccountMeta::new(*deposit, false),
+        AccountMeta::new_readonly(*protocol, false),
+    ];
+    let data = LiquityInstruction::AddDepositReward { coin, governance, token }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `BorrowWithStakePoolToken` instruction, minting the borrowed stablecoin to `user_token`
+#[allow(clippy::too_many_arguments)]
+pub fn borrow_with_stake_pool_token(
+    program_id: &Pubkey,
+    borrower: &Pubkey,
+    trove: &Pubkey,
+    protocol: &Pubkey,
+    collateral_type: &Pubkey,
+    stake_pool: &StakePoolAccounts,
+    stablecoin_mint: &Pubkey,
+    user_token: &Pubkey,
+    borrow_amount: StableAmount,
+    pool_tokens: CollateralAmount,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*borrower, true),
+        AccountMeta::new(*trove, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(*collateral_type, false),
+    ];
+    stake_pool.append_metas(program_id, &mut accounts);
+    append_stablecoin_metas(program_id, stablecoin_mint, user_token, &mut accounts);
+    let data = LiquityInstruction::BorrowWithStakePoolToken { borrow_amount, pool_tokens }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates an `InitProtocol` instruction, signed by the sys acc
+pub fn init_protocol(
+    program_id: &Pubkey,
+    protocol: &Pubkey,
+    stablecoin_mint: &Pubkey,
+    config: ProtocolConfig,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(SYSTEM_ACCOUNT_ADDRESS, true),
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(*stablecoin_mint, false),
+    ];
+    let data = LiquityInstruction::InitProtocol { config }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `QueueConfigChange` instruction
+pub fn queue_config_change(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    protocol: &Pubkey,
+    config: ProtocolConfig,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    let data = LiquityInstruction::QueueConfigChange { config }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `FlashMint` instruction, `remaining` being passed through to the receiver program
+#[allow(clippy::too_many_arguments)]
+pub fn flash_mint(
+    program_id: &Pubkey,
+    stablecoin_mint: &Pubkey,
+    receiver_token: &Pubkey,
+    fee_receiver_token: &Pubkey,
+    protocol: &Pubkey,
+    receiver_program: &Pubkey,
+    remaining: &[AccountMeta],
+    amount: StableAmount,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*stablecoin_mint, false),
+        AccountMeta::new(*receiver_token, false),
+        AccountMeta::new(*fee_receiver_token, false),
+        AccountMeta::new_readonly(find_authority_address(program_id).0, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(*protocol, false),
+        AccountMeta::new_readonly(*receiver_program, false),
+    ];
+    accounts.extend_from_slice(remaining);
+    let data = LiquityInstruction::FlashMint { amount }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `SetPause` instruction
+pub fn set_pause(
+    program_id: &Pubkey,
+    pauser: &Pubkey,
+    protocol: &Pubkey,
+    paused: u64,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*pauser, true),
+        AccountMeta::new(*protocol, false),
+    ];
+    let data = LiquityInstruction::SetPause { paused }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates an `InitCollateralType` instruction
+pub fn init_collateral_type(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    protocol: &Pubkey,
+    collateral_type: &Pubkey,
+    collateral: Pubkey,
+    debt_ceiling: StableAmount,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*admin, true),
+        AccountMeta::new_readonly(*protocol, false),
+        AccountMeta::new(*collateral_type, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    let data = LiquityInstruction::InitCollateralType { collateral, debt_ceiling }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates an `UpdateCollateralType` instruction
+pub fn update_collateral_type(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    protocol: &Pubkey,
+    collateral_type: &Pubkey,
+    debt_ceiling: StableAmount,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new_readonly(*protocol, false),
+        AccountMeta::new(*collateral_type, false),
+    ];
+    let data = LiquityInstruction::UpdateCollateralType { debt_ceiling }.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates an `ExecuteConfigChange` instruction
+pub fn execute_config_change(
+    program_id: &Pubkey,
+    protocol: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    let data = LiquityInstruction::ExecuteConfigChange {}.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `CancelConfigChange` instruction
+pub fn cancel_config_change(
+    program_id: &Pubkey,
+    guardian: &Pubkey,
+    protocol: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*guardian, true),
+        AccountMeta::new(*protocol, false),
+    ];
+    let data = LiquityInstruction::CancelConfigChange {}.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
+
+/// Creates a `MigrateAccount` instruction
+pub fn migrate_account(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    legacy_account: &Pubkey,
+    protocol: &Pubkey,
+    collateral_type: Option<&Pubkey>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new(*legacy_account, false),
+        AccountMeta::new(*protocol, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    if let Some(collateral_type) = collateral_type {
+        accounts.push(AccountMeta::new(*collateral_type, false));
+        accounts.push(AccountMeta::new_readonly(sysvar::clock::id(), false));
+    }
+    let data = LiquityInstruction::MigrateAccount {}.pack();
+
+    Instruction { program_id: *program_id, accounts, data }
+}
@@ -4,4 +4,18 @@ pub mod instruction;
 pub mod processor;
 pub mod state;
 pub mod params;
-pub mod helpers;
\ No newline at end of file
+pub mod helpers;
+pub mod math;
+pub mod units;
+pub mod collateral;
+pub mod account;
+pub mod token;
+pub mod event;
+#[cfg(feature = "idl")]
+pub mod idl;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "indexer")]
+pub mod indexer;
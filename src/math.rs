@@ -0,0 +1,50 @@
+//! Checked arithmetic, failing with `LiquityError::MathOverflow` instead of
+//! panicking or wrapping
+
+use solana_program::program_error::ProgramError;
+use crate::error::LiquityError;
+
+pub fn add(a: u64, b: u64) -> Result<u64, ProgramError> {
+    a.checked_add(b).ok_or_else(|| LiquityError::MathOverflow.into())
+}
+
+pub fn sub(a: u64, b: u64) -> Result<u64, ProgramError> {
+    a.checked_sub(b).ok_or_else(|| LiquityError::MathOverflow.into())
+}
+
+pub fn mul(a: u64, b: u64) -> Result<u64, ProgramError> {
+    a.checked_mul(b).ok_or_else(|| LiquityError::MathOverflow.into())
+}
+
+pub fn div(a: u64, b: u64) -> Result<u64, ProgramError> {
+    a.checked_div(b).ok_or_else(|| LiquityError::MathOverflow.into())
+}
+
+/// `a * b / c` rounded down, without overflowing on the intermediate product
+pub fn mul_div(a: u64, b: u64, c: u64) -> Result<u64, ProgramError> {
+    if c == 0 {
+        return Err(LiquityError::MathOverflow.into());
+    }
+    to_u64(a as u128 * b as u128 / c as u128)
+}
+
+/// `a * b / c` rounded up, without overflowing on the intermediate product
+pub fn mul_div_ceil(a: u64, b: u64, c: u64) -> Result<u64, ProgramError> {
+    if c == 0 {
+        return Err(LiquityError::MathOverflow.into());
+    }
+    to_u64((a as u128 * b as u128).div_ceil(c as u128))
+}
+
+/// Narrows a wide intermediate result back to u64
+pub fn to_u64(value: u128) -> Result<u64, ProgramError> {
+    if value > u64::MAX as u128 {
+        return Err(LiquityError::MathOverflow.into());
+    }
+    Ok(value as u64)
+}
+
+/// Difference of two unix timestamps
+pub fn elapsed(from: i64, to: i64) -> Result<i64, ProgramError> {
+    to.checked_sub(from).ok_or_else(|| LiquityError::MathOverflow.into())
+}
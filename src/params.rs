@@ -1,13 +1,38 @@
 use solana_program::pubkey::Pubkey;
 
-pub const MIN_COLLATERAL: f64 = 1.10;
-/// 2 SOL as gase fee
+/// Ratios and fees are in basis points
+pub const BPS_DENOMINATOR: u64 = 10_000;
+/// Whole stablecoins set aside from every trove debt as gas compensation
 pub const GAS_FEE: u64 = 200;
 
-pub const TOTAL_FEE: u64 = DEPOSIT_FEE + TEAM_FEE;
-pub const DEPOSIT_FEE: u64 = 4;
-pub const TEAM_FEE: u64 = 1;
+/// Mint of the governance token rewarded to depositors, BCftECVv4u3XxqvBdWiG15iubdixbP6BvdX4hHXtLk7c
+pub const GENS_TOKEN_ADDRESS: Pubkey = Pubkey::new_from_array([151,146,57,60,68,238,127,57,112,7,246,41,109,68,250,52,172,81,90,205,77,182,116,127,10,2,87,127,186,161,89,115]);
 
-pub const GENS_TOKEN_ADDRESS: &str = "BCftECVv4u3XxqvBdWiG15iubdixbP6BvdX4hHXtLk7c";
+pub const SYSTEM_ACCOUNT_ADDRESS: Pubkey = Pubkey::new_from_array([240,128,137,181,181,244,178,11,202,92,41,67,29,30,142,34,115,81,243,143,175,219,59,238,174,103,9,243,15,126,161,190]);
 
-pub const SYSTEM_ACCOUNT_ADDRESS: Pubkey = Pubkey::new_from_array([240,128,137,181,181,244,178,11,202,92,41,67,29,30,142,34,115,81,243,143,175,219,59,238,174,103,9,243,15,126,161,190]);
\ No newline at end of file
+/// SPL stake pool program, owner of every stake pool accepted as collateral
+pub const STAKE_POOL_PROGRAM_ADDRESS: Pubkey = Pubkey::new_from_array([6,129,78,212,202,246,138,23,70,114,253,172,134,3,26,99,232,78,161,94,250,29,68,183,34,147,246,219,219,0,22,80]);
+
+/// Seed of the program authority owning the collateral vaults
+pub const AUTHORITY_SEED: &[u8] = b"authority";
+
+/// Seed of the collateral type accounts, one per protocol and collateral
+pub const COLLATERAL_TYPE_SEED: &[u8] = b"collateral_type";
+
+/// Borrow index of a protocol without accrued interest
+pub const INITIAL_BORROW_INDEX: u128 = 1_000_000_000_000_000_000;
+pub const SECONDS_PER_YEAR: u128 = 365 * 24 * 60 * 60;
+
+/// Instruction tag the flash mint receiver program is called with
+pub const FLASH_MINT_RECEIVER_TAG: u8 = 0;
+
+/// Operations the pauser can halt, as bits of `Protocol::paused`.
+/// Closing troves and withdrawing deposits can never be paused.
+pub const PAUSE_BORROW: u64 = 1 << 0;
+pub const PAUSE_ADJUST_TROVE: u64 = 1 << 1;
+pub const PAUSE_REDEEM: u64 = 1 << 2;
+pub const PAUSE_LIQUIDATE: u64 = 1 << 3;
+pub const PAUSE_DEPOSIT: u64 = 1 << 4;
+pub const PAUSE_REWARDS: u64 = 1 << 5;
+pub const PAUSE_RECEIVE_TROVE: u64 = 1 << 6;
+pub const PAUSE_FLASH_MINT: u64 = 1 << 7;
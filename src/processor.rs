@@ -2,17 +2,36 @@ use solana_program::{
     account_info::{AccountInfo, next_account_info},
     entrypoint::ProgramResult,
     msg,
-    program::{invoke},
+    instruction::{AccountMeta, Instruction},
+    program::{invoke, invoke_signed},
     program_error::ProgramError,
-    program_pack::{IsInitialized, Pack},
+    program_option::COption,
+    program_pack::Pack,
     pubkey::Pubkey,
-    sysvar::{rent::Rent, Sysvar},
+    system_instruction,
+    system_program,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
+};
+use crate::{error::LiquityError, helpers, math, token};
+use crate::token::StablecoinAccounts;
+use crate::instruction::{Authorization, LiquityInstruction};
+use crate::account::{check_distinct, close_account, transfer_lamports, Account};
+use crate::event::LiquityEvent;
+use spl_token::state::Mint;
+use crate::units::{CollateralAmount, GovAmount, Lamports, Ratio, StableAmount};
+use crate::collateral::{find_authority_address, find_collateral_type_address, Collateral, CollateralAccounts};
+use crate::state::{AccountType, CollateralType, Trove, Deposit, Escrow, Protocol, ProtocolConfig};
+use crate::helpers::{
+    get_accrued_borrow_index, get_flash_mint_fee, get_depositors_fee, get_team_fee, get_trove_debt_amount, get_trove_debt_with_interest,
+    get_trove_sent_amount,
+};
+use crate::params::{
+    AUTHORITY_SEED, COLLATERAL_TYPE_SEED, FLASH_MINT_RECEIVER_TAG, GENS_TOKEN_ADDRESS, INITIAL_BORROW_INDEX, SYSTEM_ACCOUNT_ADDRESS,
+};
+use crate::params::{
+    PAUSE_ADJUST_TROVE, PAUSE_BORROW, PAUSE_DEPOSIT, PAUSE_FLASH_MINT, PAUSE_LIQUIDATE,
+    PAUSE_RECEIVE_TROVE, PAUSE_REDEEM, PAUSE_REWARDS,
 };
-use crate::{error::LiquityError, helpers, instruction::LiquityInstruction};
-use crate::state::{Trove, Deposit};
-use std::ops::{Sub, Add};
-use crate::helpers::{get_depositors_fee, get_team_fee, get_trove_debt_amount};
-use crate::params::SYSTEM_ACCOUNT_ADDRESS;
 
 pub struct Processor;
 
@@ -20,6 +39,13 @@ impl Processor {
     pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
         let instruction = LiquityInstruction::unpack(instruction_data)?;
 
+        // Every processor checks the signer against its role, this only catches a missing signature early
+        if instruction.authorization() != Authorization::Permissionless
+            && !accounts.first().is_some_and(|account| account.is_signer)
+        {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
         match instruction {
             LiquityInstruction::Borrow { borrow_amount, lamports } => {
                 msg!("Instruction Borrow");
@@ -65,15 +91,755 @@ impl Processor {
                 msg!("Instruction Add Deposit Reward");
                 Self::process_add_deposit_reward(accounts, coin, governance, token, program_id)
             }
+            LiquityInstruction::BorrowWithStakePoolToken { borrow_amount, pool_tokens } => {
+                msg!("Instruction Borrow With Stake Pool Token");
+                Self::process_borrow_with_stake_pool_token(accounts, borrow_amount, pool_tokens, program_id)
+            }
+            LiquityInstruction::InitProtocol { config } => {
+                msg!("Instruction Init Protocol");
+                Self::process_init_protocol(accounts, config, program_id)
+            }
+            LiquityInstruction::QueueConfigChange { config } => {
+                msg!("Instruction Queue Config Change");
+                Self::process_queue_config_change(accounts, config, program_id)
+            }
+            LiquityInstruction::FlashMint { amount } => {
+                msg!("Instruction Flash Mint");
+                Self::process_flash_mint(accounts, amount, program_id)
+            }
+            LiquityInstruction::SetPause { paused } => {
+                msg!("Instruction Set Pause");
+                Self::process_set_pause(accounts, paused, program_id)
+            }
+            LiquityInstruction::InitCollateralType { collateral, debt_ceiling } => {
+                msg!("Instruction Init Collateral Type");
+                Self::process_init_collateral_type(accounts, collateral, debt_ceiling, program_id)
+            }
+            LiquityInstruction::UpdateCollateralType { debt_ceiling } => {
+                msg!("Instruction Update Collateral Type");
+                Self::process_update_collateral_type(accounts, debt_ceiling, program_id)
+            }
+            LiquityInstruction::ExecuteConfigChange {} => {
+                msg!("Instruction Execute Config Change");
+                Self::process_execute_config_change(accounts, program_id)
+            }
+            LiquityInstruction::CancelConfigChange {} => {
+                msg!("Instruction Cancel Config Change");
+                Self::process_cancel_config_change(accounts, program_id)
+            }
+            LiquityInstruction::MigrateAccount {} => {
+                msg!("Instruction Migrate Account");
+                Self::process_migrate_account(accounts, program_id)
+            }
+        }
+    }
+
+    fn process_migrate_account(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
+        let accounts_info_iter = &mut accounts.iter();
+        let payer = next_account_info(accounts_info_iter)?;
+
+        if !payer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let legacy_account = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(accounts_info_iter)?)?;
+        let system_program = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[payer, legacy_account, protocol_account])?;
+
+        if *system_program.key != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        // The legacy layouts are read raw here, as the account loader only accepts current ones
+        if legacy_account.owner != program_id {
+            return Err(LiquityError::InvalidAccountOwner.into());
+        }
+
+        let old_len = legacy_account.data_len();
+        let account_type = AccountType::of(&legacy_account.data.borrow());
+        match account_type {
+            Some(AccountType::Trove) if old_len == Trove::LEGACY_LEN => {
+                // Any signer may migrate a trove, else its owner could keep it out of reach of liquidations
+                let mut trove = Trove::unpack(&legacy_account.data.borrow())?;
+
+                let collateral_type_account = next_account_info(accounts_info_iter)?;
+                let clock_account = next_account_info(accounts_info_iter)?;
+                check_distinct(&[payer, legacy_account, protocol_account, collateral_type_account])?;
+                let mut protocol = Self::accrue_interest(program_id, protocol_account, clock_account)?;
+                let mut collateral_type = Self::load_collateral_type(program_id, protocol_account.key, collateral_type_account, &trove.collateral)?;
+
+                // Legacy troves recorded whole stablecoins
+                let decimals = protocol.stablecoin_decimals;
+                trove.borrow_amount = StableAmount::from_whole(trove.borrow_amount.0, decimals)?;
+                trove.team_fee = StableAmount::from_whole(trove.team_fee.0, decimals)?;
+                trove.depositor_fee = StableAmount::from_whole(trove.depositor_fee.0, decimals)?;
+                trove.amount_to_close = StableAmount::from_whole(trove.amount_to_close.0, decimals)?;
+
+                // Interest accrues on the migrated debt from now on
+                trove.borrow_index = protocol.borrow_index;
+
+                // The debt already exists, like interest it does not count against the ceilings
+                if !trove.is_liquidated {
+                    protocol.total_debt = protocol.total_debt.checked_add(trove.amount_to_close)?;
+                    collateral_type.total_debt = collateral_type.total_debt.checked_add(trove.amount_to_close)?;
+                }
+                protocol.save()?;
+                collateral_type.save()?;
+
+                // Legacy borrows never checked the lamports against the declared collateral, the
+                // trove keeps what it holds on top of the rent, the payer only tops up the rent
+                let held = CollateralAmount(legacy_account.lamports().saturating_sub(rent.minimum_balance(old_len)));
+                if trove.collateral_amount > held {
+                    msg!("Collateral capped to the lamports held: {}", held);
+                    trove.collateral_amount = held;
+                }
+                Self::migrate_in_place(legacy_account, payer, system_program, rent, &trove)?;
+
+                if !trove.is_liquidated {
+                    LiquityEvent::TroveMigrated {
+                        trove: *legacy_account.key,
+                        owner: trove.owner,
+                        collateral: trove.collateral,
+                        collateral_amount: trove.collateral_amount,
+                        debt: trove.amount_to_close,
+                    }.emit();
+                }
+                Ok(())
+            }
+            Some(AccountType::Deposit) if old_len == Deposit::LEGACY_LEN => {
+                let mut deposit = Deposit::unpack(&legacy_account.data.borrow())?;
+                if deposit.owner != *payer.key {
+                    return Err(LiquityError::OnlyForDepositOwner.into());
+                }
+
+                // Legacy deposits recorded whole stablecoins
+                let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
+                deposit.token_amount = StableAmount::from_whole(deposit.token_amount.0, protocol.stablecoin_decimals)?;
+                Self::migrate_in_place(legacy_account, payer, system_program, rent, &deposit)
+            }
+            Some(AccountType::Escrow) if old_len == Escrow::LEGACY_LEN => {
+                let escrow = Escrow::unpack(&legacy_account.data.borrow())?;
+                if escrow.initializer_pubkey != *payer.key {
+                    return Err(LiquityError::InvalidAccountInput.into());
+                }
+                Self::migrate_in_place(legacy_account, payer, system_program, rent, &escrow)
+            }
+            Some(AccountType::Uninitialized) | None => Err(ProgramError::InvalidAccountData),
+            Some(AccountType::Closed) => Err(LiquityError::AccountClosed.into()),
+            Some(_) => Err(LiquityError::AccountAlreadyMigrated.into()),
+        }
+    }
+
+    /// Grows a legacy account to the current layout of `state` and stores it, the payer
+    /// topping up the rent of the larger account and nothing more
+    fn migrate_in_place<'a, T: Pack>(
+        account: &AccountInfo<'a>,
+        payer: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        rent: &Rent,
+        state: &T,
+    ) -> ProgramResult
+    {
+        let legacy_rent = rent.minimum_balance(account.data_len()).min(account.lamports());
+        let top_up = rent.minimum_balance(T::LEN).saturating_sub(legacy_rent);
+        if top_up > 0 {
+            invoke(
+                &system_instruction::transfer(payer.key, account.key, top_up),
+                &[payer.clone(), account.clone(), system_program.clone()],
+            )?;
+        }
+
+        account.realloc(T::LEN, false)?;
+        state.pack_into_slice(&mut account.data.borrow_mut());
+        Ok(())
+    }
+
+    fn process_init_protocol(
+        accounts: &[AccountInfo],
+        config: ProtocolConfig,
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
+        let accounts_info_iter = &mut accounts.iter();
+        let sys_acc = next_account_info(accounts_info_iter)?;
+
+        if !sys_acc.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *sys_acc.key != SYSTEM_ACCOUNT_ADDRESS {
+            return Err(LiquityError::OnlyForAdmin.into());
+        }
+
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(accounts_info_iter)?)?;
+        let clock = &Clock::from_account_info(next_account_info(accounts_info_iter)?)?;
+        let stablecoin_mint = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[sys_acc, protocol_account, stablecoin_mint])?;
+
+        let mut protocol = Account::<Protocol>::load_uninitialized(protocol_account, program_id, rent)?;
+
+        if *stablecoin_mint.owner != spl_token::id() {
+            return Err(LiquityError::InvalidTokenMint.into());
+        }
+        let mint = Mint::unpack(&stablecoin_mint.data.borrow())?;
+
+        // Borrows and flash mints are both minted by the program authority
+        if mint.mint_authority != COption::Some(find_authority_address(program_id).0) {
+            return Err(LiquityError::InvalidMarketAuthority.into());
+        }
+
+        Self::validate_config(&config)?;
+
+        protocol.is_initialized = true;
+        protocol.stablecoin_mint = *stablecoin_mint.key;
+        protocol.stablecoin_decimals = mint.decimals;
+        protocol.config = config;
+        protocol.borrow_index = INITIAL_BORROW_INDEX;
+        protocol.last_update_timestamp = clock.unix_timestamp;
+
+        protocol.save()?;
+
+        Ok(())
+    }
+
+    fn process_queue_config_change(
+        accounts: &[AccountInfo],
+        config: ProtocolConfig,
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin = next_account_info(accounts_info_iter)?;
+
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let clock = &Clock::from_account_info(next_account_info(accounts_info_iter)?)?;
+
+        check_distinct(&[admin, protocol_account])?;
+
+        let mut protocol = Account::<Protocol>::load_mut(protocol_account, program_id)?;
+
+        if *admin.key != protocol.config.admin {
+            return Err(LiquityError::OnlyForAdmin.into());
+        }
+        if protocol.pending_eta != 0 {
+            return Err(LiquityError::ConfigChangeAlreadyQueued.into());
+        }
+
+        Self::validate_config(&config)?;
+
+        // Zero marks an empty queue, so the eta is never allowed to be zero
+        protocol.pending_eta = clock.unix_timestamp
+            .checked_add(protocol.config.timelock_delay)
+            .ok_or(LiquityError::MathOverflow)?
+            .max(1);
+        protocol.pending_config = config;
+        msg!("Config change executable from {}", protocol.pending_eta);
+
+        protocol.save()?;
+
+        Ok(())
+    }
+
+    fn process_execute_config_change(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
+        let accounts_info_iter = &mut accounts.iter();
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        // The only other account is the clock sysvar, checked by its address, so nothing can alias
+        let clock_account = next_account_info(accounts_info_iter)?;
+
+        let mut protocol = Self::accrue_interest(program_id, protocol_account, clock_account)?;
+
+        if protocol.pending_eta == 0 {
+            return Err(LiquityError::NoConfigChangeQueued.into());
+        }
+        if protocol.last_update_timestamp < protocol.pending_eta {
+            return Err(LiquityError::TimelockNotExpired.into());
+        }
+
+        // The replaced config stays behind in the pending slot, which is now empty
+        std::mem::swap(&mut protocol.state.config, &mut protocol.state.pending_config);
+        protocol.pending_eta = 0;
+
+        protocol.save()?;
+
+        Ok(())
+    }
+
+    fn process_cancel_config_change(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
+        let accounts_info_iter = &mut accounts.iter();
+        let guardian = next_account_info(accounts_info_iter)?;
+
+        if !guardian.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
         }
+
+        let protocol_account = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[guardian, protocol_account])?;
+
+        let mut protocol = Account::<Protocol>::load_mut(protocol_account, program_id)?;
+
+        if *guardian.key != protocol.config.guardian && *guardian.key != protocol.config.admin {
+            return Err(LiquityError::OnlyForGuardian.into());
+        }
+        if protocol.pending_eta == 0 {
+            return Err(LiquityError::NoConfigChangeQueued.into());
+        }
+
+        protocol.pending_eta = 0;
+
+        protocol.save()?;
+
+        Ok(())
+    }
+
+    fn validate_config(
+        config: &ProtocolConfig,
+    ) -> ProgramResult
+    {
+        if config.min_collateral_ratio < Ratio::ONE {
+            msg!("Minimum collateral ratio must be at least 100%");
+            return Err(LiquityError::InvalidConfig.into());
+        }
+        if math::add(config.deposit_fee.0, config.team_fee.0)? >= Ratio::ONE.0 {
+            msg!("Trove fees must be below 100%");
+            return Err(LiquityError::InvalidConfig.into());
+        }
+        if config.flash_mint_fee > Ratio::ONE {
+            msg!("Flash mint fee must not exceed 100%");
+            return Err(LiquityError::InvalidConfig.into());
+        }
+        if config.timelock_delay < 0 {
+            msg!("Timelock delay must not be negative");
+            return Err(LiquityError::InvalidConfig.into());
+        }
+
+        Ok(())
+    }
+
+    fn process_flash_mint(
+        accounts: &[AccountInfo],
+        amount: StableAmount,
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
+        if amount == StableAmount::ZERO {
+            return Err(LiquityError::InvalidAmount.into());
+        }
+
+        let accounts_info_iter = &mut accounts.iter();
+        let stablecoin_mint = next_account_info(accounts_info_iter)?;
+        let receiver_token = next_account_info(accounts_info_iter)?;
+        let fee_receiver_token = next_account_info(accounts_info_iter)?;
+        let authority = next_account_info(accounts_info_iter)?;
+        let token_program = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let receiver_program = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[
+            stablecoin_mint,
+            receiver_token,
+            fee_receiver_token,
+            authority,
+            token_program,
+            protocol_account,
+            receiver_program,
+        ])?;
+
+        let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
+        Self::check_not_paused(&protocol, PAUSE_FLASH_MINT)?;
+
+        Self::check_stablecoin_mint(&protocol, stablecoin_mint)?;
+        token::check_token_program(token_program)?;
+
+        let (authority_id, authority_bump_seed) = find_authority_address(program_id);
+        if *authority.key != authority_id {
+            return Err(LiquityError::InvalidMarketAuthority.into());
+        }
+
+        if receiver_program.key == program_id || !receiver_program.executable {
+            return Err(LiquityError::InvalidFlashLoanReceiverProgram.into());
+        }
+
+        let fee_receiver = spl_token::state::Account::unpack(&fee_receiver_token.data.borrow())?;
+        if fee_receiver.mint != protocol.stablecoin_mint {
+            return Err(LiquityError::InvalidTokenMint.into());
+        }
+        if fee_receiver.owner != SYSTEM_ACCOUNT_ADDRESS {
+            return Err(LiquityError::InvalidTokenOwner.into());
+        }
+
+        let fee = get_flash_mint_fee(amount, protocol.config.flash_mint_fee)?;
+        let supply_before = spl_token::state::Mint::unpack(&stablecoin_mint.data.borrow())?.supply;
+        let fee_receiver_before = fee_receiver.amount;
+
+        let mint_to_ix = spl_token::instruction::mint_to(
+            token_program.key,
+            stablecoin_mint.key,
+            receiver_token.key,
+            authority.key,
+            &[authority.key],
+            amount.0,
+        )?;
+
+        msg!("Calling the token program to mint the flash amount...");
+        invoke_signed(
+            &mint_to_ix,
+            &[
+                stablecoin_mint.clone(),
+                receiver_token.clone(),
+                authority.clone(),
+                token_program.clone(),
+            ],
+            &[&[AUTHORITY_SEED, &[authority_bump_seed]]],
+        )?;
+
+        let mut data = Vec::with_capacity(17);
+        data.push(FLASH_MINT_RECEIVER_TAG);
+        data.extend_from_slice(&amount.to_le_bytes());
+        data.extend_from_slice(&fee.to_le_bytes());
+
+        let mut receiver_accounts = vec![
+            AccountMeta::new(*receiver_token.key, false),
+            AccountMeta::new(*stablecoin_mint.key, false),
+            AccountMeta::new_readonly(*token_program.key, false),
+        ];
+        let mut receiver_account_infos = vec![
+            receiver_token.clone(),
+            stablecoin_mint.clone(),
+            token_program.clone(),
+        ];
+        for account_info in accounts_info_iter {
+            receiver_accounts.push(AccountMeta {
+                pubkey: *account_info.key,
+                is_signer: account_info.is_signer,
+                is_writable: account_info.is_writable,
+            });
+            receiver_account_infos.push(account_info.clone());
+        }
+        receiver_account_infos.push(receiver_program.clone());
+
+        msg!("Calling the flash mint receiver program...");
+        invoke(
+            &Instruction {
+                program_id: *receiver_program.key,
+                accounts: receiver_accounts,
+                data,
+            },
+            &receiver_account_infos,
+        )?;
+
+        let supply_after = spl_token::state::Mint::unpack(&stablecoin_mint.data.borrow())?.supply;
+        let fee_receiver_after = spl_token::state::Account::unpack(&fee_receiver_token.data.borrow())?.amount;
+
+        let burned = (supply_before as u128 + amount.0 as u128).saturating_sub(supply_after as u128);
+        let returned = (fee_receiver_after as u128).saturating_sub(fee_receiver_before as u128);
+        if burned + returned < amount.0 as u128 + fee.0 as u128 {
+            return Err(LiquityError::NotEnoughLiquidityAfterFlashLoan.into());
+        }
+
+        Ok(())
+    }
+
+    /// Brings the protocol borrow index up to the clock and stores it
+    fn accrue_interest<'a, 'b>(
+        program_id: &Pubkey,
+        protocol_account: &'a AccountInfo<'b>,
+        clock_account: &AccountInfo,
+    ) -> Result<Account<'a, 'b, Protocol>, ProgramError>
+    {
+        let clock = &Clock::from_account_info(clock_account)?;
+
+        let mut protocol = Account::<Protocol>::load_mut(protocol_account, program_id)?;
+
+        protocol.borrow_index = get_accrued_borrow_index(
+            protocol.borrow_index,
+            protocol.config.interest_rate,
+            math::elapsed(protocol.last_update_timestamp, clock.unix_timestamp)?,
+        )?;
+        protocol.last_update_timestamp = clock.unix_timestamp;
+
+        protocol.save()?;
+
+        Ok(protocol)
+    }
+
+    /// Checks `mint` is the stablecoin mint, still with the decimals the protocol was initialized with
+    fn check_stablecoin_mint(
+        protocol: &Protocol,
+        mint: &AccountInfo,
+    ) -> ProgramResult
+    {
+        if *mint.key != protocol.stablecoin_mint || *mint.owner != spl_token::id() {
+            return Err(LiquityError::InvalidTokenMint.into());
+        }
+        if Mint::unpack(&mint.data.borrow())?.decimals != protocol.stablecoin_decimals {
+            return Err(LiquityError::InvalidTokenMint.into());
+        }
+
+        Ok(())
+    }
+
+    fn check_not_paused(
+        protocol: &Protocol,
+        operation: u64,
+    ) -> ProgramResult
+    {
+        if protocol.is_paused(operation) {
+            return Err(LiquityError::OperationPaused.into());
+        }
+
+        Ok(())
+    }
+
+    fn process_set_pause(
+        accounts: &[AccountInfo],
+        paused: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
+        let accounts_info_iter = &mut accounts.iter();
+        let pauser = next_account_info(accounts_info_iter)?;
+
+        if !pauser.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let protocol_account = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[pauser, protocol_account])?;
+
+        let mut protocol = Account::<Protocol>::load_mut(protocol_account, program_id)?;
+
+        if *pauser.key != protocol.config.pauser && *pauser.key != protocol.config.admin {
+            return Err(LiquityError::OnlyForPauser.into());
+        }
+
+        protocol.paused = paused;
+
+        protocol.save()?;
+
+        Ok(())
+    }
+
+    fn process_init_collateral_type(
+        accounts: &[AccountInfo],
+        collateral: Pubkey,
+        debt_ceiling: StableAmount,
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin = next_account_info(accounts_info_iter)?;
+
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let collateral_type_account = next_account_info(accounts_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(accounts_info_iter)?)?;
+        let system_program = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[admin, protocol_account, collateral_type_account])?;
+
+        if *system_program.key != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
+
+        if *admin.key != protocol.config.admin {
+            return Err(LiquityError::OnlyForAdmin.into());
+        }
+
+        let (collateral_type_id, bump_seed) = find_collateral_type_address(program_id, protocol_account.key, &collateral);
+        if *collateral_type_account.key != collateral_type_id {
+            return Err(LiquityError::InvalidAccountInput.into());
+        }
+
+        // Funded, allocated and assigned one by one, so lamports sent to the address beforehand can't block it
+        if collateral_type_account.owner != program_id {
+            let top_up = rent.minimum_balance(CollateralType::LEN).saturating_sub(collateral_type_account.lamports());
+            if top_up > 0 {
+                invoke(
+                    &system_instruction::transfer(admin.key, collateral_type_account.key, top_up),
+                    &[admin.clone(), collateral_type_account.clone(), system_program.clone()],
+                )?;
+            }
+
+            let seeds: &[&[u8]] = &[COLLATERAL_TYPE_SEED, protocol_account.key.as_ref(), collateral.as_ref(), &[bump_seed]];
+            invoke_signed(
+                &system_instruction::allocate(collateral_type_account.key, CollateralType::LEN as u64),
+                &[collateral_type_account.clone(), system_program.clone()],
+                &[seeds],
+            )?;
+            invoke_signed(
+                &system_instruction::assign(collateral_type_account.key, program_id),
+                &[collateral_type_account.clone(), system_program.clone()],
+                &[seeds],
+            )?;
+        }
+
+        let mut collateral_type = Account::<CollateralType>::load_uninitialized(collateral_type_account, program_id, rent)?;
+
+        collateral_type.is_initialized = true;
+        collateral_type.collateral = collateral;
+        collateral_type.debt_ceiling = debt_ceiling;
+        collateral_type.total_debt = StableAmount::ZERO;
+
+        collateral_type.save()?;
+
+        Ok(())
+    }
+
+    fn process_update_collateral_type(
+        accounts: &[AccountInfo],
+        debt_ceiling: StableAmount,
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
+        let accounts_info_iter = &mut accounts.iter();
+        let admin = next_account_info(accounts_info_iter)?;
+
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let collateral_type_account = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[admin, protocol_account, collateral_type_account])?;
+
+        let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
+
+        if *admin.key != protocol.config.admin {
+            return Err(LiquityError::OnlyForAdmin.into());
+        }
+
+        let collateral = Account::<CollateralType>::load(collateral_type_account, program_id)?.collateral;
+        let mut collateral_type = Self::load_collateral_type(program_id, protocol_account.key, collateral_type_account, &collateral)?;
+
+        collateral_type.debt_ceiling = debt_ceiling;
+
+        collateral_type.save()?;
+
+        Ok(())
+    }
+
+    /// Loads the collateral type of `collateral`, only at its address derived from the protocol
+    fn load_collateral_type<'a, 'b>(
+        program_id: &Pubkey,
+        protocol: &Pubkey,
+        collateral_type_account: &'a AccountInfo<'b>,
+        collateral: &Pubkey,
+    ) -> Result<Account<'a, 'b, CollateralType>, ProgramError>
+    {
+        if *collateral_type_account.key != find_collateral_type_address(program_id, protocol, collateral).0 {
+            return Err(LiquityError::InvalidAccountInput.into());
+        }
+
+        let collateral_type = Account::<CollateralType>::load_mut(collateral_type_account, program_id)?;
+
+        if collateral_type.collateral != *collateral {
+            return Err(LiquityError::InvalidAccountInput.into());
+        }
+
+        Ok(collateral_type)
+    }
+
+    /// Records the debt of a new trove, refusing to exceed the debt ceilings
+    fn add_debt(
+        protocol: &mut Protocol,
+        collateral_type: &mut CollateralType,
+        amount: StableAmount,
+    ) -> ProgramResult
+    {
+        protocol.total_debt = protocol.total_debt.checked_add(amount)?;
+        collateral_type.total_debt = collateral_type.total_debt.checked_add(amount)?;
+
+        if protocol.total_debt > protocol.config.debt_ceiling {
+            return Err(LiquityError::DebtCeilingExceeded.into());
+        }
+        if collateral_type.total_debt > collateral_type.debt_ceiling {
+            return Err(LiquityError::DebtCeilingExceeded.into());
+        }
+
+        Ok(())
+    }
+
+    /// Removes the debt of a closed or liquidated trove
+    fn remove_debt(
+        protocol: &mut Protocol,
+        collateral_type: &mut CollateralType,
+        amount: StableAmount,
+    ) -> ProgramResult
+    {
+        protocol.total_debt = protocol.total_debt.checked_sub(amount)?;
+        collateral_type.total_debt = collateral_type.total_debt.checked_sub(amount)?;
+        Ok(())
+    }
+
+    /// Adds the interest accrued since the trove snapshot to its debt and to the total debt
+    fn refresh_trove_debt(
+        trove: &mut Trove,
+        protocol: &mut Protocol,
+        collateral_type: &mut CollateralType,
+    ) -> ProgramResult
+    {
+        let debt = get_trove_debt_with_interest(trove.amount_to_close, trove.borrow_index, protocol.borrow_index)?;
+        let interest = debt.checked_sub(trove.amount_to_close)?;
+
+        trove.borrow_amount = trove.borrow_amount.checked_add(interest)?;
+        trove.amount_to_close = debt;
+        trove.borrow_index = protocol.borrow_index;
+
+        // Interest never counts against the ceilings, the debt already exists
+        protocol.total_debt = protocol.total_debt.checked_add(interest)?;
+        collateral_type.total_debt = collateral_type.total_debt.checked_add(interest)?;
+
+        Ok(())
+    }
+
+    /// Pays `amount` of native collateral out of the trove account, which stays rent exempt
+    fn withdraw_lamports<'a>(
+        trove_account: &AccountInfo<'a>,
+        recipient: &AccountInfo<'a>,
+        amount: CollateralAmount,
+    ) -> ProgramResult
+    {
+        transfer_lamports(trove_account, recipient, amount.0)?;
+        if !Rent::get()?.is_exempt(trove_account.lamports(), trove_account.data_len()) {
+            return Err(LiquityError::NotRentExempt.into());
+        }
+        Ok(())
     }
 
     fn process_add_deposit_reward(
         accounts: &[AccountInfo],
-        coin: u64,
-        governance: u64,
-        token: u64,
-        _program_id: &Pubkey,
+        coin: Lamports,
+        governance: GovAmount,
+        token: StableAmount,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
@@ -88,21 +854,29 @@ impl Processor {
         }
 
         let deposit_account = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[depositor, deposit_account, protocol_account])?;
+
+        let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
+        Self::check_not_paused(&protocol, PAUSE_REWARDS)?;
 
-        let mut deposit = Deposit::unpack_unchecked(&deposit_account.data.borrow())?;
+        let mut deposit = Account::<Deposit>::load_mut(deposit_account, program_id)?;
 
-        deposit.reward_coin_amount = deposit.reward_coin_amount.add(coin);
-        deposit.reward_governance_token_amount = deposit.reward_governance_token_amount.add(governance);
-        deposit.reward_token_amount = deposit.reward_token_amount.add(token);
+        deposit.reward_coin_amount = deposit.reward_coin_amount.checked_add(coin)?;
+        deposit.reward_governance_token_amount = deposit.reward_governance_token_amount.checked_add(governance)?;
+        deposit.reward_token_amount = deposit.reward_token_amount.checked_add(token)?;
 
-        Deposit::pack(deposit, &mut deposit_account.data.borrow_mut())?;
+        deposit.save()?;
+
+        LiquityEvent::RewardAdded { deposit: *deposit_account.key, coin, governance, token }.emit();
 
         Ok(())
     }
 
     fn process_receive_trove(
         accounts: &[AccountInfo],
-        _program_id: &Pubkey,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
@@ -117,22 +891,28 @@ impl Processor {
         }
 
         let trove_account = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[sys_acc, trove_account, protocol_account])?;
+
+        let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
+        Self::check_not_paused(&protocol, PAUSE_RECEIVE_TROVE)?;
 
-        let mut trove = Trove::unpack_unchecked(&trove_account.data.borrow())?;
+        let mut trove = Account::<Trove>::load_mut(trove_account, program_id)?;
         if trove.is_liquidated {
             return Err(LiquityError::TroveAlreadyLiquidated.into());
         }
 
         trove.is_received = true;
 
-        Trove::pack(trove, &mut trove_account.data.borrow_mut())?;
+        trove.save()?;
 
         Ok(())
     }
 
     fn process_claim_deposit_reward(
         accounts: &[AccountInfo],
-        _program_id: &Pubkey,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
@@ -147,55 +927,88 @@ impl Processor {
         }
 
         let deposit_account = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
 
-        let mut deposit = Deposit::unpack_unchecked(&deposit_account.data.borrow())?;
+        check_distinct(&[depositor, deposit_account, protocol_account])?;
 
-        deposit.reward_governance_token_amount = 0;
-        deposit.reward_token_amount = 0;
-        deposit.reward_coin_amount = 0;
+        let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
+        Self::check_not_paused(&protocol, PAUSE_REWARDS)?;
 
-        Deposit::pack(deposit, &mut deposit_account.data.borrow_mut())?;
+        let mut deposit = Account::<Deposit>::load_mut(deposit_account, program_id)?;
+
+        let claimed = LiquityEvent::RewardClaimed {
+            deposit: *deposit_account.key,
+            owner: deposit.owner,
+            coin: deposit.reward_coin_amount,
+            governance: deposit.reward_governance_token_amount,
+            token: deposit.reward_token_amount,
+        };
+
+        deposit.reward_governance_token_amount = GovAmount::ZERO;
+        deposit.reward_token_amount = StableAmount::ZERO;
+        deposit.reward_coin_amount = Lamports::ZERO;
+
+        deposit.save()?;
+
+        claimed.emit();
 
         Ok(())
     }
 
     fn process_withdraw_deposit(
         accounts: &[AccountInfo],
-        amount: u64,
-        _program_id: &Pubkey,
+        amount: StableAmount,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
 
-        let sys_acc = next_account_info(accounts_info_iter)?;
+        let depositor = next_account_info(accounts_info_iter)?;
 
-        if !sys_acc.is_signer {
+        if !depositor.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
 
-        if *sys_acc.key != SYSTEM_ACCOUNT_ADDRESS {
-            return Err(ProgramError::MissingRequiredSignature);
+        let deposit_account = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[depositor, deposit_account, protocol_account])?;
+
+        let mut deposit = Account::<Deposit>::load_mut(deposit_account, program_id)?;
+        if *depositor.key != deposit.owner {
+            return Err(LiquityError::OnlyForDepositOwner.into());
         }
 
-        let deposit_account = next_account_info(accounts_info_iter)?;
+        let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
 
-        let mut deposit = Deposit::unpack_unchecked(&deposit_account.data.borrow())?;
+        let stablecoin_accounts = StablecoinAccounts::next(accounts_info_iter, program_id, depositor.key)?;
+        check_distinct(&[deposit_account, protocol_account, stablecoin_accounts.mint, stablecoin_accounts.user_token])?;
+        Self::check_stablecoin_mint(&protocol, stablecoin_accounts.mint)?;
 
         if amount > deposit.token_amount {
             return Err(LiquityError::InsufficientLiquidity.into());
         }
 
-        deposit.token_amount = deposit.token_amount.sub(amount);
+        deposit.token_amount = deposit.token_amount.checked_sub(amount)?;
 
-        Deposit::pack(deposit, &mut deposit_account.data.borrow_mut())?;
+        // The deposited stablecoin was burned, it is minted back rather than paid from a vault
+        stablecoin_accounts.mint(amount)?;
+
+        deposit.save()?;
+
+        LiquityEvent::DepositChanged {
+            deposit: *deposit_account.key,
+            owner: deposit.owner,
+            token_amount: deposit.token_amount,
+        }.emit();
 
         Ok(())
     }
 
     fn process_add_deposit(
         accounts: &[AccountInfo],
-        amount: u64,
-        _program_id: &Pubkey,
+        amount: StableAmount,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
@@ -208,26 +1021,41 @@ impl Processor {
         let deposit_account = next_account_info(accounts_info_iter)?;
 
         let rent = &Rent::from_account_info(next_account_info(accounts_info_iter)?)?;
-
-        if !rent.is_exempt(deposit_account.lamports(), deposit_account.data_len()) {
-            return Err(LiquityError::NotRentExempt.into());
-        }
-
-        let mut deposit = Deposit::unpack_unchecked(&deposit_account.data.borrow())?;
-
         let token_program = next_account_info(accounts_info_iter)?;
         let temp_pda_token = next_account_info(accounts_info_iter)?;
         let temp_governance_token = next_account_info(accounts_info_iter)?;
         let token = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[
+            depositor,
+            deposit_account,
+            token_program,
+            temp_pda_token,
+            temp_governance_token,
+            token,
+            protocol_account,
+        ])?;
+
+        let mut deposit = Account::<Deposit>::load_or_uninitialized(deposit_account, program_id, rent)?;
+        let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
+        Self::check_not_paused(&protocol, PAUSE_DEPOSIT)?;
+        token::check_token_program(token_program)?;
+        Self::check_stablecoin_mint(&protocol, token)?;
+        token::check_token_account(temp_pda_token, token.key, depositor.key)?;
+        token::check_token_account(temp_governance_token, &GENS_TOKEN_ADDRESS, depositor.key)?;
 
         if deposit.is_initialized {
-            deposit.token_amount = deposit.token_amount.add(amount);
+            if *depositor.key != deposit.owner {
+                return Err(LiquityError::OnlyForDepositOwner.into());
+            }
+            deposit.token_amount = deposit.token_amount.checked_add(amount)?;
         } else {
             deposit.is_initialized = true;
             deposit.token_amount = amount;
-            deposit.reward_token_amount = 0;
-            deposit.reward_governance_token_amount = 0;
-            deposit.reward_coin_amount = 0;
+            deposit.reward_token_amount = StableAmount::ZERO;
+            deposit.reward_governance_token_amount = GovAmount::ZERO;
+            deposit.reward_coin_amount = Lamports::ZERO;
             deposit.bank = *temp_pda_token.key;
             deposit.governance_bank = *temp_governance_token.key;
             deposit.owner = *depositor.key;
@@ -238,8 +1066,8 @@ impl Processor {
             temp_pda_token.key,
             token.key,
             depositor.key,
-            &[&depositor.key],
-            amount * 1000000000,
+            &[depositor.key],
+            amount.0,
         )?;
 
         msg!("Calling the token program to transfer tokens to the escrow's initializer...");
@@ -253,15 +1081,21 @@ impl Processor {
             ],
         )?;
 
-        Deposit::pack(deposit, &mut deposit_account.data.borrow_mut())?;
+        deposit.save()?;
+
+        LiquityEvent::DepositChanged {
+            deposit: *deposit_account.key,
+            owner: deposit.owner,
+            token_amount: deposit.token_amount,
+        }.emit();
 
         Ok(())
     }
 
     fn process_add_coin(
         accounts: &[AccountInfo],
-        amount: u64,
-        _program_id: &Pubkey,
+        amount: CollateralAmount,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
@@ -272,12 +1106,11 @@ impl Processor {
         }
 
         let trove_account = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
 
-        let mut trove = Trove::unpack_unchecked(&trove_account.data.borrow())?;
+        check_distinct(&[borrower, trove_account, protocol_account])?;
 
-        if !trove.is_initialized() {
-            return Err(LiquityError::TroveIsNotInitialized.into());
-        }
+        let mut trove = Account::<Trove>::load_mut(trove_account, program_id)?;
         if trove.is_liquidated {
             return Err(LiquityError::TroveAlreadyLiquidated.into());
         }
@@ -285,23 +1118,52 @@ impl Processor {
             return Err(LiquityError::OnlyForTroveOwner.into());
         }
 
-        let temp_lamport_account = next_account_info(accounts_info_iter)?;
+        let protocol = Account::<Protocol>::load(protocol_account, program_id)?;
+        Self::check_not_paused(&protocol, PAUSE_ADJUST_TROVE)?;
 
-        if temp_lamport_account.lamports() != amount {
-            return Err(LiquityError::ExpectedAmountMismatch.into());
+        if trove.is_native_collateral() {
+            let temp_lamport_account = next_account_info(accounts_info_iter)?;
+            check_distinct(&[borrower, trove_account, protocol_account, temp_lamport_account])?;
+
+            // Only an empty account of the program holds nothing but the lamports to add
+            if temp_lamport_account.owner != program_id {
+                return Err(LiquityError::InvalidAccountOwner.into());
+            }
+            if !temp_lamport_account.data_is_empty() {
+                return Err(LiquityError::InvalidAccountType.into());
+            }
+            if temp_lamport_account.lamports() != amount.0 {
+                return Err(LiquityError::ExpectedAmountMismatch.into());
+            }
+            close_account(temp_lamport_account, trove_account)?;
+        } else {
+            let collateral_accounts = CollateralAccounts::next_for_trove(accounts_info_iter, program_id, &trove)?;
+            collateral_accounts.deposit(borrower, amount)?;
         }
 
-        trove.lamports_amount = trove.lamports_amount.add(amount);
+        trove.collateral_amount = trove.collateral_amount.checked_add(amount)?;
 
-        Trove::pack(trove, &mut trove_account.data.borrow_mut())?;
+        trove.save()?;
+
+        // The trove is left unrefreshed, the event still reports its debt with interest up to now
+        let borrow_index = get_accrued_borrow_index(
+            protocol.borrow_index,
+            protocol.config.interest_rate,
+            math::elapsed(protocol.last_update_timestamp, Clock::get()?.unix_timestamp)?,
+        )?;
+        LiquityEvent::TroveAdjusted {
+            trove: *trove_account.key,
+            collateral_amount: trove.collateral_amount,
+            debt: get_trove_debt_with_interest(trove.amount_to_close, trove.borrow_index, borrow_index)?,
+        }.emit();
 
         Ok(())
     }
 
     fn process_withdraw_coin(
         accounts: &[AccountInfo],
-        amount: u64,
-        _program_id: &Pubkey,
+        amount: CollateralAmount,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
@@ -312,12 +1174,13 @@ impl Processor {
         }
 
         let trove_account = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let clock_account = next_account_info(accounts_info_iter)?;
+        let collateral_type_account = next_account_info(accounts_info_iter)?;
 
-        let mut trove = Trove::unpack_unchecked(&trove_account.data.borrow())?;
+        check_distinct(&[borrower, trove_account, protocol_account, collateral_type_account])?;
 
-        if !trove.is_initialized() {
-            return Err(LiquityError::TroveIsNotInitialized.into());
-        }
+        let mut trove = Account::<Trove>::load_mut(trove_account, program_id)?;
         if trove.is_liquidated {
             return Err(LiquityError::TroveAlreadyLiquidated.into());
         }
@@ -325,20 +1188,46 @@ impl Processor {
             return Err(LiquityError::OnlyForTroveOwner.into());
         }
 
-        trove.lamports_amount = trove.lamports_amount.sub(amount);
+        let mut protocol = Self::accrue_interest(program_id, protocol_account, clock_account)?;
+        Self::check_not_paused(&protocol, PAUSE_ADJUST_TROVE)?;
+
+        let mut collateral_type = Self::load_collateral_type(program_id, protocol_account.key, collateral_type_account, &trove.collateral)?;
+
+        Self::refresh_trove_debt(&mut trove, &mut protocol, &mut collateral_type)?;
 
-        if !helpers::check_min_collateral_include_gas_fee(trove.borrow_amount, trove.lamports_amount) {
+        trove.collateral_amount = trove.collateral_amount.checked_sub(amount)?;
+
+        let collateral = if trove.is_native_collateral() {
+            Self::withdraw_lamports(trove_account, borrower, amount)?;
+            Collateral::Sol
+        } else {
+            let clock = &Clock::from_account_info(clock_account)?;
+            let collateral_accounts = CollateralAccounts::next_for_trove(accounts_info_iter, program_id, &trove)?;
+            collateral_accounts.withdraw(amount)?;
+            collateral_accounts.pool.collateral(clock)?
+        };
+
+        if !helpers::check_min_collateral_ratio(trove.borrow_amount, &collateral, trove.collateral_amount, &protocol.config, protocol.stablecoin_decimals)? {
             return Err(LiquityError::InvalidCollateral.into());
         }
 
-        Trove::pack(trove, &mut trove_account.data.borrow_mut())?;
+        trove.save()?;
+        protocol.save()?;
+        collateral_type.save()?;
+
+        // Refreshed above, the debt includes the interest
+        LiquityEvent::TroveAdjusted {
+            trove: *trove_account.key,
+            collateral_amount: trove.collateral_amount,
+            debt: trove.amount_to_close,
+        }.emit();
 
         Ok(())
     }
 
     fn process_liquidate_trove(
         accounts: &[AccountInfo],
-        _program_id: &Pubkey,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
@@ -350,13 +1239,20 @@ impl Processor {
 
         let trove_account = next_account_info(accounts_info_iter)?;
         let sys_account = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let clock_account = next_account_info(accounts_info_iter)?;
+        let collateral_type_account = next_account_info(accounts_info_iter)?;
+
+        // The liquidator may well be the system account itself, it is only recorded
+        check_distinct(&[liquidator, trove_account, protocol_account, collateral_type_account])?;
+        check_distinct(&[trove_account, sys_account, protocol_account, collateral_type_account])?;
 
         if *sys_account.key != SYSTEM_ACCOUNT_ADDRESS {
-            msg!("Invalid d");
-            return Err(ProgramError::MissingRequiredSignature);
+            msg!("Liquidated collateral is only paid to the system account");
+            return Err(LiquityError::InvalidAccountInput.into());
         }
 
-        let trove = Trove::unpack_unchecked(&trove_account.data.borrow())?;
+        let mut trove = Account::<Trove>::load_mut(trove_account, program_id)?;
         if trove.is_liquidated {
             return Err(LiquityError::TroveAlreadyLiquidated.into());
         }
@@ -365,20 +1261,55 @@ impl Processor {
             return Err(LiquityError::TroveIsNotReceived.into());
         }
 
-        msg!("Send lamports to the sys acc");
-        **sys_account.lamports.borrow_mut() = sys_account.lamports()
-            .checked_add(trove_account.lamports())
-            .ok_or(LiquityError::AmountOverflow)?;
+        let mut protocol = Self::accrue_interest(program_id, protocol_account, clock_account)?;
+        Self::check_not_paused(&protocol, PAUSE_LIQUIDATE)?;
 
-        **trove_account.lamports.borrow_mut() = 0;
-        *trove_account.data.borrow_mut() = &mut [];
+        let mut collateral_type = Self::load_collateral_type(program_id, protocol_account.key, collateral_type_account, &trove.collateral)?;
+
+        Self::refresh_trove_debt(&mut trove, &mut protocol, &mut collateral_type)?;
+
+        let collateral_accounts = if trove.is_native_collateral() {
+            None
+        } else {
+            Some(CollateralAccounts::next_for_trove(accounts_info_iter, program_id, &trove)?)
+        };
+        let collateral = match &collateral_accounts {
+            None => Collateral::Sol,
+            Some(collateral_accounts) => collateral_accounts.pool.collateral(&Clock::from_account_info(clock_account)?)?,
+        };
+
+        // Only troves below the minimum collateral ratio, their interest included, are liquidated
+        if helpers::check_min_collateral_ratio(trove.borrow_amount, &collateral, trove.collateral_amount, &protocol.config, protocol.stablecoin_decimals)? {
+            return Err(LiquityError::InvalidCollateral.into());
+        }
+
+        Self::remove_debt(&mut protocol, &mut collateral_type, trove.amount_to_close)?;
+        msg!("Liquidated debt: {}", trove.amount_to_close);
+
+        protocol.save()?;
+        collateral_type.save()?;
+
+        if let Some(collateral_accounts) = collateral_accounts {
+            token::check_token_account(collateral_accounts.user_token, &collateral_accounts.pool.pool_mint, &SYSTEM_ACCOUNT_ADDRESS)?;
+            collateral_accounts.withdraw(trove.collateral_amount)?;
+        }
+
+        LiquityEvent::TroveLiquidated {
+            trove: *trove_account.key,
+            liquidator: *liquidator.key,
+            debt: trove.amount_to_close,
+            collateral_amount: trove.collateral_amount,
+        }.emit();
+
+        msg!("Send lamports to the sys acc");
+        trove.close(sys_account)?;
 
         Ok(())
     }
 
     fn process_close_trove(
         accounts: &[AccountInfo],
-        _program_id: &Pubkey,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
@@ -389,23 +1320,51 @@ impl Processor {
         }
 
         let trove_account = next_account_info(accounts_info_iter)?;
-
-        let trove = Trove::unpack_unchecked(&trove_account.data.borrow())?;
+        let token_program = next_account_info(accounts_info_iter)?;
+        let temp_pda_token = next_account_info(accounts_info_iter)?;
+        let token = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let clock_account = next_account_info(accounts_info_iter)?;
+        let collateral_type_account = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[
+            borrower,
+            trove_account,
+            token_program,
+            temp_pda_token,
+            token,
+            protocol_account,
+            collateral_type_account,
+        ])?;
+
+        let mut trove = Account::<Trove>::load_mut(trove_account, program_id)?;
         if trove.is_liquidated {
             return Err(LiquityError::TroveAlreadyLiquidated.into());
         }
+        if *borrower.key != trove.owner {
+            return Err(LiquityError::OnlyForTroveOwner.into());
+        }
 
-        let token_program = next_account_info(accounts_info_iter)?;
-        let temp_pda_token = next_account_info(accounts_info_iter)?;
-        let token = next_account_info(accounts_info_iter)?;
+        let mut protocol = Self::accrue_interest(program_id, protocol_account, clock_account)?;
+        token::check_token_program(token_program)?;
+        Self::check_stablecoin_mint(&protocol, token)?;
+        token::check_token_account(temp_pda_token, token.key, borrower.key)?;
+
+        let mut collateral_type = Self::load_collateral_type(program_id, protocol_account.key, collateral_type_account, &trove.collateral)?;
+
+        Self::refresh_trove_debt(&mut trove, &mut protocol, &mut collateral_type)?;
+        Self::remove_debt(&mut protocol, &mut collateral_type, trove.amount_to_close)?;
+
+        protocol.save()?;
+        collateral_type.save()?;
 
         let transfer_to_initializer_ix = spl_token::instruction::burn(
             token_program.key,
             temp_pda_token.key,
             token.key,
             borrower.key,
-            &[&borrower.key],
-            trove.amount_to_close * 1000000000,
+            &[borrower.key],
+            trove.amount_to_close.0,
         )?;
 
         msg!("Calling the token program to transfer tokens to the escrow's initializer...");
@@ -419,30 +1378,91 @@ impl Processor {
             ],
         )?;
 
-        msg!("Send back the lamports!");
-        **borrower.lamports.borrow_mut() = borrower.lamports()
-            .checked_add(trove_account.lamports())
-            .ok_or(LiquityError::AmountOverflow)?;
+        if !trove.is_native_collateral() {
+            let collateral_accounts = CollateralAccounts::next_for_trove(accounts_info_iter, program_id, &trove)?;
+            collateral_accounts.withdraw(trove.collateral_amount)?;
+        }
+
+        LiquityEvent::TroveClosed {
+            trove: *trove_account.key,
+            owner: trove.owner,
+            repaid: trove.amount_to_close,
+            collateral_amount: trove.collateral_amount,
+        }.emit();
 
-        **trove_account.lamports.borrow_mut() = 0;
-        *trove_account.data.borrow_mut() = &mut [];
+        msg!("Send back the lamports!");
+        trove.close(borrower)?;
 
         Ok(())
     }
 
     fn process_borrow(
         accounts: &[AccountInfo],
-        borrow_amount: u64,
-        lamports: u64,
-        _program_id: &Pubkey,
+        borrow_amount: StableAmount,
+        lamports: Lamports,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
+        // Check accounts
+        let accounts_info_iter = &mut accounts.iter();
+        let borrower = next_account_info(accounts_info_iter)?;
+
+        if !borrower.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let trove_account = next_account_info(accounts_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(accounts_info_iter)?)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let clock_account = next_account_info(accounts_info_iter)?;
+        let collateral_type_account = next_account_info(accounts_info_iter)?;
+
+        check_distinct(&[borrower, trove_account, protocol_account, collateral_type_account])?;
+
+        let mut trove = Account::<Trove>::load_uninitialized(trove_account, program_id, rent)?;
+
+        // The collateral is what the trove account holds above its rent
+        if trove_account.lamports() < math::add(rent.minimum_balance(Trove::LEN), lamports.0)? {
+            return Err(LiquityError::ExpectedAmountMismatch.into());
+        }
+
+        let mut protocol = Self::accrue_interest(program_id, protocol_account, clock_account)?;
+        Self::check_not_paused(&protocol, PAUSE_BORROW)?;
+
+        let mut collateral_type = Self::load_collateral_type(program_id, protocol_account.key, collateral_type_account, &Pubkey::default())?;
+
         // check collateral
-        if !helpers::check_min_collateral_include_gas_fee(borrow_amount, lamports) {
+        if !helpers::check_min_collateral_ratio(borrow_amount, &Collateral::Sol, lamports.into(), &protocol.config, protocol.stablecoin_decimals)? {
             return Err(LiquityError::InvalidCollateral.into());
         }
 
-        // Check accounts
+        let stablecoin_accounts = StablecoinAccounts::next(accounts_info_iter, program_id, borrower.key)?;
+        check_distinct(&[trove_account, protocol_account, collateral_type_account, stablecoin_accounts.mint, stablecoin_accounts.user_token])?;
+        Self::check_stablecoin_mint(&protocol, stablecoin_accounts.mint)?;
+
+        // Create Trove
+        Self::open_trove(&mut trove, borrower.key, borrow_amount, lamports.into(), Pubkey::default(), &protocol)?;
+
+        Self::add_debt(&mut protocol, &mut collateral_type, trove.amount_to_close)?;
+
+        stablecoin_accounts.mint(get_trove_sent_amount(borrow_amount, &protocol.config, protocol.stablecoin_decimals)?)?;
+
+        trove.save()?;
+        protocol.save()?;
+        collateral_type.save()?;
+
+        Self::trove_opened(trove_account, &trove).emit();
+
+        Ok(())
+    }
+
+    fn process_borrow_with_stake_pool_token(
+        accounts: &[AccountInfo],
+        borrow_amount: StableAmount,
+        pool_tokens: CollateralAmount,
+        program_id: &Pubkey,
+    ) -> ProgramResult
+    {
         let accounts_info_iter = &mut accounts.iter();
         let borrower = next_account_info(accounts_info_iter)?;
 
@@ -451,38 +1471,91 @@ impl Processor {
         }
 
         let trove_account = next_account_info(accounts_info_iter)?;
-
         let rent = &Rent::from_account_info(next_account_info(accounts_info_iter)?)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let clock_account = next_account_info(accounts_info_iter)?;
+        let collateral_type_account = next_account_info(accounts_info_iter)?;
 
-        if !rent.is_exempt(trove_account.lamports(), trove_account.data_len()) {
-            return Err(LiquityError::NotRentExempt.into());
+        check_distinct(&[borrower, trove_account, protocol_account, collateral_type_account])?;
+
+        let mut trove = Account::<Trove>::load_uninitialized(trove_account, program_id, rent)?;
+
+        let mut protocol = Self::accrue_interest(program_id, protocol_account, clock_account)?;
+        Self::check_not_paused(&protocol, PAUSE_BORROW)?;
+        let clock = &Clock::from_account_info(clock_account)?;
+
+        let collateral_accounts = CollateralAccounts::next(accounts_info_iter, program_id)?;
+        let mut collateral_type = Self::load_collateral_type(program_id, protocol_account.key, collateral_type_account, collateral_accounts.stake_pool.key)?;
+
+        // check collateral
+        let collateral = collateral_accounts.pool.collateral(clock)?;
+        if !helpers::check_min_collateral_ratio(borrow_amount, &collateral, pool_tokens, &protocol.config, protocol.stablecoin_decimals)? {
+            return Err(LiquityError::InvalidCollateral.into());
         }
 
+        let stablecoin_accounts = StablecoinAccounts::next(accounts_info_iter, program_id, borrower.key)?;
+        check_distinct(&[trove_account, protocol_account, collateral_type_account, stablecoin_accounts.mint, stablecoin_accounts.user_token])?;
+        Self::check_stablecoin_mint(&protocol, stablecoin_accounts.mint)?;
+
+        collateral_accounts.deposit(borrower, pool_tokens)?;
+
         // Create Trove
-        let mut trove = Trove::unpack_unchecked(&trove_account.data.borrow())?;
-        if trove.is_initialized() {
-            return Err(ProgramError::AccountAlreadyInitialized);
+        Self::open_trove(&mut trove, borrower.key, borrow_amount, pool_tokens, *collateral_accounts.stake_pool.key, &protocol)?;
+
+        Self::add_debt(&mut protocol, &mut collateral_type, trove.amount_to_close)?;
+
+        stablecoin_accounts.mint(get_trove_sent_amount(borrow_amount, &protocol.config, protocol.stablecoin_decimals)?)?;
+
+        trove.save()?;
+        protocol.save()?;
+        collateral_type.save()?;
+
+        Self::trove_opened(trove_account, &trove).emit();
+
+        Ok(())
+    }
+
+    fn trove_opened(trove_account: &AccountInfo, trove: &Trove) -> LiquityEvent {
+        LiquityEvent::TroveOpened {
+            trove: *trove_account.key,
+            owner: trove.owner,
+            collateral: trove.collateral,
+            collateral_amount: trove.collateral_amount,
+            borrow_amount: trove.borrow_amount,
+            debt: trove.amount_to_close,
+            depositor_fee: trove.depositor_fee,
+            team_fee: trove.team_fee,
         }
+    }
 
+    fn open_trove(
+        trove: &mut Trove,
+        owner: &Pubkey,
+        borrow_amount: StableAmount,
+        collateral_amount: CollateralAmount,
+        collateral: Pubkey,
+        protocol: &Protocol,
+    ) -> ProgramResult
+    {
         trove.is_initialized = true;
         trove.is_liquidated = false;
         trove.is_received = false;
         trove.borrow_amount = borrow_amount;
-        trove.lamports_amount = lamports;
-        trove.depositor_fee = get_depositors_fee(borrow_amount);
-        trove.team_fee = get_team_fee(borrow_amount);
-        trove.amount_to_close = get_trove_debt_amount(borrow_amount);
-        trove.owner = *borrower.key;
-
-        Trove::pack(trove, &mut trove_account.data.borrow_mut())?;
+        trove.collateral_amount = collateral_amount;
+        trove.depositor_fee = get_depositors_fee(borrow_amount, &protocol.config, protocol.stablecoin_decimals)?;
+        trove.team_fee = get_team_fee(borrow_amount, &protocol.config, protocol.stablecoin_decimals)?;
+        trove.amount_to_close = get_trove_debt_amount(borrow_amount, protocol.stablecoin_decimals)?;
+        trove.owner = *owner;
+        trove.collateral = collateral;
+        trove.borrow_index = protocol.borrow_index;
 
         Ok(())
     }
 
     fn process_redeem_coin(
         accounts: &[AccountInfo],
-        amount: u64,
-        _program_id: &Pubkey,
+        amount: CollateralAmount,
+        program_id: &Pubkey,
     ) -> ProgramResult
     {
         let accounts_info_iter = &mut accounts.iter();
@@ -493,19 +1566,104 @@ impl Processor {
         }
 
         let trove_account = next_account_info(accounts_info_iter)?;
+        let protocol_account = next_account_info(accounts_info_iter)?;
+        let clock_account = next_account_info(accounts_info_iter)?;
+        let collateral_type_account = next_account_info(accounts_info_iter)?;
+        let token_program = next_account_info(accounts_info_iter)?;
+        let user_token = next_account_info(accounts_info_iter)?;
+        let token = next_account_info(accounts_info_iter)?;
 
-        let mut trove = Trove::unpack_unchecked(&trove_account.data.borrow())?;
-
-        if !trove.is_initialized() {
-            return Err(LiquityError::TroveIsNotInitialized.into());
-        }
+        check_distinct(&[
+            borrower,
+            trove_account,
+            protocol_account,
+            collateral_type_account,
+            token_program,
+            user_token,
+            token,
+        ])?;
+
+        let mut trove = Account::<Trove>::load_mut(trove_account, program_id)?;
         if trove.is_liquidated {
             return Err(LiquityError::TroveAlreadyLiquidated.into());
         }
+        if *borrower.key != trove.owner {
+            return Err(LiquityError::OnlyForTroveOwner.into());
+        }
+
+        let mut protocol = Self::accrue_interest(program_id, protocol_account, clock_account)?;
+        Self::check_not_paused(&protocol, PAUSE_REDEEM)?;
+        token::check_token_program(token_program)?;
+        Self::check_stablecoin_mint(&protocol, token)?;
+        token::check_token_account(user_token, token.key, borrower.key)?;
+
+        let mut collateral_type = Self::load_collateral_type(program_id, protocol_account.key, collateral_type_account, &trove.collateral)?;
+
+        Self::refresh_trove_debt(&mut trove, &mut protocol, &mut collateral_type)?;
+
+        trove.collateral_amount = trove.collateral_amount.checked_sub(amount)?;
+
+        let collateral_accounts = if trove.is_native_collateral() {
+            None
+        } else {
+            Some(CollateralAccounts::next_for_trove(accounts_info_iter, program_id, &trove)?)
+        };
+        let collateral = match &collateral_accounts {
+            None => Collateral::Sol,
+            Some(collateral_accounts) => collateral_accounts.pool.collateral(&Clock::from_account_info(clock_account)?)?,
+        };
+
+        // The redeemed collateral is paid for in stablecoin, burned against the debt of the trove
+        let repaid = helpers::get_redemption_amount(&collateral, amount, protocol.stablecoin_decimals)?;
+        trove.amount_to_close = trove.amount_to_close.checked_sub(repaid)?;
+        trove.borrow_amount = trove.borrow_amount.checked_sub(repaid)?;
+        Self::remove_debt(&mut protocol, &mut collateral_type, repaid)?;
+
+        if !helpers::check_min_collateral_ratio(trove.borrow_amount, &collateral, trove.collateral_amount, &protocol.config, protocol.stablecoin_decimals)? {
+            return Err(LiquityError::InvalidCollateral.into());
+        }
 
-        trove.lamports_amount = trove.lamports_amount.sub(amount);
+        let burn_ix = spl_token::instruction::burn(
+            token_program.key,
+            user_token.key,
+            token.key,
+            borrower.key,
+            &[borrower.key],
+            repaid.0,
+        )?;
+
+        msg!("Calling the token program to burn the redeemed stablecoin...");
+        invoke(
+            &burn_ix,
+            &[
+                token.clone(),
+                user_token.clone(),
+                borrower.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        match collateral_accounts {
+            None => Self::withdraw_lamports(trove_account, borrower, amount)?,
+            Some(collateral_accounts) => collateral_accounts.withdraw(amount)?,
+        }
 
-        Trove::pack(trove, &mut trove_account.data.borrow_mut())?;
+        trove.save()?;
+        protocol.save()?;
+        collateral_type.save()?;
+
+        LiquityEvent::Redemption {
+            trove: *trove_account.key,
+            amount,
+            collateral_amount: trove.collateral_amount,
+            repaid,
+        }.emit();
+        // Refreshed above, the debt includes the interest
+        LiquityEvent::TroveAdjusted {
+            trove: *trove_account.key,
+            collateral_amount: trove.collateral_amount,
+            debt: trove.amount_to_close,
+        }.emit();
 
         Ok(())
     }
@@ -0,0 +1,49 @@
+//! Minimal JSON RPC client of a Solana node, shared by the CLI and the indexer
+
+use serde_json::{json, Value};
+use solana_program::{hash::Hash, pubkey::Pubkey};
+use std::{error::Error, str::FromStr};
+
+pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+pub struct Rpc {
+    pub url: String,
+}
+
+impl Rpc {
+    pub fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response: Value = ureq::post(&self.url).send_json(request)?.into_json()?;
+        if let Some(error) = response.get("error") {
+            return Err(format!("{} failed: {}", method, error).into());
+        }
+        Ok(response["result"].clone())
+    }
+
+    pub fn latest_blockhash(&self) -> Result<Hash> {
+        let result = self.call("getLatestBlockhash", json!([]))?;
+        let blockhash = result["value"]["blockhash"].as_str().ok_or("getLatestBlockhash returned no blockhash")?;
+        Ok(Hash::from_str(blockhash)?)
+    }
+
+    /// Sends a serialized transaction, returning its signature
+    pub fn send_transaction(&self, transaction: &[u8]) -> Result<String> {
+        let result = self.call("sendTransaction", json!([base64::encode(transaction), { "encoding": "base64" }]))?;
+        result.as_str().map(String::from).ok_or_else(|| "sendTransaction returned no signature".into())
+    }
+
+    pub fn account_data(&self, address: &Pubkey) -> Result<Vec<u8>> {
+        let result = self.call("getAccountInfo", json!([address.to_string(), { "encoding": "base64" }]))?;
+        account_data(&result).ok_or_else(|| format!("account {} not found", address).into())
+    }
+}
+
+/// Data of an account in a `getAccountInfo` response, its result or a `solana account --output json` file
+pub fn account_data(value: &Value) -> Option<Vec<u8>> {
+    let candidates = [&value["result"]["value"], &value["value"], &value["account"], value];
+    let account = candidates.iter().find(|account| account["data"].is_array())?;
+    match account["data"].as_array()?.as_slice() {
+        [Value::String(data), Value::String(encoding)] if encoding == "base64" => base64::decode(data).ok(),
+        _ => None,
+    }
+}
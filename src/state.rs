@@ -4,19 +4,105 @@ use solana_program::{
     pubkey::Pubkey,
 };
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
-use std::convert::TryInto;
+use crate::error::LiquityError;
+use crate::units::{CollateralAmount, GovAmount, Lamports, Ratio, StableAmount};
 
+/// Kind of state held by a program account, stored in its first byte
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccountType {
+    Uninitialized,
+    Trove,
+    Deposit,
+    Escrow,
+    Protocol,
+    CollateralType,
+    /// Marker left in the data of a closed account
+    Closed = 255,
+}
+
+impl AccountType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(AccountType::Uninitialized),
+            1 => Some(AccountType::Trove),
+            2 => Some(AccountType::Deposit),
+            3 => Some(AccountType::Escrow),
+            4 => Some(AccountType::Protocol),
+            5 => Some(AccountType::CollateralType),
+            255 => Some(AccountType::Closed),
+            _ => None,
+        }
+    }
+
+    /// Type of the state in `src`, including the legacy layouts without a header
+    pub fn of(src: &[u8]) -> Option<Self> {
+        if src.first() == Some(&(AccountType::Closed as u8)) {
+            return Some(AccountType::Closed);
+        }
+        match src.len() {
+            Trove::LEGACY_LEN => Some(AccountType::Trove),
+            Deposit::LEGACY_LEN => Some(AccountType::Deposit),
+            Escrow::LEGACY_LEN => Some(AccountType::Escrow),
+            _ => src.first().and_then(|account_type| Self::from_u8(*account_type)),
+        }
+    }
+}
+
+/// State of an account owned by the program, tagged with its account type
+pub trait ProgramAccount: Pack + IsInitialized {
+    const ACCOUNT_TYPE: AccountType;
+
+    /// Error returned when an instruction expects the state to be initialized
+    fn uninitialized_error() -> ProgramError {
+        ProgramError::UninitializedAccount
+    }
+}
+
+/// Every account starts with its account type and layout version. With the
+/// `borsh` feature, the data after the header is the Borsh encoding of the state
+pub const HEADER_LEN: usize = 2;
+
+/// Checks the header of `src`, accepting the zeroed header of a fresh account
+fn unpack_header(src: &[u8], account_type: AccountType, version: u8) -> Result<(), ProgramError> {
+    match src.get(..HEADER_LEN) {
+        Some(&[0, 0]) => Ok(()),
+        Some(&[src_type, src_version]) if src_type == account_type as u8 && src_version == version => Ok(()),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+fn pack_header(dst: &mut [u8; HEADER_LEN], account_type: AccountType, version: u8) {
+    dst[0] = account_type as u8;
+    dst[1] = version;
+}
+
+/// Accepts the current layout of `T` as well as its legacy layout without a header
+fn check_len<T: Pack>(input: &[u8], legacy_len: usize) -> Result<(), ProgramError> {
+    if input.len() != T::LEN && input.len() != legacy_len {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
 pub struct Deposit {
     pub is_initialized: bool,
-    pub token_amount: u64,
-    pub reward_token_amount: u64,
-    pub reward_governance_token_amount: u64,
-    pub reward_coin_amount: u64,
+    pub token_amount: StableAmount,
+    pub reward_token_amount: StableAmount,
+    pub reward_governance_token_amount: GovAmount,
+    pub reward_coin_amount: Lamports,
     pub bank: Pubkey,
     pub governance_bank: Pubkey,
     pub owner: Pubkey,
 }
 
+impl Deposit {
+    pub const VERSION: u8 = 1;
+    /// Length of the legacy layout, the current layout without the header
+    pub const LEGACY_LEN: usize = 129;
+}
+
 impl Sealed for Deposit {}
 
 impl IsInitialized for Deposit {
@@ -25,10 +111,25 @@ impl IsInitialized for Deposit {
     }
 }
 
+impl ProgramAccount for Deposit {
+    const ACCOUNT_TYPE: AccountType = AccountType::Deposit;
+}
+
 impl Pack for Deposit {
-    const LEN: usize = 129;
+    const LEN: usize = 131;
+    fn unpack_unchecked(input: &[u8]) -> Result<Self, ProgramError> {
+        check_len::<Self>(input, Deposit::LEGACY_LEN)?;
+        Self::unpack_from_slice(input)
+    }
+
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-        let src = array_ref![src, 0, Deposit::LEN];
+        let src = if src.len() == Deposit::LEGACY_LEN {
+            src
+        } else {
+            unpack_header(src, AccountType::Deposit, Deposit::VERSION)?;
+            &src[HEADER_LEN..]
+        };
+        let src = array_ref![src, 0, Deposit::LEGACY_LEN];
         let (
             is_initialized,
             token_amount,
@@ -47,10 +148,10 @@ impl Pack for Deposit {
 
         Ok(Deposit {
             is_initialized,
-            token_amount: u64::from_le_bytes(*token_amount),
-            reward_token_amount: u64::from_le_bytes(*reward_token_amount),
-            reward_governance_token_amount: u64::from_le_bytes(*reward_governance_token_amount),
-            reward_coin_amount: u64::from_le_bytes(*reward_coin_amount),
+            token_amount: StableAmount::from_le_bytes(*token_amount),
+            reward_token_amount: StableAmount::from_le_bytes(*reward_token_amount),
+            reward_governance_token_amount: GovAmount::from_le_bytes(*reward_governance_token_amount),
+            reward_coin_amount: Lamports::from_le_bytes(*reward_coin_amount),
             bank: Pubkey::new_from_array(*bank),
             governance_bank: Pubkey::new_from_array(*governance_bank),
             owner: Pubkey::new_from_array(*owner),
@@ -60,6 +161,7 @@ impl Pack for Deposit {
     fn pack_into_slice(&self, dst: &mut [u8]) {
         let dst = array_mut_ref![dst, 0, Deposit::LEN];
         let (
+            header_dst,
             is_initialized_dst,
             token_amount_dst,
             reward_token_amount_dst,
@@ -68,7 +170,7 @@ impl Pack for Deposit {
             bank_dst,
             governance_bank_dst,
             owner_dst,
-        ) = mut_array_refs![dst, 1, 8, 8, 8, 8, 32, 32, 32];
+        ) = mut_array_refs![dst, HEADER_LEN, 1, 8, 8, 8, 8, 32, 32, 32];
 
         let Deposit {
             is_initialized,
@@ -81,6 +183,7 @@ impl Pack for Deposit {
             owner,
         } = self;
 
+        pack_header(header_dst, AccountType::Deposit, Deposit::VERSION);
         is_initialized_dst[0] = *is_initialized as u8;
         *token_amount_dst = token_amount.to_le_bytes();
         *reward_token_amount_dst = reward_token_amount.to_le_bytes();
@@ -92,41 +195,121 @@ impl Pack for Deposit {
     }
 }
 
+#[derive(Debug)]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
 pub struct Trove {
     pub is_initialized: bool,
+    /// Set by `ReceiveTrove` once the system account has booked the fees of the
+    /// trove, only received troves can be liquidated
     pub is_received: bool,
     pub is_liquidated: bool,
-    pub borrow_amount: u64,
-    pub lamports_amount: u64,
-    pub team_fee: u64,
-    pub depositor_fee: u64,
-    pub amount_to_close: u64,
+    pub borrow_amount: StableAmount,
+    /// Lamports for native SOL, pool tokens for a stake pool
+    pub collateral_amount: CollateralAmount,
+    /// Fee withheld from the minted stablecoin for the team, never minted
+    pub team_fee: StableAmount,
+    /// Fee withheld from the minted stablecoin for depositors, never minted
+    pub depositor_fee: StableAmount,
+    pub amount_to_close: StableAmount,
     pub owner: Pubkey,
+    /// Stake pool of the collateral, default for native SOL
+    pub collateral: Pubkey,
+    /// Protocol borrow index when `amount_to_close` was last updated
+    pub borrow_index: u128,
 }
 
 impl Sealed for Trove {}
 
+impl Trove {
+    pub const VERSION: u8 = 1;
+    /// Length of the legacy layout, without header, collateral and borrow index
+    pub const LEGACY_LEN: usize = 75;
+
+    pub fn is_native_collateral(&self) -> bool {
+        self.collateral == Pubkey::default()
+    }
+
+    /// Legacy troves hold native SOL and have no borrow index snapshot yet
+    fn unpack_legacy(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, Trove::LEGACY_LEN];
+        let (
+            is_initialized,
+            is_received,
+            is_liquidated,
+            borrow_amount,
+            lamports_amount,
+            team_fee,
+            depositor_fee,
+            amount_to_close,
+            owner,
+        ) = array_refs![src, 1, 1, 1, 8, 8, 8, 8, 8, 32];
+
+        Ok(Trove {
+            is_initialized: unpack_bool(is_initialized)?,
+            is_received: unpack_bool(is_received)?,
+            is_liquidated: unpack_bool(is_liquidated)?,
+            borrow_amount: StableAmount::from_le_bytes(*borrow_amount),
+            collateral_amount: CollateralAmount::from_le_bytes(*lamports_amount),
+            team_fee: StableAmount::from_le_bytes(*team_fee),
+            depositor_fee: StableAmount::from_le_bytes(*depositor_fee),
+            amount_to_close: StableAmount::from_le_bytes(*amount_to_close),
+            owner: Pubkey::new_from_array(*owner),
+            collateral: Pubkey::default(),
+            borrow_index: 0,
+        })
+    }
+}
+
+fn unpack_bool(src: &[u8; 1]) -> Result<bool, ProgramError> {
+    match src {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
 impl IsInitialized for Trove {
     fn is_initialized(&self) -> bool {
         self.is_initialized
     }
 }
 
+impl ProgramAccount for Trove {
+    const ACCOUNT_TYPE: AccountType = AccountType::Trove;
+
+    fn uninitialized_error() -> ProgramError {
+        LiquityError::TroveIsNotInitialized.into()
+    }
+}
+
 impl Pack for Trove {
-    const LEN: usize = 75;
+    const LEN: usize = 125;
+    fn unpack_unchecked(input: &[u8]) -> Result<Self, ProgramError> {
+        check_len::<Self>(input, Trove::LEGACY_LEN)?;
+        Self::unpack_from_slice(input)
+    }
+
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        if src.len() == Trove::LEGACY_LEN {
+            return Trove::unpack_legacy(src);
+        }
+        unpack_header(src, AccountType::Trove, Trove::VERSION)?;
+
         let src = array_ref![src, 0, Trove::LEN];
         let (
+            _header,
             is_initialized,
             is_received,
             is_liquidated,
             borrow_amount,
-            lamports_amount,
+            collateral_amount,
             team_fee,
             depositor_fee,
             amount_to_close,
             owner,
-        ) = array_refs![src, 1, 1, 1, 8, 8, 8, 8, 8, 32];
+            collateral,
+            borrow_index,
+        ) = array_refs![src, HEADER_LEN, 1, 1, 1, 8, 8, 8, 8, 8, 32, 32, 16];
         let is_initialized = match is_initialized {
             [0] => false,
             [1] => true,
@@ -149,53 +332,65 @@ impl Pack for Trove {
             is_initialized,
             is_received,
             is_liquidated,
-            borrow_amount: u64::from_le_bytes(*borrow_amount),
-            lamports_amount: u64::from_le_bytes(*lamports_amount),
-            team_fee: u64::from_le_bytes(*team_fee),
-            depositor_fee: u64::from_le_bytes(*depositor_fee),
-            amount_to_close: u64::from_le_bytes(*amount_to_close),
+            borrow_amount: StableAmount::from_le_bytes(*borrow_amount),
+            collateral_amount: CollateralAmount::from_le_bytes(*collateral_amount),
+            team_fee: StableAmount::from_le_bytes(*team_fee),
+            depositor_fee: StableAmount::from_le_bytes(*depositor_fee),
+            amount_to_close: StableAmount::from_le_bytes(*amount_to_close),
             owner: Pubkey::new_from_array(*owner),
+            collateral: Pubkey::new_from_array(*collateral),
+            borrow_index: u128::from_le_bytes(*borrow_index),
         })
     }
 
     fn pack_into_slice(&self, dst: &mut [u8]) {
         let dst = array_mut_ref![dst, 0, Trove::LEN];
         let (
+            header_dst,
             is_initialized_dst,
             is_received_dst,
             is_liquidated_dst,
             borrow_amount_dst,
-            lamports_amount_dst,
+            collateral_amount_dst,
             team_fee_dst,
             depositor_fee_dst,
             amount_to_close_dst,
             owner_dst,
-        ) = mut_array_refs![dst,  1, 1, 1, 8, 8, 8, 8, 8, 32];
+            collateral_dst,
+            borrow_index_dst,
+        ) = mut_array_refs![dst, HEADER_LEN, 1, 1, 1, 8, 8, 8, 8, 8, 32, 32, 16];
 
         let Trove {
             is_initialized,
             is_received,
             is_liquidated,
             borrow_amount,
-            lamports_amount,
+            collateral_amount,
             team_fee,
             depositor_fee,
             amount_to_close,
             owner,
+            collateral,
+            borrow_index,
         } = self;
 
+        pack_header(header_dst, AccountType::Trove, Trove::VERSION);
         is_initialized_dst[0] = *is_initialized as u8;
         is_received_dst[0] = *is_received as u8;
         is_liquidated_dst[0] = *is_liquidated as u8;
         *borrow_amount_dst = borrow_amount.to_le_bytes();
-        *lamports_amount_dst = lamports_amount.to_le_bytes();
+        *collateral_amount_dst = collateral_amount.to_le_bytes();
         *team_fee_dst = team_fee.to_le_bytes();
         *depositor_fee_dst = depositor_fee.to_le_bytes();
         *amount_to_close_dst = amount_to_close.to_le_bytes();
         owner_dst.copy_from_slice(owner.as_ref());
+        collateral_dst.copy_from_slice(collateral.as_ref());
+        *borrow_index_dst = borrow_index.to_le_bytes();
     }
 }
 
+#[derive(Debug)]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
 pub struct Escrow {
     pub is_initialized: bool,
     pub initializer_pubkey: Pubkey,
@@ -204,6 +399,12 @@ pub struct Escrow {
     pub expected_amount: u64,
 }
 
+impl Escrow {
+    pub const VERSION: u8 = 1;
+    /// Length of the legacy layout, the current layout without the header
+    pub const LEGACY_LEN: usize = 105;
+}
+
 impl Sealed for Escrow {}
 
 impl IsInitialized for Escrow {
@@ -212,10 +413,25 @@ impl IsInitialized for Escrow {
     }
 }
 
+impl ProgramAccount for Escrow {
+    const ACCOUNT_TYPE: AccountType = AccountType::Escrow;
+}
+
 impl Pack for Escrow {
-    const LEN: usize = 105;
+    const LEN: usize = 107;
+    fn unpack_unchecked(input: &[u8]) -> Result<Self, ProgramError> {
+        check_len::<Self>(input, Escrow::LEGACY_LEN)?;
+        Self::unpack_from_slice(input)
+    }
+
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-        let src = array_ref![src, 0, Escrow::LEN];
+        let src = if src.len() == Escrow::LEGACY_LEN {
+            src
+        } else {
+            unpack_header(src, AccountType::Escrow, Escrow::VERSION)?;
+            &src[HEADER_LEN..]
+        };
+        let src = array_ref![src, 0, Escrow::LEGACY_LEN];
         let (
             is_initialized,
             initializer_pubkey,
@@ -241,12 +457,13 @@ impl Pack for Escrow {
     fn pack_into_slice(&self, dst: &mut [u8]) {
         let dst = array_mut_ref![dst, 0, Escrow::LEN];
         let (
+            header_dst,
             is_initialized_dst,
             initializer_pubkey_dst,
             temp_token_account_pubkey_dst,
             initializer_token_to_receive_account_pubkey_dst,
             expected_amount_dst,
-        ) = mut_array_refs![dst, 1, 32, 32, 32, 8];
+        ) = mut_array_refs![dst, HEADER_LEN, 1, 32, 32, 32, 8];
 
         let Escrow {
             is_initialized,
@@ -256,10 +473,321 @@ impl Pack for Escrow {
             expected_amount,
         } = self;
 
+        pack_header(header_dst, AccountType::Escrow, Escrow::VERSION);
         is_initialized_dst[0] = *is_initialized as u8;
         initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
         temp_token_account_pubkey_dst.copy_from_slice(temp_token_account_pubkey.as_ref());
         initializer_token_to_receive_account_pubkey_dst.copy_from_slice(initializer_token_to_receive_account_pubkey.as_ref());
         *expected_amount_dst = expected_amount.to_le_bytes();
     }
-}
\ No newline at end of file
+}
+
+/// Protocol parameters the admin may change through the timelock
+#[derive(Debug)]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
+pub struct ProtocolConfig {
+    /// Annual interest rate on trove debt, zero disables accrual
+    pub interest_rate: Ratio,
+    /// Flash mint fee of the minted amount
+    pub flash_mint_fee: Ratio,
+    /// Role allowed to pause and unpause operations besides the admin
+    pub pauser: Pubkey,
+    /// Maximum total debt of all troves
+    pub debt_ceiling: StableAmount,
+    /// Role allowed to queue config changes
+    pub admin: Pubkey,
+    /// Role allowed to cancel queued config changes besides the admin
+    pub guardian: Pubkey,
+    /// Minimum collateral value to debt ratio
+    pub min_collateral_ratio: Ratio,
+    /// Share of the trove debt withheld from the borrower for depositors
+    pub deposit_fee: Ratio,
+    /// Share of the trove debt withheld from the borrower for the team
+    pub team_fee: Ratio,
+    /// Price feed of SOL, reserved: the collateral checks still use the fixed price of `get_sol_price`
+    pub oracle: Pubkey,
+    /// Seconds a queued config change waits before it can be executed
+    pub timelock_delay: i64,
+}
+
+impl Sealed for ProtocolConfig {}
+
+impl Pack for ProtocolConfig {
+    const LEN: usize = 184;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, ProtocolConfig::LEN];
+        let (
+            interest_rate,
+            flash_mint_fee,
+            pauser,
+            debt_ceiling,
+            admin,
+            guardian,
+            min_collateral_ratio,
+            deposit_fee,
+            team_fee,
+            oracle,
+            timelock_delay,
+        ) = array_refs![src, 8, 8, 32, 8, 32, 32, 8, 8, 8, 32, 8];
+
+        Ok(ProtocolConfig {
+            interest_rate: Ratio::from_le_bytes(*interest_rate),
+            flash_mint_fee: Ratio::from_le_bytes(*flash_mint_fee),
+            pauser: Pubkey::new_from_array(*pauser),
+            debt_ceiling: StableAmount::from_le_bytes(*debt_ceiling),
+            admin: Pubkey::new_from_array(*admin),
+            guardian: Pubkey::new_from_array(*guardian),
+            min_collateral_ratio: Ratio::from_le_bytes(*min_collateral_ratio),
+            deposit_fee: Ratio::from_le_bytes(*deposit_fee),
+            team_fee: Ratio::from_le_bytes(*team_fee),
+            oracle: Pubkey::new_from_array(*oracle),
+            timelock_delay: i64::from_le_bytes(*timelock_delay),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, ProtocolConfig::LEN];
+        let (
+            interest_rate_dst,
+            flash_mint_fee_dst,
+            pauser_dst,
+            debt_ceiling_dst,
+            admin_dst,
+            guardian_dst,
+            min_collateral_ratio_dst,
+            deposit_fee_dst,
+            team_fee_dst,
+            oracle_dst,
+            timelock_delay_dst,
+        ) = mut_array_refs![dst, 8, 8, 32, 8, 32, 32, 8, 8, 8, 32, 8];
+
+        let ProtocolConfig {
+            interest_rate,
+            flash_mint_fee,
+            pauser,
+            debt_ceiling,
+            admin,
+            guardian,
+            min_collateral_ratio,
+            deposit_fee,
+            team_fee,
+            oracle,
+            timelock_delay,
+        } = self;
+
+        *interest_rate_dst = interest_rate.to_le_bytes();
+        *flash_mint_fee_dst = flash_mint_fee.to_le_bytes();
+        pauser_dst.copy_from_slice(pauser.as_ref());
+        *debt_ceiling_dst = debt_ceiling.to_le_bytes();
+        admin_dst.copy_from_slice(admin.as_ref());
+        guardian_dst.copy_from_slice(guardian.as_ref());
+        *min_collateral_ratio_dst = min_collateral_ratio.to_le_bytes();
+        *deposit_fee_dst = deposit_fee.to_le_bytes();
+        *team_fee_dst = team_fee.to_le_bytes();
+        oracle_dst.copy_from_slice(oracle.as_ref());
+        *timelock_delay_dst = timelock_delay.to_le_bytes();
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
+pub struct Protocol {
+    pub is_initialized: bool,
+    /// Stablecoin mint, its mint authority is the program authority
+    pub stablecoin_mint: Pubkey,
+    /// Decimals of the stablecoin mint, which amounts are in base units of
+    pub stablecoin_decimals: u8,
+    pub config: ProtocolConfig,
+    /// Cumulative borrow index, troves owe `amount_to_close` scaled by its growth
+    pub borrow_index: u128,
+    pub last_update_timestamp: i64,
+    /// Bitmask of the paused operations, see the `PAUSE_*` params
+    pub paused: u64,
+    /// Total debt of all troves
+    pub total_debt: StableAmount,
+    /// Config change waiting for its timelock to expire
+    pub pending_config: ProtocolConfig,
+    /// Timestamp from which `pending_config` can be executed, zero if nothing is queued
+    pub pending_eta: i64,
+}
+
+impl Protocol {
+    pub const VERSION: u8 = 1;
+
+    pub fn is_paused(&self, operation: u64) -> bool {
+        self.paused & operation != 0
+    }
+}
+
+impl Sealed for Protocol {}
+
+impl IsInitialized for Protocol {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl ProgramAccount for Protocol {
+    const ACCOUNT_TYPE: AccountType = AccountType::Protocol;
+}
+
+impl Pack for Protocol {
+    const LEN: usize = 452;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        unpack_header(src, AccountType::Protocol, Protocol::VERSION)?;
+
+        let src = array_ref![src, 0, Protocol::LEN];
+        let (
+            _header,
+            is_initialized,
+            stablecoin_mint,
+            stablecoin_decimals,
+            config,
+            borrow_index,
+            last_update_timestamp,
+            paused,
+            total_debt,
+            pending_config,
+            pending_eta,
+        ) = array_refs![src, HEADER_LEN, 1, 32, 1, ProtocolConfig::LEN, 16, 8, 8, 8, ProtocolConfig::LEN, 8];
+        let is_initialized = match is_initialized {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+
+        Ok(Protocol {
+            is_initialized,
+            stablecoin_mint: Pubkey::new_from_array(*stablecoin_mint),
+            stablecoin_decimals: stablecoin_decimals[0],
+            config: ProtocolConfig::unpack_from_slice(config)?,
+            borrow_index: u128::from_le_bytes(*borrow_index),
+            last_update_timestamp: i64::from_le_bytes(*last_update_timestamp),
+            paused: u64::from_le_bytes(*paused),
+            total_debt: StableAmount::from_le_bytes(*total_debt),
+            pending_config: ProtocolConfig::unpack_from_slice(pending_config)?,
+            pending_eta: i64::from_le_bytes(*pending_eta),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, Protocol::LEN];
+        let (
+            header_dst,
+            is_initialized_dst,
+            stablecoin_mint_dst,
+            stablecoin_decimals_dst,
+            config_dst,
+            borrow_index_dst,
+            last_update_timestamp_dst,
+            paused_dst,
+            total_debt_dst,
+            pending_config_dst,
+            pending_eta_dst,
+        ) = mut_array_refs![dst, HEADER_LEN, 1, 32, 1, ProtocolConfig::LEN, 16, 8, 8, 8, ProtocolConfig::LEN, 8];
+
+        let Protocol {
+            is_initialized,
+            stablecoin_mint,
+            stablecoin_decimals,
+            config,
+            borrow_index,
+            last_update_timestamp,
+            paused,
+            total_debt,
+            pending_config,
+            pending_eta,
+        } = self;
+
+        pack_header(header_dst, AccountType::Protocol, Protocol::VERSION);
+        is_initialized_dst[0] = *is_initialized as u8;
+        stablecoin_mint_dst.copy_from_slice(stablecoin_mint.as_ref());
+        stablecoin_decimals_dst[0] = *stablecoin_decimals;
+        config.pack_into_slice(config_dst);
+        *borrow_index_dst = borrow_index.to_le_bytes();
+        *last_update_timestamp_dst = last_update_timestamp.to_le_bytes();
+        *paused_dst = paused.to_le_bytes();
+        *total_debt_dst = total_debt.to_le_bytes();
+        pending_config.pack_into_slice(pending_config_dst);
+        *pending_eta_dst = pending_eta.to_le_bytes();
+    }
+}
+
+/// Debt limit and total debt of the troves backed by one kind of collateral
+#[derive(Debug)]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
+pub struct CollateralType {
+    pub is_initialized: bool,
+    /// Stake pool of the collateral, default for native SOL
+    pub collateral: Pubkey,
+    pub debt_ceiling: StableAmount,
+    pub total_debt: StableAmount,
+}
+
+impl CollateralType {
+    pub const VERSION: u8 = 1;
+}
+
+impl Sealed for CollateralType {}
+
+impl IsInitialized for CollateralType {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl ProgramAccount for CollateralType {
+    const ACCOUNT_TYPE: AccountType = AccountType::CollateralType;
+}
+
+impl Pack for CollateralType {
+    const LEN: usize = 51;
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        unpack_header(src, AccountType::CollateralType, CollateralType::VERSION)?;
+
+        let src = array_ref![src, 0, CollateralType::LEN];
+        let (
+            _header,
+            is_initialized,
+            collateral,
+            debt_ceiling,
+            total_debt,
+        ) = array_refs![src, HEADER_LEN, 1, 32, 8, 8];
+        let is_initialized = match is_initialized {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+
+        Ok(CollateralType {
+            is_initialized,
+            collateral: Pubkey::new_from_array(*collateral),
+            debt_ceiling: StableAmount::from_le_bytes(*debt_ceiling),
+            total_debt: StableAmount::from_le_bytes(*total_debt),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, CollateralType::LEN];
+        let (
+            header_dst,
+            is_initialized_dst,
+            collateral_dst,
+            debt_ceiling_dst,
+            total_debt_dst,
+        ) = mut_array_refs![dst, HEADER_LEN, 1, 32, 8, 8];
+
+        let CollateralType {
+            is_initialized,
+            collateral,
+            debt_ceiling,
+            total_debt,
+        } = self;
+
+        pack_header(header_dst, AccountType::CollateralType, CollateralType::VERSION);
+        is_initialized_dst[0] = *is_initialized as u8;
+        collateral_dst.copy_from_slice(collateral.as_ref());
+        *debt_ceiling_dst = debt_ceiling.to_le_bytes();
+        *total_debt_dst = total_debt.to_le_bytes();
+    }
+}
@@ -0,0 +1,105 @@
+//! Checks of the SPL token accounts passed to token program calls, and the stablecoin
+//! minted through the program authority
+
+use solana_program::{
+    account_info::{AccountInfo, next_account_info},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+};
+use crate::account::check_distinct;
+use crate::collateral::find_authority_address;
+use crate::error::LiquityError;
+use crate::params::AUTHORITY_SEED;
+use crate::units::StableAmount;
+
+pub fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+    if *token_program.key != spl_token::id() {
+        return Err(LiquityError::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+/// Checks `token_account` is a token account of `mint` owned by `owner`
+pub fn check_token_account(token_account: &AccountInfo, mint: &Pubkey, owner: &Pubkey) -> ProgramResult {
+    if *token_account.owner != spl_token::id() {
+        return Err(LiquityError::InvalidTokenOwner.into());
+    }
+    let token = spl_token::state::Account::unpack(&token_account.data.borrow())?;
+    if token.mint != *mint {
+        return Err(LiquityError::InvalidTokenMint.into());
+    }
+    if token.owner != *owner {
+        return Err(LiquityError::InvalidTokenOwner.into());
+    }
+    Ok(())
+}
+
+/// Accounts minting stablecoin to a user, signed by the program authority
+///
+/// 0. `[writable]` The stablecoin mint
+/// 1. `[writable]` User stablecoin token acc
+/// 2. `[]` The program authority
+/// 3. `[]` Token program
+pub struct StablecoinAccounts<'a, 'b> {
+    pub mint: &'a AccountInfo<'b>,
+    pub user_token: &'a AccountInfo<'b>,
+    pub authority: &'a AccountInfo<'b>,
+    pub token_program: &'a AccountInfo<'b>,
+    authority_bump_seed: u8,
+}
+
+impl<'a, 'b> StablecoinAccounts<'a, 'b> {
+    /// Reads the accounts minting to a stablecoin token acc of `owner`. The mint is checked
+    /// against the protocol by the caller
+    pub fn next<I: Iterator<Item = &'a AccountInfo<'b>>>(
+        iter: &mut I,
+        program_id: &Pubkey,
+        owner: &Pubkey,
+    ) -> Result<Self, ProgramError> {
+        let mint = next_account_info(iter)?;
+        let user_token = next_account_info(iter)?;
+        let authority = next_account_info(iter)?;
+        let token_program = next_account_info(iter)?;
+
+        check_distinct(&[mint, user_token, authority, token_program])?;
+
+        check_token_program(token_program)?;
+
+        let (authority_id, authority_bump_seed) = find_authority_address(program_id);
+        if *authority.key != authority_id {
+            return Err(LiquityError::InvalidMarketAuthority.into());
+        }
+
+        check_token_account(user_token, mint.key, owner)?;
+
+        Ok(StablecoinAccounts { mint, user_token, authority, token_program, authority_bump_seed })
+    }
+
+    /// Mints `amount` stablecoin to the user token account
+    pub fn mint(&self, amount: StableAmount) -> ProgramResult {
+        let mint_to_ix = spl_token::instruction::mint_to(
+            self.token_program.key,
+            self.mint.key,
+            self.user_token.key,
+            self.authority.key,
+            &[self.authority.key],
+            amount.0,
+        )?;
+
+        msg!("Calling the token program to mint the stablecoin...");
+        invoke_signed(
+            &mint_to_ix,
+            &[
+                self.mint.clone(),
+                self.user_token.clone(),
+                self.authority.clone(),
+                self.token_program.clone(),
+            ],
+            &[&[AUTHORITY_SEED, &[self.authority_bump_seed]]],
+        )
+    }
+}
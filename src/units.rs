@@ -0,0 +1,144 @@
+//! Amounts tagged with their unit, so that lamports, collateral, stablecoin and
+//! governance token amounts cannot be mixed up
+
+use solana_program::{native_token::LAMPORTS_PER_SOL, program_error::ProgramError};
+use crate::error::LiquityError;
+use crate::math;
+use crate::params::BPS_DENOMINATOR;
+use std::fmt;
+
+macro_rules! amount {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+        #[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
+        pub struct $name(pub u64);
+
+        impl $name {
+            pub const ZERO: Self = $name(0);
+
+            pub fn checked_add(self, other: Self) -> Result<Self, ProgramError> {
+                math::add(self.0, other.0).map($name)
+            }
+
+            pub fn checked_sub(self, other: Self) -> Result<Self, ProgramError> {
+                math::sub(self.0, other.0).map($name)
+            }
+
+            pub fn saturating_sub(self, other: Self) -> Self {
+                $name(self.0.saturating_sub(other.0))
+            }
+
+            pub fn from_le_bytes(bytes: [u8; 8]) -> Self {
+                $name(u64::from_le_bytes(bytes))
+            }
+
+            pub fn to_le_bytes(self) -> [u8; 8] {
+                self.0.to_le_bytes()
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+amount!(
+    /// Native SOL amount
+    Lamports
+);
+amount!(
+    /// Collateral amount of a trove, lamports for native SOL and pool tokens for a stake pool
+    CollateralAmount
+);
+amount!(
+    /// Stablecoin amount in base units of its mint
+    StableAmount
+);
+amount!(
+    /// Governance token amount in base units of its mint
+    GovAmount
+);
+
+/// Native SOL collateral is counted in lamports
+impl From<Lamports> for CollateralAmount {
+    fn from(lamports: Lamports) -> Self {
+        CollateralAmount(lamports.0)
+    }
+}
+
+/// Base units of `whole` tokens of a mint with `decimals`
+fn from_whole(whole: u64, decimals: u8) -> Result<u64, ProgramError> {
+    let scale = 10u64.checked_pow(decimals as u32).ok_or(LiquityError::MathOverflow)?;
+    math::mul(whole, scale)
+}
+
+impl StableAmount {
+    /// `whole` stablecoins of a mint with `decimals`
+    pub fn from_whole(whole: u64, decimals: u8) -> Result<Self, ProgramError> {
+        from_whole(whole, decimals).map(StableAmount)
+    }
+}
+
+impl GovAmount {
+    /// `whole` governance tokens of a mint with `decimals`
+    pub fn from_whole(whole: u64, decimals: u8) -> Result<Self, ProgramError> {
+        from_whole(whole, decimals).map(GovAmount)
+    }
+}
+
+/// Stablecoin value of one SOL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Price(pub StableAmount);
+
+impl Price {
+    /// Stablecoin value of `lamports`, rounded down
+    pub fn value(&self, lamports: Lamports) -> Result<StableAmount, ProgramError> {
+        math::mul_div(lamports.0, (self.0).0, LAMPORTS_PER_SOL).map(StableAmount)
+    }
+
+    /// Stablecoin value of `lamports`, rounded up
+    pub fn value_ceil(&self, lamports: Lamports) -> Result<StableAmount, ProgramError> {
+        math::mul_div_ceil(lamports.0, (self.0).0, LAMPORTS_PER_SOL).map(StableAmount)
+    }
+}
+
+/// Dimensionless ratio in basis points
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize, borsh::BorshDeserialize, borsh::BorshSchema))]
+pub struct Ratio(pub u64);
+
+impl Ratio {
+    /// A ratio of 100%
+    pub const ONE: Ratio = Ratio(BPS_DENOMINATOR);
+
+    pub fn from_percent(percent: u64) -> Result<Self, ProgramError> {
+        math::mul(percent, BPS_DENOMINATOR / 100).map(Ratio)
+    }
+
+    /// This ratio of `amount`, rounded down
+    pub fn apply(&self, amount: StableAmount) -> Result<StableAmount, ProgramError> {
+        math::mul_div(amount.0, self.0, BPS_DENOMINATOR).map(StableAmount)
+    }
+
+    /// This ratio of `amount`, rounded up
+    pub fn apply_ceil(&self, amount: StableAmount) -> Result<StableAmount, ProgramError> {
+        math::mul_div_ceil(amount.0, self.0, BPS_DENOMINATOR).map(StableAmount)
+    }
+
+    /// Whether `value` is at least this ratio of `debt`
+    pub fn is_covered(&self, value: StableAmount, debt: StableAmount) -> bool {
+        value.0 as u128 * BPS_DENOMINATOR as u128 >= debt.0 as u128 * self.0 as u128
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 8]) -> Self {
+        Ratio(u64::from_le_bytes(bytes))
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+}
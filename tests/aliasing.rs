@@ -0,0 +1,308 @@
+use solana_escrow::account::close_account;
+use solana_escrow::collateral::CollateralAccounts;
+use solana_escrow::error::LiquityError;
+use solana_escrow::processor::Processor;
+use solana_escrow::state::{CollateralType, ProtocolConfig, Trove, HEADER_LEN};
+use solana_escrow::units::StableAmount;
+use solana_program::{
+    account_info::AccountInfo,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program,
+};
+
+mod common;
+use common::{protocol, trove, TestAccount};
+
+fn duplicate_account() -> ProgramError {
+    LiquityError::DuplicateAccount.into()
+}
+
+fn u64_data(tag: u8, values: &[u64]) -> Vec<u8> {
+    let mut data = vec![tag];
+    for value in values {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data
+}
+
+/// Passes every pair of the `distinct` roles as the same account and expects the instruction to refuse it
+fn assert_rejects_aliasing(accounts: Vec<TestAccount>, distinct: &[usize], data: &[u8]) {
+    assert_rejects_aliasing_in(&Pubkey::new_unique(), accounts, distinct, data);
+}
+
+fn assert_rejects_aliasing_in(program_id: &Pubkey, mut accounts: Vec<TestAccount>, distinct: &[usize], data: &[u8]) {
+    let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+
+    for (index, &first) in distinct.iter().enumerate() {
+        for &second in &distinct[index + 1..] {
+            let mut aliased = infos.clone();
+            aliased[second] = infos[first].clone();
+
+            assert_eq!(
+                Processor::process(program_id, &aliased, data),
+                Err(duplicate_account()),
+                "accounts {} and {} aliased",
+                first,
+                second,
+            );
+        }
+    }
+}
+
+#[test]
+fn test_borrow_aliasing() {
+    let accounts = vec![
+        TestAccount::signer(),
+        TestAccount::new(),
+        TestAccount::rent(),
+        TestAccount::new(),
+        TestAccount::clock(),
+        TestAccount::new(),
+    ];
+    assert_rejects_aliasing(accounts, &[0, 1, 3, 5], &u64_data(0, &[1, 1]));
+}
+
+#[test]
+fn test_close_trove_aliasing() {
+    let accounts = vec![
+        TestAccount::signer(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::clock(),
+        TestAccount::new(),
+    ];
+    assert_rejects_aliasing(accounts, &[0, 1, 2, 3, 4, 5, 7], &[1]);
+}
+
+#[test]
+fn test_liquidate_trove_aliasing() {
+    let accounts = || {
+        vec![
+            TestAccount::signer(),
+            TestAccount::new(),
+            TestAccount::system(),
+            TestAccount::new(),
+            TestAccount::clock(),
+            TestAccount::new(),
+        ]
+    };
+    // The liquidator may be the system account
+    assert_rejects_aliasing(accounts(), &[0, 1, 3, 5], &[2]);
+    assert_rejects_aliasing(accounts(), &[1, 2, 3, 5], &[2]);
+}
+
+#[test]
+fn test_withdraw_coin_aliasing() {
+    let accounts = vec![
+        TestAccount::signer(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::clock(),
+        TestAccount::new(),
+    ];
+    assert_rejects_aliasing(accounts, &[0, 1, 2, 4], &u64_data(3, &[1]));
+}
+
+#[test]
+fn test_add_coin_aliasing() {
+    let accounts = vec![TestAccount::signer(), TestAccount::new(), TestAccount::new()];
+    assert_rejects_aliasing(accounts, &[0, 1, 2], &u64_data(4, &[1]));
+}
+
+#[test]
+fn test_redeem_coin_aliasing() {
+    let accounts = vec![
+        TestAccount::signer(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::clock(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+    ];
+    assert_rejects_aliasing(accounts, &[0, 1, 2, 4, 5, 6, 7], &u64_data(5, &[1]));
+}
+
+#[test]
+fn test_add_native_coin_aliasing() {
+    let program_id = Pubkey::new_unique();
+    let borrower = TestAccount::signer();
+    let trove = trove(borrower.key);
+    // The temp account holds exactly the lamports added, so only aliasing stops the instruction
+    let temp = TestAccount { owner: program_id, ..TestAccount::new() };
+    let accounts = vec![
+        borrower,
+        TestAccount::program(&program_id, trove),
+        TestAccount::program(&program_id, protocol()),
+        temp,
+    ];
+    assert_rejects_aliasing_in(&program_id, accounts, &[0, 1, 2, 3], &u64_data(4, &[1_000_000]));
+}
+
+#[test]
+fn test_add_deposit_aliasing() {
+    let accounts = vec![
+        TestAccount::signer(),
+        TestAccount::new(),
+        TestAccount::rent(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+    ];
+    assert_rejects_aliasing(accounts, &[0, 1, 3, 4, 5, 6, 7], &u64_data(6, &[1]));
+}
+
+#[test]
+fn test_withdraw_deposit_aliasing() {
+    let accounts = vec![
+        TestAccount::system(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+    ];
+    assert_rejects_aliasing(accounts, &[0, 1, 2], &u64_data(7, &[1]));
+}
+
+#[test]
+fn test_deposit_reward_aliasing() {
+    for data in [vec![8], u64_data(10, &[1, 1, 1])] {
+        let accounts = vec![TestAccount::system(), TestAccount::new(), TestAccount::new()];
+        assert_rejects_aliasing(accounts, &[0, 1, 2], &data);
+    }
+}
+
+#[test]
+fn test_receive_trove_aliasing() {
+    let accounts = vec![TestAccount::system(), TestAccount::new(), TestAccount::new()];
+    assert_rejects_aliasing(accounts, &[0, 1, 2], &[9]);
+}
+
+#[test]
+fn test_borrow_with_stake_pool_token_aliasing() {
+    let accounts = vec![
+        TestAccount::signer(),
+        TestAccount::new(),
+        TestAccount::rent(),
+        TestAccount::new(),
+        TestAccount::clock(),
+        TestAccount::new(),
+    ];
+    assert_rejects_aliasing(accounts, &[0, 1, 3, 5], &u64_data(11, &[1, 1]));
+}
+
+#[test]
+fn test_init_protocol_aliasing() {
+    let accounts = vec![
+        TestAccount::system(),
+        TestAccount::new(),
+        TestAccount::rent(),
+        TestAccount::clock(),
+        TestAccount::new(),
+    ];
+    let mut data = vec![12];
+    data.extend_from_slice(&[0; ProtocolConfig::LEN]);
+    assert_rejects_aliasing(accounts, &[0, 1, 4], &data);
+}
+
+#[test]
+fn test_config_change_aliasing() {
+    let accounts = vec![TestAccount::signer(), TestAccount::new(), TestAccount::clock()];
+    let mut data = vec![13];
+    data.extend_from_slice(&[0; ProtocolConfig::LEN]);
+    assert_rejects_aliasing(accounts, &[0, 1], &data);
+
+    let accounts = vec![TestAccount::signer(), TestAccount::new()];
+    assert_rejects_aliasing(accounts, &[0, 1], &[19]);
+}
+
+#[test]
+fn test_set_pause_aliasing() {
+    let accounts = vec![TestAccount::signer(), TestAccount::new()];
+    assert_rejects_aliasing(accounts, &[0, 1], &u64_data(15, &[1]));
+}
+
+#[test]
+fn test_flash_mint_aliasing() {
+    let accounts = (0..7).map(|_| TestAccount::new()).collect();
+    assert_rejects_aliasing(accounts, &[0, 1, 2, 3, 4, 5, 6], &u64_data(14, &[1]));
+}
+
+#[test]
+fn test_collateral_type_aliasing() {
+    let accounts = vec![
+        TestAccount::signer(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::rent(),
+        TestAccount { key: system_program::id(), ..TestAccount::new() },
+    ];
+    let mut data = vec![16];
+    data.extend_from_slice(Pubkey::new_unique().as_ref());
+    data.extend_from_slice(&1u64.to_le_bytes());
+    assert_rejects_aliasing(accounts, &[0, 1, 2], &data);
+
+    let accounts = vec![TestAccount::signer(), TestAccount::new(), TestAccount::new()];
+    assert_rejects_aliasing(accounts, &[0, 1, 2], &u64_data(17, &[1]));
+}
+
+#[test]
+fn test_migrate_account_aliasing() {
+    let program_id = Pubkey::new_unique();
+    let owner = TestAccount::signer();
+    // A legacy trove is the current layout without the header, collateral and borrow index
+    let mut legacy_trove = TestAccount::program(&program_id, trove(owner.key));
+    legacy_trove.data = legacy_trove.data[HEADER_LEN..HEADER_LEN + Trove::LEGACY_LEN].to_vec();
+    let collateral_type = CollateralType {
+        is_initialized: true,
+        collateral: Pubkey::default(),
+        debt_ceiling: StableAmount(u64::MAX),
+        total_debt: StableAmount(1_000),
+    };
+    let accounts = vec![
+        owner,
+        legacy_trove,
+        TestAccount::program(&program_id, protocol()),
+        TestAccount::rent(),
+        TestAccount { key: system_program::id(), ..TestAccount::new() },
+        TestAccount::program(&program_id, collateral_type),
+        TestAccount::clock(),
+    ];
+    assert_rejects_aliasing_in(&program_id, accounts, &[0, 1, 2, 5], &[20]);
+}
+
+#[test]
+fn test_collateral_accounts_aliasing() {
+    let program_id = Pubkey::new_unique();
+    let mut accounts: Vec<TestAccount> = (0..5).map(|_| TestAccount::new()).collect();
+    let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+
+    for first in 0..infos.len() {
+        for second in first + 1..infos.len() {
+            let mut aliased = infos.clone();
+            aliased[second] = infos[first].clone();
+
+            let result = CollateralAccounts::next(&mut aliased.iter(), &program_id);
+            assert_eq!(result.err(), Some(duplicate_account()), "accounts {} and {} aliased", first, second);
+        }
+    }
+}
+
+#[test]
+fn test_close_account_into_itself() {
+    let mut account = TestAccount::new();
+    let info = account.info();
+
+    assert_eq!(close_account(&info, &info), Err(duplicate_account()));
+    assert_eq!(info.lamports(), 1_000_000);
+}
@@ -0,0 +1,115 @@
+use solana_escrow::error::LiquityError;
+use solana_escrow::instruction::{Authorization, LiquityInstruction};
+use solana_escrow::processor::Processor;
+use solana_program::{
+    account_info::AccountInfo,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+mod common;
+use common::{deposit, protocol, trove, TestAccount};
+
+fn process(program_id: &Pubkey, accounts: &mut [TestAccount], data: &[u8]) -> Result<(), ProgramError> {
+    let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+    Processor::process(program_id, &infos, data)
+}
+
+#[test]
+fn test_redeem_coin_only_for_trove_owner() {
+    let program_id = Pubkey::new_unique();
+    let mut accounts = [
+        TestAccount::signer(),
+        TestAccount::program(&program_id, trove(Pubkey::new_unique())),
+        TestAccount::new(),
+        TestAccount::clock(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+    ];
+    let mut data = vec![5];
+    data.extend_from_slice(&1u64.to_le_bytes());
+
+    assert_eq!(
+        process(&program_id, &mut accounts, &data),
+        Err(LiquityError::OnlyForTroveOwner.into()),
+    );
+}
+
+#[test]
+fn test_close_trove_only_for_trove_owner() {
+    let program_id = Pubkey::new_unique();
+    let mut accounts = [
+        TestAccount::signer(),
+        TestAccount::program(&program_id, trove(Pubkey::new_unique())),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::clock(),
+        TestAccount::new(),
+    ];
+
+    assert_eq!(
+        process(&program_id, &mut accounts, &[1]),
+        Err(LiquityError::OnlyForTroveOwner.into()),
+    );
+}
+
+#[test]
+fn test_withdraw_deposit_only_for_deposit_owner() {
+    let program_id = Pubkey::new_unique();
+    let mut accounts = [
+        TestAccount::signer(),
+        TestAccount::program(&program_id, deposit(Pubkey::new_unique())),
+        TestAccount::program(&program_id, protocol()),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+        TestAccount::new(),
+    ];
+    let mut data = vec![7];
+    data.extend_from_slice(&1u64.to_le_bytes());
+
+    assert_eq!(
+        process(&program_id, &mut accounts, &data),
+        Err(LiquityError::OnlyForDepositOwner.into()),
+    );
+
+    // The owner gets as far as the accounts minting the withdrawn stablecoin back
+    accounts[1] = TestAccount::program(&program_id, deposit(accounts[0].key));
+    assert_eq!(process(&program_id, &mut accounts, &data), Err(LiquityError::InvalidTokenProgram.into()));
+}
+
+#[test]
+fn test_missing_signature() {
+    let program_id = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut accounts = [
+        TestAccount { key: owner, ..TestAccount::new() },
+        TestAccount::program(&program_id, deposit(owner)),
+    ];
+    let mut data = vec![7];
+    data.extend_from_slice(&1u64.to_le_bytes());
+
+    assert_eq!(
+        process(&program_id, &mut accounts, &data),
+        Err(ProgramError::MissingRequiredSignature),
+    );
+}
+
+#[test]
+fn test_authorization() {
+    let authorization = |data: &[u8]| LiquityInstruction::unpack(data).unwrap().authorization();
+
+    assert_eq!(authorization(&[1]), Authorization::Owner);
+    assert_eq!(authorization(&[5, 0, 0, 0, 0, 0, 0, 0, 0]), Authorization::Owner);
+    assert_eq!(authorization(&[7, 0, 0, 0, 0, 0, 0, 0, 0]), Authorization::Owner);
+    assert_eq!(authorization(&[15, 0, 0, 0, 0, 0, 0, 0, 0]), Authorization::Delegate);
+    assert_eq!(authorization(&[19]), Authorization::Delegate);
+    assert_eq!(authorization(&[9]), Authorization::Authority);
+    assert_eq!(authorization(&[2]), Authorization::AnySigner);
+    assert_eq!(authorization(&[20]), Authorization::AnySigner);
+    assert_eq!(authorization(&[18]), Authorization::Permissionless);
+}
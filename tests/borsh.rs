@@ -0,0 +1,93 @@
+#![cfg(feature = "borsh")]
+
+use borsh::{schema_container_of, BorshDeserialize, BorshSerialize};
+use solana_escrow::event::LiquityEvent;
+use solana_escrow::instruction::LiquityInstruction;
+use solana_escrow::state::{CollateralType, Deposit, Escrow, Protocol, Trove, HEADER_LEN};
+use solana_escrow::units::StableAmount;
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+
+mod common;
+use common::{deposit, events, instructions, protocol, trove};
+
+fn escrow() -> Escrow {
+    Escrow {
+        is_initialized: true,
+        initializer_pubkey: Pubkey::new_unique(),
+        temp_token_account_pubkey: Pubkey::new_unique(),
+        initializer_token_to_receive_account_pubkey: Pubkey::new_unique(),
+        expected_amount: 1,
+    }
+}
+
+fn collateral_type() -> CollateralType {
+    CollateralType {
+        is_initialized: true,
+        collateral: Pubkey::new_unique(),
+        debt_ceiling: StableAmount(1),
+        total_debt: StableAmount(2),
+    }
+}
+
+fn pack<T: Pack>(state: &T) -> Vec<u8> {
+    let mut data = vec![0; T::LEN];
+    state.pack_into_slice(&mut data);
+    data
+}
+
+/// The Borsh encoding of the state is the packed account without its header
+fn assert_compatible<T: Pack + BorshSerialize + BorshDeserialize>(state: T) {
+    let data = pack(&state);
+    assert_eq!(borsh::to_vec(&state).unwrap(), data[HEADER_LEN..]);
+
+    let decoded = T::try_from_slice(&data[HEADER_LEN..]).unwrap();
+    assert_eq!(pack(&decoded), data);
+}
+
+#[test]
+fn test_instruction_compatible() {
+    for instruction in instructions() {
+        let packed = instruction.pack();
+        assert_eq!(borsh::to_vec(&instruction).unwrap(), packed);
+
+        let decoded = LiquityInstruction::try_from_slice(&packed).unwrap();
+        assert_eq!(decoded.pack(), packed);
+    }
+}
+
+#[test]
+fn test_event_compatible() {
+    for event in events() {
+        let packed = event.pack();
+        assert_eq!(borsh::to_vec(&event).unwrap(), packed);
+        assert_eq!(LiquityEvent::try_from_slice(&packed).unwrap(), event);
+    }
+}
+
+#[test]
+fn test_state_compatible() {
+    assert_compatible(Trove { is_received: true, borrow_index: u128::MAX - 6, ..trove(Pubkey::new_unique()) });
+    assert_compatible(deposit(Pubkey::new_unique()));
+    assert_compatible(escrow());
+    assert_compatible(Protocol { last_update_timestamp: -2, paused: 3, ..protocol() });
+    assert_compatible(collateral_type());
+}
+
+#[test]
+fn test_legacy_layout() {
+    // Legacy deposits and escrows are the current layout without the header
+    let data = pack(&deposit(Pubkey::new_unique()));
+    let legacy = Deposit::unpack(&data[HEADER_LEN..]).unwrap();
+    assert_eq!(borsh::to_vec(&legacy).unwrap(), data[HEADER_LEN..]);
+
+    let data = pack(&escrow());
+    let legacy = Escrow::unpack(&data[HEADER_LEN..]).unwrap();
+    assert_eq!(borsh::to_vec(&legacy).unwrap(), data[HEADER_LEN..]);
+}
+
+#[test]
+fn test_schema() {
+    assert_eq!(schema_container_of::<Trove>().declaration(), "Trove");
+    assert_eq!(schema_container_of::<LiquityInstruction>().declaration(), "LiquityInstruction");
+    assert!(schema_container_of::<Deposit>().get_definition("StableAmount").is_some());
+}
@@ -0,0 +1,117 @@
+#![cfg(feature = "cli")]
+
+use solana_escrow::state::Trove;
+use solana_escrow::units::{CollateralAmount, StableAmount};
+use solana_program::{hash::Hash, program_pack::Pack, pubkey::Pubkey};
+use solana_sdk::{
+    signature::{keypair_from_seed, write_keypair_file, Signer},
+    transaction::Transaction,
+};
+use std::{fs, path::PathBuf, process::Command};
+
+/// Writes a keypair file in the `solana-keygen` format, returning its path and pubkey
+fn keypair_file(name: &str, seed: u8) -> (PathBuf, Pubkey) {
+    let keypair = keypair_from_seed(&[seed; 32]).unwrap();
+    let path = std::env::temp_dir().join(format!("liquity-cli-{}-{}.json", name, std::process::id()));
+    write_keypair_file(&keypair, &path).unwrap();
+    (path, keypair.pubkey())
+}
+
+fn cli(args: &[&str]) -> Result<String, String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_liquity-cli")).args(args).output().unwrap();
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout).unwrap())
+    } else {
+        Err(String::from_utf8(output.stderr).unwrap())
+    }
+}
+
+#[test]
+fn test_tx_offline() {
+    let program_id = Pubkey::new_unique().to_string();
+    let (keypair, borrower) = keypair_file("borrower", 1);
+    let [trove, protocol, collateral_type, mint, user_token] = [(); 5].map(|_| Pubkey::new_unique().to_string());
+    let blockhash = Hash::new_unique().to_string();
+
+    let transaction = cli(&[
+        "tx", "--program-id", &program_id, "--keypair", keypair.to_str().unwrap(), "--blockhash", &blockhash,
+        "borrow", "--borrower", &borrower.to_string(), "--trove", &trove, "--protocol", &protocol,
+        "--collateral-type", &collateral_type, "--stablecoin-mint", &mint, "--user-token", &user_token,
+        "--borrow-amount", "1000", "--lamports", "2000",
+    ])
+    .unwrap();
+
+    // Signed by the borrower alone, over the given blockhash
+    let decoded: Transaction = bincode::deserialize(&base64::decode(transaction.trim()).unwrap()).unwrap();
+    assert_eq!(decoded.signatures.len(), 1);
+    assert_eq!(decoded.message.account_keys[0], borrower);
+    assert_eq!(decoded.message.recent_blockhash.to_string(), blockhash);
+    decoded.verify().unwrap();
+
+    let inspected = cli(&["inspect", "--program-id", &program_id, transaction.trim()]).unwrap();
+    assert!(inspected.contains(&format!("Recent blockhash: {}", blockhash)));
+    assert!(inspected.contains(&format!("Signature of {}", borrower)));
+    assert!(inspected.contains(&format!("{} signer writable", borrower)));
+    assert!(inspected.contains("Borrow {"));
+    assert!(inspected.contains("borrow_amount: StableAmount(\n        1000,"));
+
+    fs::remove_file(keypair).unwrap();
+}
+
+#[test]
+fn test_tx_missing_signer() {
+    let (keypair, _) = keypair_file("payer", 2);
+    let program_id = Pubkey::new_unique().to_string();
+    let depositor = Pubkey::new_unique().to_string();
+    let deposit = Pubkey::new_unique().to_string();
+    let (protocol, stablecoin_mint, user_token) =
+        (Pubkey::new_unique().to_string(), Pubkey::new_unique().to_string(), Pubkey::new_unique().to_string());
+
+    let err = cli(&[
+        "tx", "--program-id", &program_id, "--keypair", keypair.to_str().unwrap(),
+        "--blockhash", &Hash::new_unique().to_string(),
+        "withdraw-deposit", "--depositor", &depositor, "--deposit", &deposit, "--protocol", &protocol,
+        "--stablecoin-mint", &stablecoin_mint, "--user-token", &user_token, "--amount", "1",
+    ])
+    .unwrap_err();
+    assert!(err.contains(&format!("missing --keypair of signer {}", depositor)));
+
+    fs::remove_file(keypair).unwrap();
+}
+
+#[test]
+fn test_decode() {
+    let trove = Trove {
+        is_initialized: true,
+        is_received: false,
+        is_liquidated: false,
+        borrow_amount: StableAmount(1234),
+        collateral_amount: CollateralAmount(5678),
+        team_fee: StableAmount(0),
+        depositor_fee: StableAmount(0),
+        amount_to_close: StableAmount(1234),
+        owner: Pubkey::new_unique(),
+        collateral: Pubkey::default(),
+        borrow_index: 1,
+    };
+    let mut data = vec![0; Trove::LEN];
+    trove.pack_into_slice(&mut data);
+    let data = base64::encode(&data);
+
+    let dump = std::env::temp_dir().join(format!("liquity-cli-dump-{}.txt", std::process::id()));
+    fs::write(&dump, &data).unwrap();
+    let decoded = cli(&["decode", dump.to_str().unwrap()]).unwrap();
+    assert!(decoded.starts_with("Trove("));
+    assert!(decoded.contains(&trove.owner.to_string()));
+
+    // A `getAccountInfo` response
+    let response = serde_json::json!({
+        "jsonrpc": "2.0",
+        "result": { "context": { "slot": 1 }, "value": { "data": [data, "base64"], "lamports": 1 } },
+        "id": 1,
+    });
+    fs::write(&dump, response.to_string()).unwrap();
+    assert_eq!(cli(&["decode", dump.to_str().unwrap()]).unwrap(), decoded);
+
+    fs::remove_file(dump).unwrap();
+}
@@ -0,0 +1,143 @@
+#![cfg(feature = "client")]
+
+use solana_escrow::client::{self, ProgramAccountState};
+use solana_escrow::collateral::Collateral;
+use solana_escrow::error::LiquityError;
+use solana_escrow::helpers::{check_min_collateral_ratio, get_sol_price};
+use solana_escrow::params::INITIAL_BORROW_INDEX;
+use solana_escrow::state::{AccountType, Deposit, Trove};
+use solana_escrow::units::{CollateralAmount, Lamports, Price, StableAmount};
+use solana_program::{native_token::LAMPORTS_PER_SOL, program_pack::Pack, pubkey::Pubkey};
+
+mod common;
+use common::{config, protocol};
+
+const DECIMALS: u8 = 9;
+
+fn trove(borrow_amount: u64, collateral_amount: u64) -> Trove {
+    Trove {
+        borrow_amount: StableAmount(borrow_amount),
+        collateral_amount: CollateralAmount(collateral_amount),
+        amount_to_close: StableAmount(borrow_amount),
+        ..common::trove(Pubkey::new_unique())
+    }
+}
+
+fn pack<T: Pack>(state: &T) -> Vec<u8> {
+    let mut data = vec![0; T::LEN];
+    state.pack_into_slice(&mut data);
+    data
+}
+
+fn price() -> Price {
+    get_sol_price(DECIMALS).unwrap()
+}
+
+/// The on-chain check of `amount` of `collateral` against the debt
+fn is_covered(debt: StableAmount, collateral: &Collateral, amount: u64) -> bool {
+    check_min_collateral_ratio(debt, collateral, CollateralAmount(amount), &config(), DECIMALS).unwrap()
+}
+
+#[test]
+fn test_decode() {
+    let trove = trove(100, 200);
+    let data = pack(&trove);
+
+    assert_eq!(pack(&client::decode_trove(&data).unwrap()), data);
+    assert!(matches!(client::decode_account(&data), Ok(ProgramAccountState::Trove(_))));
+    assert_eq!(client::decode_deposit(&data).err(), Some(LiquityError::InvalidAccountType.into()));
+
+    let mut closed = vec![0; Trove::LEN];
+    closed[0] = AccountType::Closed as u8;
+    assert_eq!(client::decode_trove(&closed).err(), Some(LiquityError::AccountClosed.into()));
+    assert_eq!(client::decode_account(&closed).err(), Some(LiquityError::AccountClosed.into()));
+
+    assert!(matches!(client::decode_account(&[0; Deposit::LEN]), Ok(ProgramAccountState::Uninitialized)));
+}
+
+#[test]
+fn test_decode_legacy() {
+    let deposit = Deposit {
+        is_initialized: true,
+        token_amount: StableAmount(1),
+        reward_token_amount: StableAmount(2),
+        reward_governance_token_amount: Default::default(),
+        reward_coin_amount: Lamports(3),
+        bank: Pubkey::new_unique(),
+        governance_bank: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+    };
+    let data = pack(&deposit);
+
+    let legacy = client::decode_deposit(&data[Deposit::LEN - Deposit::LEGACY_LEN..]).unwrap();
+    assert_eq!(pack(&legacy), data);
+}
+
+#[test]
+fn test_max_borrow() {
+    let lamports = Lamports(3 * LAMPORTS_PER_SOL + 7);
+    let max = client::max_borrow(lamports, price(), &config()).unwrap();
+
+    assert!(is_covered(max, &Collateral::Sol, lamports.0));
+    assert!(!is_covered(StableAmount(max.0 + 1), &Collateral::Sol, lamports.0));
+}
+
+#[test]
+fn test_health() {
+    let debt = StableAmount::from_whole(100, DECIMALS).unwrap();
+    let trove = trove(debt.0, 2 * LAMPORTS_PER_SOL);
+
+    let health = trove.health(&Collateral::Sol, price(), &protocol(), 0).unwrap();
+    assert_eq!(health.debt, debt);
+    assert_eq!(health.collateral_value, StableAmount::from_whole(140, DECIMALS).unwrap());
+    assert_eq!(health.collateral_ratio.unwrap().0, 14_000);
+    assert!(health.is_collateralized);
+
+    // Withdrawing more than the maximum fails the check of the program
+    let remaining = trove.collateral_amount.0 - health.max_withdrawable.0;
+    assert!(is_covered(debt, &Collateral::Sol, remaining));
+    assert!(!is_covered(debt, &Collateral::Sol, remaining - 1));
+
+    // Below the liquidation price the trove is no longer collateralized
+    let liquidation_price = health.liquidation_price.unwrap();
+    let at = trove.health(&Collateral::Sol, liquidation_price, &protocol(), 0).unwrap();
+    let below = trove.health(&Collateral::Sol, Price(StableAmount((liquidation_price.0).0 - 1)), &protocol(), 0).unwrap();
+    assert!(at.is_collateralized);
+    assert!(!below.is_collateralized);
+    assert_eq!(below.max_withdrawable, CollateralAmount::ZERO);
+}
+
+#[test]
+fn test_health_stake_pool() {
+    let collateral = Collateral::StakePool { total_lamports: 1_100_000_007, pool_token_supply: 1_000_000_000 };
+    let debt = StableAmount::from_whole(50, DECIMALS).unwrap();
+    let trove = trove(debt.0, LAMPORTS_PER_SOL);
+
+    let health = trove.health(&collateral, price(), &protocol(), 0).unwrap();
+    let remaining = trove.collateral_amount.0 - health.max_withdrawable.0;
+    assert!(is_covered(debt, &collateral, remaining));
+    assert!(!is_covered(debt, &collateral, remaining - 1));
+}
+
+#[test]
+fn test_health_accrues_interest() {
+    let trove = trove(StableAmount::from_whole(100, DECIMALS).unwrap().0, 2 * LAMPORTS_PER_SOL);
+    let protocol = protocol();
+    let year = 365 * 24 * 60 * 60;
+
+    let health = trove.health(&Collateral::Sol, price(), &protocol, year).unwrap();
+    let borrow_index = client::borrow_index_at(&protocol, year).unwrap();
+    assert!(borrow_index > INITIAL_BORROW_INDEX);
+    assert_eq!(health.debt, trove.debt(borrow_index).unwrap());
+    assert!(health.debt > trove.borrow_amount);
+}
+
+#[test]
+fn test_health_without_debt() {
+    let trove = trove(0, LAMPORTS_PER_SOL);
+
+    let health = trove.health(&Collateral::Sol, price(), &protocol(), 0).unwrap();
+    assert_eq!(health.collateral_ratio, None);
+    assert_eq!(health.liquidation_price, None);
+    assert_eq!(health.max_withdrawable, trove.collateral_amount);
+}
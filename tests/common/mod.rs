@@ -0,0 +1,210 @@
+// Each test crate uses its own part of these helpers
+#![allow(dead_code)]
+
+use solana_escrow::event::LiquityEvent;
+use solana_escrow::instruction::LiquityInstruction;
+use solana_escrow::params::{INITIAL_BORROW_INDEX, SYSTEM_ACCOUNT_ADDRESS};
+use solana_escrow::state::{Deposit, Protocol, ProtocolConfig, Trove};
+use solana_escrow::units::{CollateralAmount, GovAmount, Lamports, Ratio, StableAmount};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    sysvar::{self, Sysvar},
+};
+
+/// Storage behind an `AccountInfo` of an instruction
+pub struct TestAccount {
+    pub key: Pubkey,
+    pub is_signer: bool,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+}
+
+impl TestAccount {
+    pub fn new() -> Self {
+        TestAccount {
+            key: Pubkey::new_unique(),
+            is_signer: false,
+            lamports: 1_000_000,
+            data: vec![],
+            owner: Pubkey::new_unique(),
+        }
+    }
+
+    pub fn signer() -> Self {
+        TestAccount { is_signer: true, ..Self::new() }
+    }
+
+    pub fn system() -> Self {
+        TestAccount { key: SYSTEM_ACCOUNT_ADDRESS, ..Self::signer() }
+    }
+
+    pub fn sysvar<S: Sysvar>(key: Pubkey, sysvar: &S) -> Self {
+        let mut account = TestAccount {
+            key,
+            data: vec![0; S::size_of()],
+            owner: sysvar::id(),
+            ..Self::new()
+        };
+        sysvar.to_account_info(&mut account.info()).unwrap();
+        account
+    }
+
+    /// Account owned by `program_id` holding `state` in its current layout
+    pub fn program<T: Pack>(program_id: &Pubkey, state: T) -> Self {
+        let mut account = TestAccount {
+            data: vec![0; T::LEN],
+            owner: *program_id,
+            ..Self::new()
+        };
+        state.pack_into_slice(&mut account.data);
+        account
+    }
+
+    pub fn rent() -> Self {
+        Self::sysvar(sysvar::rent::id(), &Rent::default())
+    }
+
+    pub fn clock() -> Self {
+        Self::sysvar(sysvar::clock::id(), &Clock::default())
+    }
+
+    pub fn info(&mut self) -> AccountInfo<'_> {
+        AccountInfo::new(
+            &self.key,
+            self.is_signer,
+            true,
+            &mut self.lamports,
+            &mut self.data,
+            &self.owner,
+            false,
+            0,
+        )
+    }
+}
+
+pub fn config() -> ProtocolConfig {
+    ProtocolConfig {
+        interest_rate: Ratio(5),
+        flash_mint_fee: Ratio(9),
+        pauser: Pubkey::new_unique(),
+        debt_ceiling: StableAmount(1_000_000),
+        admin: Pubkey::new_unique(),
+        guardian: Pubkey::new_unique(),
+        min_collateral_ratio: Ratio(11_000),
+        deposit_fee: Ratio(300),
+        team_fee: Ratio(100),
+        oracle: Pubkey::new_unique(),
+        timelock_delay: 86_400,
+    }
+}
+
+/// Open trove of `owner` owing 1000 against 1000 lamports of native SOL, without interest
+/// since its snapshot of the `protocol` borrow index
+pub fn trove(owner: Pubkey) -> Trove {
+    Trove {
+        is_initialized: true,
+        is_received: false,
+        is_liquidated: false,
+        borrow_amount: StableAmount(1_000),
+        collateral_amount: CollateralAmount(1_000),
+        team_fee: StableAmount::ZERO,
+        depositor_fee: StableAmount::ZERO,
+        amount_to_close: StableAmount(1_000),
+        owner,
+        collateral: Pubkey::default(),
+        borrow_index: INITIAL_BORROW_INDEX,
+    }
+}
+
+/// Deposit of 1000 by `owner`, without rewards
+pub fn deposit(owner: Pubkey) -> Deposit {
+    Deposit {
+        is_initialized: true,
+        token_amount: StableAmount(1_000),
+        reward_token_amount: StableAmount::ZERO,
+        reward_governance_token_amount: GovAmount::ZERO,
+        reward_coin_amount: Lamports::ZERO,
+        bank: Pubkey::new_unique(),
+        governance_bank: Pubkey::new_unique(),
+        owner,
+    }
+}
+
+/// Protocol of a stablecoin with 9 decimals, its total debt that of one `trove`
+pub fn protocol() -> Protocol {
+    Protocol {
+        is_initialized: true,
+        stablecoin_mint: Pubkey::new_unique(),
+        stablecoin_decimals: 9,
+        config: config(),
+        borrow_index: INITIAL_BORROW_INDEX,
+        last_update_timestamp: 0,
+        paused: 0,
+        total_debt: StableAmount(1_000),
+        pending_config: config(),
+        pending_eta: 0,
+    }
+}
+
+/// One instruction of every kind, in tag order
+pub fn instructions() -> Vec<LiquityInstruction> {
+    vec![
+        LiquityInstruction::Borrow { borrow_amount: StableAmount(1), lamports: Lamports(2) },
+        LiquityInstruction::CloseTrove {},
+        LiquityInstruction::LiquidateTrove {},
+        LiquityInstruction::WithdrawCoin { amount: CollateralAmount(3) },
+        LiquityInstruction::AddCoin { amount: CollateralAmount(4) },
+        LiquityInstruction::RedeemCoin { amount: CollateralAmount(5) },
+        LiquityInstruction::AddDeposit { amount: StableAmount(6) },
+        LiquityInstruction::WithdrawDeposit { amount: StableAmount(7) },
+        LiquityInstruction::ClaimDepositReward {},
+        LiquityInstruction::ReceiveTrove {},
+        LiquityInstruction::AddDepositReward { coin: Lamports(8), governance: GovAmount(9), token: StableAmount(10) },
+        LiquityInstruction::BorrowWithStakePoolToken { borrow_amount: StableAmount(11), pool_tokens: CollateralAmount(12) },
+        LiquityInstruction::InitProtocol { config: config() },
+        LiquityInstruction::QueueConfigChange { config: config() },
+        LiquityInstruction::FlashMint { amount: StableAmount(13) },
+        LiquityInstruction::SetPause { paused: 14 },
+        LiquityInstruction::InitCollateralType { collateral: Pubkey::new_unique(), debt_ceiling: StableAmount(15) },
+        LiquityInstruction::UpdateCollateralType { debt_ceiling: StableAmount(16) },
+        LiquityInstruction::ExecuteConfigChange {},
+        LiquityInstruction::CancelConfigChange {},
+        LiquityInstruction::MigrateAccount {},
+    ]
+}
+
+/// One event of every kind, in tag order
+pub fn events() -> Vec<LiquityEvent> {
+    let [trove, owner, deposit] = [(); 3].map(|_| Pubkey::new_unique());
+    vec![
+        LiquityEvent::TroveOpened {
+            trove,
+            owner,
+            collateral: Pubkey::new_unique(),
+            collateral_amount: CollateralAmount(1),
+            borrow_amount: StableAmount(2),
+            debt: StableAmount(3),
+            depositor_fee: StableAmount(4),
+            team_fee: StableAmount(5),
+        },
+        LiquityEvent::TroveAdjusted { trove, collateral_amount: CollateralAmount(1), debt: StableAmount(2) },
+        LiquityEvent::TroveClosed { trove, owner, repaid: StableAmount(1), collateral_amount: CollateralAmount(2) },
+        LiquityEvent::TroveLiquidated { trove, liquidator: owner, debt: StableAmount(1), collateral_amount: CollateralAmount(2) },
+        LiquityEvent::Redemption { trove, amount: CollateralAmount(1), collateral_amount: CollateralAmount(2), repaid: StableAmount(3) },
+        LiquityEvent::DepositChanged { deposit, owner, token_amount: StableAmount(1) },
+        LiquityEvent::RewardClaimed { deposit, owner, coin: Lamports(1), governance: GovAmount(2), token: StableAmount(3) },
+        LiquityEvent::RewardAdded { deposit, coin: Lamports(1), governance: GovAmount(2), token: StableAmount(3) },
+        LiquityEvent::TroveMigrated {
+            trove,
+            owner,
+            collateral: Pubkey::new_unique(),
+            collateral_amount: CollateralAmount(1),
+            debt: StableAmount(2),
+        },
+    ]
+}
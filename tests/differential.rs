@@ -0,0 +1,696 @@
+//! Differential tests of the processor against the reference model in `model/`.
+//! Random operation sequences run on a `solana-program-test` bank and on the model,
+//! the result of every instruction and the state of every account are compared after
+//! each step and the invariants of the model are asserted throughout. Off-chain actions
+//! the protocol relies on, like the system account sending the borrowed stablecoin,
+//! are done on both sides by the test
+
+mod model;
+
+use model::{Account, Model, StakePoolModel, State};
+use proptest::prelude::*;
+use solana_escrow::collateral::{find_authority_address, find_collateral_type_address};
+use solana_escrow::instruction::{self, CoinSource, StakePoolAccounts};
+use solana_escrow::params::{
+    GENS_TOKEN_ADDRESS, PAUSE_ADJUST_TROVE, PAUSE_BORROW, PAUSE_DEPOSIT, PAUSE_LIQUIDATE, PAUSE_RECEIVE_TROVE,
+    PAUSE_REDEEM, PAUSE_REWARDS, STAKE_POOL_PROGRAM_ADDRESS, SYSTEM_ACCOUNT_ADDRESS,
+};
+use solana_escrow::processor::Processor;
+use solana_escrow::state::{Deposit, Protocol, ProtocolConfig, Trove};
+use solana_escrow::units::{CollateralAmount, GovAmount, Lamports, Ratio, StableAmount};
+use solana_program::{
+    clock::Clock,
+    instruction::Instruction,
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account as BankAccount,
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+
+const DECIMALS: u8 = 6;
+const UNIT: u64 = 1_000_000;
+/// Pool tokens have the decimals of SOL
+const POOL_UNIT: u64 = LAMPORTS_PER_SOL;
+const USERS: usize = 3;
+
+/// Everything a user holds, their deposit account still uninitialized at the start
+struct User {
+    keypair: Keypair,
+    stablecoin: Pubkey,
+    governance: Pubkey,
+    pool_token: Pubkey,
+    deposit: Pubkey,
+}
+
+/// Trove account created by the test, opened or not
+struct TroveAccount {
+    address: Pubkey,
+    owner: usize,
+    stake_pool: bool,
+}
+
+/// A protocol with a native SOL and a stake pool collateral type, mirrored by the model
+struct Env {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    rent: Rent,
+    pauser: Keypair,
+    protocol: Pubkey,
+    stablecoin_mint: Pubkey,
+    native_type: Pubkey,
+    stake_pool_type: Pubkey,
+    stake_pool: Pubkey,
+    pool_mint: Pubkey,
+    vault: Pubkey,
+    system_pool_token: Pubkey,
+    users: Vec<User>,
+    troves: Vec<TroveAccount>,
+    signatures: HashSet<Signature>,
+    model: Model,
+}
+
+impl Env {
+    async fn new() -> Self {
+        let program_id = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new("solana_escrow", program_id, processor!(Processor::process));
+        program_test.add_account(
+            SYSTEM_ACCOUNT_ADDRESS,
+            BankAccount { lamports: LAMPORTS_PER_SOL, ..BankAccount::default() },
+        );
+        let mut context = program_test.start_with_context().await;
+        let rent = context.banks_client.get_rent().await.unwrap();
+        let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+
+        let mut env = Env {
+            context,
+            program_id,
+            rent,
+            pauser: Keypair::new(),
+            protocol: Pubkey::default(),
+            stablecoin_mint: Pubkey::new_unique(),
+            native_type: Pubkey::default(),
+            stake_pool_type: Pubkey::default(),
+            stake_pool: Pubkey::new_unique(),
+            pool_mint: Pubkey::new_unique(),
+            vault: Pubkey::default(),
+            system_pool_token: Pubkey::default(),
+            users: Vec::new(),
+            troves: Vec::new(),
+            signatures: HashSet::new(),
+            model: Model::new(program_id, rent, clock.unix_timestamp, BTreeMap::new()),
+        };
+
+        env.set_mint(env.stablecoin_mint, find_authority_address(&program_id).0, DECIMALS, 0).await;
+        env.set_mint(GENS_TOKEN_ADDRESS, Pubkey::new_unique(), DECIMALS, 0).await;
+        env.set_mint(env.pool_mint, Pubkey::new_unique(), 9, USERS as u64 * 100 * POOL_UNIT).await;
+
+        // A pool worth 1.05 SOL per token, updated for the current epoch
+        let mut stake_pool = vec![0; 282];
+        stake_pool[0] = 1;
+        stake_pool[162..194].copy_from_slice(env.pool_mint.as_ref());
+        stake_pool[258..266].copy_from_slice(&(1_050 * LAMPORTS_PER_SOL).to_le_bytes());
+        stake_pool[266..274].copy_from_slice(&(1_000 * POOL_UNIT).to_le_bytes());
+        stake_pool[274..282].copy_from_slice(&clock.epoch.to_le_bytes());
+        env.set_account(env.stake_pool, STAKE_POOL_PROGRAM_ADDRESS, stake_pool).await;
+        env.vault = env.token_account(env.pool_mint, find_authority_address(&program_id).0, 0).await;
+        env.system_pool_token = env.token_account(env.pool_mint, SYSTEM_ACCOUNT_ADDRESS, 0).await;
+
+        let admin = Keypair::new();
+        env.context.set_account(&admin.pubkey(), &BankAccount { lamports: LAMPORTS_PER_SOL, ..BankAccount::default() }.into());
+        let config = ProtocolConfig {
+            interest_rate: Ratio(500),
+            flash_mint_fee: Ratio(9),
+            pauser: env.pauser.pubkey(),
+            debt_ceiling: StableAmount(20_000 * UNIT),
+            admin: admin.pubkey(),
+            guardian: Pubkey::new_unique(),
+            min_collateral_ratio: Ratio(11_000),
+            deposit_fee: Ratio(300),
+            team_fee: Ratio(100),
+            oracle: Pubkey::new_unique(),
+            timelock_delay: 86_400,
+        };
+        env.protocol = env.program_account(Protocol::LEN).await;
+        let ix = instruction::init_protocol(&program_id, &env.protocol, &env.stablecoin_mint, config);
+        env.send(&[ix], &[]).await.unwrap();
+        env.native_type = env.init_collateral_type(&admin, Pubkey::default(), 15_000 * UNIT).await;
+        env.stake_pool_type = env.init_collateral_type(&admin, env.stake_pool, 8_000 * UNIT).await;
+
+        for _ in 0..USERS {
+            let keypair = Keypair::new();
+            let owner = keypair.pubkey();
+            env.context.set_account(
+                &owner,
+                &BankAccount { lamports: 10_000 * LAMPORTS_PER_SOL, ..BankAccount::default() }.into(),
+            );
+            let user = User {
+                stablecoin: env.token_account(env.stablecoin_mint, owner, 0).await,
+                governance: env.token_account(GENS_TOKEN_ADDRESS, owner, 0).await,
+                pool_token: env.token_account(env.pool_mint, owner, 100 * POOL_UNIT).await,
+                deposit: env.program_account(Deposit::LEN).await,
+                keypair,
+            };
+            env.users.push(user);
+        }
+
+        // The model starts from the accounts set up on the bank
+        let mut keys = vec![
+            SYSTEM_ACCOUNT_ADDRESS,
+            env.protocol,
+            env.native_type,
+            env.stake_pool_type,
+            env.stablecoin_mint,
+            env.pool_mint,
+            env.vault,
+            env.system_pool_token,
+        ];
+        for user in &env.users {
+            keys.extend_from_slice(&[user.keypair.pubkey(), user.stablecoin, user.governance, user.pool_token, user.deposit]);
+        }
+        for key in keys {
+            let account = env.chain_account(&key).await;
+            env.model.create(key, account.unwrap());
+        }
+        env.model.unix_timestamp = clock.unix_timestamp;
+        env.model.stake_pools.insert(
+            env.stake_pool,
+            StakePoolModel {
+                pool_mint: env.pool_mint,
+                vault: env.vault,
+                total_lamports: 1_050 * LAMPORTS_PER_SOL,
+                pool_token_supply: 1_000 * POOL_UNIT,
+            },
+        );
+        env.compare().await;
+        env
+    }
+
+    /// Sends a transaction paid by the payer, which the model does not track. Signatures
+    /// of the system account are left empty
+    async fn send(&mut self, instructions: &[Instruction], signers: &[&Keypair]) -> Result<(), TransactionError> {
+        let payer = self.context.payer.insecure_clone();
+        let mut transaction = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+        let mut keypairs = vec![&payer];
+        keypairs.extend_from_slice(signers);
+        transaction.partial_sign(&keypairs, self.context.last_blockhash);
+
+        // A transaction sent again would be rejected as already processed
+        if !self.signatures.insert(transaction.signatures[0]) {
+            self.context.last_blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+            transaction.partial_sign(&keypairs, self.context.last_blockhash);
+            self.signatures.insert(transaction.signatures[0]);
+        }
+
+        self.context.banks_client.process_transaction_with_metadata(transaction).await.unwrap().result
+    }
+
+    /// Sends `ix` to the program and processes it on the model, comparing the results
+    async fn step(&mut self, ix: Instruction, signers: &[&Keypair]) -> bool {
+        self.step_after(&[], ix, signers).await
+    }
+
+    /// Sends `setup` and `ix` in one transaction, the model holding the effect of `setup`
+    async fn step_after(&mut self, setup: &[Instruction], ix: Instruction, signers: &[&Keypair]) -> bool {
+        let expected = self.model.process(&ix);
+        let index = setup.len() as u8;
+        let result = match self.send(&[setup, &[ix]].concat(), signers).await {
+            Ok(()) => Ok(()),
+            Err(TransactionError::InstructionError(failed, error)) if failed == index => {
+                Err(ProgramError::try_from(error).unwrap())
+            }
+            Err(other) => panic!("transaction failed with {:?}", other),
+        };
+        assert_eq!(result, expected);
+        result.is_ok()
+    }
+
+    async fn set_account(&mut self, address: Pubkey, owner: Pubkey, data: Vec<u8>) {
+        let account = BankAccount { lamports: self.rent.minimum_balance(data.len()), data, owner, ..BankAccount::default() };
+        self.context.set_account(&address, &account.into());
+    }
+
+    async fn set_mint(&mut self, address: Pubkey, mint_authority: Pubkey, decimals: u8, supply: u64) {
+        let mint = spl_token::state::Mint {
+            mint_authority: COption::Some(mint_authority),
+            supply,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        mint.pack_into_slice(&mut data);
+        self.set_account(address, spl_token::id(), data).await;
+    }
+
+    async fn token_account(&mut self, mint: Pubkey, owner: Pubkey, amount: u64) -> Pubkey {
+        let address = Pubkey::new_unique();
+        let account = spl_token::state::Account {
+            mint,
+            owner,
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..spl_token::state::Account::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        account.pack_into_slice(&mut data);
+        self.set_account(address, spl_token::id(), data).await;
+        address
+    }
+
+    /// Zeroed rent exempt account of the program
+    async fn program_account(&mut self, len: usize) -> Pubkey {
+        let address = Pubkey::new_unique();
+        self.set_account(address, self.program_id, vec![0; len]).await;
+        address
+    }
+
+    async fn init_collateral_type(&mut self, admin: &Keypair, collateral: Pubkey, debt_ceiling: u64) -> Pubkey {
+        let (address, _) = find_collateral_type_address(&self.program_id, &self.protocol, &collateral);
+        let ix = instruction::init_collateral_type(
+            &self.program_id,
+            &admin.pubkey(),
+            &self.protocol,
+            &address,
+            collateral,
+            StableAmount(debt_ceiling),
+        );
+        self.send(&[ix], &[admin]).await.unwrap();
+        address
+    }
+
+    async fn chain_account(&mut self, key: &Pubkey) -> Option<Account> {
+        let account = self.context.banks_client.get_account(*key).await.unwrap()?;
+        Some(Account::from_chain(&self.program_id, &account.owner, account.lamports, &account.data))
+    }
+
+    /// Asserts every account the model tracks matches the bank, then the invariants
+    async fn compare(&mut self) {
+        let keys: Vec<Pubkey> = self.model.accounts.keys().copied().collect();
+        for key in keys {
+            let account = self.chain_account(&key).await;
+            assert_eq!(account, self.model.account(&key), "account {}", key);
+        }
+        self.model.check_invariants();
+    }
+
+    /// Mints stablecoin to `user`, as bought outside of the protocol. Only the program mints
+    /// the stablecoin, the bank accounts are written instead
+    async fn buy(&mut self, user: usize, amount: u64) {
+        let stablecoin = self.users[user].stablecoin;
+        let stablecoin_mint = self.stablecoin_mint;
+        self.model.mint(&stablecoin_mint, &stablecoin, amount);
+        self.model.ledger.bought += amount;
+
+        let owner = self.users[user].keypair.pubkey();
+        let token = spl_token::state::Account {
+            mint: stablecoin_mint,
+            owner,
+            amount: self.model.token_amount(&stablecoin),
+            state: spl_token::state::AccountState::Initialized,
+            ..spl_token::state::Account::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        token.pack_into_slice(&mut data);
+        self.set_account(stablecoin, spl_token::id(), data).await;
+
+        let supply = match self.model.account(&stablecoin_mint) {
+            Some(Account::Mint { supply }) => supply,
+            other => panic!("stablecoin mint {:?}", other),
+        };
+        self.set_mint(stablecoin_mint, find_authority_address(&self.program_id).0, DECIMALS, supply).await;
+    }
+
+    /// Creates a trove account funded by `owner` with rent and `lamports` of collateral
+    async fn create_trove(&mut self, owner: usize, lamports: u64, stake_pool: bool) -> Pubkey {
+        let keypair = Keypair::new();
+        let funder = self.users[owner].keypair.insecure_clone();
+        let lamports = self.rent.minimum_balance(Trove::LEN) + lamports;
+        let ix = system_instruction::create_account(
+            &funder.pubkey(),
+            &keypair.pubkey(),
+            lamports,
+            Trove::LEN as u64,
+            &self.program_id,
+        );
+        self.send(&[ix], &[&funder, &keypair]).await.unwrap();
+        self.model.create_program_account(&funder.pubkey(), keypair.pubkey(), lamports, Trove::LEN);
+        self.troves.push(TroveAccount { address: keypair.pubkey(), owner, stake_pool });
+        keypair.pubkey()
+    }
+
+    fn stake_pool_accounts(&self, user_token: Pubkey) -> StakePoolAccounts {
+        StakePoolAccounts { stake_pool: self.stake_pool, vault: self.vault, user_token }
+    }
+
+    fn collateral_type(&self, stake_pool: bool) -> Pubkey {
+        if stake_pool {
+            self.stake_pool_type
+        } else {
+            self.native_type
+        }
+    }
+
+    /// The trove picked by `index` and the user acting on it, its owner unless `impostor`
+    fn pick_trove(&self, index: usize, impostor: bool) -> Option<(Pubkey, usize, bool)> {
+        if self.troves.is_empty() {
+            return None;
+        }
+        let trove = &self.troves[index % self.troves.len()];
+        let user = if impostor { (trove.owner + 1) % USERS } else { trove.owner };
+        Some((trove.address, user, trove.stake_pool))
+    }
+
+    async fn run(&mut self, op: Op) {
+        let program_id = self.program_id;
+        match op {
+            Op::Borrow { user, borrow_amount, lamports } => {
+                let trove = self.create_trove(user, lamports, false).await;
+                let borrower = self.users[user].keypair.insecure_clone();
+                let ix = instruction::borrow(
+                    &program_id,
+                    &borrower.pubkey(),
+                    &trove,
+                    &self.protocol,
+                    &self.native_type,
+                    &self.stablecoin_mint,
+                    &self.users[user].stablecoin,
+                    StableAmount(borrow_amount),
+                    Lamports(lamports),
+                );
+                self.step(ix, &[&borrower]).await;
+            }
+            Op::BorrowWithStakePoolToken { user, borrow_amount, pool_tokens } => {
+                let trove = self.create_trove(user, 0, true).await;
+                let borrower = self.users[user].keypair.insecure_clone();
+                let ix = instruction::borrow_with_stake_pool_token(
+                    &program_id,
+                    &borrower.pubkey(),
+                    &trove,
+                    &self.protocol,
+                    &self.stake_pool_type,
+                    &self.stake_pool_accounts(self.users[user].pool_token),
+                    &self.stablecoin_mint,
+                    &self.users[user].stablecoin,
+                    StableAmount(borrow_amount),
+                    CollateralAmount(pool_tokens),
+                );
+                self.step(ix, &[&borrower]).await;
+            }
+            Op::ReceiveTrove { trove } => {
+                let (trove, _, _) = match self.pick_trove(trove, false) {
+                    Some(picked) => picked,
+                    None => return,
+                };
+                let ix = instruction::receive_trove(&program_id, &trove, &self.protocol);
+                self.step(ix, &[]).await;
+            }
+            Op::AddCoin { trove, impostor, amount, mismatch } => {
+                let (trove, user, stake_pool) = match self.pick_trove(trove, impostor) {
+                    Some(picked) => picked,
+                    None => return,
+                };
+                let borrower = self.users[user].keypair.insecure_clone();
+                if stake_pool {
+                    let source = CoinSource::StakePool(self.stake_pool_accounts(self.users[user].pool_token));
+                    let ix = instruction::add_coin(&program_id, &borrower.pubkey(), &trove, &self.protocol, &source, CollateralAmount(amount));
+                    self.step(ix, &[&borrower]).await;
+                } else {
+                    // The temp account is created in the same transaction, it is swept before the rent is due
+                    let temp = Keypair::new();
+                    let funded = if mismatch { amount + LAMPORTS_PER_SOL } else { amount };
+                    let create = system_instruction::create_account(&borrower.pubkey(), &temp.pubkey(), funded, 0, &program_id);
+                    let source = CoinSource::Lamports(temp.pubkey());
+                    let ix = instruction::add_coin(&program_id, &borrower.pubkey(), &trove, &self.protocol, &source, CollateralAmount(amount));
+
+                    let before = self.model.clone();
+                    self.model.create_program_account(&borrower.pubkey(), temp.pubkey(), funded, 0);
+                    if !self.step_after(&[create], ix, &[&borrower, &temp]).await {
+                        self.model = before;
+                    }
+                }
+            }
+            Op::WithdrawCoin { trove, impostor, amount } => {
+                let (trove, user, stake_pool) = match self.pick_trove(trove, impostor) {
+                    Some(picked) => picked,
+                    None => return,
+                };
+                let borrower = self.users[user].keypair.insecure_clone();
+                let accounts = self.stake_pool_accounts(self.users[user].pool_token);
+                let ix = instruction::withdraw_coin(
+                    &program_id,
+                    &borrower.pubkey(),
+                    &trove,
+                    &self.protocol,
+                    &self.collateral_type(stake_pool),
+                    Some(&accounts).filter(|_| stake_pool),
+                    CollateralAmount(amount),
+                );
+                self.step(ix, &[&borrower]).await;
+            }
+            Op::RedeemCoin { trove, impostor, amount } => {
+                let (trove, user, stake_pool) = match self.pick_trove(trove, impostor) {
+                    Some(picked) => picked,
+                    None => return,
+                };
+                let borrower = self.users[user].keypair.insecure_clone();
+                let accounts = self.stake_pool_accounts(self.users[user].pool_token);
+                let ix = instruction::redeem_coin(
+                    &program_id,
+                    &borrower.pubkey(),
+                    &trove,
+                    &self.protocol,
+                    &self.collateral_type(stake_pool),
+                    &self.users[user].stablecoin,
+                    &self.stablecoin_mint,
+                    Some(&accounts).filter(|_| stake_pool),
+                    CollateralAmount(amount),
+                );
+                self.step(ix, &[&borrower]).await;
+            }
+            Op::CloseTrove { trove, impostor, repay } => {
+                let (trove, user, stake_pool) = match self.pick_trove(trove, impostor) {
+                    Some(picked) => picked,
+                    None => return,
+                };
+                if repay {
+                    // Buys what the stablecoin received falls short of the debt with its interest
+                    let balance = self.model.token_amount(&self.users[user].stablecoin);
+                    if let Ok(debt) = self.model.debt_with_interest(&trove) {
+                        if debt > balance {
+                            self.buy(user, debt - balance).await;
+                        }
+                    }
+                }
+                let borrower = self.users[user].keypair.insecure_clone();
+                let accounts = self.stake_pool_accounts(self.users[user].pool_token);
+                let ix = instruction::close_trove(
+                    &program_id,
+                    &borrower.pubkey(),
+                    &trove,
+                    &self.users[user].stablecoin,
+                    &self.stablecoin_mint,
+                    &self.protocol,
+                    &self.collateral_type(stake_pool),
+                    Some(&accounts).filter(|_| stake_pool),
+                );
+                self.step(ix, &[&borrower]).await;
+            }
+            Op::LiquidateTrove { trove, liquidator } => {
+                let (trove, _, stake_pool) = match self.pick_trove(trove, false) {
+                    Some(picked) => picked,
+                    None => return,
+                };
+                let liquidator = self.users[liquidator].keypair.insecure_clone();
+                let accounts = self.stake_pool_accounts(self.system_pool_token);
+                let ix = instruction::liquidate_trove(
+                    &program_id,
+                    &liquidator.pubkey(),
+                    &trove,
+                    &self.protocol,
+                    &self.collateral_type(stake_pool),
+                    Some(&accounts).filter(|_| stake_pool),
+                );
+                self.step(ix, &[&liquidator]).await;
+            }
+            Op::AddDeposit { deposit, impostor, amount, buy } => {
+                let user = if impostor { (deposit + 1) % USERS } else { deposit };
+                if buy {
+                    self.buy(user, amount).await;
+                }
+                let depositor = self.users[user].keypair.insecure_clone();
+                let ix = instruction::add_deposit(
+                    &program_id,
+                    &depositor.pubkey(),
+                    &self.users[deposit].deposit,
+                    &self.users[user].stablecoin,
+                    &self.users[user].governance,
+                    &self.stablecoin_mint,
+                    &self.protocol,
+                    StableAmount(amount),
+                );
+                self.step(ix, &[&depositor]).await;
+            }
+            Op::WithdrawDeposit { deposit, impostor, amount } => {
+                let user = if impostor { (deposit + 1) % USERS } else { deposit };
+                let depositor = self.users[user].keypair.insecure_clone();
+                let ix = instruction::withdraw_deposit(
+                    &program_id,
+                    &depositor.pubkey(),
+                    &self.users[deposit].deposit,
+                    &self.protocol,
+                    &self.stablecoin_mint,
+                    &self.users[user].stablecoin,
+                    StableAmount(amount),
+                );
+                self.step(ix, &[&depositor]).await;
+            }
+            Op::AddDepositReward { deposit, coin, governance, token } => {
+                let ix = instruction::add_deposit_reward(
+                    &program_id,
+                    &self.users[deposit].deposit,
+                    &self.protocol,
+                    Lamports(coin),
+                    GovAmount(governance),
+                    StableAmount(token),
+                );
+                self.step(ix, &[]).await;
+            }
+            Op::ClaimDepositReward { deposit } => {
+                let ix = instruction::claim_deposit_reward(&program_id, &self.users[deposit].deposit, &self.protocol);
+                self.step(ix, &[]).await;
+            }
+            Op::SetPause { impostor, paused } => {
+                let pauser = if impostor { self.users[0].keypair.insecure_clone() } else { self.pauser.insecure_clone() };
+                let ix = instruction::set_pause(&program_id, &pauser.pubkey(), &self.protocol, paused);
+                self.step(ix, &[&pauser]).await;
+            }
+            Op::Warp { seconds } => {
+                let clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+                let unix_timestamp = clock.unix_timestamp + seconds;
+                self.context.set_sysvar(&Clock { unix_timestamp, ..clock });
+                self.model.unix_timestamp = unix_timestamp;
+            }
+        }
+    }
+}
+
+/// A step of a random sequence. Troves are picked among the trove accounts created so far,
+/// deposits by the user they were set up for, an impostor being the next user
+#[derive(Clone, Debug)]
+enum Op {
+    Borrow { user: usize, borrow_amount: u64, lamports: u64 },
+    BorrowWithStakePoolToken { user: usize, borrow_amount: u64, pool_tokens: u64 },
+    ReceiveTrove { trove: usize },
+    AddCoin { trove: usize, impostor: bool, amount: u64, mismatch: bool },
+    WithdrawCoin { trove: usize, impostor: bool, amount: u64 },
+    RedeemCoin { trove: usize, impostor: bool, amount: u64 },
+    CloseTrove { trove: usize, impostor: bool, repay: bool },
+    LiquidateTrove { trove: usize, liquidator: usize },
+    AddDeposit { deposit: usize, impostor: bool, amount: u64, buy: bool },
+    WithdrawDeposit { deposit: usize, impostor: bool, amount: u64 },
+    AddDepositReward { deposit: usize, coin: u64, governance: u64, token: u64 },
+    ClaimDepositReward { deposit: usize },
+    SetPause { impostor: bool, paused: u64 },
+    Warp { seconds: i64 },
+}
+
+/// Mostly the owner, sometimes another user
+fn impostor() -> impl Strategy<Value = bool> {
+    prop::bool::weighted(0.1)
+}
+
+/// Pause bits, most often none so that the sequence goes on
+fn paused() -> impl Strategy<Value = u64> {
+    let operations = prop::sample::select(vec![
+        PAUSE_BORROW,
+        PAUSE_ADJUST_TROVE,
+        PAUSE_REDEEM,
+        PAUSE_LIQUIDATE,
+        PAUSE_DEPOSIT,
+        PAUSE_REWARDS,
+        PAUSE_RECEIVE_TROVE,
+    ]);
+    prop_oneof![3 => Just(0), 1 => operations]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let user = 0..USERS;
+    let trove = any::<usize>();
+    prop_oneof![
+        4 => (user.clone(), 0..3_000 * UNIT, 1..60 * LAMPORTS_PER_SOL)
+            .prop_map(|(user, borrow_amount, lamports)| Op::Borrow { user, borrow_amount, lamports }),
+        2 => (user.clone(), 0..3_000 * UNIT, 0..60 * POOL_UNIT)
+            .prop_map(|(user, borrow_amount, pool_tokens)| Op::BorrowWithStakePoolToken { user, borrow_amount, pool_tokens }),
+        4 => trove.prop_map(|trove| Op::ReceiveTrove { trove }),
+        2 => (trove, impostor(), LAMPORTS_PER_SOL..5 * LAMPORTS_PER_SOL, prop::bool::weighted(0.1))
+            .prop_map(|(trove, impostor, amount, mismatch)| Op::AddCoin { trove, impostor, amount, mismatch }),
+        2 => (trove, impostor(), 0..20 * LAMPORTS_PER_SOL)
+            .prop_map(|(trove, impostor, amount)| Op::WithdrawCoin { trove, impostor, amount }),
+        1 => (trove, impostor(), 0..10 * LAMPORTS_PER_SOL)
+            .prop_map(|(trove, impostor, amount)| Op::RedeemCoin { trove, impostor, amount }),
+        2 => (trove, impostor(), prop::bool::weighted(0.8))
+            .prop_map(|(trove, impostor, repay)| Op::CloseTrove { trove, impostor, repay }),
+        2 => (trove, user.clone()).prop_map(|(trove, liquidator)| Op::LiquidateTrove { trove, liquidator }),
+        2 => (user.clone(), impostor(), 0..500 * UNIT, prop::bool::weighted(0.8))
+            .prop_map(|(deposit, impostor, amount, buy)| Op::AddDeposit { deposit, impostor, amount, buy }),
+        1 => (user.clone(), impostor(), 0..500 * UNIT)
+            .prop_map(|(deposit, impostor, amount)| Op::WithdrawDeposit { deposit, impostor, amount }),
+        1 => (user.clone(), any::<[u32; 3]>()).prop_map(|(deposit, [coin, governance, token])| {
+            Op::AddDepositReward { deposit, coin: coin as u64, governance: governance as u64, token: token as u64 }
+        }),
+        1 => user.prop_map(|deposit| Op::ClaimDepositReward { deposit }),
+        1 => (impostor(), paused()).prop_map(|(impostor, paused)| Op::SetPause { impostor, paused }),
+        2 => (1..30 * 86_400i64).prop_map(|seconds| Op::Warp { seconds }),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig { cases: 16, ..ProptestConfig::default() })]
+
+    #[test]
+    fn test_processor_matches_model(ops in prop::collection::vec(op(), 1..40)) {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut env = Env::new().await;
+            for op in ops {
+                env.run(op).await;
+                env.compare().await;
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_trove_lifecycle() {
+    // A trove opened, received, adjusted, accruing interest, redeemed from and closed, step by step
+    let mut env = Env::new().await;
+    let ops = vec![
+        Op::Borrow { user: 0, borrow_amount: 1_000 * UNIT, lamports: 20 * LAMPORTS_PER_SOL },
+        Op::ReceiveTrove { trove: 0 },
+        Op::AddCoin { trove: 0, impostor: false, amount: 2 * LAMPORTS_PER_SOL, mismatch: false },
+        Op::Warp { seconds: 365 * 86_400 },
+        Op::WithdrawCoin { trove: 0, impostor: false, amount: LAMPORTS_PER_SOL },
+        Op::RedeemCoin { trove: 0, impostor: false, amount: LAMPORTS_PER_SOL },
+        Op::CloseTrove { trove: 0, impostor: false, repay: true },
+    ];
+    for op in ops {
+        env.run(op).await;
+        env.compare().await;
+    }
+    assert!(env.model.trove(&env.troves[0].address).is_none());
+    assert!(env.model.ledger.bought > 0);
+    match env.model.account(&env.native_type) {
+        Some(Account::Program { state: State::CollateralType(collateral_type), .. }) => assert_eq!(collateral_type.total_debt, 0),
+        other => panic!("collateral type {:?}", other),
+    }
+}
@@ -0,0 +1,111 @@
+use solana_escrow::event::{decode_logs, LiquityEvent, EVENT_LOG_PREFIX};
+use solana_escrow::processor::Processor;
+use solana_escrow::units::{CollateralAmount, GovAmount, Lamports, StableAmount};
+use solana_program::{
+    account_info::AccountInfo,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+};
+use std::sync::Mutex;
+
+mod common;
+use common::{deposit, events, protocol, TestAccount};
+
+#[test]
+fn test_pack_unpack() {
+    for (tag, event) in events().iter().enumerate() {
+        let packed = event.pack();
+        assert_eq!(packed[0] as usize, tag);
+        assert_eq!(LiquityEvent::unpack(&packed).as_ref(), Some(event));
+
+        // Appended fields are ignored, truncated events are rejected
+        let mut extended = packed.clone();
+        extended.push(0);
+        assert_eq!(LiquityEvent::unpack(&extended).as_ref(), Some(event));
+        assert_eq!(LiquityEvent::unpack(&packed[..packed.len() - 1]), None);
+    }
+    assert_eq!(LiquityEvent::unpack(&[255]), None);
+}
+
+#[test]
+fn test_pack_layout() {
+    let trove = Pubkey::new_from_array([7; 32]);
+    let packed =
+        LiquityEvent::Redemption { trove, amount: CollateralAmount(1), collateral_amount: CollateralAmount(2), repaid: StableAmount(3) }
+            .pack();
+
+    let mut expected = vec![4];
+    expected.extend_from_slice(&[7; 32]);
+    expected.extend_from_slice(&1u64.to_le_bytes());
+    expected.extend_from_slice(&2u64.to_le_bytes());
+    expected.extend_from_slice(&3u64.to_le_bytes());
+    assert_eq!(packed, expected);
+}
+
+fn log_line(event: &LiquityEvent) -> String {
+    format!("Program log: {}{}", EVENT_LOG_PREFIX, base64::encode(event.pack()))
+}
+
+#[test]
+fn test_decode_logs() {
+    let program_id = Pubkey::new_unique();
+    let other_program = Pubkey::new_unique();
+    let events = events();
+
+    let logs = vec![
+        format!("Program {} invoke [1]", program_id),
+        "Program log: Instruction Borrow".to_string(),
+        log_line(&events[0]),
+        // A program invoked by ours cannot forge events
+        format!("Program {} invoke [2]", other_program),
+        log_line(&events[1]),
+        format!("Program {} consumed 100 of 200000 compute units", other_program),
+        format!("Program {} success", other_program),
+        log_line(&events[2]),
+        format!("Program {} success", program_id),
+        // Nor can another program of the transaction
+        format!("Program {} invoke [1]", other_program),
+        log_line(&events[3]),
+        format!("Program {} failed: custom program error: 0x1", other_program),
+    ];
+
+    assert_eq!(decode_logs(&program_id, &logs), vec![events[0].clone(), events[2].clone()]);
+    assert_eq!(LiquityEvent::from_log(&logs[2]).as_ref(), Some(&events[0]));
+    assert_eq!(LiquityEvent::from_log(&logs[1]), None);
+}
+
+static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct CaptureLogs;
+
+impl SyscallStubs for CaptureLogs {
+    fn sol_log(&self, message: &str) {
+        LOGS.lock().unwrap().push(message.to_string());
+    }
+}
+
+#[test]
+fn test_processor_emits() {
+    set_syscall_stubs(Box::new(CaptureLogs));
+
+    let program_id = Pubkey::new_unique();
+    let mut accounts = [
+        TestAccount::system(),
+        TestAccount::program(&program_id, deposit(Pubkey::new_unique())),
+        TestAccount::program(&program_id, protocol()),
+    ];
+    let deposit_key = accounts[1].key;
+    let mut data = vec![10];
+    for value in [1u64, 2, 3] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+    Processor::process(&program_id, &infos, &data).unwrap();
+
+    let events: Vec<LiquityEvent> = LOGS.lock().unwrap().iter().filter_map(|line| LiquityEvent::from_log(line)).collect();
+    assert_eq!(
+        events,
+        vec![LiquityEvent::RewardAdded { deposit: deposit_key, coin: Lamports(1), governance: GovAmount(2), token: StableAmount(3) }]
+    );
+}
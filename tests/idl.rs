@@ -0,0 +1,13 @@
+#![cfg(feature = "idl")]
+
+use serde_json::Value;
+use solana_escrow::idl;
+
+#[test]
+fn test_idl_up_to_date() {
+    let committed: Value = serde_json::from_str(include_str!("../idl/solana_escrow.json")).unwrap();
+    assert!(
+        idl::generate() == committed,
+        "idl/solana_escrow.json is stale, regenerate it with `cargo run --features idl --bin liquity-idl`",
+    );
+}
@@ -0,0 +1,239 @@
+#![cfg(feature = "indexer")]
+
+use serde_json::{json, Value};
+use solana_escrow::event::{LiquityEvent, EVENT_LOG_PREFIX};
+use solana_escrow::indexer::Indexer;
+use solana_escrow::state::{Deposit, Trove};
+use solana_escrow::units::{CollateralAmount, GovAmount, Lamports, StableAmount};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+use std::fs;
+
+/// A `getTransaction` response of the program logging `events`
+fn transaction(program_id: &Pubkey, signature: &str, slot: u64, failed: bool, events: &[LiquityEvent]) -> Value {
+    let mut logs = vec![format!("Program {} invoke [1]", program_id)];
+    for event in events {
+        logs.push(format!("Program log: {}{}", EVENT_LOG_PREFIX, base64::encode(event.pack())));
+    }
+    logs.push(if failed {
+        format!("Program {} failed: custom program error: 0x1", program_id)
+    } else {
+        format!("Program {} success", program_id)
+    });
+
+    json!({
+        "jsonrpc": "2.0",
+        "result": {
+            "slot": slot,
+            "blockTime": 1_600_000_000 + slot,
+            "meta": { "err": if failed { json!({ "InstructionError": [0, { "Custom": 1 }] }) } else { Value::Null }, "logMessages": logs },
+            "transaction": { "signatures": [signature], "message": {} },
+        },
+        "id": 1,
+    })
+}
+
+fn account(address: &Pubkey, data: &[u8]) -> Value {
+    json!({ "pubkey": address.to_string(), "account": { "data": [base64::encode(data), "base64"], "lamports": 1 } })
+}
+
+fn count(indexer: &Indexer, table: &str) -> i64 {
+    indexer.connection().query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+}
+
+fn trove_row(indexer: &Indexer, trove: &Pubkey) -> (String, u64, u64) {
+    indexer
+        .connection()
+        .query_row(
+            "SELECT status, collateral_amount, debt FROM troves WHERE address = ?1",
+            [trove.to_string()],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_index_transactions() {
+    let program_id = Pubkey::new_unique();
+    let [trove, owner, liquidator, deposit, legacy_trove] = [(); 5].map(|_| Pubkey::new_unique());
+    let mut indexer = Indexer::open(program_id, ":memory:").unwrap();
+
+    let opened = LiquityEvent::TroveOpened {
+        trove,
+        owner,
+        collateral: Pubkey::default(),
+        collateral_amount: CollateralAmount(10_000),
+        borrow_amount: StableAmount(1_000),
+        debt: StableAmount(1_110),
+        depositor_fee: StableAmount(5),
+        team_fee: StableAmount(5),
+    };
+    indexer.index_response(&transaction(&program_id, "a", 1, false, &[opened])).unwrap();
+    assert_eq!(trove_row(&indexer, &trove), ("open".to_string(), 10_000, 1_110));
+
+    let redemption = LiquityEvent::Redemption {
+        trove,
+        amount: CollateralAmount(100),
+        collateral_amount: CollateralAmount(9_000),
+        repaid: StableAmount(7),
+    };
+    indexer.index_response(&transaction(&program_id, "b", 2, false, &[redemption])).unwrap();
+    assert_eq!(trove_row(&indexer, &trove), ("open".to_string(), 9_000, 1_110));
+    let repaid: u64 = indexer.connection().query_row("SELECT repaid FROM redemptions", [], |row| row.get(0)).unwrap();
+    assert_eq!(repaid, 7);
+
+    // The events of a failed transaction were rolled back
+    let failed = LiquityEvent::TroveClosed { trove, owner, repaid: StableAmount(1_110), collateral_amount: CollateralAmount(9_000) };
+    indexer.index_response(&transaction(&program_id, "c", 3, true, &[failed])).unwrap();
+    assert_eq!(trove_row(&indexer, &trove).0, "open");
+
+    let liquidated =
+        LiquityEvent::TroveLiquidated { trove, liquidator, debt: StableAmount(1_110), collateral_amount: CollateralAmount(9_000) };
+    let deposited = LiquityEvent::DepositChanged { deposit, owner, token_amount: StableAmount(500) };
+    let rewarded =
+        LiquityEvent::RewardAdded { deposit, coin: Lamports(9_000), governance: GovAmount::ZERO, token: StableAmount(5) };
+    // A migrated legacy trove is indexed with its debt, like an opened one
+    let migrated = LiquityEvent::TroveMigrated {
+        trove: legacy_trove,
+        owner,
+        collateral: Pubkey::default(),
+        collateral_amount: CollateralAmount(5_000),
+        debt: StableAmount(800),
+    };
+    let response = transaction(&program_id, "d", 4, false, &[liquidated, deposited, rewarded, migrated]);
+    indexer.index_response(&response).unwrap();
+    // Indexing a transaction twice changes nothing
+    indexer.index_response(&response).unwrap();
+
+    assert_eq!(trove_row(&indexer, &trove), ("liquidated".to_string(), 0, 0));
+    assert_eq!(trove_row(&indexer, &legacy_trove), ("open".to_string(), 5_000, 800));
+    assert_eq!(count(&indexer, "transactions"), 3);
+    assert_eq!(count(&indexer, "trove_history"), 3);
+    assert_eq!(count(&indexer, "liquidations"), 1);
+    assert_eq!(count(&indexer, "redemptions"), 1);
+    assert_eq!(count(&indexer, "deposit_history"), 1);
+
+    // Zero amounts are not fee flows
+    let fees: Vec<(String, String, u64)> = indexer
+        .connection()
+        .prepare("SELECT kind, unit, amount FROM fee_flows ORDER BY rowid")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let fee = |kind: &str, unit: &str, amount| (kind.to_string(), unit.to_string(), amount);
+    assert_eq!(
+        fees,
+        vec![
+            fee("depositor_fee", "stablecoin", 5),
+            fee("team_fee", "stablecoin", 5),
+            fee("reward_added", "lamports", 9_000),
+            fee("reward_added", "stablecoin", 5),
+        ]
+    );
+}
+
+#[test]
+fn test_index_accounts() {
+    let program_id = Pubkey::new_unique();
+    let [trove_key, deposit_key] = [(); 2].map(|_| Pubkey::new_unique());
+    let mut indexer = Indexer::open(program_id, ":memory:").unwrap();
+
+    let trove = Trove {
+        is_initialized: true,
+        is_received: false,
+        is_liquidated: false,
+        borrow_amount: StableAmount(1_000),
+        collateral_amount: CollateralAmount(10_000),
+        team_fee: StableAmount(5),
+        depositor_fee: StableAmount(5),
+        amount_to_close: StableAmount(1_110),
+        owner: Pubkey::new_unique(),
+        collateral: Pubkey::default(),
+        borrow_index: 1,
+    };
+    let mut trove_data = vec![0; Trove::LEN];
+    trove.pack_into_slice(&mut trove_data);
+    let deposit = Deposit {
+        is_initialized: true,
+        token_amount: StableAmount(500),
+        reward_token_amount: StableAmount::ZERO,
+        reward_governance_token_amount: GovAmount::ZERO,
+        reward_coin_amount: Lamports::ZERO,
+        bank: Pubkey::new_unique(),
+        governance_bank: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+    };
+    let mut deposit_data = vec![0; Deposit::LEN];
+    deposit.pack_into_slice(&mut deposit_data);
+
+    // A `getProgramAccounts` response with context, the unknown account is skipped
+    let response = json!({
+        "jsonrpc": "2.0",
+        "result": {
+            "context": { "slot": 10 },
+            "value": [
+                account(&trove_key, &trove_data),
+                account(&deposit_key, &deposit_data),
+                account(&Pubkey::new_unique(), &[1, 2, 3]),
+            ],
+        },
+        "id": 1,
+    });
+    indexer.index_response(&response).unwrap();
+    assert_eq!(trove_row(&indexer, &trove_key), ("open".to_string(), 10_000, 1_110));
+    let token_amount: u64 = indexer
+        .connection()
+        .query_row("SELECT token_amount FROM deposits WHERE address = ?1", [deposit_key.to_string()], |row| row.get(0))
+        .unwrap();
+    assert_eq!(token_amount, 500);
+
+    // Updates older than the indexed state are ignored
+    let stale = LiquityEvent::TroveAdjusted { trove: trove_key, collateral_amount: CollateralAmount(1), debt: StableAmount(1) };
+    indexer.index_response(&transaction(&program_id, "a", 9, false, &[stale])).unwrap();
+    assert_eq!(trove_row(&indexer, &trove_key), ("open".to_string(), 10_000, 1_110));
+
+    // A `programNotification` of the liquidated trove
+    let liquidated = Trove { is_liquidated: true, ..trove };
+    liquidated.pack_into_slice(&mut trove_data);
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "programNotification",
+        "params": { "result": { "context": { "slot": 11 }, "value": account(&trove_key, &trove_data) }, "subscription": 1 },
+    });
+    indexer.index_response(&notification).unwrap();
+    assert_eq!(trove_row(&indexer, &trove_key).0, "liquidated");
+}
+
+#[test]
+fn test_index_file() {
+    let program_id = Pubkey::new_unique();
+    let deposit = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let changed = |token_amount| LiquityEvent::DepositChanged { deposit, owner, token_amount: StableAmount(token_amount) };
+
+    // One response per line
+    let lines = [
+        transaction(&program_id, "a", 1, false, &[changed(100)]).to_string(),
+        transaction(&program_id, "b", 2, false, &[changed(40)]).to_string(),
+    ];
+    let path = std::env::temp_dir().join(format!("liquity-indexer-{}.jsonl", std::process::id()));
+    fs::write(&path, lines.join("\n")).unwrap();
+
+    let db = std::env::temp_dir().join(format!("liquity-indexer-{}.sqlite", std::process::id()));
+    let mut indexer = Indexer::open(program_id, db.to_str().unwrap()).unwrap();
+    indexer.index_file(path.to_str().unwrap()).unwrap();
+    drop(indexer);
+
+    // The database persists across runs
+    let indexer = Indexer::open(program_id, db.to_str().unwrap()).unwrap();
+    assert_eq!(count(&indexer, "deposit_history"), 2);
+    let token_amount: u64 = indexer
+        .connection()
+        .query_row("SELECT token_amount FROM deposits WHERE address = ?1", [deposit.to_string()], |row| row.get(0))
+        .unwrap();
+    assert_eq!(token_amount, 40);
+
+    fs::remove_file(path).unwrap();
+    fs::remove_file(db).unwrap();
+}
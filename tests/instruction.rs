@@ -0,0 +1,130 @@
+use solana_escrow::collateral::find_authority_address;
+use solana_escrow::instruction::{self, CoinSource, LiquityInstruction, StakePoolAccounts};
+use solana_escrow::params::SYSTEM_ACCOUNT_ADDRESS;
+use solana_escrow::units::{CollateralAmount, Lamports, StableAmount};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    sysvar,
+};
+
+mod common;
+use common::{config, instructions};
+
+#[test]
+fn test_pack_unpack() {
+    for (tag, instruction) in instructions().iter().enumerate() {
+        let packed = instruction.pack();
+        assert_eq!(packed[0] as usize, tag);
+        assert_eq!(LiquityInstruction::unpack(&packed).unwrap().pack(), packed);
+    }
+}
+
+#[test]
+fn test_pack_layout() {
+    let packed = LiquityInstruction::Borrow { borrow_amount: StableAmount(1), lamports: Lamports(2) }.pack();
+    assert_eq!(packed, [0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+
+    let packed = LiquityInstruction::InitProtocol { config: config() }.pack();
+    assert_eq!(packed.len(), 1 + 184);
+}
+
+fn assert_unpacks(instruction: &Instruction) {
+    assert!(LiquityInstruction::unpack(&instruction.data).is_ok());
+}
+
+#[test]
+fn test_borrow() {
+    let program_id = Pubkey::new_unique();
+    let [borrower, trove, protocol, collateral_type, mint, user_token] = [(); 6].map(|_| Pubkey::new_unique());
+
+    let ix = instruction::borrow(
+        &program_id, &borrower, &trove, &protocol, &collateral_type, &mint, &user_token, StableAmount(1), Lamports(2),
+    );
+
+    assert_eq!(ix.program_id, program_id);
+    assert_eq!(ix.accounts, vec![
+        AccountMeta::new_readonly(borrower, true),
+        AccountMeta::new(trove, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(protocol, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(collateral_type, false),
+        AccountMeta::new(mint, false),
+        AccountMeta::new(user_token, false),
+        AccountMeta::new_readonly(find_authority_address(&program_id).0, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+    assert_unpacks(&ix);
+}
+
+#[test]
+fn test_close_trove() {
+    let program_id = Pubkey::new_unique();
+    let [borrower, trove, user_token, mint, protocol, collateral_type] = [(); 6].map(|_| Pubkey::new_unique());
+    let stake_pool = StakePoolAccounts {
+        stake_pool: Pubkey::new_unique(),
+        vault: Pubkey::new_unique(),
+        user_token: Pubkey::new_unique(),
+    };
+
+    let ix = instruction::close_trove(&program_id, &borrower, &trove, &user_token, &mint, &protocol, &collateral_type, None);
+    assert_eq!(ix.accounts.len(), 8);
+    // The trove lamports are swept to the borrower
+    assert_eq!(ix.accounts[0], AccountMeta::new(borrower, true));
+    assert_unpacks(&ix);
+
+    let ix = instruction::close_trove(&program_id, &borrower, &trove, &user_token, &mint, &protocol, &collateral_type, Some(&stake_pool));
+    assert_eq!(ix.accounts[8..], [
+        AccountMeta::new_readonly(stake_pool.stake_pool, false),
+        AccountMeta::new(stake_pool.vault, false),
+        AccountMeta::new(stake_pool.user_token, false),
+        AccountMeta::new_readonly(find_authority_address(&program_id).0, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+}
+
+#[test]
+fn test_liquidate_trove() {
+    let program_id = Pubkey::new_unique();
+    let [liquidator, trove, protocol, collateral_type] = [(); 4].map(|_| Pubkey::new_unique());
+
+    let ix = instruction::liquidate_trove(&program_id, &liquidator, &trove, &protocol, &collateral_type, None);
+
+    assert_eq!(ix.accounts[2], AccountMeta::new(SYSTEM_ACCOUNT_ADDRESS, false));
+    assert_unpacks(&ix);
+}
+
+#[test]
+fn test_add_coin() {
+    let program_id = Pubkey::new_unique();
+    let [borrower, trove, protocol, temp] = [(); 4].map(|_| Pubkey::new_unique());
+
+    let ix = instruction::add_coin(&program_id, &borrower, &trove, &protocol, &CoinSource::Lamports(temp), CollateralAmount(1));
+    assert_eq!(ix.accounts.len(), 4);
+    assert_eq!(ix.accounts[3], AccountMeta::new(temp, false));
+
+    let stake_pool = StakePoolAccounts {
+        stake_pool: Pubkey::new_unique(),
+        vault: Pubkey::new_unique(),
+        user_token: Pubkey::new_unique(),
+    };
+    let ix = instruction::add_coin(&program_id, &borrower, &trove, &protocol, &CoinSource::StakePool(stake_pool), CollateralAmount(1));
+    assert_eq!(ix.accounts.len(), 8);
+    assert_unpacks(&ix);
+}
+
+#[test]
+fn test_flash_mint() {
+    let program_id = Pubkey::new_unique();
+    let [mint, receiver_token, fee_receiver_token, protocol, receiver_program] = [(); 5].map(|_| Pubkey::new_unique());
+    let remaining = [AccountMeta::new(Pubkey::new_unique(), false)];
+
+    let ix = instruction::flash_mint(
+        &program_id, &mint, &receiver_token, &fee_receiver_token, &protocol, &receiver_program, &remaining, StableAmount(1),
+    );
+
+    assert_eq!(ix.accounts.len(), 8);
+    assert_eq!(ix.accounts[7], remaining[0]);
+    assert_unpacks(&ix);
+}
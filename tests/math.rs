@@ -0,0 +1,169 @@
+use solana_escrow::collateral::Collateral;
+use solana_escrow::error::LiquityError;
+use solana_escrow::helpers::{
+    check_min_collateral_ratio, get_depositors_fee, get_flash_mint_fee, get_team_fee,
+    get_trove_debt_amount, get_trove_sent_amount, get_trove_debt_with_interest,
+};
+use solana_escrow::math;
+use solana_escrow::params::GAS_FEE;
+use solana_escrow::units::{CollateralAmount, Lamports, Price, Ratio, StableAmount};
+use solana_program::program_error::ProgramError;
+
+mod common;
+use common::config;
+
+fn overflow() -> ProgramError {
+    LiquityError::MathOverflow.into()
+}
+
+#[test]
+fn add_fails_past_u64_max() {
+    assert_eq!(math::add(u64::MAX - 1, 1), Ok(u64::MAX));
+    assert_eq!(math::add(u64::MAX, 1), Err(overflow()));
+}
+
+#[test]
+fn sub_fails_below_zero() {
+    assert_eq!(math::sub(1, 1), Ok(0));
+    assert_eq!(math::sub(0, 1), Err(overflow()));
+}
+
+#[test]
+fn mul_fails_past_u64_max() {
+    assert_eq!(math::mul(u64::MAX, 1), Ok(u64::MAX));
+    assert_eq!(math::mul(u64::MAX / 2 + 1, 2), Err(overflow()));
+}
+
+#[test]
+fn div_fails_on_zero() {
+    assert_eq!(math::div(7, 2), Ok(3));
+    assert_eq!(math::div(7, 0), Err(overflow()));
+}
+
+#[test]
+fn mul_div_keeps_wide_intermediate() {
+    assert_eq!(math::mul_div(u64::MAX, u64::MAX, u64::MAX), Ok(u64::MAX));
+    assert_eq!(math::mul_div(u64::MAX, 2, 1), Err(overflow()));
+    assert_eq!(math::mul_div(1, 1, 0), Err(overflow()));
+}
+
+#[test]
+fn mul_div_ceil_rounds_up() {
+    assert_eq!(math::mul_div_ceil(10, 1, 3), Ok(4));
+    assert_eq!(math::mul_div_ceil(9, 1, 3), Ok(3));
+    assert_eq!(math::mul_div_ceil(u64::MAX, u64::MAX, 1), Err(overflow()));
+}
+
+#[test]
+fn to_u64_fails_past_u64_max() {
+    assert_eq!(math::to_u64(u64::MAX as u128), Ok(u64::MAX));
+    assert_eq!(math::to_u64(u64::MAX as u128 + 1), Err(overflow()));
+}
+
+#[test]
+fn elapsed_fails_on_overflow() {
+    assert_eq!(math::elapsed(10, 25), Ok(15));
+    assert_eq!(math::elapsed(i64::MIN, i64::MAX), Err(overflow()));
+}
+
+const DECIMALS: u8 = 9;
+
+fn gas_fee() -> StableAmount {
+    StableAmount::from_whole(GAS_FEE, DECIMALS).unwrap()
+}
+
+#[test]
+fn trove_debt_below_gas_fee_fails() {
+    let below_gas_fee = StableAmount(gas_fee().0 - 1);
+    assert_eq!(get_trove_debt_amount(gas_fee(), DECIMALS), Ok(StableAmount::ZERO));
+    assert_eq!(get_trove_debt_amount(below_gas_fee, DECIMALS), Err(overflow()));
+    assert_eq!(get_depositors_fee(below_gas_fee, &config(), DECIMALS), Err(overflow()));
+    assert_eq!(get_team_fee(below_gas_fee, &config(), DECIMALS), Err(overflow()));
+    assert_eq!(get_trove_sent_amount(below_gas_fee, &config(), DECIMALS), Err(overflow()));
+}
+
+#[test]
+fn gas_fee_follows_mint_decimals() {
+    assert_eq!(get_trove_debt_amount(StableAmount(250), 0), Ok(StableAmount(50)));
+    assert_eq!(get_trove_debt_amount(StableAmount(250), 1), Err(overflow()));
+}
+
+#[test]
+fn trove_fees_keep_fractional_amounts() {
+    // 1.5 stablecoins of debt past the gas fee
+    let amount = StableAmount(gas_fee().0 + 1_500_000_000);
+    assert_eq!(get_depositors_fee(amount, &config(), DECIMALS), Ok(StableAmount(45_000_000)));
+    assert_eq!(get_team_fee(amount, &config(), DECIMALS), Ok(StableAmount(15_000_000)));
+    assert_eq!(get_trove_sent_amount(amount, &config(), DECIMALS), Ok(StableAmount(1_440_000_000)));
+}
+
+#[test]
+fn trove_fees_do_not_overflow_on_large_amounts() {
+    let debt = u64::MAX - gas_fee().0;
+    let amount = StableAmount(u64::MAX);
+    assert_eq!(get_depositors_fee(amount, &config(), DECIMALS), Ok(StableAmount((debt as u128 * 3 / 100) as u64)));
+    assert_eq!(get_team_fee(amount, &config(), DECIMALS), Ok(StableAmount(debt / 100)));
+    assert!(get_trove_sent_amount(amount, &config(), DECIMALS).is_ok());
+}
+
+#[test]
+fn flash_mint_fee_rounds_up_and_fits_u64() {
+    assert_eq!(get_flash_mint_fee(StableAmount(1), Ratio(9)), Ok(StableAmount(1)));
+    assert_eq!(get_flash_mint_fee(StableAmount::ZERO, Ratio(9)), Ok(StableAmount::ZERO));
+    assert_eq!(get_flash_mint_fee(StableAmount(u64::MAX), Ratio::ONE), Ok(StableAmount(u64::MAX)));
+}
+
+#[test]
+fn trove_debt_with_interest_fails_past_u64_max() {
+    let debt = StableAmount(u64::MAX);
+    assert_eq!(get_trove_debt_with_interest(debt, 1, 1), Ok(debt));
+    assert_eq!(get_trove_debt_with_interest(debt, 1, 2), Err(overflow()));
+}
+
+#[test]
+fn collateral_ratio_is_checked_on_the_whole_debt() {
+    let config = config();
+    let sol = CollateralAmount(1_000_000_000);
+    // One SOL is worth 70, covering a debt of 63.63 at 110%
+    let covered = StableAmount(63_636_363_636);
+    assert_eq!(check_min_collateral_ratio(covered, &Collateral::Sol, sol, &config, DECIMALS), Ok(true));
+    let uncovered = StableAmount(covered.0 + 1);
+    assert_eq!(check_min_collateral_ratio(uncovered, &Collateral::Sol, sol, &config, DECIMALS), Ok(false));
+    assert_eq!(check_min_collateral_ratio(StableAmount(1), &Collateral::Sol, CollateralAmount::ZERO, &config, DECIMALS), Ok(false));
+}
+
+#[test]
+fn stake_pool_collateral_does_not_overflow() {
+    let collateral = Collateral::StakePool { total_lamports: u64::MAX, pool_token_supply: u64::MAX };
+    assert_eq!(collateral.to_lamports(CollateralAmount(u64::MAX)), Ok(Lamports(u64::MAX)));
+
+    let collateral = Collateral::StakePool { total_lamports: u64::MAX, pool_token_supply: 1 };
+    assert_eq!(collateral.to_lamports(CollateralAmount(2)), Err(overflow()));
+}
+
+#[test]
+fn price_values_lamports_rounding_down() {
+    let price = Price(StableAmount(70));
+    assert_eq!(price.value(Lamports(1_000_000_000)), Ok(StableAmount(70)));
+    assert_eq!(price.value(Lamports(14_285_714)), Ok(StableAmount(0)));
+    assert_eq!(Price(StableAmount(70_000_000_000)).value(Lamports(1)), Ok(StableAmount(70)));
+    assert_eq!(Price(StableAmount(u64::MAX)).value(Lamports(u64::MAX)), Err(overflow()));
+}
+
+#[test]
+fn ratio_applies_in_basis_points() {
+    assert_eq!(Ratio::from_percent(3), Ok(Ratio(300)));
+    assert_eq!(Ratio::from_percent(u64::MAX), Err(overflow()));
+    assert_eq!(Ratio(300).apply(StableAmount(1_000)), Ok(StableAmount(30)));
+    assert!(Ratio(11_000).is_covered(StableAmount(110), StableAmount(100)));
+    assert!(!Ratio(11_000).is_covered(StableAmount(109), StableAmount(100)));
+    assert!(!Ratio(u64::MAX).is_covered(StableAmount(u64::MAX), StableAmount(u64::MAX)));
+}
+
+#[test]
+fn whole_amounts_convert_to_mint_base_units() {
+    assert_eq!(StableAmount::from_whole(5, 9), Ok(StableAmount(5_000_000_000)));
+    assert_eq!(StableAmount::from_whole(5, 0), Ok(StableAmount(5)));
+    assert_eq!(StableAmount::from_whole(u64::MAX, 1), Err(overflow()));
+    assert_eq!(StableAmount::from_whole(1, 20), Err(overflow()));
+}
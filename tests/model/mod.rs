@@ -0,0 +1,1009 @@
+//! Reference model of the protocol for the differential tests: troves, stability
+//! pool deposits, their fees, interest and liquidations, kept as plain values
+//! instead of accounts. It processes the same instructions as `Processor`, reading
+//! the role of each account from its position, and makes its checks in the same
+//! order so that both fail with the same error. Program, sysvar, mint, stake pool
+//! and authority accounts are assumed to be the ones the instruction builders pass
+
+// Each test crate uses its own part of the model
+#![allow(dead_code)]
+
+use solana_escrow::collateral::find_collateral_type_address;
+use solana_escrow::error::LiquityError;
+use solana_escrow::instruction::LiquityInstruction;
+use solana_escrow::params::{
+    BPS_DENOMINATOR, GAS_FEE, GENS_TOKEN_ADDRESS, PAUSE_ADJUST_TROVE, PAUSE_BORROW, PAUSE_DEPOSIT, PAUSE_LIQUIDATE,
+    PAUSE_RECEIVE_TROVE, PAUSE_REDEEM, PAUSE_REWARDS, SECONDS_PER_YEAR, SYSTEM_ACCOUNT_ADDRESS,
+};
+use solana_escrow::state::{AccountType, CollateralType, Deposit, Protocol, Trove};
+use solana_program::{
+    instruction::Instruction,
+    native_token::LAMPORTS_PER_SOL,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+};
+use spl_token::error::TokenError;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// Whole stablecoins one SOL of collateral is worth to the program
+const SOL_PRICE: u64 = 70;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProtocolModel {
+    pub stablecoin_mint: Pubkey,
+    pub stablecoin_decimals: u8,
+    pub interest_rate: u64,
+    pub debt_ceiling: u64,
+    pub min_collateral_ratio: u64,
+    pub deposit_fee: u64,
+    pub team_fee: u64,
+    pub pauser: Pubkey,
+    pub admin: Pubkey,
+    pub borrow_index: u128,
+    pub last_update_timestamp: i64,
+    pub paused: u64,
+    pub total_debt: u64,
+}
+
+impl From<Protocol> for ProtocolModel {
+    fn from(protocol: Protocol) -> Self {
+        ProtocolModel {
+            stablecoin_mint: protocol.stablecoin_mint,
+            stablecoin_decimals: protocol.stablecoin_decimals,
+            interest_rate: protocol.config.interest_rate.0,
+            debt_ceiling: protocol.config.debt_ceiling.0,
+            min_collateral_ratio: protocol.config.min_collateral_ratio.0,
+            deposit_fee: protocol.config.deposit_fee.0,
+            team_fee: protocol.config.team_fee.0,
+            pauser: protocol.config.pauser,
+            admin: protocol.config.admin,
+            borrow_index: protocol.borrow_index,
+            last_update_timestamp: protocol.last_update_timestamp,
+            paused: protocol.paused,
+            total_debt: protocol.total_debt.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollateralTypeModel {
+    pub collateral: Pubkey,
+    pub debt_ceiling: u64,
+    pub total_debt: u64,
+}
+
+impl From<CollateralType> for CollateralTypeModel {
+    fn from(collateral_type: CollateralType) -> Self {
+        CollateralTypeModel {
+            collateral: collateral_type.collateral,
+            debt_ceiling: collateral_type.debt_ceiling.0,
+            total_debt: collateral_type.total_debt.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TroveModel {
+    pub is_received: bool,
+    pub is_liquidated: bool,
+    pub borrow_amount: u64,
+    pub collateral_amount: u64,
+    pub team_fee: u64,
+    pub depositor_fee: u64,
+    pub amount_to_close: u64,
+    pub owner: Pubkey,
+    pub collateral: Pubkey,
+    pub borrow_index: u128,
+}
+
+impl TroveModel {
+    pub fn is_native_collateral(&self) -> bool {
+        self.collateral == Pubkey::default()
+    }
+}
+
+impl From<Trove> for TroveModel {
+    fn from(trove: Trove) -> Self {
+        TroveModel {
+            is_received: trove.is_received,
+            is_liquidated: trove.is_liquidated,
+            borrow_amount: trove.borrow_amount.0,
+            collateral_amount: trove.collateral_amount.0,
+            team_fee: trove.team_fee.0,
+            depositor_fee: trove.depositor_fee.0,
+            amount_to_close: trove.amount_to_close.0,
+            owner: trove.owner,
+            collateral: trove.collateral,
+            borrow_index: trove.borrow_index,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepositModel {
+    pub token_amount: u64,
+    pub reward_token_amount: u64,
+    pub reward_governance_token_amount: u64,
+    pub reward_coin_amount: u64,
+    pub bank: Pubkey,
+    pub governance_bank: Pubkey,
+    pub owner: Pubkey,
+}
+
+impl From<Deposit> for DepositModel {
+    fn from(deposit: Deposit) -> Self {
+        DepositModel {
+            token_amount: deposit.token_amount.0,
+            reward_token_amount: deposit.reward_token_amount.0,
+            reward_governance_token_amount: deposit.reward_governance_token_amount.0,
+            reward_coin_amount: deposit.reward_coin_amount.0,
+            bank: deposit.bank,
+            governance_bank: deposit.governance_bank,
+            owner: deposit.owner,
+        }
+    }
+}
+
+/// State of an account owned by the program
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// Created for the program and still zeroed
+    Uninitialized { len: usize },
+    Protocol(ProtocolModel),
+    CollateralType(CollateralTypeModel),
+    Trove(TroveModel),
+    Deposit(DepositModel),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Account {
+    Program { lamports: u64, state: State },
+    Mint { supply: u64 },
+    Token { mint: Pubkey, owner: Pubkey, amount: u64 },
+    /// Account of the system program, only its lamports matter
+    Wallet { lamports: u64 },
+}
+
+impl Account {
+    /// Model of an account read from the bank
+    pub fn from_chain(program_id: &Pubkey, owner: &Pubkey, lamports: u64, data: &[u8]) -> Self {
+        if owner == program_id {
+            let state = match AccountType::of(data) {
+                Some(AccountType::Protocol) => State::Protocol(Protocol::unpack_unchecked(data).unwrap().into()),
+                Some(AccountType::CollateralType) => {
+                    State::CollateralType(CollateralType::unpack_unchecked(data).unwrap().into())
+                }
+                Some(AccountType::Trove) => State::Trove(Trove::unpack_unchecked(data).unwrap().into()),
+                Some(AccountType::Deposit) => State::Deposit(Deposit::unpack_unchecked(data).unwrap().into()),
+                Some(AccountType::Uninitialized) => State::Uninitialized { len: data.len() },
+                None if data.is_empty() => State::Uninitialized { len: 0 },
+                other => panic!("unexpected program account {:?}", other),
+            };
+            return Account::Program { lamports, state };
+        }
+        if *owner == spl_token::id() {
+            if data.len() == spl_token::state::Mint::LEN {
+                return Account::Mint { supply: spl_token::state::Mint::unpack(data).unwrap().supply };
+            }
+            let token = spl_token::state::Account::unpack(data).unwrap();
+            return Account::Token { mint: token.mint, owner: token.owner, amount: token.amount };
+        }
+        Account::Wallet { lamports }
+    }
+}
+
+/// Stake pool accepted as collateral, its exchange rate fixed for the whole run
+#[derive(Clone, Copy, Debug)]
+pub struct StakePoolModel {
+    pub pool_mint: Pubkey,
+    /// The collateral vault of the pool mint
+    pub vault: Pubkey,
+    pub total_lamports: u64,
+    pub pool_token_supply: u64,
+}
+
+/// Stablecoin and collateral flows the invariants are stated in
+#[derive(Clone, Debug, Default)]
+pub struct Ledger {
+    /// Interest each open trove accrued when it was refreshed, part of its debt but never minted
+    pub interest: BTreeMap<Pubkey, u64>,
+    /// Stablecoin troves that were closed or liquidated left in circulation, their debt
+    /// less the fees and interest, which redemptions can turn negative
+    pub settled: i128,
+    /// Stablecoin minted to users outside of the protocol
+    pub bought: u64,
+    /// Stablecoin burned by closing troves and by deposits, less the deposits withdrawn.
+    /// Redemptions burn against the debt of their trove instead
+    pub burned: u64,
+}
+
+/// The protocol as a map of account models, `None` for accounts which do not exist
+#[derive(Clone, Debug)]
+pub struct Model {
+    pub program_id: Pubkey,
+    pub rent: Rent,
+    pub unix_timestamp: i64,
+    pub accounts: BTreeMap<Pubkey, Option<Account>>,
+    pub stake_pools: BTreeMap<Pubkey, StakePoolModel>,
+    pub ledger: Ledger,
+}
+
+type Result<T> = std::result::Result<T, ProgramError>;
+
+fn overflow() -> ProgramError {
+    LiquityError::MathOverflow.into()
+}
+
+impl Model {
+    pub fn new(program_id: Pubkey, rent: Rent, unix_timestamp: i64, accounts: BTreeMap<Pubkey, Option<Account>>) -> Self {
+        Model { program_id, rent, unix_timestamp, accounts, stake_pools: BTreeMap::new(), ledger: Ledger::default() }
+    }
+
+    pub fn account(&self, key: &Pubkey) -> Option<Account> {
+        self.accounts.get(key).copied().flatten()
+    }
+
+    fn set(&mut self, key: Pubkey, account: Account) {
+        let account = match account {
+            // The runtime deletes accounts left without lamports
+            Account::Wallet { lamports: 0 } | Account::Program { lamports: 0, .. } => None,
+            account => Some(account),
+        };
+        self.accounts.insert(key, account);
+    }
+
+    pub fn trove(&self, key: &Pubkey) -> Option<TroveModel> {
+        match self.account(key) {
+            Some(Account::Program { state: State::Trove(trove), .. }) => Some(trove),
+            _ => None,
+        }
+    }
+
+    pub fn protocol(&self) -> ProtocolModel {
+        self.accounts
+            .values()
+            .find_map(|account| match account {
+                Some(Account::Program { state: State::Protocol(protocol), .. }) => Some(*protocol),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    pub fn lamports(&self, key: &Pubkey) -> u64 {
+        match self.account(key) {
+            Some(Account::Program { lamports, .. }) | Some(Account::Wallet { lamports }) => lamports,
+            _ => 0,
+        }
+    }
+
+    pub fn token_amount(&self, key: &Pubkey) -> u64 {
+        match self.account(key) {
+            Some(Account::Token { amount, .. }) => amount,
+            _ => 0,
+        }
+    }
+
+    /// Debt of the trove if it was refreshed now
+    pub fn debt_with_interest(&self, key: &Pubkey) -> Result<u64> {
+        let trove = self.trove(key).ok_or(LiquityError::InvalidAccountOwner)?;
+        let protocol = self.accrued(self.protocol())?;
+        Self::refreshed(trove, protocol.borrow_index).map(|trove| trove.amount_to_close)
+    }
+
+    /// Records an account created outside of the program
+    pub fn create(&mut self, key: Pubkey, account: Account) {
+        self.set(key, account);
+    }
+
+    /// Creates a zeroed account of the program, funded by `funder`
+    pub fn create_program_account(&mut self, funder: &Pubkey, key: Pubkey, lamports: u64, len: usize) {
+        self.add_lamports(funder, lamports, false);
+        self.set(key, Account::Program { lamports, state: State::Uninitialized { len } });
+    }
+
+    fn add_lamports(&mut self, key: &Pubkey, lamports: u64, credit: bool) {
+        let apply = |balance: u64| if credit { balance + lamports } else { balance - lamports };
+        let account = match self.account(key) {
+            Some(Account::Program { lamports: balance, state }) => Account::Program { lamports: apply(balance), state },
+            Some(Account::Wallet { lamports: balance }) => Account::Wallet { lamports: apply(balance) },
+            None => Account::Wallet { lamports: apply(0) },
+            Some(other) => panic!("lamports moved from {:?}", other),
+        };
+        self.set(*key, account);
+    }
+
+    /// Mints stablecoin or pool tokens from outside of the protocol
+    pub fn mint_to(&mut self, token: &Pubkey, amount: u64) {
+        self.add_tokens(token, amount, true).unwrap();
+    }
+
+    fn add_tokens(&mut self, token: &Pubkey, amount: u64, credit: bool) -> Result<()> {
+        let (mint, owner, balance) = match self.account(token) {
+            Some(Account::Token { mint, owner, amount }) => (mint, owner, amount),
+            other => panic!("tokens moved from {:?}", other),
+        };
+        let balance = if credit {
+            balance.checked_add(amount).ok_or(TokenError::Overflow)?
+        } else {
+            balance.checked_sub(amount).ok_or(TokenError::InsufficientFunds)?
+        };
+        self.set(*token, Account::Token { mint, owner, amount: balance });
+        Ok(())
+    }
+
+    fn change_supply(&mut self, mint: &Pubkey, amount: u64, credit: bool) {
+        let supply = match self.account(mint) {
+            Some(Account::Mint { supply }) => supply,
+            other => panic!("supply of {:?}", other),
+        };
+        let supply = if credit { supply + amount } else { supply - amount };
+        self.set(*mint, Account::Mint { supply });
+    }
+
+    /// Mints tokens of `mint`, as the system account or a user buying them
+    pub fn mint(&mut self, mint: &Pubkey, token: &Pubkey, amount: u64) {
+        self.mint_to(token, amount);
+        self.change_supply(mint, amount, true);
+    }
+
+    fn burn(&mut self, mint: &Pubkey, token: &Pubkey, amount: u64) -> Result<()> {
+        self.add_tokens(token, amount, false)?;
+        self.change_supply(mint, amount, false);
+        Ok(())
+    }
+
+    fn transfer_tokens(&mut self, from: &Pubkey, to: &Pubkey, amount: u64) -> Result<()> {
+        self.add_tokens(from, amount, false)?;
+        self.add_tokens(to, amount, true)
+    }
+
+    /// Processes `instruction` as the program would, leaving the model unchanged on error
+    pub fn process(&mut self, instruction: &Instruction) -> Result<()> {
+        let mut next = self.clone();
+        let keys: Vec<Pubkey> = instruction.accounts.iter().map(|meta| meta.pubkey).collect();
+        match LiquityInstruction::unpack(&instruction.data)? {
+            LiquityInstruction::Borrow { borrow_amount, lamports } => next.borrow(&keys, borrow_amount.0, lamports.0)?,
+            LiquityInstruction::BorrowWithStakePoolToken { borrow_amount, pool_tokens } => {
+                next.borrow_with_stake_pool_token(&keys, borrow_amount.0, pool_tokens.0)?
+            }
+            LiquityInstruction::CloseTrove {} => next.close_trove(&keys)?,
+            LiquityInstruction::LiquidateTrove {} => next.liquidate_trove(&keys)?,
+            LiquityInstruction::WithdrawCoin { amount } => next.withdraw_coin(&keys, amount.0)?,
+            LiquityInstruction::AddCoin { amount } => next.add_coin(&keys, amount.0)?,
+            LiquityInstruction::RedeemCoin { amount } => next.redeem_coin(&keys, amount.0)?,
+            LiquityInstruction::ReceiveTrove {} => next.receive_trove(&keys)?,
+            LiquityInstruction::AddDeposit { amount } => next.add_deposit(&keys, amount.0)?,
+            LiquityInstruction::WithdrawDeposit { amount } => next.withdraw_deposit(&keys, amount.0)?,
+            LiquityInstruction::AddDepositReward { coin, governance, token } => {
+                next.add_deposit_reward(&keys, coin.0, governance.0, token.0)?
+            }
+            LiquityInstruction::ClaimDepositReward {} => next.claim_deposit_reward(&keys)?,
+            LiquityInstruction::SetPause { paused } => next.set_pause(&keys, paused)?,
+            other => panic!("{:?} is not modelled", other),
+        }
+        *self = next;
+        Ok(())
+    }
+
+    fn program_state(&self, key: &Pubkey) -> Result<(u64, State)> {
+        match self.account(key) {
+            Some(Account::Program { lamports, state }) => Ok((lamports, state)),
+            _ => Err(LiquityError::InvalidAccountOwner.into()),
+        }
+    }
+
+    fn save(&mut self, key: &Pubkey, state: State) {
+        let (lamports, _) = self.program_state(key).unwrap();
+        self.set(*key, Account::Program { lamports, state });
+    }
+
+    fn load_protocol(&self, key: &Pubkey) -> Result<ProtocolModel> {
+        match self.program_state(key)?.1 {
+            State::Protocol(protocol) => Ok(protocol),
+            _ => Err(LiquityError::InvalidAccountType.into()),
+        }
+    }
+
+    fn load_trove(&self, key: &Pubkey) -> Result<TroveModel> {
+        match self.program_state(key)?.1 {
+            State::Trove(trove) => Ok(trove),
+            State::Uninitialized { len } if len == Trove::LEN => Err(LiquityError::TroveIsNotInitialized.into()),
+            State::Uninitialized { .. } => Err(LiquityError::AccountNotMigrated.into()),
+            _ => Err(LiquityError::InvalidAccountType.into()),
+        }
+    }
+
+    /// Checks a fresh trove account like `Account::load_uninitialized`
+    fn load_uninitialized_trove(&self, key: &Pubkey) -> Result<()> {
+        if !self.rent.is_exempt(self.lamports(key), Trove::LEN) {
+            return Err(LiquityError::NotRentExempt.into());
+        }
+        match self.program_state(key)?.1 {
+            State::Uninitialized { len } if len == Trove::LEN => Ok(()),
+            State::Uninitialized { .. } => Err(LiquityError::AccountNotMigrated.into()),
+            State::Trove(_) => Err(ProgramError::AccountAlreadyInitialized),
+            _ => Err(LiquityError::InvalidAccountType.into()),
+        }
+    }
+
+    /// The deposit, `None` for a fresh deposit account
+    fn load_or_uninitialized_deposit(&self, key: &Pubkey) -> Result<Option<DepositModel>> {
+        if !self.rent.is_exempt(self.lamports(key), Deposit::LEN) {
+            return Err(LiquityError::NotRentExempt.into());
+        }
+        match self.program_state(key)?.1 {
+            State::Deposit(deposit) => Ok(Some(deposit)),
+            State::Uninitialized { len } if len == Deposit::LEN => Ok(None),
+            State::Uninitialized { .. } => Err(LiquityError::AccountNotMigrated.into()),
+            _ => Err(LiquityError::InvalidAccountType.into()),
+        }
+    }
+
+    fn load_deposit(&self, key: &Pubkey) -> Result<DepositModel> {
+        match self.program_state(key)?.1 {
+            State::Deposit(deposit) => Ok(deposit),
+            State::Uninitialized { len } if len == Deposit::LEN => Err(ProgramError::UninitializedAccount),
+            State::Uninitialized { .. } => Err(LiquityError::AccountNotMigrated.into()),
+            _ => Err(LiquityError::InvalidAccountType.into()),
+        }
+    }
+
+    fn load_collateral_type(&self, key: &Pubkey, protocol: &Pubkey, collateral: &Pubkey) -> Result<CollateralTypeModel> {
+        if *key != find_collateral_type_address(&self.program_id, protocol, collateral).0 {
+            return Err(LiquityError::InvalidAccountInput.into());
+        }
+        let collateral_type = match self.program_state(key)?.1 {
+            State::CollateralType(collateral_type) => collateral_type,
+            _ => return Err(LiquityError::InvalidAccountType.into()),
+        };
+        if collateral_type.collateral != *collateral {
+            return Err(LiquityError::InvalidAccountInput.into());
+        }
+        Ok(collateral_type)
+    }
+
+    fn check_token_account(&self, key: &Pubkey, mint: &Pubkey, owner: &Pubkey) -> Result<()> {
+        match self.account(key) {
+            Some(Account::Token { mint: token_mint, .. }) if token_mint != *mint => Err(LiquityError::InvalidTokenMint.into()),
+            Some(Account::Token { owner: token_owner, .. }) if token_owner == *owner => Ok(()),
+            _ => Err(LiquityError::InvalidTokenOwner.into()),
+        }
+    }
+
+    fn check_not_paused(protocol: &ProtocolModel, operation: u64) -> Result<()> {
+        if protocol.paused & operation != 0 {
+            return Err(LiquityError::OperationPaused.into());
+        }
+        Ok(())
+    }
+
+    /// The protocol with simple interest accrued on its borrow index up to now
+    fn accrued(&self, mut protocol: ProtocolModel) -> Result<ProtocolModel> {
+        let elapsed = self.unix_timestamp.checked_sub(protocol.last_update_timestamp).ok_or_else(overflow)?;
+        if elapsed > 0 && protocol.interest_rate > 0 {
+            let interest = protocol
+                .borrow_index
+                .checked_mul(protocol.interest_rate as u128)
+                .and_then(|interest| interest.checked_mul(elapsed as u128))
+                .ok_or_else(overflow)?
+                / (BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR);
+            protocol.borrow_index = protocol.borrow_index.checked_add(interest).ok_or_else(overflow)?;
+        }
+        protocol.last_update_timestamp = self.unix_timestamp;
+        Ok(protocol)
+    }
+
+    /// The trove with the interest since its snapshot added to its debt
+    fn refreshed(mut trove: TroveModel, borrow_index: u128) -> Result<TroveModel> {
+        if trove.borrow_index != 0 && trove.borrow_index != borrow_index {
+            let debt = (trove.amount_to_close as u128).checked_mul(borrow_index).ok_or_else(overflow)? / trove.borrow_index;
+            let debt = u64::try_from(debt).map_err(|_| overflow())?;
+            let interest = debt - trove.amount_to_close;
+            trove.borrow_amount = trove.borrow_amount.checked_add(interest).ok_or_else(overflow)?;
+            trove.amount_to_close = debt;
+        }
+        trove.borrow_index = borrow_index;
+        Ok(trove)
+    }
+
+    /// Refreshes the trove, adding its interest to the total debts
+    fn refresh(
+        &mut self,
+        key: &Pubkey,
+        trove: TroveModel,
+        protocol: &mut ProtocolModel,
+        collateral_type: &mut CollateralTypeModel,
+    ) -> Result<TroveModel> {
+        let refreshed = Self::refreshed(trove, protocol.borrow_index)?;
+        let interest = refreshed.amount_to_close - trove.amount_to_close;
+        protocol.total_debt = protocol.total_debt.checked_add(interest).ok_or_else(overflow)?;
+        collateral_type.total_debt = collateral_type.total_debt.checked_add(interest).ok_or_else(overflow)?;
+        *self.ledger.interest.entry(*key).or_default() += interest;
+        Ok(refreshed)
+    }
+
+    fn remove_debt(protocol: &mut ProtocolModel, collateral_type: &mut CollateralTypeModel, amount: u64) -> Result<()> {
+        protocol.total_debt = protocol.total_debt.checked_sub(amount).ok_or_else(overflow)?;
+        collateral_type.total_debt = collateral_type.total_debt.checked_sub(amount).ok_or_else(overflow)?;
+        Ok(())
+    }
+
+    /// Lamports `amount` of the collateral is worth, pool tokens at the pool rate
+    fn to_lamports(&self, collateral: &Pubkey, amount: u64) -> Result<u64> {
+        match self.stake_pools.get(collateral) {
+            None => Ok(amount),
+            Some(pool) if pool.pool_token_supply == 0 => Ok(amount),
+            Some(pool) => {
+                let lamports = amount as u128 * pool.total_lamports as u128 / pool.pool_token_supply as u128;
+                u64::try_from(lamports).map_err(|_| overflow())
+            }
+        }
+    }
+
+    /// Whether the collateral is worth the minimum collateral ratio of `borrow_amount`
+    fn is_covered(&self, protocol: &ProtocolModel, collateral: &Pubkey, amount: u64, borrow_amount: u64) -> Result<bool> {
+        let price = SOL_PRICE as u128 * 10u128.pow(protocol.stablecoin_decimals as u32);
+        let value = self.to_lamports(collateral, amount)? as u128 * price / LAMPORTS_PER_SOL as u128;
+        let value = u64::try_from(value).map_err(|_| overflow())?;
+        Ok(value as u128 * BPS_DENOMINATOR as u128 >= borrow_amount as u128 * protocol.min_collateral_ratio as u128)
+    }
+
+    /// Stablecoin burned to redeem `amount` of the collateral, its value rounded up
+    fn redemption_amount(&self, protocol: &ProtocolModel, collateral: &Pubkey, amount: u64) -> Result<u64> {
+        let price = SOL_PRICE as u128 * 10u128.pow(protocol.stablecoin_decimals as u32);
+        let value = (self.to_lamports(collateral, amount)? as u128 * price).div_ceil(LAMPORTS_PER_SOL as u128);
+        u64::try_from(value).map_err(|_| overflow())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn open_trove(
+        &mut self,
+        protocol: &mut ProtocolModel,
+        collateral_type: &mut CollateralTypeModel,
+        key: &Pubkey,
+        owner: &Pubkey,
+        borrow_amount: u64,
+        collateral_amount: u64,
+        collateral: Pubkey,
+    ) -> Result<()> {
+        let gas_fee = GAS_FEE * 10u64.pow(protocol.stablecoin_decimals as u32);
+        let debt = borrow_amount.checked_sub(gas_fee).ok_or_else(overflow)?;
+        let trove = TroveModel {
+            is_received: false,
+            is_liquidated: false,
+            borrow_amount,
+            collateral_amount,
+            depositor_fee: (debt as u128 * protocol.deposit_fee as u128 / BPS_DENOMINATOR as u128) as u64,
+            team_fee: (debt as u128 * protocol.team_fee as u128 / BPS_DENOMINATOR as u128) as u64,
+            amount_to_close: debt,
+            owner: *owner,
+            collateral,
+            borrow_index: protocol.borrow_index,
+        };
+
+        protocol.total_debt = protocol.total_debt.checked_add(debt).ok_or_else(overflow)?;
+        collateral_type.total_debt = collateral_type.total_debt.checked_add(debt).ok_or_else(overflow)?;
+        if protocol.total_debt > protocol.debt_ceiling || collateral_type.total_debt > collateral_type.debt_ceiling {
+            return Err(LiquityError::DebtCeilingExceeded.into());
+        }
+
+        self.save(key, State::Trove(trove));
+        Ok(())
+    }
+
+    /// Mints the stablecoin of a new trove to its owner, the debt less the fees, which are never minted
+    fn mint_sent(&mut self, trove: &Pubkey, mint: &Pubkey, user_token: &Pubkey) {
+        let trove = self.trove(trove).unwrap();
+        self.mint(mint, user_token, trove.amount_to_close - trove.depositor_fee - trove.team_fee);
+    }
+
+    /// `[borrower, trove, rent, protocol, clock, collateral type, mint, user token, authority, token program]`
+    fn borrow(&mut self, keys: &[Pubkey], borrow_amount: u64, lamports: u64) -> Result<()> {
+        let (borrower, trove, protocol_key, collateral_type_key) = (&keys[0], &keys[1], &keys[3], &keys[5]);
+        self.load_uninitialized_trove(trove)?;
+        let rent = self.rent.minimum_balance(Trove::LEN);
+        if self.lamports(trove) < rent.checked_add(lamports).ok_or_else(overflow)? {
+            return Err(LiquityError::ExpectedAmountMismatch.into());
+        }
+        let mut protocol = self.accrued(self.load_protocol(protocol_key)?)?;
+        Self::check_not_paused(&protocol, PAUSE_BORROW)?;
+        let mut collateral_type = self.load_collateral_type(collateral_type_key, protocol_key, &Pubkey::default())?;
+
+        if !self.is_covered(&protocol, &Pubkey::default(), lamports, borrow_amount)? {
+            return Err(LiquityError::InvalidCollateral.into());
+        }
+        let (mint, user_token) = (&keys[6], &keys[7]);
+        self.check_token_account(user_token, mint, borrower)?;
+
+        self.open_trove(&mut protocol, &mut collateral_type, trove, borrower, borrow_amount, lamports, Pubkey::default())?;
+        self.mint_sent(trove, mint, user_token);
+        self.save(protocol_key, State::Protocol(protocol));
+        self.save(collateral_type_key, State::CollateralType(collateral_type));
+        Ok(())
+    }
+
+    /// `[borrower, trove, rent, protocol, clock, collateral type, stake pool, vault, user pool token, authority, token program,
+    /// mint, user token, authority, token program]`
+    fn borrow_with_stake_pool_token(&mut self, keys: &[Pubkey], borrow_amount: u64, pool_tokens: u64) -> Result<()> {
+        let (borrower, trove, protocol_key, collateral_type_key) = (&keys[0], &keys[1], &keys[3], &keys[5]);
+        let (stake_pool, vault, user_token) = (&keys[6], &keys[7], &keys[8]);
+        self.load_uninitialized_trove(trove)?;
+        let mut protocol = self.accrued(self.load_protocol(protocol_key)?)?;
+        Self::check_not_paused(&protocol, PAUSE_BORROW)?;
+        let mut collateral_type = self.load_collateral_type(collateral_type_key, protocol_key, stake_pool)?;
+
+        if !self.is_covered(&protocol, stake_pool, pool_tokens, borrow_amount)? {
+            return Err(LiquityError::InvalidCollateral.into());
+        }
+        let (mint, stablecoin) = (&keys[11], &keys[12]);
+        self.check_token_account(stablecoin, mint, borrower)?;
+        self.transfer_tokens(user_token, vault, pool_tokens)?;
+
+        self.open_trove(&mut protocol, &mut collateral_type, trove, borrower, borrow_amount, pool_tokens, *stake_pool)?;
+        self.mint_sent(trove, mint, stablecoin);
+        self.save(protocol_key, State::Protocol(protocol));
+        self.save(collateral_type_key, State::CollateralType(collateral_type));
+        Ok(())
+    }
+
+    /// Loads a trove the instruction changes on behalf of its owner
+    fn load_owned_trove(&self, trove: &Pubkey, owner: &Pubkey) -> Result<TroveModel> {
+        let trove = self.load_trove(trove)?;
+        if trove.is_liquidated {
+            return Err(LiquityError::TroveAlreadyLiquidated.into());
+        }
+        if trove.owner != *owner {
+            return Err(LiquityError::OnlyForTroveOwner.into());
+        }
+        Ok(trove)
+    }
+
+    /// Pays native collateral out of the trove account, which stays rent exempt
+    fn withdraw_lamports(&mut self, trove: &Pubkey, recipient: &Pubkey, amount: u64) -> Result<()> {
+        let lamports = self.lamports(trove).checked_sub(amount).ok_or_else(overflow)?;
+        if !self.rent.is_exempt(lamports, Trove::LEN) {
+            return Err(LiquityError::NotRentExempt.into());
+        }
+        self.add_lamports(trove, amount, false);
+        self.add_lamports(recipient, amount, true);
+        Ok(())
+    }
+
+    /// Moves what the trove left in circulation out of the open troves, before it is closed
+    fn settle(&mut self, key: &Pubkey, trove: &TroveModel) {
+        let interest = self.ledger.interest.remove(key).unwrap_or_default();
+        self.ledger.settled += Self::circulating(trove, interest);
+    }
+
+    /// Stablecoin the trove put in circulation, its debt less the fees and `interest`
+    fn circulating(trove: &TroveModel, interest: u64) -> i128 {
+        trove.amount_to_close as i128 - trove.depositor_fee as i128 - trove.team_fee as i128 - interest as i128
+    }
+
+    /// Closes a program account, sending its lamports to `recipient`
+    fn close(&mut self, key: &Pubkey, recipient: &Pubkey) {
+        let lamports = self.lamports(key);
+        self.add_lamports(recipient, lamports, true);
+        self.accounts.insert(*key, None);
+    }
+
+    /// `[borrower, trove, token program, user token, mint, protocol, clock, collateral type, stake pool accounts..]`
+    fn close_trove(&mut self, keys: &[Pubkey]) -> Result<()> {
+        let (borrower, trove_key, user_token, mint) = (&keys[0], &keys[1], &keys[3], &keys[4]);
+        let (protocol_key, collateral_type_key) = (&keys[5], &keys[7]);
+        let trove = self.load_owned_trove(trove_key, borrower)?;
+        let mut protocol = self.accrued(self.load_protocol(protocol_key)?)?;
+        self.check_token_account(user_token, mint, borrower)?;
+        let mut collateral_type = self.load_collateral_type(collateral_type_key, protocol_key, &trove.collateral)?;
+
+        let trove = self.refresh(trove_key, trove, &mut protocol, &mut collateral_type)?;
+        Self::remove_debt(&mut protocol, &mut collateral_type, trove.amount_to_close)?;
+        self.save(protocol_key, State::Protocol(protocol));
+        self.save(collateral_type_key, State::CollateralType(collateral_type));
+
+        self.burn(mint, user_token, trove.amount_to_close)?;
+        self.ledger.burned += trove.amount_to_close;
+        if !trove.is_native_collateral() {
+            self.transfer_tokens(&keys[9], &keys[10], trove.collateral_amount)?;
+        }
+
+        self.settle(trove_key, &trove);
+        self.close(trove_key, borrower);
+        Ok(())
+    }
+
+    /// `[liquidator, trove, sys acc, protocol, clock, collateral type, stake pool accounts..]`
+    fn liquidate_trove(&mut self, keys: &[Pubkey]) -> Result<()> {
+        let (trove_key, protocol_key, collateral_type_key) = (&keys[1], &keys[3], &keys[5]);
+        let trove = self.load_trove(trove_key)?;
+        if trove.is_liquidated {
+            return Err(LiquityError::TroveAlreadyLiquidated.into());
+        }
+        if !trove.is_received {
+            return Err(LiquityError::TroveIsNotReceived.into());
+        }
+        let mut protocol = self.accrued(self.load_protocol(protocol_key)?)?;
+        Self::check_not_paused(&protocol, PAUSE_LIQUIDATE)?;
+        let mut collateral_type = self.load_collateral_type(collateral_type_key, protocol_key, &trove.collateral)?;
+
+        let trove = self.refresh(trove_key, trove, &mut protocol, &mut collateral_type)?;
+        if self.is_covered(&protocol, &trove.collateral, trove.collateral_amount, trove.borrow_amount)? {
+            return Err(LiquityError::InvalidCollateral.into());
+        }
+        Self::remove_debt(&mut protocol, &mut collateral_type, trove.amount_to_close)?;
+        self.save(protocol_key, State::Protocol(protocol));
+        self.save(collateral_type_key, State::CollateralType(collateral_type));
+
+        if !trove.is_native_collateral() {
+            let pool_mint = self.stake_pools[&trove.collateral].pool_mint;
+            self.check_token_account(&keys[8], &pool_mint, &SYSTEM_ACCOUNT_ADDRESS)?;
+            self.transfer_tokens(&keys[7], &keys[8], trove.collateral_amount)?;
+        }
+
+        self.settle(trove_key, &trove);
+        self.close(trove_key, &SYSTEM_ACCOUNT_ADDRESS);
+        Ok(())
+    }
+
+    /// `[borrower, trove, protocol, clock, collateral type, stake pool accounts..]`
+    fn withdraw_coin(&mut self, keys: &[Pubkey], amount: u64) -> Result<()> {
+        let (borrower, trove_key, protocol_key, collateral_type_key) = (&keys[0], &keys[1], &keys[2], &keys[4]);
+        let trove = self.load_owned_trove(trove_key, borrower)?;
+        let mut protocol = self.accrued(self.load_protocol(protocol_key)?)?;
+        Self::check_not_paused(&protocol, PAUSE_ADJUST_TROVE)?;
+        let mut collateral_type = self.load_collateral_type(collateral_type_key, protocol_key, &trove.collateral)?;
+
+        let mut trove = self.refresh(trove_key, trove, &mut protocol, &mut collateral_type)?;
+        trove.collateral_amount = trove.collateral_amount.checked_sub(amount).ok_or_else(overflow)?;
+        if trove.is_native_collateral() {
+            self.withdraw_lamports(trove_key, borrower, amount)?;
+        } else {
+            self.transfer_tokens(&keys[6], &keys[7], amount)?;
+        }
+
+        if !self.is_covered(&protocol, &trove.collateral, trove.collateral_amount, trove.borrow_amount)? {
+            return Err(LiquityError::InvalidCollateral.into());
+        }
+
+        self.save(trove_key, State::Trove(trove));
+        self.save(protocol_key, State::Protocol(protocol));
+        self.save(collateral_type_key, State::CollateralType(collateral_type));
+        Ok(())
+    }
+
+    /// `[borrower, trove, protocol, temp lamports]`, or the stake pool accounts instead of the temp account
+    fn add_coin(&mut self, keys: &[Pubkey], amount: u64) -> Result<()> {
+        let (borrower, trove_key, protocol_key) = (&keys[0], &keys[1], &keys[2]);
+        let mut trove = self.load_owned_trove(trove_key, borrower)?;
+        let protocol = self.load_protocol(protocol_key)?;
+        Self::check_not_paused(&protocol, PAUSE_ADJUST_TROVE)?;
+
+        if trove.is_native_collateral() {
+            let temp = &keys[3];
+            if self.program_state(temp)?.1 != (State::Uninitialized { len: 0 }) {
+                return Err(LiquityError::InvalidAccountType.into());
+            }
+            if self.lamports(temp) != amount {
+                return Err(LiquityError::ExpectedAmountMismatch.into());
+            }
+            self.close(temp, trove_key);
+        } else {
+            self.transfer_tokens(&keys[5], &keys[4], amount)?;
+        }
+
+        trove.collateral_amount = trove.collateral_amount.checked_add(amount).ok_or_else(overflow)?;
+        self.save(trove_key, State::Trove(trove));
+        Ok(())
+    }
+
+    /// `[borrower, trove, protocol, clock, collateral type, token program, user token, mint, stake pool accounts..]`
+    fn redeem_coin(&mut self, keys: &[Pubkey], amount: u64) -> Result<()> {
+        let (borrower, trove_key, protocol_key, collateral_type_key) = (&keys[0], &keys[1], &keys[2], &keys[4]);
+        let (user_token, mint) = (&keys[6], &keys[7]);
+        let trove = self.load_owned_trove(trove_key, borrower)?;
+        let mut protocol = self.accrued(self.load_protocol(protocol_key)?)?;
+        Self::check_not_paused(&protocol, PAUSE_REDEEM)?;
+        self.check_token_account(user_token, mint, borrower)?;
+        let mut collateral_type = self.load_collateral_type(collateral_type_key, protocol_key, &trove.collateral)?;
+
+        let mut trove = self.refresh(trove_key, trove, &mut protocol, &mut collateral_type)?;
+        trove.collateral_amount = trove.collateral_amount.checked_sub(amount).ok_or_else(overflow)?;
+        let repaid = self.redemption_amount(&protocol, &trove.collateral, amount)?;
+        trove.amount_to_close = trove.amount_to_close.checked_sub(repaid).ok_or_else(overflow)?;
+        trove.borrow_amount = trove.borrow_amount.checked_sub(repaid).ok_or_else(overflow)?;
+        Self::remove_debt(&mut protocol, &mut collateral_type, repaid)?;
+
+        if !self.is_covered(&protocol, &trove.collateral, trove.collateral_amount, trove.borrow_amount)? {
+            return Err(LiquityError::InvalidCollateral.into());
+        }
+
+        self.burn(mint, user_token, repaid)?;
+        if trove.is_native_collateral() {
+            self.withdraw_lamports(trove_key, borrower, amount)?;
+        } else {
+            self.transfer_tokens(&keys[9], &keys[10], amount)?;
+        }
+        self.save(trove_key, State::Trove(trove));
+        self.save(protocol_key, State::Protocol(protocol));
+        self.save(collateral_type_key, State::CollateralType(collateral_type));
+        Ok(())
+    }
+
+    /// `[sys acc, trove, protocol]`
+    fn receive_trove(&mut self, keys: &[Pubkey]) -> Result<()> {
+        let (trove_key, protocol_key) = (&keys[1], &keys[2]);
+        let protocol = self.load_protocol(protocol_key)?;
+        Self::check_not_paused(&protocol, PAUSE_RECEIVE_TROVE)?;
+        let mut trove = self.load_trove(trove_key)?;
+        if trove.is_liquidated {
+            return Err(LiquityError::TroveAlreadyLiquidated.into());
+        }
+
+        trove.is_received = true;
+        self.save(trove_key, State::Trove(trove));
+        Ok(())
+    }
+
+    /// `[depositor, deposit, rent, token program, user token, user governance token, mint, protocol]`
+    fn add_deposit(&mut self, keys: &[Pubkey], amount: u64) -> Result<()> {
+        let (depositor, deposit_key, user_token, user_governance_token) = (&keys[0], &keys[1], &keys[4], &keys[5]);
+        let (mint, protocol_key) = (&keys[6], &keys[7]);
+        let deposit = self.load_or_uninitialized_deposit(deposit_key)?;
+        let protocol = self.load_protocol(protocol_key)?;
+        Self::check_not_paused(&protocol, PAUSE_DEPOSIT)?;
+        self.check_token_account(user_token, mint, depositor)?;
+        self.check_token_account(user_governance_token, &GENS_TOKEN_ADDRESS, depositor)?;
+
+        let deposit = match deposit {
+            Some(mut deposit) => {
+                if deposit.owner != *depositor {
+                    return Err(LiquityError::OnlyForDepositOwner.into());
+                }
+                deposit.token_amount = deposit.token_amount.checked_add(amount).ok_or_else(overflow)?;
+                deposit
+            }
+            None => DepositModel {
+                token_amount: amount,
+                reward_token_amount: 0,
+                reward_governance_token_amount: 0,
+                reward_coin_amount: 0,
+                bank: *user_token,
+                governance_bank: *user_governance_token,
+                owner: *depositor,
+            },
+        };
+
+        self.burn(mint, user_token, amount)?;
+        self.ledger.burned += amount;
+        self.save(deposit_key, State::Deposit(deposit));
+        Ok(())
+    }
+
+    /// `[depositor, deposit, protocol, mint, user token, authority, token program]`
+    fn withdraw_deposit(&mut self, keys: &[Pubkey], amount: u64) -> Result<()> {
+        let (depositor, deposit_key, protocol_key, mint, user_token) = (&keys[0], &keys[1], &keys[2], &keys[3], &keys[4]);
+        let mut deposit = self.load_deposit(deposit_key)?;
+        if deposit.owner != *depositor {
+            return Err(LiquityError::OnlyForDepositOwner.into());
+        }
+        self.load_protocol(protocol_key)?;
+        self.check_token_account(user_token, mint, depositor)?;
+        if amount > deposit.token_amount {
+            return Err(LiquityError::InsufficientLiquidity.into());
+        }
+
+        deposit.token_amount -= amount;
+        self.mint(mint, user_token, amount);
+        self.ledger.burned -= amount;
+        self.save(deposit_key, State::Deposit(deposit));
+        Ok(())
+    }
+
+    /// `[sys acc, deposit, protocol]`
+    fn add_deposit_reward(&mut self, keys: &[Pubkey], coin: u64, governance: u64, token: u64) -> Result<()> {
+        let (deposit_key, protocol_key) = (&keys[1], &keys[2]);
+        let protocol = self.load_protocol(protocol_key)?;
+        Self::check_not_paused(&protocol, PAUSE_REWARDS)?;
+        let mut deposit = self.load_deposit(deposit_key)?;
+
+        deposit.reward_coin_amount = deposit.reward_coin_amount.checked_add(coin).ok_or_else(overflow)?;
+        deposit.reward_governance_token_amount =
+            deposit.reward_governance_token_amount.checked_add(governance).ok_or_else(overflow)?;
+        deposit.reward_token_amount = deposit.reward_token_amount.checked_add(token).ok_or_else(overflow)?;
+        self.save(deposit_key, State::Deposit(deposit));
+        Ok(())
+    }
+
+    /// `[sys acc, deposit, protocol]`
+    fn claim_deposit_reward(&mut self, keys: &[Pubkey]) -> Result<()> {
+        let (deposit_key, protocol_key) = (&keys[1], &keys[2]);
+        let protocol = self.load_protocol(protocol_key)?;
+        Self::check_not_paused(&protocol, PAUSE_REWARDS)?;
+        let mut deposit = self.load_deposit(deposit_key)?;
+
+        deposit.reward_coin_amount = 0;
+        deposit.reward_governance_token_amount = 0;
+        deposit.reward_token_amount = 0;
+        self.save(deposit_key, State::Deposit(deposit));
+        Ok(())
+    }
+
+    /// `[pauser, protocol]`
+    fn set_pause(&mut self, keys: &[Pubkey], paused: u64) -> Result<()> {
+        let (pauser, protocol_key) = (&keys[0], &keys[1]);
+        let mut protocol = self.load_protocol(protocol_key)?;
+        if *pauser != protocol.pauser && *pauser != protocol.admin {
+            return Err(LiquityError::OnlyForPauser.into());
+        }
+
+        protocol.paused = paused;
+        self.save(protocol_key, State::Protocol(protocol));
+        Ok(())
+    }
+
+    /// Asserts the invariants of the debt, the stablecoin supply and the collateral
+    pub fn check_invariants(&self) {
+        let protocol = self.protocol();
+        let gas_fee = GAS_FEE * 10u64.pow(protocol.stablecoin_decimals as u32);
+        let mut collateral_type_debt = 0;
+        let mut troves = Vec::new();
+        let mut stablecoin = 0;
+        for (key, account) in &self.accounts {
+            match account {
+                Some(Account::Program { state: State::CollateralType(collateral_type), .. }) => {
+                    collateral_type_debt += collateral_type.total_debt;
+                }
+                Some(Account::Program { lamports, state: State::Trove(trove) }) => troves.push((key, *lamports, *trove)),
+                Some(Account::Token { mint, amount, .. }) if *mint == protocol.stablecoin_mint => stablecoin += amount,
+                _ => {}
+            }
+        }
+
+        // Every open trove is counted once in the total debts, with its interest
+        let trove_debt: u64 = troves.iter().map(|(_, _, trove)| trove.amount_to_close).sum();
+        assert_eq!(protocol.total_debt, trove_debt, "total debt of the protocol");
+        assert_eq!(collateral_type_debt, trove_debt, "total debt of the collateral types");
+
+        // The supply is what every token account of the stablecoin holds
+        let supply = match self.account(&protocol.stablecoin_mint) {
+            Some(Account::Mint { supply }) => supply,
+            other => panic!("stablecoin mint {:?}", other),
+        };
+        assert_eq!(supply, stablecoin, "stablecoin supply");
+
+        // Each trove put its debt less the fees and interest in circulation, redemptions taking
+        // from both alike, and users bought and burned the rest
+        let circulating: i128 = troves
+            .iter()
+            .map(|(key, _, trove)| Self::circulating(trove, self.ledger.interest.get(key).copied().unwrap_or_default()))
+            .sum();
+        assert_eq!(
+            supply as i128 + self.ledger.burned as i128,
+            circulating + self.ledger.settled + self.ledger.bought as i128,
+            "stablecoin supply against the trove debts"
+        );
+
+        for (key, lamports, trove) in &troves {
+            assert_eq!(trove.borrow_amount - trove.amount_to_close, gas_fee, "gas fee of {}", key);
+
+            // Native collateral is held by the trove account itself
+            if trove.is_native_collateral() {
+                let rent = self.rent.minimum_balance(Trove::LEN);
+                assert!(*lamports >= rent + trove.collateral_amount, "lamports of {}", key);
+            }
+        }
+
+        // Each vault holds the collateral of its stake pool troves
+        for (stake_pool, pool) in &self.stake_pools {
+            let collateral: u64 = troves
+                .iter()
+                .filter(|(_, _, trove)| trove.collateral == *stake_pool)
+                .map(|(_, _, trove)| trove.collateral_amount)
+                .sum();
+            assert_eq!(self.token_amount(&pool.vault), collateral, "vault of {}", stake_pool);
+        }
+    }
+}
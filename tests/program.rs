@@ -0,0 +1,1644 @@
+//! End to end tests of the trove, deposit and flash mint instructions on a
+//! `solana-program-test` bank, with the SPL token program, a local stablecoin mint
+//! and a flash mint receiver program. Every error an
+//! instruction can return is covered, stake pools are accounts of the stake pool
+//! program written by the tests. Instructions of the system account are sent unsigned
+//! for it, `process_transaction_with_metadata` does not verify signatures
+
+use solana_escrow::collateral::{find_authority_address, find_collateral_type_address};
+use solana_escrow::error::LiquityError;
+use solana_escrow::event::{decode_logs, LiquityEvent};
+use solana_escrow::instruction::{self, CoinSource, LiquityInstruction, StakePoolAccounts};
+use solana_escrow::params::{
+    FLASH_MINT_RECEIVER_TAG, GENS_TOKEN_ADDRESS, PAUSE_ADJUST_TROVE, PAUSE_BORROW, PAUSE_DEPOSIT, PAUSE_FLASH_MINT,
+    PAUSE_LIQUIDATE, PAUSE_RECEIVE_TROVE, PAUSE_REDEEM, PAUSE_REWARDS, STAKE_POOL_PROGRAM_ADDRESS,
+    SYSTEM_ACCOUNT_ADDRESS,
+};
+use solana_escrow::processor::Processor;
+use solana_escrow::state::{AccountType, CollateralType, Deposit, Protocol, ProtocolConfig, Trove, HEADER_LEN};
+use solana_escrow::units::{CollateralAmount, GovAmount, Lamports, Ratio, StableAmount};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+    system_instruction,
+    system_program,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use spl_token::error::TokenError;
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+
+const DECIMALS: u8 = 6;
+const UNIT: u64 = 1_000_000;
+
+/// Stablecoin owed for a borrow of 1000, after the gas fee of 200
+const DEBT: u64 = 800 * UNIT;
+
+/// A protocol with a native SOL collateral type, ready to open troves
+struct Env {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    /// The flash mint receiver of `process_receiver`
+    receiver_program: Pubkey,
+    admin: Keypair,
+    pauser: Keypair,
+    guardian: Keypair,
+    protocol: Pubkey,
+    stablecoin_mint: Pubkey,
+    collateral_type: Pubkey,
+    signatures: HashSet<Signature>,
+}
+
+impl Env {
+    async fn new() -> Self {
+        let program_id = Pubkey::new_unique();
+        let receiver_program = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new("solana_escrow", program_id, processor!(Processor::process));
+        program_test.add_program("flash_mint_receiver", receiver_program, processor!(process_receiver));
+        program_test.add_account(
+            SYSTEM_ACCOUNT_ADDRESS,
+            Account { lamports: LAMPORTS_PER_SOL, ..Account::default() },
+        );
+        // The admin pays the rent of the collateral types
+        let admin = Keypair::new();
+        program_test.add_account(admin.pubkey(), Account { lamports: LAMPORTS_PER_SOL, ..Account::default() });
+
+        let mut env = Env {
+            context: program_test.start_with_context().await,
+            program_id,
+            receiver_program,
+            admin,
+            pauser: Keypair::new(),
+            guardian: Keypair::new(),
+            protocol: Pubkey::default(),
+            stablecoin_mint: Pubkey::new_unique(),
+            collateral_type: Pubkey::default(),
+            signatures: HashSet::new(),
+        };
+
+        let (authority, _) = find_authority_address(&program_id);
+        env.set_mint(env.stablecoin_mint, Some(authority), DECIMALS).await;
+        env.set_mint(GENS_TOKEN_ADDRESS, None, DECIMALS).await;
+
+        let config = ProtocolConfig {
+            interest_rate: Ratio(0),
+            flash_mint_fee: Ratio(9),
+            pauser: env.pauser.pubkey(),
+            debt_ceiling: StableAmount(1_000_000 * UNIT),
+            admin: env.admin.pubkey(),
+            guardian: env.guardian.pubkey(),
+            min_collateral_ratio: Ratio(11_000),
+            deposit_fee: Ratio(300),
+            team_fee: Ratio(100),
+            oracle: Pubkey::new_unique(),
+            timelock_delay: 86_400,
+        };
+        env.protocol = env.create_account(Protocol::LEN).await;
+        let ix = instruction::init_protocol(&program_id, &env.protocol, &env.stablecoin_mint, config);
+        env.process(&[ix], &[]).await.unwrap();
+
+        env.collateral_type = env.init_collateral_type(Pubkey::default(), StableAmount(1_000_000 * UNIT)).await;
+        env
+    }
+
+    /// Sends a transaction paid by the payer and signed by `signers`. Signatures of
+    /// the system account are left empty
+    async fn process(&mut self, instructions: &[Instruction], signers: &[&Keypair]) -> Result<Vec<String>, TransactionError> {
+        let payer = self.context.payer.insecure_clone();
+        let mut transaction = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+        let mut keypairs = vec![&payer];
+        keypairs.extend_from_slice(signers);
+        transaction.partial_sign(&keypairs, self.context.last_blockhash);
+
+        // A transaction sent again would be rejected as already processed
+        if !self.signatures.insert(transaction.signatures[0]) {
+            self.context.last_blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+            transaction.partial_sign(&keypairs, self.context.last_blockhash);
+            self.signatures.insert(transaction.signatures[0]);
+        }
+
+        let result = self.context.banks_client.process_transaction_with_metadata(transaction).await.unwrap();
+        let metadata = result.metadata;
+        result.result.map(|()| metadata.unwrap().log_messages)
+    }
+
+    /// Events logged by a successful transaction
+    async fn events(&mut self, instructions: &[Instruction], signers: &[&Keypair]) -> Vec<LiquityEvent> {
+        let logs = self.process(instructions, signers).await.unwrap();
+        decode_logs(&self.program_id, &logs)
+    }
+
+    async fn set_account(&mut self, address: Pubkey, owner: Pubkey, data: Vec<u8>) {
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+        let account = Account { lamports: rent.minimum_balance(data.len()), data, owner, ..Account::default() };
+        self.context.set_account(&address, &account.into());
+    }
+
+    async fn set_mint(&mut self, address: Pubkey, mint_authority: Option<Pubkey>, decimals: u8) {
+        let mint = spl_token::state::Mint {
+            mint_authority: mint_authority.map_or(COption::None, COption::Some),
+            supply: 0,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        mint.pack_into_slice(&mut data);
+        self.set_account(address, spl_token::id(), data).await;
+    }
+
+    /// Creates an empty token account of `mint` owned by `owner`
+    async fn token_account(&mut self, mint: Pubkey, owner: Pubkey) -> Pubkey {
+        self.funded_token_account(mint, owner, 0).await
+    }
+
+    /// Creates a token account of `mint` owned by `owner` holding `amount`, leaving the supply
+    /// of the mint unchanged
+    async fn funded_token_account(&mut self, mint: Pubkey, owner: Pubkey, amount: u64) -> Pubkey {
+        let address = Pubkey::new_unique();
+        let account = spl_token::state::Account {
+            mint,
+            owner,
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..spl_token::state::Account::default()
+        };
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        account.pack_into_slice(&mut data);
+        self.set_account(address, spl_token::id(), data).await;
+        address
+    }
+
+    /// Creates a stablecoin account of `owner` holding `amount`, minted outside the
+    /// program by writing the account and the supply of the mint
+    async fn stablecoin_account(&mut self, owner: Pubkey, amount: u64) -> Pubkey {
+        let address = self.funded_token_account(self.stablecoin_mint, owner, amount).await;
+
+        let mint_account = self.context.banks_client.get_account(self.stablecoin_mint).await.unwrap().unwrap();
+        let mut mint = spl_token::state::Mint::unpack(&mint_account.data).unwrap();
+        mint.supply += amount;
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        mint.pack_into_slice(&mut data);
+        self.set_account(self.stablecoin_mint, spl_token::id(), data).await;
+        address
+    }
+
+    /// Writes a stake pool of `pool_mint` worth 1.05 SOL a pool token, last updated in `epoch`
+    async fn set_stake_pool(&mut self, address: Pubkey, pool_mint: Pubkey, epoch: u64) {
+        let mut data = vec![0; 282];
+        data[0] = 1;
+        data[162..194].copy_from_slice(pool_mint.as_ref());
+        data[258..266].copy_from_slice(&(1_050 * LAMPORTS_PER_SOL).to_le_bytes());
+        data[266..274].copy_from_slice(&(1_000 * LAMPORTS_PER_SOL).to_le_bytes());
+        data[274..282].copy_from_slice(&epoch.to_le_bytes());
+        self.set_account(address, STAKE_POOL_PROGRAM_ADDRESS, data).await;
+    }
+
+    async fn token_amount(&mut self, address: Pubkey) -> u64 {
+        let account = self.context.banks_client.get_account(address).await.unwrap().unwrap();
+        spl_token::state::Account::unpack(&account.data).unwrap().amount
+    }
+
+    /// Creates a rent exempt account of the program through the system program
+    async fn create_account(&mut self, len: usize) -> Pubkey {
+        self.create_funded_account(len, 0).await
+    }
+
+    /// Creates an account of the program holding its rent and `reserved`
+    async fn create_funded_account(&mut self, len: usize, reserved: u64) -> Pubkey {
+        let keypair = Keypair::new();
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+        let ix = system_instruction::create_account(
+            &self.context.payer.pubkey(),
+            &keypair.pubkey(),
+            rent.minimum_balance(len) + reserved,
+            len as u64,
+            &self.program_id,
+        );
+        self.process(&[ix], &[&keypair]).await.unwrap();
+        keypair.pubkey()
+    }
+
+    /// Stores `state` in a new account of the program
+    async fn program_account<T: Pack>(&mut self, state: T) -> Pubkey {
+        let address = Pubkey::new_unique();
+        let mut data = vec![0; T::LEN];
+        state.pack_into_slice(&mut data);
+        self.set_account(address, self.program_id, data).await;
+        address
+    }
+
+    /// Stores `state` in its legacy layout, the current one without the header and the
+    /// fields added since, in a new account of the program holding its rent and `reserved`
+    async fn legacy_account<T: Pack>(&mut self, state: T, legacy_len: usize, reserved: u64) -> Pubkey {
+        let address = Pubkey::new_unique();
+        let mut data = vec![0; T::LEN];
+        state.pack_into_slice(&mut data);
+        let data = data[HEADER_LEN..HEADER_LEN + legacy_len].to_vec();
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+        let lamports = rent.minimum_balance(legacy_len) + reserved;
+        let account = Account { lamports, data, owner: self.program_id, ..Account::default() };
+        self.context.set_account(&address, &account.into());
+        address
+    }
+
+    async fn state<T: Pack + IsInitialized>(&mut self, address: Pubkey) -> T {
+        let account = self.context.banks_client.get_account(address).await.unwrap().unwrap();
+        T::unpack(&account.data).unwrap()
+    }
+
+    async fn init_collateral_type(&mut self, collateral: Pubkey, debt_ceiling: StableAmount) -> Pubkey {
+        let (address, _) = find_collateral_type_address(&self.program_id, &self.protocol, &collateral);
+        let ix = instruction::init_collateral_type(
+            &self.program_id,
+            &self.admin.pubkey(),
+            &self.protocol,
+            &address,
+            collateral,
+            debt_ceiling,
+        );
+        let admin = self.admin.insecure_clone();
+        self.process(&[ix], &[&admin]).await.unwrap();
+        address
+    }
+
+    async fn update_collateral_type(&mut self, collateral_type: Pubkey, debt_ceiling: StableAmount) {
+        let ix = instruction::update_collateral_type(
+            &self.program_id,
+            &self.admin.pubkey(),
+            &self.protocol,
+            &collateral_type,
+            debt_ceiling,
+        );
+        let admin = self.admin.insecure_clone();
+        self.process(&[ix], &[&admin]).await.unwrap();
+    }
+
+    /// Writes a change of the protocol config straight into the account, without the timelock
+    async fn update_config(&mut self, update: impl FnOnce(&mut ProtocolConfig)) {
+        let mut protocol: Protocol = self.state(self.protocol).await;
+        update(&mut protocol.config);
+        let mut data = vec![0; Protocol::LEN];
+        protocol.pack_into_slice(&mut data);
+        self.set_account(self.protocol, self.program_id, data).await;
+    }
+
+    async fn set_pause(&mut self, paused: u64) {
+        let ix = instruction::set_pause(&self.program_id, &self.pauser.pubkey(), &self.protocol, paused);
+        let pauser = self.pauser.insecure_clone();
+        self.process(&[ix], &[&pauser]).await.unwrap();
+    }
+
+    /// Borrow into a new stablecoin account of the borrower, account 7 of the instruction
+    async fn borrow_ix(&mut self, borrower: &Keypair, trove: &Pubkey, borrow_amount: u64, lamports: u64) -> Instruction {
+        let user_token = self.token_account(self.stablecoin_mint, borrower.pubkey()).await;
+        instruction::borrow(
+            &self.program_id,
+            &borrower.pubkey(),
+            trove,
+            &self.protocol,
+            &self.collateral_type,
+            &self.stablecoin_mint,
+            &user_token,
+            StableAmount(borrow_amount),
+            Lamports(lamports),
+        )
+    }
+
+    /// Opens a trove borrowing 1000 against 20 SOL
+    async fn borrow(&mut self, borrower: &Keypair) -> Pubkey {
+        let trove = self.create_funded_account(Trove::LEN, 20 * LAMPORTS_PER_SOL).await;
+        let ix = self.borrow_ix(borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+        self.process(&[ix], &[borrower]).await.unwrap();
+        trove
+    }
+
+    async fn receive_trove(&mut self, trove: &Pubkey) {
+        let ix = instruction::receive_trove(&self.program_id, trove, &self.protocol);
+        self.process(&[ix], &[]).await.unwrap();
+    }
+
+    fn trove(&self, owner: Pubkey, is_liquidated: bool) -> Trove {
+        Trove {
+            is_initialized: true,
+            is_received: true,
+            is_liquidated,
+            borrow_amount: StableAmount(1_000 * UNIT),
+            collateral_amount: CollateralAmount(20 * LAMPORTS_PER_SOL),
+            team_fee: StableAmount::ZERO,
+            depositor_fee: StableAmount::ZERO,
+            amount_to_close: StableAmount(DEBT),
+            owner,
+            collateral: Pubkey::default(),
+            borrow_index: 0,
+        }
+    }
+
+    /// Opens a deposit of 500 stablecoins
+    async fn deposit(&mut self, depositor: &Keypair) -> Pubkey {
+        let deposit = self.create_account(Deposit::LEN).await;
+        let ix = self.add_deposit_ix(depositor, &deposit, 500 * UNIT).await;
+        self.process(&[ix], &[depositor]).await.unwrap();
+        deposit
+    }
+
+    async fn add_deposit_ix(&mut self, depositor: &Keypair, deposit: &Pubkey, amount: u64) -> Instruction {
+        let user_token = self.stablecoin_account(depositor.pubkey(), amount).await;
+        let user_governance_token = self.token_account(GENS_TOKEN_ADDRESS, depositor.pubkey()).await;
+        instruction::add_deposit(
+            &self.program_id,
+            &depositor.pubkey(),
+            deposit,
+            &user_token,
+            &user_governance_token,
+            &self.stablecoin_mint,
+            &self.protocol,
+            StableAmount(amount),
+        )
+    }
+
+    /// Flash mints `amount` to a new token account of the receiver program holding `prepaid`,
+    /// which it pays to `fee_receiver` with the minted amount burned
+    async fn flash_mint_ix(&mut self, fee_receiver: &Pubkey, amount: u64, prepaid: u64) -> Instruction {
+        let (receiver_authority, _) = Pubkey::find_program_address(&[RECEIVER_SEED], &self.receiver_program);
+        let receiver_token = self.stablecoin_account(receiver_authority, prepaid).await;
+        instruction::flash_mint(
+            &self.program_id,
+            &self.stablecoin_mint,
+            &receiver_token,
+            fee_receiver,
+            &self.protocol,
+            &self.receiver_program,
+            &[AccountMeta::new(*fee_receiver, false), AccountMeta::new_readonly(receiver_authority, false)],
+            StableAmount(amount),
+        )
+    }
+}
+
+/// Seed of the authority of the flash mint receiver over its token accounts
+const RECEIVER_SEED: &[u8] = b"receiver";
+
+/// Flash mint receiver burning the minted amount and paying the rest of its balance to the
+/// fee receiver, with the accounts `[receiver token, mint, token program, fee receiver token, authority]`
+fn process_receiver(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    assert_eq!(data[0], FLASH_MINT_RECEIVER_TAG);
+    let amount = u64::from_le_bytes(data[1..9].try_into().unwrap());
+    let (receiver_token, mint, token_program, fee_receiver, authority) =
+        (&accounts[0], &accounts[1], &accounts[2], &accounts[3], &accounts[4]);
+    let (_, bump_seed) = Pubkey::find_program_address(&[RECEIVER_SEED], program_id);
+    let seeds: &[&[u8]] = &[RECEIVER_SEED, &[bump_seed]];
+
+    let ix = spl_token::instruction::burn(token_program.key, receiver_token.key, mint.key, authority.key, &[], amount)?;
+    invoke_signed(&ix, &[receiver_token.clone(), mint.clone(), authority.clone(), token_program.clone()], &[seeds])?;
+
+    let balance = spl_token::state::Account::unpack(&receiver_token.data.borrow())?.amount;
+    let ix = spl_token::instruction::transfer(token_program.key, receiver_token.key, fee_receiver.key, authority.key, &[], balance)?;
+    invoke_signed(&ix, &[receiver_token.clone(), fee_receiver.clone(), authority.clone(), token_program.clone()], &[seeds])
+}
+
+#[track_caller]
+fn assert_error<T: std::fmt::Debug>(result: Result<T, TransactionError>, expected: impl Into<ProgramError>) {
+    let error = match result {
+        Err(TransactionError::InstructionError(_, error)) => error,
+        other => panic!("expected an instruction error, got {:?}", other),
+    };
+    assert_eq!(ProgramError::try_from(error), Ok(expected.into()));
+}
+
+fn unsigned(mut ix: Instruction) -> Instruction {
+    ix.accounts[0].is_signer = false;
+    ix
+}
+
+/// `ix` with its account `index` replaced by `address`
+fn with_account(mut ix: Instruction, index: usize, address: Pubkey, is_signer: bool) -> Instruction {
+    ix.accounts[index].pubkey = address;
+    ix.accounts[index].is_signer = is_signer;
+    ix
+}
+
+#[tokio::test]
+async fn test_borrow() {
+    let mut env = Env::new().await;
+    let borrower = Keypair::new();
+
+    let trove = env.create_funded_account(Trove::LEN, 20 * LAMPORTS_PER_SOL).await;
+    let ix = env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    let user_token = ix.accounts[7].pubkey;
+    let events = env.events(&[ix], &[&borrower]).await;
+
+    // The borrower is sent the debt less the fees, the fees are never minted
+    assert_eq!(env.token_amount(user_token).await, 768 * UNIT);
+    let mint_account = env.context.banks_client.get_account(env.stablecoin_mint).await.unwrap().unwrap();
+    assert_eq!(spl_token::state::Mint::unpack(&mint_account.data).unwrap().supply, 768 * UNIT);
+
+    let state: Trove = env.state(trove).await;
+    assert_eq!(state.owner, borrower.pubkey());
+    assert_eq!(state.collateral_amount, CollateralAmount(20 * LAMPORTS_PER_SOL));
+    assert_eq!(state.amount_to_close, StableAmount(DEBT));
+    assert_eq!(state.depositor_fee, StableAmount(24 * UNIT));
+    assert_eq!(state.team_fee, StableAmount(8 * UNIT));
+    assert_eq!(env.state::<Protocol>(env.protocol).await.total_debt, StableAmount(DEBT));
+    assert_eq!(env.state::<CollateralType>(env.collateral_type).await.total_debt, StableAmount(DEBT));
+    assert_eq!(
+        events,
+        vec![LiquityEvent::TroveOpened {
+            trove,
+            owner: borrower.pubkey(),
+            collateral: Pubkey::default(),
+            collateral_amount: CollateralAmount(20 * LAMPORTS_PER_SOL),
+            borrow_amount: StableAmount(1_000 * UNIT),
+            debt: StableAmount(DEBT),
+            depositor_fee: StableAmount(24 * UNIT),
+            team_fee: StableAmount(8 * UNIT),
+        }]
+    );
+
+    // The trove is already open
+    let ix = env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, ProgramError::AccountAlreadyInitialized);
+
+    let trove = env.create_funded_account(Trove::LEN, 20 * LAMPORTS_PER_SOL).await;
+    let ix = env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[unsigned(ix)], &[]).await, ProgramError::MissingRequiredSignature);
+
+    // The trove account holds the collateral it declares
+    let ix = env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL + 1).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::ExpectedAmountMismatch);
+    let unfunded = env.create_account(Trove::LEN).await;
+    let ix = env.borrow_ix(&borrower, &unfunded, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::ExpectedAmountMismatch);
+
+    let protocol = env.protocol;
+    let ix = env.borrow_ix(&borrower, &protocol, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::DuplicateAccount);
+
+    let mut ix = env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    ix.accounts[1].is_writable = false;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::AccountNotWritable);
+
+    // 15 SOL at 70 is below 110% of 1000
+    let ix = env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 15 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidCollateral);
+
+    // Borrows below the gas fee leave no debt to open the trove with
+    let ix = env.borrow_ix(&borrower, &trove, 100 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::MathOverflow);
+
+    env.set_pause(PAUSE_BORROW).await;
+    let ix = env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::OperationPaused);
+    env.set_pause(0).await;
+
+    // The trove account must belong to the program, be rent exempt and hold a trove
+    let foreign = Pubkey::new_unique();
+    env.set_account(foreign, Pubkey::new_unique(), vec![0; Trove::LEN]).await;
+    let ix = env.borrow_ix(&borrower, &foreign, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidAccountOwner);
+
+    let poor = Pubkey::new_unique();
+    let account = Account { lamports: 1, data: vec![0; Trove::LEN], owner: env.program_id, ..Account::default() };
+    env.context.set_account(&poor, &account.into());
+    let ix = env.borrow_ix(&borrower, &poor, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::NotRentExempt);
+
+    let deposit = env.deposit(&borrower).await;
+    let ix = env.borrow_ix(&borrower, &deposit, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidAccountType);
+
+    let legacy = Pubkey::new_unique();
+    env.set_account(legacy, env.program_id, vec![0; Trove::LEGACY_LEN]).await;
+    let ix = env.borrow_ix(&borrower, &legacy, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::AccountNotMigrated);
+
+    // A closed account cannot be opened again
+    let closed = Pubkey::new_unique();
+    let mut data = vec![0; Trove::LEN];
+    data[0] = AccountType::Closed as u8;
+    env.set_account(closed, env.program_id, data).await;
+    let ix = env.borrow_ix(&borrower, &closed, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::AccountClosed);
+
+    // The collateral type must be the native SOL one, with room under its ceiling
+    let stake_pool_type = env.init_collateral_type(Pubkey::new_unique(), StableAmount(1_000_000 * UNIT)).await;
+    let ix = with_account(env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await, 5, stake_pool_type, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidAccountInput);
+
+    // A native collateral type anywhere but at the address derived from the protocol is another one
+    let foreign_type = env.program_account(CollateralType {
+        is_initialized: true,
+        collateral: Pubkey::default(),
+        debt_ceiling: StableAmount(1_000_000 * UNIT),
+        total_debt: StableAmount::ZERO,
+    }).await;
+    let ix = with_account(env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await, 5, foreign_type, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidAccountInput);
+
+    let collateral_type = env.collateral_type;
+    env.update_collateral_type(collateral_type, StableAmount(500 * UNIT)).await;
+    let ix = env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await;
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::DebtCeilingExceeded);
+    env.update_collateral_type(collateral_type, StableAmount(1_000_000 * UNIT)).await;
+
+    // The stablecoin is minted by the program authority, to a token account of the borrower
+    let other_mint = Pubkey::new_unique();
+    env.set_mint(other_mint, Some(find_authority_address(&env.program_id).0), DECIMALS).await;
+    let ix = with_account(env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await, 6, other_mint, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidTokenMint);
+
+    let foreign_token = env.token_account(env.stablecoin_mint, Pubkey::new_unique()).await;
+    let ix = with_account(env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await, 7, foreign_token, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidTokenOwner);
+
+    let ix = with_account(env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 20 * LAMPORTS_PER_SOL).await, 8, Pubkey::new_unique(), false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidMarketAuthority);
+}
+
+#[tokio::test]
+async fn test_borrow_with_stake_pool_token() {
+    let mut env = Env::new().await;
+    let borrower = Keypair::new();
+    let clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    let (authority, _) = find_authority_address(&env.program_id);
+    let (stake_pool, pool_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    env.set_mint(pool_mint, None, 9).await;
+    env.set_stake_pool(stake_pool, pool_mint, clock.epoch).await;
+    let stake_pool_type = env.init_collateral_type(stake_pool, StableAmount(1_000_000 * UNIT)).await;
+    let vault = env.token_account(pool_mint, authority).await;
+    let pool_token = env.funded_token_account(pool_mint, borrower.pubkey(), 20 * LAMPORTS_PER_SOL).await;
+    let user_token = env.token_account(env.stablecoin_mint, borrower.pubkey()).await;
+    let trove = env.create_account(Trove::LEN).await;
+    let borrow = |env: &Env, stake_pool: Pubkey, pool_tokens: u64| {
+        instruction::borrow_with_stake_pool_token(
+            &env.program_id,
+            &borrower.pubkey(),
+            &trove,
+            &env.protocol,
+            &stake_pool_type,
+            &StakePoolAccounts { stake_pool, vault, user_token: pool_token },
+            &env.stablecoin_mint,
+            &user_token,
+            StableAmount(1_000 * UNIT),
+            CollateralAmount(pool_tokens),
+        )
+    };
+
+    assert_error(
+        env.process(&[unsigned(borrow(&env, stake_pool, 20 * LAMPORTS_PER_SOL))], &[]).await,
+        ProgramError::MissingRequiredSignature,
+    );
+
+    // The stake pool must be an account of the stake pool program holding a stake pool
+    let data = env.context.banks_client.get_account(stake_pool).await.unwrap().unwrap().data;
+    let foreign = Pubkey::new_unique();
+    env.set_account(foreign, Pubkey::new_unique(), data.clone()).await;
+    let short = Pubkey::new_unique();
+    env.set_account(short, STAKE_POOL_PROGRAM_ADDRESS, data[..281].to_vec()).await;
+    let validator_list = Pubkey::new_unique();
+    env.set_account(validator_list, STAKE_POOL_PROGRAM_ADDRESS, [&[2], &data[1..]].concat()).await;
+    for invalid in [foreign, short, validator_list] {
+        let ix = borrow(&env, invalid, 20 * LAMPORTS_PER_SOL);
+        assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidStakePool);
+    }
+
+    // It must be the collateral of the collateral type
+    let other_pool = Pubkey::new_unique();
+    env.set_stake_pool(other_pool, pool_mint, clock.epoch).await;
+    let ix = borrow(&env, other_pool, 20 * LAMPORTS_PER_SOL);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidAccountInput);
+
+    // Its exchange rate must have been updated in the current epoch
+    env.set_stake_pool(stake_pool, pool_mint, clock.epoch + 1).await;
+    let ix = borrow(&env, stake_pool, 20 * LAMPORTS_PER_SOL);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::StakePoolStale);
+    env.set_stake_pool(stake_pool, pool_mint, clock.epoch).await;
+
+    // The vault is a pool token account of the program authority
+    let other_vault = env.token_account(pool_mint, borrower.pubkey()).await;
+    let ix = with_account(borrow(&env, stake_pool, 20 * LAMPORTS_PER_SOL), 7, other_vault, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidTokenOwner);
+
+    // 10 pool tokens are worth 10.5 SOL, below the 110% of 1000
+    let ix = borrow(&env, stake_pool, 10 * LAMPORTS_PER_SOL);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidCollateral);
+    let ix = borrow(&env, stake_pool, 30 * LAMPORTS_PER_SOL);
+    assert_error(env.process(&[ix], &[&borrower]).await, TokenError::InsufficientFunds);
+
+    env.set_pause(PAUSE_BORROW).await;
+    let ix = borrow(&env, stake_pool, 20 * LAMPORTS_PER_SOL);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::OperationPaused);
+    env.set_pause(0).await;
+
+    // The pool tokens move into the vault and the borrower is sent the debt less the fees
+    let events = env.events(&[borrow(&env, stake_pool, 20 * LAMPORTS_PER_SOL)], &[&borrower]).await;
+    assert_eq!(env.token_amount(pool_token).await, 0);
+    assert_eq!(env.token_amount(vault).await, 20 * LAMPORTS_PER_SOL);
+    assert_eq!(env.token_amount(user_token).await, 768 * UNIT);
+
+    let state: Trove = env.state(trove).await;
+    assert_eq!((state.owner, state.collateral), (borrower.pubkey(), stake_pool));
+    assert_eq!(state.collateral_amount, CollateralAmount(20 * LAMPORTS_PER_SOL));
+    assert_eq!(state.amount_to_close, StableAmount(DEBT));
+    assert_eq!(env.state::<CollateralType>(stake_pool_type).await.total_debt, StableAmount(DEBT));
+    assert_eq!(env.state::<CollateralType>(env.collateral_type).await.total_debt, StableAmount::ZERO);
+    assert_eq!(
+        events,
+        vec![LiquityEvent::TroveOpened {
+            trove,
+            owner: borrower.pubkey(),
+            collateral: stake_pool,
+            collateral_amount: CollateralAmount(20 * LAMPORTS_PER_SOL),
+            borrow_amount: StableAmount(1_000 * UNIT),
+            debt: StableAmount(DEBT),
+            depositor_fee: StableAmount(24 * UNIT),
+            team_fee: StableAmount(8 * UNIT),
+        }]
+    );
+
+    let ix = borrow(&env, stake_pool, 20 * LAMPORTS_PER_SOL);
+    assert_error(env.process(&[ix], &[&borrower]).await, ProgramError::AccountAlreadyInitialized);
+}
+
+#[tokio::test]
+async fn test_add_coin() {
+    let mut env = Env::new().await;
+    let borrower = Keypair::new();
+    let trove = env.borrow(&borrower).await;
+
+    // The temp account is an empty account of the program holding exactly the lamports added
+    let temp = Pubkey::new_unique();
+    let program_id = env.program_id;
+    let fund = |env: &mut Env, owner: Pubkey| {
+        env.context.set_account(&temp, &Account { lamports: LAMPORTS_PER_SOL, owner, ..Account::default() }.into());
+    };
+    let add_coin_from = |env: &Env, trove: &Pubkey, source: Pubkey, amount| {
+        instruction::add_coin(&env.program_id, &borrower.pubkey(), trove, &env.protocol, &CoinSource::Lamports(source), CollateralAmount(amount))
+    };
+    let add_coin = |env: &Env, trove: &Pubkey, amount| add_coin_from(env, trove, temp, amount);
+
+    fund(&mut env, program_id);
+    let trove_lamports = env.context.banks_client.get_balance(trove).await.unwrap();
+    let events = env.events(&[add_coin(&env, &trove, LAMPORTS_PER_SOL)], &[&borrower]).await;
+    assert_eq!(env.state::<Trove>(trove).await.collateral_amount, CollateralAmount(21 * LAMPORTS_PER_SOL));
+    assert_eq!(
+        events,
+        vec![LiquityEvent::TroveAdjusted { trove, collateral_amount: CollateralAmount(21 * LAMPORTS_PER_SOL), debt: StableAmount(DEBT) }]
+    );
+    // The lamports join the collateral in the trove account
+    assert_eq!(env.context.banks_client.get_balance(trove).await.unwrap(), trove_lamports + LAMPORTS_PER_SOL);
+    assert_eq!(env.context.banks_client.get_account(temp).await.unwrap(), None);
+
+    fund(&mut env, program_id);
+    assert_error(
+        env.process(&[add_coin(&env, &trove, 2 * LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::ExpectedAmountMismatch,
+    );
+
+    // Neither the trove, the protocol nor another account of the program can stand in for the temp account
+    for source in [trove, env.protocol] {
+        assert_error(
+            env.process(&[add_coin_from(&env, &trove, source, LAMPORTS_PER_SOL)], &[&borrower]).await,
+            LiquityError::DuplicateAccount,
+        );
+    }
+    let collateral_type = env.collateral_type;
+    assert_error(
+        env.process(&[add_coin_from(&env, &trove, collateral_type, LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::InvalidAccountType,
+    );
+    fund(&mut env, system_program::id());
+    assert_error(
+        env.process(&[add_coin(&env, &trove, LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::InvalidAccountOwner,
+    );
+    fund(&mut env, program_id);
+    assert_error(env.process(&[unsigned(add_coin(&env, &trove, LAMPORTS_PER_SOL))], &[]).await, ProgramError::MissingRequiredSignature);
+    assert_error(
+        env.process(&[add_coin(&env, &env.protocol, LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::DuplicateAccount,
+    );
+
+    let other = Keypair::new();
+    let other_trove = env.borrow(&other).await;
+    assert_error(
+        env.process(&[add_coin(&env, &other_trove, LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::OnlyForTroveOwner,
+    );
+
+    let liquidated = env.program_account(env.trove(borrower.pubkey(), true)).await;
+    assert_error(
+        env.process(&[add_coin(&env, &liquidated, LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::TroveAlreadyLiquidated,
+    );
+
+    let uninitialized = env.create_account(Trove::LEN).await;
+    assert_error(
+        env.process(&[add_coin(&env, &uninitialized, LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::TroveIsNotInitialized,
+    );
+
+    // The event reports the debt with the interest accrued since, a year at 5% here
+    let mut clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 365 * 24 * 60 * 60;
+    env.context.set_sysvar(&clock);
+    let mut protocol: Protocol = env.state(env.protocol).await;
+    protocol.config.interest_rate = Ratio(500);
+    let mut data = vec![0; Protocol::LEN];
+    protocol.pack_into_slice(&mut data);
+    env.set_account(env.protocol, env.program_id, data).await;
+    fund(&mut env, program_id);
+    let events = env.events(&[add_coin(&env, &trove, LAMPORTS_PER_SOL)], &[&borrower]).await;
+    assert_eq!(
+        events,
+        vec![LiquityEvent::TroveAdjusted { trove, collateral_amount: CollateralAmount(22 * LAMPORTS_PER_SOL), debt: StableAmount(840 * UNIT) }]
+    );
+    assert_eq!(env.state::<Trove>(trove).await.amount_to_close, StableAmount(DEBT));
+
+    env.set_pause(PAUSE_ADJUST_TROVE).await;
+    assert_error(env.process(&[add_coin(&env, &trove, LAMPORTS_PER_SOL)], &[&borrower]).await, LiquityError::OperationPaused);
+}
+
+#[tokio::test]
+async fn test_withdraw_coin() {
+    let mut env = Env::new().await;
+    let borrower = Keypair::new();
+    let trove = env.borrow(&borrower).await;
+    let withdraw_coin = |env: &Env, trove: &Pubkey, amount| {
+        instruction::withdraw_coin(&env.program_id, &borrower.pubkey(), trove, &env.protocol, &env.collateral_type, None, CollateralAmount(amount))
+    };
+
+    // 16 SOL still cover 110% of 1000, the 4 SOL withdrawn are paid to the borrower
+    let trove_lamports = env.context.banks_client.get_balance(trove).await.unwrap();
+    let events = env.events(&[withdraw_coin(&env, &trove, 4 * LAMPORTS_PER_SOL)], &[&borrower]).await;
+    assert_eq!(env.state::<Trove>(trove).await.collateral_amount, CollateralAmount(16 * LAMPORTS_PER_SOL));
+    assert_eq!(env.context.banks_client.get_balance(trove).await.unwrap(), trove_lamports - 4 * LAMPORTS_PER_SOL);
+    assert_eq!(env.context.banks_client.get_balance(borrower.pubkey()).await.unwrap(), 4 * LAMPORTS_PER_SOL);
+    assert_eq!(
+        events,
+        vec![LiquityEvent::TroveAdjusted { trove, collateral_amount: CollateralAmount(16 * LAMPORTS_PER_SOL), debt: StableAmount(DEBT) }]
+    );
+
+    assert_error(env.process(&[withdraw_coin(&env, &trove, LAMPORTS_PER_SOL)], &[&borrower]).await, LiquityError::InvalidCollateral);
+    assert_error(env.process(&[withdraw_coin(&env, &trove, 17 * LAMPORTS_PER_SOL)], &[&borrower]).await, LiquityError::MathOverflow);
+    assert_error(env.process(&[unsigned(withdraw_coin(&env, &trove, 1))], &[]).await, ProgramError::MissingRequiredSignature);
+    assert_error(
+        env.process(&[withdraw_coin(&env, &env.collateral_type, 1)], &[&borrower]).await,
+        LiquityError::DuplicateAccount,
+    );
+
+    let other = Keypair::new();
+    let other_trove = env.borrow(&other).await;
+    assert_error(env.process(&[withdraw_coin(&env, &other_trove, 1)], &[&borrower]).await, LiquityError::OnlyForTroveOwner);
+
+    let liquidated = env.program_account(env.trove(borrower.pubkey(), true)).await;
+    assert_error(env.process(&[withdraw_coin(&env, &liquidated, 1)], &[&borrower]).await, LiquityError::TroveAlreadyLiquidated);
+
+    let stake_pool_type = env.init_collateral_type(Pubkey::new_unique(), StableAmount(1_000_000 * UNIT)).await;
+    let ix = with_account(withdraw_coin(&env, &trove, 1), 4, stake_pool_type, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidAccountInput);
+
+    env.set_pause(PAUSE_ADJUST_TROVE).await;
+    assert_error(env.process(&[withdraw_coin(&env, &trove, 1)], &[&borrower]).await, LiquityError::OperationPaused);
+}
+
+#[tokio::test]
+async fn test_redeem_coin() {
+    let mut env = Env::new().await;
+    let borrower = Keypair::new();
+    let trove = env.borrow(&borrower).await;
+    let user_token = env.stablecoin_account(borrower.pubkey(), 100 * UNIT).await;
+    let redeem_coin = |env: &Env, trove: &Pubkey, user_token: &Pubkey, amount| {
+        instruction::redeem_coin(
+            &env.program_id,
+            &borrower.pubkey(),
+            trove,
+            &env.protocol,
+            &env.collateral_type,
+            user_token,
+            &env.stablecoin_mint,
+            None,
+            CollateralAmount(amount),
+        )
+    };
+
+    // The redeemed SOL is paid out of the trove account to the borrower, its value of 70 burned against the debt
+    let trove_lamports = env.context.banks_client.get_balance(trove).await.unwrap();
+    let events = env.events(&[redeem_coin(&env, &trove, &user_token, LAMPORTS_PER_SOL)], &[&borrower]).await;
+    let state = env.state::<Trove>(trove).await;
+    assert_eq!(state.collateral_amount, CollateralAmount(19 * LAMPORTS_PER_SOL));
+    assert_eq!(state.amount_to_close, StableAmount(DEBT - 70 * UNIT));
+    assert_eq!(state.borrow_amount, StableAmount(930 * UNIT));
+    assert_eq!(env.context.banks_client.get_balance(trove).await.unwrap(), trove_lamports - LAMPORTS_PER_SOL);
+    assert_eq!(env.context.banks_client.get_balance(borrower.pubkey()).await.unwrap(), LAMPORTS_PER_SOL);
+    assert_eq!(env.token_amount(user_token).await, 30 * UNIT);
+    assert_eq!(env.state::<Protocol>(env.protocol).await.total_debt, StableAmount(DEBT - 70 * UNIT));
+    assert_eq!(env.state::<CollateralType>(env.collateral_type).await.total_debt, StableAmount(DEBT - 70 * UNIT));
+    assert_eq!(
+        events,
+        vec![
+            LiquityEvent::Redemption {
+                trove,
+                amount: CollateralAmount(LAMPORTS_PER_SOL),
+                collateral_amount: CollateralAmount(19 * LAMPORTS_PER_SOL),
+                repaid: StableAmount(70 * UNIT),
+            },
+            LiquityEvent::TroveAdjusted { trove, collateral_amount: CollateralAmount(19 * LAMPORTS_PER_SOL), debt: StableAmount(DEBT - 70 * UNIT) },
+        ]
+    );
+
+    // The stablecoin burned must be there, and the collateral value must not exceed the debt
+    assert_error(
+        env.process(&[redeem_coin(&env, &trove, &user_token, LAMPORTS_PER_SOL)], &[&borrower]).await,
+        TokenError::InsufficientFunds,
+    );
+    let rich_token = env.stablecoin_account(borrower.pubkey(), 2_000 * UNIT).await;
+    assert_error(
+        env.process(&[redeem_coin(&env, &trove, &rich_token, 11 * LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::MathOverflow,
+    );
+    assert_error(
+        env.process(&[redeem_coin(&env, &trove, &rich_token, 20 * LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::MathOverflow,
+    );
+
+    assert_error(env.process(&[unsigned(redeem_coin(&env, &trove, &rich_token, 1))], &[]).await, ProgramError::MissingRequiredSignature);
+    assert_error(
+        env.process(&[redeem_coin(&env, &env.protocol, &rich_token, 1)], &[&borrower]).await,
+        LiquityError::DuplicateAccount,
+    );
+
+    let ix = with_account(redeem_coin(&env, &trove, &rich_token, 1), 5, Pubkey::new_unique(), false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidTokenProgram);
+
+    let other_mint = Pubkey::new_unique();
+    env.set_mint(other_mint, None, DECIMALS).await;
+    let ix = with_account(redeem_coin(&env, &trove, &rich_token, 1), 7, other_mint, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidTokenMint);
+
+    let foreign_token = env.stablecoin_account(Pubkey::new_unique(), 100 * UNIT).await;
+    assert_error(
+        env.process(&[redeem_coin(&env, &trove, &foreign_token, 1)], &[&borrower]).await,
+        LiquityError::InvalidTokenOwner,
+    );
+
+    let other = Keypair::new();
+    let other_trove = env.borrow(&other).await;
+    assert_error(
+        env.process(&[redeem_coin(&env, &other_trove, &rich_token, 1)], &[&borrower]).await,
+        LiquityError::OnlyForTroveOwner,
+    );
+
+    let liquidated = env.program_account(env.trove(borrower.pubkey(), true)).await;
+    assert_error(
+        env.process(&[redeem_coin(&env, &liquidated, &rich_token, 1)], &[&borrower]).await,
+        LiquityError::TroveAlreadyLiquidated,
+    );
+
+    let stake_pool_type = env.init_collateral_type(Pubkey::new_unique(), StableAmount(1_000_000 * UNIT)).await;
+    let ix = with_account(redeem_coin(&env, &trove, &rich_token, 1), 4, stake_pool_type, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidAccountInput);
+
+    // The trove left by a redemption still covers the minimum collateral ratio, 19 SOL for 930 falls short of 150%
+    env.update_config(|config| config.min_collateral_ratio = Ratio(15_000)).await;
+    assert_error(
+        env.process(&[redeem_coin(&env, &trove, &rich_token, LAMPORTS_PER_SOL)], &[&borrower]).await,
+        LiquityError::InvalidCollateral,
+    );
+
+    env.set_pause(PAUSE_REDEEM).await;
+    assert_error(env.process(&[redeem_coin(&env, &trove, &rich_token, 1)], &[&borrower]).await, LiquityError::OperationPaused);
+}
+
+#[tokio::test]
+async fn test_close_trove() {
+    let mut env = Env::new().await;
+    let borrower = Keypair::new();
+    let trove = env.borrow(&borrower).await;
+    let user_token = env.stablecoin_account(borrower.pubkey(), DEBT).await;
+    let close_trove = |env: &Env, trove: &Pubkey, user_token: &Pubkey| {
+        instruction::close_trove(
+            &env.program_id,
+            &borrower.pubkey(),
+            trove,
+            user_token,
+            &env.stablecoin_mint,
+            &env.protocol,
+            &env.collateral_type,
+            None,
+        )
+    };
+
+    assert_error(env.process(&[unsigned(close_trove(&env, &trove, &user_token))], &[]).await, ProgramError::MissingRequiredSignature);
+    assert_error(
+        env.process(&[close_trove(&env, &env.protocol, &user_token)], &[&borrower]).await,
+        LiquityError::DuplicateAccount,
+    );
+
+    let ix = with_account(close_trove(&env, &trove, &user_token), 2, Pubkey::new_unique(), false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidTokenProgram);
+
+    // The stablecoin must come from the borrower, of the protocol mint
+    let other_mint = Pubkey::new_unique();
+    env.set_mint(other_mint, None, DECIMALS).await;
+    let ix = with_account(close_trove(&env, &trove, &user_token), 4, other_mint, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidTokenMint);
+
+    let foreign_token = env.token_account(env.stablecoin_mint, Pubkey::new_unique()).await;
+    assert_error(env.process(&[close_trove(&env, &trove, &foreign_token)], &[&borrower]).await, LiquityError::InvalidTokenOwner);
+
+    let other_token = env.token_account(other_mint, borrower.pubkey()).await;
+    assert_error(env.process(&[close_trove(&env, &trove, &other_token)], &[&borrower]).await, LiquityError::InvalidTokenMint);
+
+    let poor_token = env.stablecoin_account(borrower.pubkey(), DEBT - 1).await;
+    assert_error(env.process(&[close_trove(&env, &trove, &poor_token)], &[&borrower]).await, TokenError::InsufficientFunds);
+
+    let other = Keypair::new();
+    let other_trove = env.borrow(&other).await;
+    assert_error(env.process(&[close_trove(&env, &other_trove, &user_token)], &[&borrower]).await, LiquityError::OnlyForTroveOwner);
+
+    let liquidated = env.program_account(env.trove(borrower.pubkey(), true)).await;
+    assert_error(env.process(&[close_trove(&env, &liquidated, &user_token)], &[&borrower]).await, LiquityError::TroveAlreadyLiquidated);
+
+    let stake_pool_type = env.init_collateral_type(Pubkey::new_unique(), StableAmount(1_000_000 * UNIT)).await;
+    let ix = with_account(close_trove(&env, &trove, &user_token), 7, stake_pool_type, false);
+    assert_error(env.process(&[ix], &[&borrower]).await, LiquityError::InvalidAccountInput);
+
+    // Closing burns the debt and returns the rent of the trove, even while paused
+    env.set_pause(u64::MAX).await;
+    let trove_lamports = env.context.banks_client.get_balance(trove).await.unwrap();
+    let borrower_lamports = env.context.banks_client.get_balance(borrower.pubkey()).await.unwrap();
+    let events = env.events(&[close_trove(&env, &trove, &user_token)], &[&borrower]).await;
+
+    assert_eq!(env.context.banks_client.get_account(trove).await.unwrap(), None);
+    assert_eq!(env.context.banks_client.get_balance(borrower.pubkey()).await.unwrap(), borrower_lamports + trove_lamports);
+    assert_eq!(env.token_amount(user_token).await, 0);
+    assert_eq!(env.state::<Protocol>(env.protocol).await.total_debt, StableAmount(DEBT));
+    assert_eq!(
+        events,
+        vec![LiquityEvent::TroveClosed {
+            trove,
+            owner: borrower.pubkey(),
+            repaid: StableAmount(DEBT),
+            collateral_amount: CollateralAmount(20 * LAMPORTS_PER_SOL),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_liquidate_trove() {
+    let mut env = Env::new().await;
+    let borrower = Keypair::new();
+    let liquidator = Keypair::new();
+    let trove = env.borrow(&borrower).await;
+    let liquidate_trove = |env: &Env, trove: &Pubkey| {
+        instruction::liquidate_trove(&env.program_id, &liquidator.pubkey(), trove, &env.protocol, &env.collateral_type, None)
+    };
+
+    // Only troves the system account received can be liquidated
+    assert_error(env.process(&[liquidate_trove(&env, &trove)], &[&liquidator]).await, LiquityError::TroveIsNotReceived);
+    env.receive_trove(&trove).await;
+
+    assert_error(env.process(&[unsigned(liquidate_trove(&env, &trove))], &[]).await, ProgramError::MissingRequiredSignature);
+    let ix = with_account(liquidate_trove(&env, &trove), 2, Pubkey::new_unique(), false);
+    assert_error(env.process(&[ix], &[&liquidator]).await, LiquityError::InvalidAccountInput);
+    assert_error(env.process(&[liquidate_trove(&env, &env.protocol)], &[&liquidator]).await, LiquityError::DuplicateAccount);
+    let ix = with_account(liquidate_trove(&env, &trove), 0, trove, true);
+    assert_error(env.process(&[ix], &[]).await, LiquityError::DuplicateAccount);
+
+    let liquidated = env.program_account(env.trove(borrower.pubkey(), true)).await;
+    assert_error(env.process(&[liquidate_trove(&env, &liquidated)], &[&liquidator]).await, LiquityError::TroveAlreadyLiquidated);
+
+    let stake_pool_type = env.init_collateral_type(Pubkey::new_unique(), StableAmount(1_000_000 * UNIT)).await;
+    let ix = with_account(liquidate_trove(&env, &trove), 5, stake_pool_type, false);
+    assert_error(env.process(&[ix], &[&liquidator]).await, LiquityError::InvalidAccountInput);
+
+    env.set_pause(PAUSE_LIQUIDATE).await;
+    assert_error(env.process(&[liquidate_trove(&env, &trove)], &[&liquidator]).await, LiquityError::OperationPaused);
+    env.set_pause(0).await;
+
+    // 20 SOL for 1000 covers the minimum collateral ratio of 110%, not one of 150%
+    assert_error(env.process(&[liquidate_trove(&env, &trove)], &[&liquidator]).await, LiquityError::InvalidCollateral);
+    env.update_config(|config| config.min_collateral_ratio = Ratio(15_000)).await;
+
+    // The system account takes the trove and its debt leaves the totals
+    let trove_lamports = env.context.banks_client.get_balance(trove).await.unwrap();
+    let events = env.events(&[liquidate_trove(&env, &trove)], &[&liquidator]).await;
+
+    assert_eq!(env.context.banks_client.get_account(trove).await.unwrap(), None);
+    assert_eq!(env.context.banks_client.get_balance(SYSTEM_ACCOUNT_ADDRESS).await.unwrap(), LAMPORTS_PER_SOL + trove_lamports);
+    assert_eq!(env.state::<Protocol>(env.protocol).await.total_debt, StableAmount::ZERO);
+    assert_eq!(env.state::<CollateralType>(env.collateral_type).await.total_debt, StableAmount::ZERO);
+    assert_eq!(
+        events,
+        vec![LiquityEvent::TroveLiquidated {
+            trove,
+            liquidator: liquidator.pubkey(),
+            debt: StableAmount(DEBT),
+            collateral_amount: CollateralAmount(20 * LAMPORTS_PER_SOL),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_liquidate_trove_with_interest() {
+    let mut env = Env::new().await;
+    let borrower = Keypair::new();
+    let liquidator = Keypair::new();
+    let liquidate_trove = |env: &Env, trove: &Pubkey| {
+        instruction::liquidate_trove(&env.program_id, &liquidator.pubkey(), trove, &env.protocol, &env.collateral_type, None)
+    };
+
+    // 16 SOL cover 110% of 1000 when borrowing
+    let trove = env.create_funded_account(Trove::LEN, 16 * LAMPORTS_PER_SOL).await;
+    let ix = env.borrow_ix(&borrower, &trove, 1_000 * UNIT, 16 * LAMPORTS_PER_SOL).await;
+    env.process(&[ix], &[&borrower]).await.unwrap();
+    env.receive_trove(&trove).await;
+    env.update_config(|config| config.interest_rate = Ratio(500)).await;
+    assert_error(env.process(&[liquidate_trove(&env, &trove)], &[&liquidator]).await, LiquityError::InvalidCollateral);
+
+    // A year at 5% adds 40 to the debt, 1040 which 16 SOL no longer cover
+    let mut clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 365 * 24 * 60 * 60;
+    env.context.set_sysvar(&clock);
+    let events = env.events(&[liquidate_trove(&env, &trove)], &[&liquidator]).await;
+    assert_eq!(
+        events,
+        vec![LiquityEvent::TroveLiquidated {
+            trove,
+            liquidator: liquidator.pubkey(),
+            debt: StableAmount(840 * UNIT),
+            collateral_amount: CollateralAmount(16 * LAMPORTS_PER_SOL),
+        }]
+    );
+    assert_eq!(env.state::<Protocol>(env.protocol).await.total_debt, StableAmount::ZERO);
+}
+
+#[tokio::test]
+async fn test_receive_trove() {
+    let mut env = Env::new().await;
+    let borrower = Keypair::new();
+    let trove = env.borrow(&borrower).await;
+    let receive_trove = |env: &Env, trove: &Pubkey| instruction::receive_trove(&env.program_id, trove, &env.protocol);
+
+    env.process(&[receive_trove(&env, &trove)], &[]).await.unwrap();
+    assert!(env.state::<Trove>(trove).await.is_received);
+
+    let impostor = Keypair::new();
+    let ix = with_account(receive_trove(&env, &trove), 0, impostor.pubkey(), true);
+    assert_error(env.process(&[ix], &[&impostor]).await, ProgramError::MissingRequiredSignature);
+    assert_error(env.process(&[unsigned(receive_trove(&env, &trove))], &[]).await, ProgramError::MissingRequiredSignature);
+    assert_error(env.process(&[receive_trove(&env, &env.protocol)], &[]).await, LiquityError::DuplicateAccount);
+
+    let liquidated = env.program_account(env.trove(borrower.pubkey(), true)).await;
+    assert_error(env.process(&[receive_trove(&env, &liquidated)], &[]).await, LiquityError::TroveAlreadyLiquidated);
+
+    env.set_pause(PAUSE_RECEIVE_TROVE).await;
+    assert_error(env.process(&[receive_trove(&env, &trove)], &[]).await, LiquityError::OperationPaused);
+}
+
+#[tokio::test]
+async fn test_add_deposit() {
+    let mut env = Env::new().await;
+    let depositor = Keypair::new();
+
+    let deposit = env.create_account(Deposit::LEN).await;
+    let ix = env.add_deposit_ix(&depositor, &deposit, 500 * UNIT).await;
+    let user_token = ix.accounts[4].pubkey;
+    let events = env.events(&[ix], &[&depositor]).await;
+
+    let state: Deposit = env.state(deposit).await;
+    assert_eq!(state.owner, depositor.pubkey());
+    assert_eq!(state.token_amount, StableAmount(500 * UNIT));
+    assert_eq!(state.bank, user_token);
+    assert_eq!(env.token_amount(user_token).await, 0);
+    assert_eq!(
+        events,
+        vec![LiquityEvent::DepositChanged { deposit, owner: depositor.pubkey(), token_amount: StableAmount(500 * UNIT) }]
+    );
+
+    // Adding to an open deposit
+    let ix = env.add_deposit_ix(&depositor, &deposit, 100 * UNIT).await;
+    env.process(&[ix], &[&depositor]).await.unwrap();
+    assert_eq!(env.state::<Deposit>(deposit).await.token_amount, StableAmount(600 * UNIT));
+
+    let ix = env.add_deposit_ix(&depositor, &deposit, 100 * UNIT).await;
+    assert_error(env.process(&[unsigned(ix.clone())], &[]).await, ProgramError::MissingRequiredSignature);
+
+    let other = Keypair::new();
+    let other_ix = env.add_deposit_ix(&other, &deposit, 100 * UNIT).await;
+    assert_error(env.process(&[other_ix], &[&other]).await, LiquityError::OnlyForDepositOwner);
+
+    assert_error(env.process(&[with_account(ix.clone(), 1, env.protocol, false)], &[&depositor]).await, LiquityError::DuplicateAccount);
+    assert_error(env.process(&[with_account(ix.clone(), 3, Pubkey::new_unique(), false)], &[&depositor]).await, LiquityError::InvalidTokenProgram);
+
+    let other_mint = Pubkey::new_unique();
+    env.set_mint(other_mint, None, DECIMALS).await;
+    assert_error(env.process(&[with_account(ix.clone(), 6, other_mint, false)], &[&depositor]).await, LiquityError::InvalidTokenMint);
+
+    // Both token accounts must be the depositor's, of the stablecoin and governance mints
+    let foreign_token = env.stablecoin_account(Pubkey::new_unique(), 100 * UNIT).await;
+    assert_error(env.process(&[with_account(ix.clone(), 4, foreign_token, false)], &[&depositor]).await, LiquityError::InvalidTokenOwner);
+    let foreign_governance = env.token_account(GENS_TOKEN_ADDRESS, Pubkey::new_unique()).await;
+    assert_error(env.process(&[with_account(ix.clone(), 5, foreign_governance, false)], &[&depositor]).await, LiquityError::InvalidTokenOwner);
+    let stablecoin_as_governance = env.token_account(env.stablecoin_mint, depositor.pubkey()).await;
+    assert_error(
+        env.process(&[with_account(ix.clone(), 5, stablecoin_as_governance, false)], &[&depositor]).await,
+        LiquityError::InvalidTokenMint,
+    );
+
+    let mut too_much = env.add_deposit_ix(&depositor, &deposit, 100 * UNIT).await;
+    too_much.data = LiquityInstruction::AddDeposit { amount: StableAmount(101 * UNIT) }.pack();
+    assert_error(env.process(&[too_much], &[&depositor]).await, TokenError::InsufficientFunds);
+
+    let poor = Pubkey::new_unique();
+    let account = Account { lamports: 1, data: vec![0; Deposit::LEN], owner: env.program_id, ..Account::default() };
+    env.context.set_account(&poor, &account.into());
+    assert_error(env.process(&[with_account(ix.clone(), 1, poor, false)], &[&depositor]).await, LiquityError::NotRentExempt);
+
+    env.set_pause(PAUSE_DEPOSIT).await;
+    assert_error(env.process(&[ix], &[&depositor]).await, LiquityError::OperationPaused);
+}
+
+#[tokio::test]
+async fn test_withdraw_deposit() {
+    let mut env = Env::new().await;
+    let depositor = Keypair::new();
+    let deposit = env.deposit(&depositor).await;
+    let other_deposit = env.deposit(&Keypair::new()).await;
+    let uninitialized = env.create_account(Deposit::LEN).await;
+    let user_token = env.token_account(env.stablecoin_mint, depositor.pubkey()).await;
+    let withdraw_deposit_to = |env: &Env, deposit: &Pubkey, user_token: &Pubkey, amount| {
+        instruction::withdraw_deposit(
+            &env.program_id,
+            &depositor.pubkey(),
+            deposit,
+            &env.protocol,
+            &env.stablecoin_mint,
+            user_token,
+            StableAmount(amount),
+        )
+    };
+    let withdraw_deposit = |env: &Env, deposit: &Pubkey, amount| withdraw_deposit_to(env, deposit, &user_token, amount);
+
+    // Withdrawing can never be paused, the burned deposit is minted back to the owner
+    env.set_pause(u64::MAX).await;
+    let events = env.events(&[withdraw_deposit(&env, &deposit, 200 * UNIT)], &[&depositor]).await;
+    assert_eq!(env.state::<Deposit>(deposit).await.token_amount, StableAmount(300 * UNIT));
+    assert_eq!(env.token_amount(user_token).await, 200 * UNIT);
+    assert_eq!(
+        events,
+        vec![LiquityEvent::DepositChanged { deposit, owner: depositor.pubkey(), token_amount: StableAmount(300 * UNIT) }]
+    );
+
+    assert_error(env.process(&[withdraw_deposit(&env, &deposit, 301 * UNIT)], &[&depositor]).await, LiquityError::InsufficientLiquidity);
+    assert_error(env.process(&[unsigned(withdraw_deposit(&env, &deposit, 1))], &[]).await, ProgramError::MissingRequiredSignature);
+    assert_error(
+        env.process(&[withdraw_deposit(&env, &depositor.pubkey(), 1)], &[&depositor]).await,
+        LiquityError::DuplicateAccount,
+    );
+
+    assert_error(env.process(&[withdraw_deposit(&env, &other_deposit, 1)], &[&depositor]).await, LiquityError::OnlyForDepositOwner);
+    assert_error(env.process(&[withdraw_deposit(&env, &uninitialized, 1)], &[&depositor]).await, ProgramError::UninitializedAccount);
+
+    let foreign_token = env.token_account(env.stablecoin_mint, Pubkey::new_unique()).await;
+    let ix = withdraw_deposit_to(&env, &deposit, &foreign_token, 1);
+    assert_error(env.process(&[ix], &[&depositor]).await, LiquityError::InvalidTokenOwner);
+    let other_mint = Pubkey::new_unique();
+    env.set_mint(other_mint, Some(find_authority_address(&env.program_id).0), DECIMALS).await;
+    let other_token = env.token_account(other_mint, depositor.pubkey()).await;
+    let ix = with_account(withdraw_deposit_to(&env, &deposit, &other_token, 1), 3, other_mint, false);
+    assert_error(env.process(&[ix], &[&depositor]).await, LiquityError::InvalidTokenMint);
+}
+
+#[tokio::test]
+async fn test_deposit_rewards() {
+    let mut env = Env::new().await;
+    let depositor = Keypair::new();
+    let deposit = env.deposit(&depositor).await;
+    let add_deposit_reward = |env: &Env, deposit: &Pubkey, coin| {
+        instruction::add_deposit_reward(&env.program_id, deposit, &env.protocol, Lamports(coin), GovAmount(2), StableAmount(3))
+    };
+    let claim_deposit_reward = |env: &Env, deposit: &Pubkey| instruction::claim_deposit_reward(&env.program_id, deposit, &env.protocol);
+
+    let events = env.events(&[add_deposit_reward(&env, &deposit, 1)], &[]).await;
+    let state: Deposit = env.state(deposit).await;
+    assert_eq!(
+        (state.reward_coin_amount, state.reward_governance_token_amount, state.reward_token_amount),
+        (Lamports(1), GovAmount(2), StableAmount(3))
+    );
+    assert_eq!(
+        events,
+        vec![LiquityEvent::RewardAdded { deposit, coin: Lamports(1), governance: GovAmount(2), token: StableAmount(3) }]
+    );
+
+    let events = env.events(&[claim_deposit_reward(&env, &deposit)], &[]).await;
+    let state: Deposit = env.state(deposit).await;
+    assert_eq!(
+        (state.reward_coin_amount, state.reward_governance_token_amount, state.reward_token_amount),
+        (Lamports::ZERO, GovAmount::ZERO, StableAmount::ZERO)
+    );
+    assert_eq!(
+        events,
+        vec![LiquityEvent::RewardClaimed {
+            deposit,
+            owner: depositor.pubkey(),
+            coin: Lamports(1),
+            governance: GovAmount(2),
+            token: StableAmount(3),
+        }]
+    );
+
+    // Only the system account adds and pays out rewards
+    for ix in [add_deposit_reward(&env, &deposit, 1), claim_deposit_reward(&env, &deposit)] {
+        let impostor = Keypair::new();
+        assert_error(
+            env.process(&[with_account(ix.clone(), 0, impostor.pubkey(), true)], &[&impostor]).await,
+            ProgramError::MissingRequiredSignature,
+        );
+        assert_error(env.process(&[unsigned(ix.clone())], &[]).await, ProgramError::MissingRequiredSignature);
+        assert_error(env.process(&[with_account(ix, 1, env.protocol, false)], &[]).await, LiquityError::DuplicateAccount);
+    }
+
+    let uninitialized = env.create_account(Deposit::LEN).await;
+    assert_error(env.process(&[add_deposit_reward(&env, &uninitialized, 1)], &[]).await, ProgramError::UninitializedAccount);
+    assert_error(env.process(&[claim_deposit_reward(&env, &uninitialized)], &[]).await, ProgramError::UninitializedAccount);
+
+    env.process(&[add_deposit_reward(&env, &deposit, u64::MAX)], &[]).await.unwrap();
+    assert_error(env.process(&[add_deposit_reward(&env, &deposit, 1)], &[]).await, LiquityError::MathOverflow);
+
+    env.set_pause(PAUSE_REWARDS).await;
+    assert_error(env.process(&[add_deposit_reward(&env, &deposit, 0)], &[]).await, LiquityError::OperationPaused);
+    assert_error(env.process(&[claim_deposit_reward(&env, &deposit)], &[]).await, LiquityError::OperationPaused);
+}
+
+#[tokio::test]
+async fn test_migrate_account() {
+    let mut env = Env::new().await;
+    let owner = Keypair::new();
+    env.context.set_account(&owner.pubkey(), &Account { lamports: LAMPORTS_PER_SOL, ..Account::default() }.into());
+    let rent = env.context.banks_client.get_rent().await.unwrap();
+    let balance = |account: &Option<Account>| account.as_ref().unwrap().lamports;
+
+    // The trove keeps its address, the signer paying the rent of the larger layout
+    let legacy_trove = Trove {
+        borrow_amount: StableAmount(1_000),
+        team_fee: StableAmount(1),
+        depositor_fee: StableAmount(3),
+        amount_to_close: StableAmount(800),
+        ..env.trove(owner.pubkey(), false)
+    };
+    let trove = env.legacy_account(legacy_trove, Trove::LEGACY_LEN, 20 * LAMPORTS_PER_SOL).await;
+    let migrate_trove = instruction::migrate_account(
+        &env.program_id,
+        &owner.pubkey(),
+        &trove,
+        &env.protocol,
+        Some(&env.collateral_type),
+    );
+    let events = env.events(std::slice::from_ref(&migrate_trove), &[&owner]).await;
+
+    let account = env.context.banks_client.get_account(trove).await.unwrap();
+    assert_eq!(account.as_ref().unwrap().data.len(), Trove::LEN);
+    assert_eq!(balance(&account), rent.minimum_balance(Trove::LEN) + 20 * LAMPORTS_PER_SOL);
+    let owner_account = env.context.banks_client.get_account(owner.pubkey()).await.unwrap();
+    assert_eq!(
+        balance(&owner_account),
+        LAMPORTS_PER_SOL - (rent.minimum_balance(Trove::LEN) - rent.minimum_balance(Trove::LEGACY_LEN))
+    );
+    // Legacy amounts were whole stablecoins
+    let state: Trove = env.state(trove).await;
+    assert_eq!(state.owner, owner.pubkey());
+    assert_eq!(state.borrow_amount, StableAmount(1_000 * UNIT));
+    assert_eq!(state.team_fee, StableAmount(UNIT));
+    assert_eq!(state.depositor_fee, StableAmount(3 * UNIT));
+    assert_eq!(state.amount_to_close, StableAmount(DEBT));
+    // Interest accrues from the migration on
+    assert_eq!(state.borrow_index, env.state::<Protocol>(env.protocol).await.borrow_index);
+    assert_ne!(state.borrow_index, 0);
+
+    // Its debt joins the total debts, and the trove the indexed ones
+    assert_eq!(env.state::<Protocol>(env.protocol).await.total_debt, StableAmount(DEBT));
+    assert_eq!(env.state::<CollateralType>(env.collateral_type).await.total_debt, StableAmount(DEBT));
+    assert_eq!(
+        events,
+        vec![LiquityEvent::TroveMigrated {
+            trove,
+            owner: owner.pubkey(),
+            collateral: state.collateral,
+            collateral_amount: state.collateral_amount,
+            debt: StableAmount(DEBT),
+        }]
+    );
+
+    assert_error(env.process(&[migrate_trove], &[&owner]).await, LiquityError::AccountAlreadyMigrated);
+
+    let closed = Pubkey::new_unique();
+    let mut data = vec![0; Trove::LEGACY_LEN];
+    data[0] = AccountType::Closed as u8;
+    env.set_account(closed, env.program_id, data).await;
+    let ix = instruction::migrate_account(&env.program_id, &owner.pubkey(), &closed, &env.protocol, Some(&env.collateral_type));
+    assert_error(env.process(&[ix], &[&owner]).await, LiquityError::AccountClosed);
+
+    // Closing the migrated trove burns its whole debt and returns the collateral
+    let close_trove = |env: &Env, user_token: &Pubkey| {
+        instruction::close_trove(
+            &env.program_id,
+            &owner.pubkey(),
+            &trove,
+            user_token,
+            &env.stablecoin_mint,
+            &env.protocol,
+            &env.collateral_type,
+            None,
+        )
+    };
+    let poor_token = env.stablecoin_account(owner.pubkey(), DEBT - 1).await;
+    assert_error(env.process(&[close_trove(&env, &poor_token)], &[&owner]).await, TokenError::InsufficientFunds);
+
+    let user_token = env.stablecoin_account(owner.pubkey(), DEBT).await;
+    let owner_lamports = env.context.banks_client.get_balance(owner.pubkey()).await.unwrap();
+    env.process(&[close_trove(&env, &user_token)], &[&owner]).await.unwrap();
+    assert_eq!(env.token_amount(user_token).await, 0);
+    assert_eq!(env.context.banks_client.get_account(trove).await.unwrap(), None);
+    assert_eq!(
+        env.context.banks_client.get_balance(owner.pubkey()).await.unwrap(),
+        owner_lamports + rent.minimum_balance(Trove::LEN) + 20 * LAMPORTS_PER_SOL
+    );
+    assert_eq!(env.state::<Protocol>(env.protocol).await.total_debt, StableAmount::ZERO);
+    assert_eq!(env.state::<CollateralType>(env.collateral_type).await.total_debt, StableAmount::ZERO);
+
+    let deposit_state = Deposit {
+        is_initialized: true,
+        token_amount: StableAmount(500),
+        reward_token_amount: StableAmount::ZERO,
+        reward_governance_token_amount: GovAmount::ZERO,
+        reward_coin_amount: Lamports::ZERO,
+        bank: Pubkey::new_unique(),
+        governance_bank: Pubkey::new_unique(),
+        owner: owner.pubkey(),
+    };
+    let deposit = env.legacy_account(deposit_state, Deposit::LEGACY_LEN, 0).await;
+    let migrate_deposit = |env: &Env, signer: &Pubkey| {
+        instruction::migrate_account(&env.program_id, signer, &deposit, &env.protocol, None)
+    };
+
+    let other = Keypair::new();
+    assert_error(env.process(&[migrate_deposit(&env, &other.pubkey())], &[&other]).await, LiquityError::OnlyForDepositOwner);
+
+    env.process(&[migrate_deposit(&env, &owner.pubkey())], &[&owner]).await.unwrap();
+    let account = env.context.banks_client.get_account(deposit).await.unwrap();
+    assert_eq!(account.as_ref().unwrap().data.len(), Deposit::LEN);
+    assert_eq!(balance(&account), rent.minimum_balance(Deposit::LEN));
+    let state: Deposit = env.state(deposit).await;
+    assert_eq!((state.owner, state.token_amount), (owner.pubkey(), StableAmount(500 * UNIT)));
+
+    // Anyone may migrate a trove, paying its rent, so that its owner cannot keep an undercollateralised
+    // trove from liquidations. The 20 SOL declared are capped to the 14 SOL held, short of 110% of 1000
+    let liquidator = Keypair::new();
+    env.context.set_account(&liquidator.pubkey(), &Account { lamports: LAMPORTS_PER_SOL, ..Account::default() }.into());
+    let legacy_trove = Trove {
+        borrow_amount: StableAmount(1_000),
+        collateral_amount: CollateralAmount(20 * LAMPORTS_PER_SOL),
+        amount_to_close: StableAmount(800),
+        ..env.trove(owner.pubkey(), false)
+    };
+    let trove = env.legacy_account(legacy_trove, Trove::LEGACY_LEN, 14 * LAMPORTS_PER_SOL).await;
+    let liquidate_trove = instruction::liquidate_trove(
+        &env.program_id,
+        &liquidator.pubkey(),
+        &trove,
+        &env.protocol,
+        &env.collateral_type,
+        None,
+    );
+    assert_error(env.process(std::slice::from_ref(&liquidate_trove), &[&liquidator]).await, LiquityError::AccountNotMigrated);
+
+    let owner_lamports = env.context.banks_client.get_balance(owner.pubkey()).await.unwrap();
+    let migrate_trove = instruction::migrate_account(
+        &env.program_id,
+        &liquidator.pubkey(),
+        &trove,
+        &env.protocol,
+        Some(&env.collateral_type),
+    );
+    env.process(&[migrate_trove], &[&liquidator]).await.unwrap();
+    assert_eq!(env.context.banks_client.get_balance(owner.pubkey()).await.unwrap(), owner_lamports);
+    assert_eq!(
+        env.context.banks_client.get_balance(liquidator.pubkey()).await.unwrap(),
+        LAMPORTS_PER_SOL - (rent.minimum_balance(Trove::LEN) - rent.minimum_balance(Trove::LEGACY_LEN))
+    );
+    let state: Trove = env.state(trove).await;
+    assert_eq!((state.owner, state.collateral_amount), (owner.pubkey(), CollateralAmount(14 * LAMPORTS_PER_SOL)));
+    assert_eq!(
+        env.context.banks_client.get_balance(trove).await.unwrap(),
+        rent.minimum_balance(Trove::LEN) + 14 * LAMPORTS_PER_SOL
+    );
+
+    env.process(&[liquidate_trove], &[&liquidator]).await.unwrap();
+    assert_eq!(env.context.banks_client.get_account(trove).await.unwrap(), None);
+    assert_eq!(env.state::<Protocol>(env.protocol).await.total_debt, StableAmount::ZERO);
+}
+
+#[tokio::test]
+async fn test_init_protocol() {
+    let mut env = Env::new().await;
+    let config = env.state::<Protocol>(env.protocol).await.config;
+
+    // Borrows and flash mints mint through the program authority
+    let mint = Pubkey::new_unique();
+    env.set_mint(mint, Some(Pubkey::new_unique()), DECIMALS).await;
+    let protocol = env.create_account(Protocol::LEN).await;
+    let ix = instruction::init_protocol(&env.program_id, &protocol, &mint, config);
+    assert_error(env.process(&[ix], &[]).await, LiquityError::InvalidMarketAuthority);
+
+    // Collateral ratios below 100%, fees of the whole debt and negative delays are refused
+    let invalidations: [fn(&mut ProtocolConfig); 4] = [
+        |config| config.min_collateral_ratio = Ratio(9_999),
+        |config| config.deposit_fee = Ratio(9_900),
+        |config| config.flash_mint_fee = Ratio(10_001),
+        |config| config.timelock_delay = -1,
+    ];
+    for invalidate in invalidations {
+        let mut config = env.state::<Protocol>(env.protocol).await.config;
+        invalidate(&mut config);
+        let ix = instruction::init_protocol(&env.program_id, &protocol, &env.stablecoin_mint, config);
+        assert_error(env.process(&[ix], &[]).await, LiquityError::InvalidConfig);
+    }
+    let config = env.state::<Protocol>(env.protocol).await.config;
+    let ix = instruction::init_protocol(&env.program_id, &protocol, &env.stablecoin_mint, config);
+    env.process(&[ix], &[]).await.unwrap();
+    assert_eq!(env.state::<Protocol>(protocol).await.config.min_collateral_ratio, Ratio(11_000));
+}
+
+#[tokio::test]
+async fn test_collateral_type() {
+    let mut env = Env::new().await;
+    let admin = env.admin.insecure_clone();
+    let stake_pool = Pubkey::new_unique();
+    let (address, _) = find_collateral_type_address(&env.program_id, &env.protocol, &stake_pool);
+    let init = |env: &Env, admin: &Pubkey, collateral_type: &Pubkey| {
+        instruction::init_collateral_type(&env.program_id, admin, &env.protocol, collateral_type, stake_pool, StableAmount(UNIT))
+    };
+
+    // A collateral type lives only at the address derived from the protocol and the collateral
+    let other = Pubkey::new_unique();
+    assert_error(env.process(&[init(&env, &admin.pubkey(), &other)], &[&admin]).await, LiquityError::InvalidAccountInput);
+    let not_admin = Keypair::new();
+    env.context.set_account(&not_admin.pubkey(), &Account { lamports: LAMPORTS_PER_SOL, ..Account::default() }.into());
+    assert_error(env.process(&[init(&env, &not_admin.pubkey(), &address)], &[&not_admin]).await, LiquityError::OnlyForAdmin);
+
+    // Lamports sent to the address beforehand don't block its creation
+    env.context.set_account(&address, &Account { lamports: 1_000, ..Account::default() }.into());
+    env.process(&[init(&env, &admin.pubkey(), &address)], &[&admin]).await.unwrap();
+    assert_eq!(env.state::<CollateralType>(address).await.collateral, stake_pool);
+    assert_error(env.process(&[init(&env, &admin.pubkey(), &address)], &[&admin]).await, ProgramError::AccountAlreadyInitialized);
+
+    env.update_collateral_type(address, StableAmount(2 * UNIT)).await;
+    assert_eq!(env.state::<CollateralType>(address).await.debt_ceiling, StableAmount(2 * UNIT));
+
+    // The collateral type of another protocol is not updated through this one
+    let foreign_type = env.program_account(CollateralType {
+        is_initialized: true,
+        collateral: stake_pool,
+        debt_ceiling: StableAmount(UNIT),
+        total_debt: StableAmount::ZERO,
+    }).await;
+    let ix = instruction::update_collateral_type(&env.program_id, &admin.pubkey(), &env.protocol, &foreign_type, StableAmount::ZERO);
+    assert_error(env.process(&[ix], &[&admin]).await, LiquityError::InvalidAccountInput);
+}
+
+#[tokio::test]
+async fn test_config_timelock() {
+    let mut env = Env::new().await;
+    let admin = env.admin.insecure_clone();
+    let guardian = env.guardian.insecure_clone();
+    let mut config = env.state::<Protocol>(env.protocol).await.config;
+    let oracle = Pubkey::new_unique();
+    config.debt_ceiling = StableAmount(2_000_000 * UNIT);
+    config.oracle = oracle;
+    let queue = |env: &Env, admin: &Pubkey, config| instruction::queue_config_change(&env.program_id, admin, &env.protocol, config);
+    let execute = |env: &Env| instruction::execute_config_change(&env.program_id, &env.protocol);
+
+    let other = Keypair::new();
+    let current = env.state::<Protocol>(env.protocol).await.config;
+    let ix = queue(&env, &other.pubkey(), current);
+    assert_error(env.process(&[ix], &[&other]).await, LiquityError::OnlyForAdmin);
+    let invalid = ProtocolConfig { min_collateral_ratio: Ratio(9_999), ..env.state::<Protocol>(env.protocol).await.config };
+    let ix = queue(&env, &admin.pubkey(), invalid);
+    assert_error(env.process(&[ix], &[&admin]).await, LiquityError::InvalidConfig);
+    assert_error(env.process(&[execute(&env)], &[]).await, LiquityError::NoConfigChangeQueued);
+
+    env.process(&[queue(&env, &admin.pubkey(), config)], &[&admin]).await.unwrap();
+    let queued: Protocol = env.state(env.protocol).await;
+    let ix = queue(&env, &admin.pubkey(), queued.pending_config);
+    assert_error(env.process(&[ix], &[&admin]).await, LiquityError::ConfigChangeAlreadyQueued);
+
+    // The change, the oracle included, waits out the delay from when it was queued
+    assert_ne!(queued.config.oracle, oracle);
+    assert_error(env.process(&[execute(&env)], &[]).await, LiquityError::TimelockNotExpired);
+    let mut clock: Clock = env.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = queued.pending_eta;
+    env.context.set_sysvar(&clock);
+    env.process(&[execute(&env)], &[]).await.unwrap();
+    let executed: Protocol = env.state(env.protocol).await;
+    assert_eq!(executed.config.debt_ceiling, StableAmount(2_000_000 * UNIT));
+    assert_eq!(executed.config.oracle, oracle);
+    assert_eq!(executed.pending_eta, 0);
+
+    // The guardian cancels a queued change, which can then never be executed
+    let mut config = executed.config;
+    config.debt_ceiling = StableAmount::ZERO;
+    env.process(&[queue(&env, &admin.pubkey(), config)], &[&admin]).await.unwrap();
+    let ix = instruction::cancel_config_change(&env.program_id, &other.pubkey(), &env.protocol);
+    assert_error(env.process(&[ix], &[&other]).await, LiquityError::OnlyForGuardian);
+    let cancel = |env: &Env| instruction::cancel_config_change(&env.program_id, &guardian.pubkey(), &env.protocol);
+    env.process(&[cancel(&env)], &[&guardian]).await.unwrap();
+    assert_error(env.process(&[cancel(&env)], &[&guardian]).await, LiquityError::NoConfigChangeQueued);
+
+    clock.unix_timestamp += 2 * 86_400;
+    env.context.set_sysvar(&clock);
+    assert_error(env.process(&[execute(&env)], &[]).await, LiquityError::NoConfigChangeQueued);
+    let state: Protocol = env.state(env.protocol).await;
+    assert_eq!(state.config.debt_ceiling, StableAmount(2_000_000 * UNIT));
+}
+
+#[tokio::test]
+async fn test_set_pause() {
+    let mut env = Env::new().await;
+    let admin = env.admin.insecure_clone();
+    let pauser = env.pauser.insecure_clone();
+    let set_pause = |env: &Env, pauser: &Pubkey, paused| instruction::set_pause(&env.program_id, pauser, &env.protocol, paused);
+
+    // The pauser or the admin sets the paused operations
+    env.process(&[set_pause(&env, &pauser.pubkey(), PAUSE_BORROW | PAUSE_REDEEM)], &[&pauser]).await.unwrap();
+    assert_eq!(env.state::<Protocol>(env.protocol).await.paused, PAUSE_BORROW | PAUSE_REDEEM);
+    env.process(&[set_pause(&env, &admin.pubkey(), 0)], &[&admin]).await.unwrap();
+    assert_eq!(env.state::<Protocol>(env.protocol).await.paused, 0);
+
+    let other = Keypair::new();
+    assert_error(env.process(&[set_pause(&env, &other.pubkey(), PAUSE_BORROW)], &[&other]).await, LiquityError::OnlyForPauser);
+    assert_error(
+        env.process(&[unsigned(set_pause(&env, &pauser.pubkey(), PAUSE_BORROW))], &[]).await,
+        ProgramError::MissingRequiredSignature,
+    );
+}
+
+#[tokio::test]
+async fn test_flash_mint() {
+    let mut env = Env::new().await;
+    let fee_receiver = env.token_account(env.stablecoin_mint, SYSTEM_ACCOUNT_ADDRESS).await;
+    // 9 bps of 1000
+    let fee = 900_000;
+
+    let ix = env.flash_mint_ix(&fee_receiver, 1_000 * UNIT, fee).await;
+    let receiver_token = ix.accounts[1].pubkey;
+    env.process(&[ix], &[]).await.unwrap();
+    assert_eq!(env.token_amount(receiver_token).await, 0);
+    assert_eq!(env.token_amount(fee_receiver).await, fee);
+    let mint = env.context.banks_client.get_account(env.stablecoin_mint).await.unwrap().unwrap();
+    assert_eq!(spl_token::state::Mint::unpack(&mint.data).unwrap().supply, fee);
+
+    // The receiver pays back less than the amount and the fee
+    let ix = env.flash_mint_ix(&fee_receiver, 1_000 * UNIT, fee - 1).await;
+    assert_error(env.process(&[ix], &[]).await, LiquityError::NotEnoughLiquidityAfterFlashLoan);
+
+    // Paying back more than the fee is accepted, the fee receiver keeps it
+    let ix = env.flash_mint_ix(&fee_receiver, 1_000 * UNIT, fee + UNIT).await;
+    env.process(&[ix], &[]).await.unwrap();
+    assert_eq!(env.token_amount(fee_receiver).await, 2 * fee + UNIT);
+
+    let ix = env.flash_mint_ix(&fee_receiver, 0, fee).await;
+    assert_error(env.process(&[ix], &[]).await, LiquityError::InvalidAmount);
+
+    // The receiver is another program, not the program itself nor a plain account
+    for receiver_program in [env.program_id, GENS_TOKEN_ADDRESS] {
+        let ix = with_account(env.flash_mint_ix(&fee_receiver, 1_000 * UNIT, fee).await, 6, receiver_program, false);
+        assert_error(env.process(&[ix], &[]).await, LiquityError::InvalidFlashLoanReceiverProgram);
+    }
+
+    env.set_pause(PAUSE_FLASH_MINT).await;
+    let ix = env.flash_mint_ix(&fee_receiver, 1_000 * UNIT, fee).await;
+    assert_error(env.process(&[ix], &[]).await, LiquityError::OperationPaused);
+}
+
+#[tokio::test]
+async fn test_invalid_instruction() {
+    let mut env = Env::new().await;
+    let ix = Instruction { program_id: env.program_id, accounts: vec![], data: vec![255] };
+    assert_error(env.process(&[ix], &[]).await, LiquityError::InvalidInstruction);
+    let ix = Instruction { program_id: env.program_id, accounts: vec![], data: vec![20, 0] };
+    assert_error(env.process(&[ix], &[]).await, LiquityError::InstructionUnpackError);
+}
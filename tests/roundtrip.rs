@@ -0,0 +1,284 @@
+use proptest::prelude::*;
+use solana_escrow::error::LiquityError;
+use solana_escrow::instruction::LiquityInstruction;
+use solana_escrow::state::{CollateralType, Deposit, Escrow, Protocol, ProtocolConfig, Trove, HEADER_LEN};
+use solana_escrow::units::{CollateralAmount, GovAmount, Lamports, Ratio, StableAmount};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+};
+
+fn pubkey() -> impl Strategy<Value = Pubkey> {
+    any::<[u8; 32]>().prop_map(Pubkey::new_from_array)
+}
+
+fn config() -> impl Strategy<Value = ProtocolConfig> {
+    (any::<[u64; 6]>(), [pubkey(), pubkey(), pubkey(), pubkey()], any::<i64>()).prop_map(
+        |([interest_rate, flash_mint_fee, debt_ceiling, min_collateral_ratio, deposit_fee, team_fee], [pauser, admin, guardian, oracle], timelock_delay)| {
+            ProtocolConfig {
+                interest_rate: Ratio(interest_rate),
+                flash_mint_fee: Ratio(flash_mint_fee),
+                pauser,
+                debt_ceiling: StableAmount(debt_ceiling),
+                admin,
+                guardian,
+                min_collateral_ratio: Ratio(min_collateral_ratio),
+                deposit_fee: Ratio(deposit_fee),
+                team_fee: Ratio(team_fee),
+                oracle,
+                timelock_delay,
+            }
+        },
+    )
+}
+
+/// Any instruction, the fields of each variant drawn from the same values
+fn instruction() -> impl Strategy<Value = LiquityInstruction> {
+    (0u8..21, any::<[u64; 3]>(), pubkey(), config()).prop_map(|(tag, [a, b, c], key, config)| match tag {
+        0 => LiquityInstruction::Borrow { borrow_amount: StableAmount(a), lamports: Lamports(b) },
+        1 => LiquityInstruction::CloseTrove {},
+        2 => LiquityInstruction::LiquidateTrove {},
+        3 => LiquityInstruction::WithdrawCoin { amount: CollateralAmount(a) },
+        4 => LiquityInstruction::AddCoin { amount: CollateralAmount(a) },
+        5 => LiquityInstruction::RedeemCoin { amount: CollateralAmount(a) },
+        6 => LiquityInstruction::AddDeposit { amount: StableAmount(a) },
+        7 => LiquityInstruction::WithdrawDeposit { amount: StableAmount(a) },
+        8 => LiquityInstruction::ClaimDepositReward {},
+        9 => LiquityInstruction::ReceiveTrove {},
+        10 => LiquityInstruction::AddDepositReward { coin: Lamports(a), governance: GovAmount(b), token: StableAmount(c) },
+        11 => LiquityInstruction::BorrowWithStakePoolToken { borrow_amount: StableAmount(a), pool_tokens: CollateralAmount(b) },
+        12 => LiquityInstruction::InitProtocol { config },
+        13 => LiquityInstruction::QueueConfigChange { config },
+        14 => LiquityInstruction::FlashMint { amount: StableAmount(a) },
+        15 => LiquityInstruction::SetPause { paused: a },
+        16 => LiquityInstruction::InitCollateralType { collateral: key, debt_ceiling: StableAmount(a) },
+        17 => LiquityInstruction::UpdateCollateralType { debt_ceiling: StableAmount(a) },
+        18 => LiquityInstruction::ExecuteConfigChange {},
+        19 => LiquityInstruction::CancelConfigChange {},
+        _ => LiquityInstruction::MigrateAccount {},
+    })
+}
+
+fn trove() -> impl Strategy<Value = Trove> {
+    (any::<[bool; 3]>(), any::<[u64; 5]>(), pubkey(), pubkey(), any::<u128>()).prop_map(
+        |([is_initialized, is_received, is_liquidated], [borrow_amount, collateral_amount, team_fee, depositor_fee, amount_to_close], owner, collateral, borrow_index)| {
+            Trove {
+                is_initialized,
+                is_received,
+                is_liquidated,
+                borrow_amount: StableAmount(borrow_amount),
+                collateral_amount: CollateralAmount(collateral_amount),
+                team_fee: StableAmount(team_fee),
+                depositor_fee: StableAmount(depositor_fee),
+                amount_to_close: StableAmount(amount_to_close),
+                owner,
+                collateral,
+                borrow_index,
+            }
+        },
+    )
+}
+
+fn deposit() -> impl Strategy<Value = Deposit> {
+    (any::<bool>(), any::<[u64; 4]>(), [pubkey(), pubkey(), pubkey()]).prop_map(
+        |(is_initialized, [token_amount, reward_token_amount, reward_governance_token_amount, reward_coin_amount], [bank, governance_bank, owner])| {
+            Deposit {
+                is_initialized,
+                token_amount: StableAmount(token_amount),
+                reward_token_amount: StableAmount(reward_token_amount),
+                reward_governance_token_amount: GovAmount(reward_governance_token_amount),
+                reward_coin_amount: Lamports(reward_coin_amount),
+                bank,
+                governance_bank,
+                owner,
+            }
+        },
+    )
+}
+
+fn escrow() -> impl Strategy<Value = Escrow> {
+    (any::<bool>(), [pubkey(), pubkey(), pubkey()], any::<u64>()).prop_map(
+        |(is_initialized, [initializer_pubkey, temp_token_account_pubkey, initializer_token_to_receive_account_pubkey], expected_amount)| {
+            Escrow {
+                is_initialized,
+                initializer_pubkey,
+                temp_token_account_pubkey,
+                initializer_token_to_receive_account_pubkey,
+                expected_amount,
+            }
+        },
+    )
+}
+
+fn protocol() -> impl Strategy<Value = Protocol> {
+    (any::<(bool, u8, u128, i64, u64, u64, i64)>(), pubkey(), config(), config()).prop_map(
+        |((is_initialized, stablecoin_decimals, borrow_index, last_update_timestamp, paused, total_debt, pending_eta), stablecoin_mint, config, pending_config)| {
+            Protocol {
+                is_initialized,
+                stablecoin_mint,
+                stablecoin_decimals,
+                config,
+                borrow_index,
+                last_update_timestamp,
+                paused,
+                total_debt: StableAmount(total_debt),
+                pending_config,
+                pending_eta,
+            }
+        },
+    )
+}
+
+fn collateral_type() -> impl Strategy<Value = CollateralType> {
+    (any::<bool>(), pubkey(), any::<[u64; 2]>()).prop_map(|(is_initialized, collateral, [debt_ceiling, total_debt])| {
+        CollateralType {
+            is_initialized,
+            collateral,
+            debt_ceiling: StableAmount(debt_ceiling),
+            total_debt: StableAmount(total_debt),
+        }
+    })
+}
+
+fn invalid_instruction() -> ProgramError {
+    LiquityError::InvalidInstruction.into()
+}
+
+fn pack<T: Pack>(state: &T) -> Vec<u8> {
+    let mut data = vec![0; T::LEN];
+    state.pack_into_slice(&mut data);
+    data
+}
+
+/// The packed `state` with one byte overwritten
+fn mutated<T: Pack>(state: impl Strategy<Value = T>) -> impl Strategy<Value = Vec<u8>> {
+    (state, any::<prop::sample::Index>(), any::<u8>()).prop_map(|(state, index, byte)| {
+        let mut data = pack(&state);
+        data[index.index(T::LEN)] = byte;
+        data
+    })
+}
+
+/// Unpacking then packing `state` gives back the same value and bytes
+fn assert_round_trip<T: Pack + IsInitialized + std::fmt::Debug>(state: T) -> Result<(), TestCaseError> {
+    let data = pack(&state);
+    let unpacked = T::unpack_unchecked(&data).unwrap();
+    prop_assert_eq!(format!("{:?}", unpacked), format!("{:?}", state));
+    prop_assert_eq!(pack(&unpacked), data);
+    Ok(())
+}
+
+/// Any account data that unpacks packs back to the same bytes, apart from
+/// the zeroed header of a fresh account, and anything else is rejected
+fn assert_canonical<T: Pack + IsInitialized>(data: &[u8]) -> Result<(), TestCaseError> {
+    match T::unpack_unchecked(data) {
+        Ok(state) => {
+            let packed = pack(&state);
+            if data[..HEADER_LEN] == [0, 0] {
+                prop_assert_eq!(&packed[HEADER_LEN..], &data[HEADER_LEN..]);
+            } else {
+                prop_assert_eq!(packed, data);
+            }
+        }
+        Err(error) => prop_assert_eq!(error, ProgramError::InvalidAccountData),
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn test_instruction_round_trip(instruction in instruction()) {
+        let packed = instruction.pack();
+        let unpacked = LiquityInstruction::unpack(&packed).unwrap();
+        prop_assert_eq!(format!("{:?}", unpacked), format!("{:?}", instruction));
+        prop_assert_eq!(unpacked.pack(), packed);
+    }
+
+    #[test]
+    fn test_instruction_truncated(instruction in instruction(), len in any::<prop::sample::Index>()) {
+        let packed = instruction.pack();
+        let len = len.index(packed.len());
+        prop_assert_eq!(
+            LiquityInstruction::unpack(&packed[..len]).unwrap_err(),
+            if len == 0 { invalid_instruction() } else { LiquityError::InstructionUnpackError.into() }
+        );
+    }
+
+    #[test]
+    fn test_instruction_trailing_bytes(instruction in instruction(), rest in prop::collection::vec(any::<u8>(), 1..64)) {
+        let packed = instruction.pack();
+        prop_assert_eq!(
+            LiquityInstruction::unpack(&[packed.as_slice(), &rest].concat()).unwrap_err(),
+            LiquityError::InstructionUnpackError.into()
+        );
+    }
+
+    #[test]
+    fn test_instruction_arbitrary_bytes(input in prop::collection::vec(any::<u8>(), 0..256)) {
+        // The encoding is canonical, whatever unpacks packs back to the same bytes
+        match LiquityInstruction::unpack(&input) {
+            Ok(instruction) => prop_assert_eq!(instruction.pack(), input),
+            Err(error) => prop_assert!(
+                error == invalid_instruction() || error == LiquityError::InstructionUnpackError.into()
+            ),
+        }
+    }
+
+    #[test]
+    fn test_state_round_trip(
+        trove in trove(),
+        deposit in deposit(),
+        escrow in escrow(),
+        protocol in protocol(),
+        collateral_type in collateral_type(),
+    ) {
+        assert_round_trip(trove)?;
+        assert_round_trip(deposit)?;
+        assert_round_trip(escrow)?;
+        assert_round_trip(protocol)?;
+        assert_round_trip(collateral_type)?;
+    }
+
+    #[test]
+    fn test_state_canonical(
+        trove in mutated(trove()),
+        deposit in mutated(deposit()),
+        escrow in mutated(escrow()),
+        protocol in mutated(protocol()),
+        collateral_type in mutated(collateral_type()),
+    ) {
+        assert_canonical::<Trove>(&trove)?;
+        assert_canonical::<Deposit>(&deposit)?;
+        assert_canonical::<Escrow>(&escrow)?;
+        assert_canonical::<Protocol>(&protocol)?;
+        assert_canonical::<CollateralType>(&collateral_type)?;
+    }
+
+    #[test]
+    fn test_legacy_canonical(deposit in mutated(deposit()), escrow in mutated(escrow())) {
+        // Legacy deposits and escrows pack into the current layout with the same fields
+        for (data, state) in [
+            (&deposit[HEADER_LEN..], Deposit::unpack_unchecked(&deposit[HEADER_LEN..]).map(|state| pack(&state))),
+            (&escrow[HEADER_LEN..], Escrow::unpack_unchecked(&escrow[HEADER_LEN..]).map(|state| pack(&state))),
+        ] {
+            match state {
+                Ok(packed) => prop_assert_eq!(&packed[HEADER_LEN..], data),
+                Err(error) => prop_assert_eq!(error, ProgramError::InvalidAccountData),
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_arbitrary_bytes(data in prop::collection::vec(any::<u8>(), 0..512)) {
+        for error in [
+            Trove::unpack_unchecked(&data).err(),
+            Deposit::unpack_unchecked(&data).err(),
+            Escrow::unpack_unchecked(&data).err(),
+            Protocol::unpack_unchecked(&data).err(),
+            CollateralType::unpack_unchecked(&data).err(),
+        ].iter().flatten() {
+            prop_assert_eq!(error, &ProgramError::InvalidAccountData);
+        }
+    }
+}